@@ -0,0 +1,710 @@
+/* Generated by cbindgen from src/ffi. Do not edit. */
+
+#ifndef ARIESKMS_H
+#define ARIESKMS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ Symmetric key capability `SymmetricCapability::ENCRYPT`
+ */
+#define ARIESKMS_SYMMETRIC_ENCRYPT 1
+
+/*
+ Symmetric key capability `SymmetricCapability::DECRYPT`
+ */
+#define ARIESKMS_SYMMETRIC_DECRYPT 2
+
+/*
+ Symmetric key capability `SymmetricCapability::HMAC_SIGN`
+ */
+#define ARIESKMS_SYMMETRIC_HMAC_SIGN 4
+
+/*
+ Symmetric key capability `SymmetricCapability::HMAC_VERIFY`
+ */
+#define ARIESKMS_SYMMETRIC_HMAC_VERIFY 8
+
+/*
+ Symmetric key capability `SymmetricCapability::EXPORT_WRAPPED`
+ */
+#define ARIESKMS_SYMMETRIC_EXPORT_WRAPPED 16
+
+/*
+ Symmetric key capability `SymmetricCapability::IMPORT_WRAPPED`
+ */
+#define ARIESKMS_SYMMETRIC_IMPORT_WRAPPED 32
+
+/*
+ Symmetric key capability `SymmetricCapability::EXPORTABLE_WHEN_WRAPPED`
+ */
+#define ARIESKMS_SYMMETRIC_EXPORTABLE_WHEN_WRAPPED 256
+
+/*
+ Ecc key capability `EccCapability::SIGN`
+ */
+#define ARIESKMS_ECC_SIGN 1
+
+/*
+ Ecc key capability `EccCapability::VERIFY`
+ */
+#define ARIESKMS_ECC_VERIFY 2
+
+/*
+ Ecc key capability `EccCapability::DERIVE_DIFFIE_HELLMAN`
+ */
+#define ARIESKMS_ECC_DERIVE_DIFFIE_HELLMAN 4
+
+/*
+ Ecc key capability `EccCapability::EXPORTABLE_WHEN_WRAPPED`
+ */
+#define ARIESKMS_ECC_EXPORTABLE_WHEN_WRAPPED 256
+
+/*
+ The version of the plugin ABI described in this header
+ */
+#define ARIESKMS_PLUGIN_ABI_VERSION 1
+
+/*
+ The result of every fallible function. Zero is success.
+ */
+typedef enum ArieskmsErrorCode {
+  /*
+   The call succeeded
+   */
+  ARIESKMS_ERROR_CODE_SUCCESS = 0,
+  /*
+   A pointer was null, a string was not UTF-8 or a value was out of range
+   */
+  ARIESKMS_ERROR_CODE_INVALID_ARGUMENT = 1,
+  /*
+   The library panicked. The handles used in the call should be freed.
+   */
+  ARIESKMS_ERROR_CODE_PANIC = 2,
+  /*
+   `EnclaveErrorKind::ConnectionFailure`
+   */
+  ARIESKMS_ERROR_CODE_ENCLAVE_CONNECTION_FAILURE = 100,
+  /*
+   `EnclaveErrorKind::AccessDenied`
+   */
+  ARIESKMS_ERROR_CODE_ENCLAVE_ACCESS_DENIED = 101,
+  /*
+   `EnclaveErrorKind::ItemNotFound`
+   */
+  ARIESKMS_ERROR_CODE_ENCLAVE_ITEM_NOT_FOUND = 102,
+  /*
+   `EnclaveErrorKind::UnsupportedOperation`
+   */
+  ARIESKMS_ERROR_CODE_ENCLAVE_UNSUPPORTED_OPERATION = 103,
+  /*
+   `EnclaveErrorKind::GeneralError`
+   */
+  ARIESKMS_ERROR_CODE_ENCLAVE_GENERAL_ERROR = 104,
+  /*
+   `EnclaveErrorKind::InvalidCapabilities`
+   */
+  ARIESKMS_ERROR_CODE_ENCLAVE_INVALID_CAPABILITIES = 105,
+  /*
+   `PersistenceErrorKind::InvalidConfig`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_INVALID_CONFIG = 200,
+  /*
+   `PersistenceErrorKind::IOError`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_IO_ERROR = 201,
+  /*
+   `PersistenceErrorKind::ItemNotFound`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_ITEM_NOT_FOUND = 202,
+  /*
+   `PersistenceErrorKind::DuplicateItem`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_DUPLICATE_ITEM = 203,
+  /*
+   `PersistenceErrorKind::SerializationError`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_SERIALIZATION_ERROR = 204,
+  /*
+   `PersistenceErrorKind::InvalidQuery`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_INVALID_QUERY = 205,
+  /*
+   `PersistenceErrorKind::Conflict`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_CONFLICT = 206,
+  /*
+   `PersistenceErrorKind::UnsupportedVersion`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_UNSUPPORTED_VERSION = 207,
+  /*
+   `PersistenceErrorKind::UnsupportedOperation`
+   */
+  ARIESKMS_ERROR_CODE_PERSISTENCE_UNSUPPORTED_OPERATION = 208,
+  /*
+   `ProtectionErrorKind::InvalidData`
+   */
+  ARIESKMS_ERROR_CODE_PROTECTION_INVALID_DATA = 300,
+} ArieskmsErrorCode;
+
+/*
+ The key types that can be generated with `arieskms_key_generate`
+ */
+typedef enum ArieskmsKeyType {
+  /*
+   `EnclaveKeyType::Ed25519`
+   */
+  ARIESKMS_KEY_TYPE_ED25519 = 0,
+  /*
+   `EnclaveKeyType::X25519`
+   */
+  ARIESKMS_KEY_TYPE_X25519 = 1,
+  /*
+   ECDSA with SHA-256 over P-256
+   */
+  ARIESKMS_KEY_TYPE_ECDSA_P256 = 2,
+  /*
+   ECDSA with SHA-384 over P-384
+   */
+  ARIESKMS_KEY_TYPE_ECDSA_P384 = 3,
+  /*
+   ECDSA with SHA-256 over secp256k1
+   */
+  ARIESKMS_KEY_TYPE_ECDSA_SECP256K1 = 4,
+  /*
+   XChaCha20-Poly1305 wrapping key
+   */
+  ARIESKMS_KEY_TYPE_X_CHA_CHA20_POLY1305 = 5,
+  /*
+   AES-128-GCM wrapping key
+   */
+  ARIESKMS_KEY_TYPE_AES128_GCM = 6,
+  /*
+   AES-256-GCM wrapping key
+   */
+  ARIESKMS_KEY_TYPE_AES256_GCM = 7,
+  /*
+   AES-256-GCM-SIV wrapping key
+   */
+  ARIESKMS_KEY_TYPE_AES256_GCM_SIV = 8,
+  /*
+   HMAC-SHA256 key
+   */
+  ARIESKMS_KEY_TYPE_HMAC_SHA256 = 9,
+  /*
+   HMAC-SHA512 key
+   */
+  ARIESKMS_KEY_TYPE_HMAC_SHA512 = 10,
+} ArieskmsKeyType;
+
+/*
+ Categories returned by `arieskms_store_categories`
+ */
+typedef struct ArieskmsCategories ArieskmsCategories;
+
+/*
+ An enclave opened through the C API
+ */
+typedef struct ArieskmsEnclave ArieskmsEnclave;
+
+/*
+ A key held by an enclave
+ */
+typedef struct ArieskmsKey ArieskmsKey;
+
+/*
+ Records returned by a fetch or search
+ */
+typedef struct ArieskmsRecords ArieskmsRecords;
+
+/*
+ A store whose records are protected by an enclave
+ */
+typedef struct ArieskmsStore ArieskmsStore;
+
+/*
+ Bytes owned by the caller that must be released with `arieskms_buffer_free`
+ */
+typedef struct ArieskmsBuffer {
+  /*
+   The first byte
+   */
+  uint8_t *data;
+  /*
+   The number of bytes
+   */
+  size_t len;
+} ArieskmsBuffer;
+
+/*
+ Bytes borrowed from the caller. `data` may be null when `len` is zero.
+ */
+typedef struct ArieskmsSlice {
+  /*
+   The first byte
+   */
+  const uint8_t *data;
+  /*
+   The number of bytes
+   */
+  size_t len;
+} ArieskmsSlice;
+
+/*
+ A tag borrowed from the caller
+ */
+typedef struct ArieskmsTag {
+  /*
+   The tag name
+   */
+  struct ArieskmsSlice name;
+  /*
+   The tag value
+   */
+  struct ArieskmsSlice value;
+  /*
+   Persist the tag as is so it can be used for range and pattern
+   lookups instead of encrypting it
+   */
+  bool plaintext;
+} ArieskmsTag;
+
+/*
+ A record borrowed from an `ArieskmsRecords` handle
+ */
+typedef struct ArieskmsRecord {
+  /*
+   The category of the record
+   */
+  struct ArieskmsSlice category;
+  /*
+   The name of the record
+   */
+  struct ArieskmsSlice name;
+  /*
+   The decrypted value
+   */
+  struct ArieskmsSlice value;
+  /*
+   The first of `tags_len` decrypted tags
+   */
+  const struct ArieskmsTag *tags;
+  /*
+   The number of tags
+   */
+  size_t tags_len;
+} ArieskmsRecord;
+
+/*
+ The enclave functions of a plugin. `enclave` is the instance `open` returned.
+ */
+typedef struct ArieskmsEnclavePlugin {
+  /*
+   Open the enclave named by `uri`
+   */
+  int32_t (*open)(const char *uri, void **out);
+  /*
+   Close the enclave
+   */
+  void (*close)(void *enclave);
+  /*
+   The `EnclaveCapabilities` bits of the enclave
+   */
+  uint64_t (*capabilities)(const void *enclave);
+  /*
+   Create a new key
+   */
+  int32_t (*generate_key)(const void *enclave,
+                          const char *id,
+                          uint32_t key_type,
+                          uint16_t capabilities);
+  /*
+   Save an existing unwrapped key
+   */
+  int32_t (*put_key)(const void *enclave,
+                     const char *id,
+                     uint32_t key_type,
+                     uint16_t capabilities,
+                     struct ArieskmsSlice key);
+  /*
+   Export a key wrapped with XChaCha20-Poly1305 as described by `EnclaveLike::export_wrapped_key`
+   */
+  int32_t (*export_wrapped_key)(const void *enclave,
+                                const char *id,
+                                struct ArieskmsSlice wrapping_key,
+                                struct ArieskmsBuffer *out);
+  /*
+   Encrypt `plaintext` with a fresh nonce authenticating `aad`
+   */
+  int32_t (*encrypt)(const void *enclave,
+                     const char *id,
+                     struct ArieskmsSlice plaintext,
+                     struct ArieskmsSlice aad,
+                     struct ArieskmsBuffer *out);
+  /*
+   Encrypt `plaintext` so the same inputs always give the same ciphertext
+   */
+  int32_t (*encrypt_deterministic)(const void *enclave,
+                                   const char *id,
+                                   struct ArieskmsSlice plaintext,
+                                   struct ArieskmsSlice aad,
+                                   struct ArieskmsBuffer *out);
+  /*
+   Decrypt `ciphertext` authenticating `aad`
+   */
+  int32_t (*decrypt)(const void *enclave,
+                     const char *id,
+                     struct ArieskmsSlice ciphertext,
+                     struct ArieskmsSlice aad,
+                     struct ArieskmsBuffer *out);
+  /*
+   Compute the HMAC of `data`
+   */
+  int32_t (*sign_hmac)(const void *enclave,
+                       const char *id,
+                       struct ArieskmsSlice data,
+                       struct ArieskmsBuffer *out);
+  /*
+   Save a secret like a password or PIN
+   */
+  int32_t (*put_secret)(const void *enclave, const char *id, struct ArieskmsSlice secret);
+  /*
+   Retrieve a secret saved with `put_secret`
+   */
+  int32_t (*fetch_secret)(const void *enclave, const char *id, struct ArieskmsBuffer *out);
+} ArieskmsEnclavePlugin;
+
+/*
+ The persistence functions of a plugin. All of them are required.
+ `store` is the instance `open` returned.
+ */
+typedef struct ArieskmsPersistencePlugin {
+  /*
+   Open the store named by `uri`
+   */
+  int32_t (*open)(const char *uri, void **out);
+  /*
+   Close the store
+   */
+  void (*close)(void *store);
+  /*
+   Save the new JSON `record`
+   */
+  int32_t (*insert)(const void *store, struct ArieskmsSlice record);
+  /*
+   Write the JSON record `name` in `category` to `out`
+   */
+  int32_t (*fetch)(const void *store,
+                   struct ArieskmsSlice category,
+                   struct ArieskmsSlice name,
+                   struct ArieskmsBuffer *out);
+  /*
+   Replace the value and tags of the existing JSON `record`
+   */
+  int32_t (*update)(const void *store, struct ArieskmsSlice record);
+  /*
+   Remove the record `name` in `category`
+   */
+  int32_t (*delete_)(const void *store, struct ArieskmsSlice category, struct ArieskmsSlice name);
+  /*
+   Write the JSON array of records in `category` matching the JSON `query` to `out`
+   */
+  int32_t (*search)(const void *store,
+                    struct ArieskmsSlice category,
+                    struct ArieskmsSlice query,
+                    struct ArieskmsBuffer *out);
+  /*
+   Write the JSON array of categories that have records to `out`
+   */
+  int32_t (*categories)(const void *store, struct ArieskmsBuffer *out);
+  /*
+   Write the JSON page of up to `limit` records in `category` matching the
+   JSON `query` ordered by name to `out`. The page starts after the record
+   named `after` if `has_after` isn't zero.
+   */
+  int32_t (*search_page)(const void *store,
+                         struct ArieskmsSlice category,
+                         struct ArieskmsSlice query,
+                         int has_after,
+                         struct ArieskmsSlice after,
+                         size_t limit,
+                         struct ArieskmsBuffer *out);
+  /*
+   Apply the JSON array of `operations` atomically
+   */
+  int32_t (*apply)(const void *store, struct ArieskmsSlice operations);
+} ArieskmsPersistencePlugin;
+
+/*
+ Returned by the function `arieskms_plugin` a plugin exports
+ */
+typedef struct ArieskmsPlugin {
+  /*
+   `ARIESKMS_PLUGIN_ABI_VERSION` of the header the plugin was built with
+   */
+  uint32_t abi_version;
+  /*
+   The URI scheme the drivers are registered under, like `hsm`
+   */
+  const char *scheme;
+  /*
+   The enclave driver or null
+   */
+  const struct ArieskmsEnclavePlugin *enclave;
+  /*
+   The persistence driver or null
+   */
+  const struct ArieskmsPersistencePlugin *persistence;
+  /*
+   Release a buffer the plugin returned
+   */
+  void (*buffer_free)(struct ArieskmsBuffer buffer);
+  /*
+   The message of the last error on the calling thread or null
+   */
+  const char *(*last_error_message)(void);
+} ArieskmsPlugin;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Zeroize and release `buffer`. Does nothing if it is empty.
+ */
+void arieskms_buffer_free(struct ArieskmsBuffer buffer);
+
+/*
+ The message of the last error on the calling thread, or null if the
+ last call succeeded. The string is valid until the next call on the thread.
+ */
+const char *arieskms_last_error_message(void);
+
+/*
+ The version of this library
+ */
+const char *arieskms_version(void);
+
+/*
+ Open the enclave named by `uri` with the drivers built into this library,
+ like `software:` or `oskeyring:`. The null enclave, which passes data
+ through unencrypted, can't be opened through the C API.
+ */
+enum ArieskmsErrorCode arieskms_enclave_open(const char *uri, struct ArieskmsEnclave **out);
+
+#if (defined(__APPLE__) || defined(__APPLE__))
+/*
+ Open the OS keyring at `path` or the default keyring if `path` is null,
+ unlocking it with `password` if it isn't null
+ */
+enum ArieskmsErrorCode arieskms_enclave_open_os_keyring(const char *path,
+                                                        const char *password,
+                                                        struct ArieskmsEnclave **out);
+#endif
+
+/*
+ Release `enclave`. Keys and stores opened from it remain usable.
+ */
+void arieskms_enclave_free(struct ArieskmsEnclave *enclave);
+
+/*
+ Generate the key `id` of type `key_type` with the `ARIESKMS_SYMMETRIC_*`
+ or `ARIESKMS_ECC_*` bits `capabilities` and open it
+ */
+enum ArieskmsErrorCode arieskms_key_generate(const struct ArieskmsEnclave *enclave,
+                                             const char *id,
+                                             uint32_t key_type,
+                                             uint16_t capabilities,
+                                             struct ArieskmsKey **out);
+
+/*
+ Open the existing key `id`. The enclave reports a missing
+ key when the key is first used.
+ */
+enum ArieskmsErrorCode arieskms_key_open(const struct ArieskmsEnclave *enclave,
+                                         const char *id,
+                                         struct ArieskmsKey **out);
+
+/*
+ Encrypt `plaintext` authenticating `aad`
+ */
+enum ArieskmsErrorCode arieskms_key_encrypt(const struct ArieskmsKey *key,
+                                            struct ArieskmsSlice plaintext,
+                                            struct ArieskmsSlice aad,
+                                            struct ArieskmsBuffer *out);
+
+/*
+ Decrypt `ciphertext` authenticating `aad`
+ */
+enum ArieskmsErrorCode arieskms_key_decrypt(const struct ArieskmsKey *key,
+                                            struct ArieskmsSlice ciphertext,
+                                            struct ArieskmsSlice aad,
+                                            struct ArieskmsBuffer *out);
+
+/*
+ Compute the HMAC of `data`
+ */
+enum ArieskmsErrorCode arieskms_key_sign_hmac(const struct ArieskmsKey *key,
+                                              struct ArieskmsSlice data,
+                                              struct ArieskmsBuffer *out);
+
+/*
+ Release `key`. The key stays in the enclave.
+ */
+void arieskms_key_free(struct ArieskmsKey *key);
+
+#if defined(ARIESKMS_STORAGE_SLED)
+/*
+ Open the sled database at `path`, or a temporary one if `path` is null
+ */
+enum ArieskmsErrorCode arieskms_store_open_sled(const struct ArieskmsEnclave *enclave,
+                                                const char *path,
+                                                const char *key_prefix,
+                                                bool generate_keys,
+                                                struct ArieskmsStore **out);
+#endif
+
+#if defined(ARIESKMS_STORAGE_SQLITE)
+/*
+ Open the SQLite database at `path`, or an in memory one if `path` is null
+ */
+enum ArieskmsErrorCode arieskms_store_open_sqlite(const struct ArieskmsEnclave *enclave,
+                                                  const char *path,
+                                                  const char *key_prefix,
+                                                  bool generate_keys,
+                                                  struct ArieskmsStore **out);
+#endif
+
+#if defined(ARIESKMS_STORAGE_S3)
+/*
+ Open the S3 bucket `bucket` at `endpoint`
+ */
+enum ArieskmsErrorCode arieskms_store_open_s3(const struct ArieskmsEnclave *enclave,
+                                              const char *endpoint,
+                                              const char *bucket,
+                                              const char *region,
+                                              const char *access_key_id,
+                                              const char *secret_access_key,
+                                              const char *key_prefix,
+                                              bool generate_keys,
+                                              struct ArieskmsStore **out);
+#endif
+
+/*
+ Save a new record with the `tags_len` tags in `tags`
+ */
+enum ArieskmsErrorCode arieskms_store_insert(const struct ArieskmsStore *store,
+                                             struct ArieskmsSlice category,
+                                             struct ArieskmsSlice name,
+                                             struct ArieskmsSlice value,
+                                             const struct ArieskmsTag *tags,
+                                             size_t tags_len);
+
+/*
+ Replace the value and tags of an existing record
+ */
+enum ArieskmsErrorCode arieskms_store_update(const struct ArieskmsStore *store,
+                                             struct ArieskmsSlice category,
+                                             struct ArieskmsSlice name,
+                                             struct ArieskmsSlice value,
+                                             const struct ArieskmsTag *tags,
+                                             size_t tags_len);
+
+/*
+ Fetch the value of a record. Use `arieskms_store_fetch_record`
+ to also read its tags.
+ */
+enum ArieskmsErrorCode arieskms_store_fetch(const struct ArieskmsStore *store,
+                                            struct ArieskmsSlice category,
+                                            struct ArieskmsSlice name,
+                                            struct ArieskmsBuffer *out);
+
+/*
+ Fetch a record with its tags as the only record in `out`
+ */
+enum ArieskmsErrorCode arieskms_store_fetch_record(const struct ArieskmsStore *store,
+                                                   struct ArieskmsSlice category,
+                                                   struct ArieskmsSlice name,
+                                                   struct ArieskmsRecords **out);
+
+/*
+ Find every record in `category` whose tags match the WQL query `query`
+ */
+enum ArieskmsErrorCode arieskms_store_search(const struct ArieskmsStore *store,
+                                             struct ArieskmsSlice category,
+                                             const char *query,
+                                             struct ArieskmsRecords **out);
+
+/*
+ Find up to `limit` records in `category` whose tags match the WQL query
+ `query`, starting after the continuation token `cursor` unless it is empty.
+ The token of the next page is written to `next`, which is left empty after
+ the last page.
+ */
+enum ArieskmsErrorCode arieskms_store_search_page(const struct ArieskmsStore *store,
+                                                  struct ArieskmsSlice category,
+                                                  const char *query,
+                                                  struct ArieskmsSlice cursor,
+                                                  size_t limit,
+                                                  struct ArieskmsRecords **out,
+                                                  struct ArieskmsBuffer *next);
+
+/*
+ List every category that has records
+ */
+enum ArieskmsErrorCode arieskms_store_categories(const struct ArieskmsStore *store,
+                                                 struct ArieskmsCategories **out);
+
+/*
+ Delete a record
+ */
+enum ArieskmsErrorCode arieskms_store_delete(const struct ArieskmsStore *store,
+                                             struct ArieskmsSlice category,
+                                             struct ArieskmsSlice name);
+
+/*
+ Close and release `store`
+ */
+void arieskms_store_free(struct ArieskmsStore *store);
+
+/*
+ The number of records in `records`, or zero if it is null
+ */
+size_t arieskms_records_len(const struct ArieskmsRecords *records);
+
+/*
+ Borrow the record at `index` until `records` is freed
+ */
+enum ArieskmsErrorCode arieskms_records_get(const struct ArieskmsRecords *records,
+                                            size_t index,
+                                            struct ArieskmsRecord *out);
+
+/*
+ Zeroize and release `records`
+ */
+void arieskms_records_free(struct ArieskmsRecords *records);
+
+/*
+ The number of categories in `categories`, or zero if it is null
+ */
+size_t arieskms_categories_len(const struct ArieskmsCategories *categories);
+
+/*
+ Borrow the category at `index` until `categories` is freed
+ */
+enum ArieskmsErrorCode arieskms_categories_get(const struct ArieskmsCategories *categories,
+                                               size_t index,
+                                               struct ArieskmsSlice *out);
+
+/*
+ Release `categories`
+ */
+void arieskms_categories_free(struct ArieskmsCategories *categories);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* ARIESKMS_H */
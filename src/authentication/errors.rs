@@ -0,0 +1,125 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+use crate::{
+    persistence::errors::{PersistenceError, PersistenceErrorKind},
+    security::errors::{EnclaveError, EnclaveErrorKind},
+};
+use failure::{Backtrace, Context, Fail};
+use std::fmt;
+
+/// Represents possible errors that could occur while authenticating to a store.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum AuthenticationErrorKind {
+    /// Occurs when a credential is missing or in the wrong format
+    #[fail(display = "Invalid configuration")]
+    InvalidConfig,
+    /// Occurs when a credential does not open the store
+    #[fail(display = "The credentials are not valid for this store")]
+    InvalidCredentials,
+    /// Occurs when a credential can't be read
+    #[fail(display = "IO Error")]
+    IOError,
+    /// Occurs when the enclave holding a credential fails
+    #[fail(display = "Enclave Error: {}", _0)]
+    Enclave(EnclaveErrorKind),
+    /// Occurs when the persistence backend fails
+    #[fail(display = "Persistence Error: {}", _0)]
+    Persistence(PersistenceErrorKind),
+}
+
+/// Represents an authentication error that includes a context and backtrace
+#[derive(Debug)]
+pub struct AuthenticationError {
+    inner: Context<AuthenticationErrorKind>,
+}
+
+impl AuthenticationError {
+    /// Create from a message and kind
+    pub fn from_msg<D: fmt::Display + fmt::Debug + Send + Sync + 'static>(
+        kind: AuthenticationErrorKind,
+        msg: D,
+    ) -> Self {
+        Self {
+            inner: Context::new(msg).context(kind),
+        }
+    }
+
+    /// Get `AuthenticationErrorKind` wrapped by this error
+    pub fn kind(&self) -> AuthenticationErrorKind {
+        self.inner.get_context().clone()
+    }
+}
+
+impl From<AuthenticationErrorKind> for AuthenticationError {
+    fn from(kind: AuthenticationErrorKind) -> Self {
+        Self {
+            inner: Context::new("").context(kind),
+        }
+    }
+}
+
+impl From<Context<AuthenticationErrorKind>> for AuthenticationError {
+    fn from(inner: Context<AuthenticationErrorKind>) -> Self {
+        AuthenticationError { inner }
+    }
+}
+
+impl From<EnclaveError> for AuthenticationError {
+    fn from(e: EnclaveError) -> Self {
+        let kind = AuthenticationErrorKind::Enclave(e.kind());
+        e.context(kind).into()
+    }
+}
+
+impl From<PersistenceError> for AuthenticationError {
+    fn from(e: PersistenceError) -> Self {
+        let kind = AuthenticationErrorKind::Persistence(e.kind());
+        e.context(kind).into()
+    }
+}
+
+impl From<std::io::Error> for AuthenticationError {
+    fn from(e: std::io::Error) -> Self {
+        AuthenticationError::from_msg(AuthenticationErrorKind::IOError, e.to_string())
+    }
+}
+
+impl Fail for AuthenticationError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.inner.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.inner.backtrace()
+    }
+}
+
+impl fmt::Display for AuthenticationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        for cause in Fail::iter_chain(&self.inner) {
+            if first {
+                first = false;
+                writeln!(f, "Error: {}", cause)?;
+            } else {
+                writeln!(f, "Caused by: {}", cause)?;
+            }
+        }
+        Ok(())
+    }
+}
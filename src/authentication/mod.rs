@@ -0,0 +1,581 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Authentication is how a connection to the storage is created and authorized.
+//!
+//! An authenticator turns a credential into a 32 byte store key. The
+//! credential can be a raw key, a passphrase stretched with Argon2id, a key
+//! file or an HMAC key that never leaves an OS keyring. Each store has a
+//! random salt so the same credential gives a different key for every store.
+//!
+//! The first time a store is opened the salt and a verifier computed from the
+//! store key are saved in a reserved category. Later sessions only open the
+//! store when their credential produces a store key that matches the
+//! verifier. The verifier is an HMAC so it doesn't reveal the store key, but
+//! anyone who can read the store can still guess passphrases offline, which
+//! is what the Argon2id cost is for.
+//!
+//! Authentication only decides who may open a store. Keeping records
+//! confidential is up to the data protection layer.
+
+use crate::{
+    persistence::{
+        errors::PersistenceErrorKind, PersistenceConnector, PersistenceLike, Record,
+        RESERVED_CATEGORY_PREFIX,
+    },
+    security::{
+        keys::{HmacKey, KeyExport},
+        source::SecretSource,
+        EnclaveConnector, EnclaveLike, HmacAlgorithm, OsKeyRingConnector,
+    },
+};
+
+use argon2::{Config, ThreadMode, Variant, Version};
+use errors::{AuthenticationError, AuthenticationErrorKind};
+use hmac::{Hmac, Mac, NewMac};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Typical result from authenticating to a store
+pub type AuthenticationResult<T> = Result<T, AuthenticationError>;
+
+/// The size of store keys and raw keys
+pub const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+const VERIFIER_NAME: &[u8] = b"verifier";
+const VERIFIER_LABEL: &[u8] = b"arieskms authentication verifier";
+
+/// The category holding the verifier, `arieskms:authentication`
+fn verifier_category() -> Vec<u8> {
+    [RESERVED_CATEGORY_PREFIX, b"authentication"].concat()
+}
+
+/// All authenticators should use this trait so a store can be
+/// opened the same way whatever the credential is
+pub trait AuthenticatorLike {
+    /// Derive the store key from the credential and the `salt` saved in the store
+    fn store_key(&self, salt: &[u8]) -> AuthenticationResult<Zeroizing<Vec<u8>>>;
+}
+
+/// Authenticates with a random 32 byte key
+pub struct RawKeyAuthenticator {
+    key: Zeroizing<Vec<u8>>,
+}
+
+impl RawKeyAuthenticator {
+    /// Authenticate with `key`
+    pub fn new(key: &[u8]) -> AuthenticationResult<Self> {
+        Ok(Self {
+            key: Zeroizing::new(check_key(key)?.to_vec()),
+        })
+    }
+}
+
+impl AuthenticatorLike for RawKeyAuthenticator {
+    fn store_key(&self, salt: &[u8]) -> AuthenticationResult<Zeroizing<Vec<u8>>> {
+        hmac_sha256(&self.key, salt)
+    }
+}
+
+/// How much work it takes to derive a key from a passphrase
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KdfLevel {
+    /// libsodium's moderate Argon2id limits, 3 passes over 256 MiB
+    Moderate,
+    /// libsodium's interactive Argon2id limits, 2 passes over 64 MiB.
+    /// Only use this when opening a store must be fast.
+    Interactive,
+}
+
+/// Authenticates with a passphrase stretched with Argon2id
+pub struct PassphraseAuthenticator {
+    passphrase: Zeroizing<String>,
+    level: KdfLevel,
+}
+
+impl PassphraseAuthenticator {
+    /// Authenticate with `passphrase` using the moderate limits
+    pub fn new<B: Into<String>>(passphrase: B) -> Self {
+        Self {
+            passphrase: Zeroizing::new(passphrase.into()),
+            level: KdfLevel::Moderate,
+        }
+    }
+
+    /// Authenticate with the passphrase read from `source` using the moderate limits
+    pub fn from_source(source: &SecretSource) -> AuthenticationResult<Self> {
+        Ok(Self {
+            passphrase: source.read()?,
+            level: KdfLevel::Moderate,
+        })
+    }
+
+    /// Set the limits for deriving the key. A store must always
+    /// be opened with the limits it was created with.
+    pub fn level(mut self, level: KdfLevel) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+impl AuthenticatorLike for PassphraseAuthenticator {
+    fn store_key(&self, salt: &[u8]) -> AuthenticationResult<Zeroizing<Vec<u8>>> {
+        // Memory is in KiB
+        let (time_cost, mem_cost) = match self.level {
+            KdfLevel::Moderate => (3, 262_144),
+            KdfLevel::Interactive => (2, 65_536),
+        };
+        let config = Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost,
+            time_cost,
+            lanes: 1,
+            thread_mode: ThreadMode::Sequential,
+            secret: &[],
+            ad: &[],
+            hash_length: KEY_SIZE as u32,
+        };
+        argon2::hash_raw(self.passphrase.as_bytes(), salt, &config)
+            .map(Zeroizing::new)
+            .map_err(|e| {
+                AuthenticationError::from_msg(AuthenticationErrorKind::InvalidConfig, e.to_string())
+            })
+    }
+}
+
+/// Authenticates with a key read from a file. The file holds the
+/// 32 byte key either as is or hex encoded.
+pub struct KeyFileAuthenticator<A: AsRef<Path>> {
+    path: A,
+}
+
+impl<A: AsRef<Path>> KeyFileAuthenticator<A> {
+    /// Authenticate with the key in the file at `path`
+    pub fn new(path: A) -> Self {
+        Self { path }
+    }
+
+    fn read_key(&self) -> AuthenticationResult<Zeroizing<Vec<u8>>> {
+        let path = self.path.as_ref();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = std::fs::metadata(path)?.permissions().mode();
+            if mode & 0o077 != 0 {
+                return Err(AuthenticationError::from_msg(
+                    AuthenticationErrorKind::InvalidConfig,
+                    format!(
+                        "The key file {} must only be accessible by its owner",
+                        path.display()
+                    ),
+                ));
+            }
+        }
+        let contents = Zeroizing::new(std::fs::read(path)?);
+        if contents.len() == KEY_SIZE {
+            return Ok(contents);
+        }
+        let trimmed = std::str::from_utf8(&contents).map(str::trim).unwrap_or("");
+        let key = Zeroizing::new(hex::decode(trimmed).unwrap_or_default());
+        check_key(&key)?;
+        Ok(key)
+    }
+}
+
+impl<A: AsRef<Path>> AuthenticatorLike for KeyFileAuthenticator<A> {
+    fn store_key(&self, salt: &[u8]) -> AuthenticationResult<Zeroizing<Vec<u8>>> {
+        hmac_sha256(&self.read_key()?, salt)
+    }
+}
+
+/// Authenticates with an HMAC key held in an OS keyring.
+/// The key is used in the keyring and never read out of it.
+pub struct OsKeyRingAuthenticator<E: EnclaveLike> {
+    enclave: E,
+    key_id: String,
+}
+
+impl<E: EnclaveLike> OsKeyRingAuthenticator<E> {
+    /// Authenticate with the key `key_id` in `enclave`
+    pub fn new<B: Into<String>>(enclave: E, key_id: B) -> Self {
+        Self {
+            enclave,
+            key_id: key_id.into(),
+        }
+    }
+
+    /// Connect to the keyring described by `config` and
+    /// authenticate with its key `key_id`
+    pub fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: OsKeyRingConnector<A, B>,
+        key_id: B,
+    ) -> AuthenticationResult<Self> {
+        let enclave = E::connect(EnclaveConnector::OsKeyRing(config))?;
+        Ok(Self::new(enclave, key_id))
+    }
+
+    /// Create the key in the keyring. Only needed once before
+    /// the first store is opened with it.
+    pub fn generate_key(&self) -> AuthenticationResult<()> {
+        HmacKey::generate(
+            &self.enclave,
+            self.key_id.as_str(),
+            HmacAlgorithm::Sha256,
+            KeyExport::Never,
+        )?;
+        Ok(())
+    }
+
+    /// Close the connection to the keyring
+    pub fn close(self) {
+        self.enclave.close()
+    }
+}
+
+impl<E: EnclaveLike> AuthenticatorLike for OsKeyRingAuthenticator<E> {
+    fn store_key(&self, salt: &[u8]) -> AuthenticationResult<Zeroizing<Vec<u8>>> {
+        let key = Zeroizing::new(self.enclave.sign_hmac(&self.key_id, salt)?);
+        check_key(&key)?;
+        Ok(key)
+    }
+}
+
+/// The salt and verifier saved in each store
+#[derive(Serialize, Deserialize)]
+struct Verifier {
+    salt: String,
+    verifier: String,
+}
+
+impl Verifier {
+    fn new<T: AuthenticatorLike>(authenticator: &T) -> AuthenticationResult<Self> {
+        let mut salt = [0u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        let store_key = authenticator.store_key(&salt)?;
+        Ok(Self {
+            salt: hex::encode(salt),
+            verifier: hex::encode(&*hmac_sha256(&store_key, VERIFIER_LABEL)?),
+        })
+    }
+
+    fn verify<T: AuthenticatorLike>(&self, authenticator: &T) -> AuthenticationResult<()> {
+        let (salt, verifier) = match (hex::decode(&self.salt), hex::decode(&self.verifier)) {
+            (Ok(s), Ok(v)) => (s, v),
+            _ => {
+                return Err(AuthenticationError::from_msg(
+                    AuthenticationErrorKind::InvalidConfig,
+                    "The store verifier is malformed",
+                ))
+            }
+        };
+        let store_key = authenticator.store_key(&salt)?;
+        let mut mac = new_hmac(&store_key)?;
+        mac.update(VERIFIER_LABEL);
+        mac.verify(&verifier).map_err(|_| {
+            AuthenticationError::from_msg(
+                AuthenticationErrorKind::InvalidCredentials,
+                "The credentials do not match the store",
+            )
+        })
+    }
+
+    fn to_record(&self) -> AuthenticationResult<Record> {
+        Ok(Record {
+            category: verifier_category(),
+            name: VERIFIER_NAME.to_vec(),
+            value: serde_json::to_vec(self).map_err(|e| {
+                AuthenticationError::from_msg(AuthenticationErrorKind::InvalidConfig, e.to_string())
+            })?,
+            tags: Vec::new(),
+        })
+    }
+
+    fn from_record(record: &Record) -> AuthenticationResult<Self> {
+        serde_json::from_slice(&record.value).map_err(|e| {
+            AuthenticationError::from_msg(
+                AuthenticationErrorKind::InvalidConfig,
+                format!("The store verifier is malformed: {}", e),
+            )
+        })
+    }
+}
+
+/// A persistence backend opened with valid credentials
+pub struct Session<P: PersistenceLike> {
+    persistence: P,
+}
+
+impl<P: PersistenceLike> Session<P> {
+    /// Connect to the backend described by `config` and authorize it with `authenticator`
+    pub fn connect<A: AsRef<Path>, B: Into<String>, T: AuthenticatorLike>(
+        authenticator: &T,
+        config: PersistenceConnector<A, B>,
+    ) -> AuthenticationResult<Self> {
+        Self::open(authenticator, P::connect(config)?)
+    }
+
+    /// Authorize `persistence` with `authenticator`. A store that has never been
+    /// opened before is bound to the credentials of `authenticator`.
+    pub fn open<T: AuthenticatorLike>(
+        authenticator: &T,
+        persistence: P,
+    ) -> AuthenticationResult<Self> {
+        let record = match persistence.fetch(&verifier_category(), VERIFIER_NAME) {
+            Ok(record) => Some(record),
+            Err(e) if e.kind() == PersistenceErrorKind::ItemNotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        match record {
+            Some(record) => Verifier::from_record(&record)?.verify(authenticator)?,
+            None => match persistence.insert(Verifier::new(authenticator)?.to_record()?) {
+                Ok(()) => {}
+                // Another session created the verifier first
+                Err(e) if e.kind() == PersistenceErrorKind::DuplicateItem => {
+                    let record = persistence.fetch(&verifier_category(), VERIFIER_NAME)?;
+                    Verifier::from_record(&record)?.verify(authenticator)?
+                }
+                Err(e) => return Err(e.into()),
+            },
+        }
+        Ok(Self { persistence })
+    }
+
+    /// Bind the store to the credentials of `authenticator`
+    /// instead of the ones it was opened with
+    pub fn change_credentials<T: AuthenticatorLike>(
+        &self,
+        authenticator: &T,
+    ) -> AuthenticationResult<()> {
+        self.persistence
+            .update(Verifier::new(authenticator)?.to_record()?)?;
+        Ok(())
+    }
+
+    /// The authorized persistence backend
+    pub fn persistence(&self) -> &P {
+        &self.persistence
+    }
+
+    /// End the session and keep using the persistence backend
+    pub fn into_persistence(self) -> P {
+        self.persistence
+    }
+
+    /// Close the session and the persistence backend
+    pub fn close(self) {
+        self.persistence.close()
+    }
+}
+
+fn check_key(key: &[u8]) -> AuthenticationResult<&[u8]> {
+    if key.len() != KEY_SIZE {
+        return Err(AuthenticationError::from_msg(
+            AuthenticationErrorKind::InvalidConfig,
+            format!("Keys must be {} bytes", KEY_SIZE),
+        ));
+    }
+    Ok(key)
+}
+
+fn new_hmac(key: &[u8]) -> AuthenticationResult<Hmac<Sha256>> {
+    Hmac::<Sha256>::new_varkey(key).map_err(|_| {
+        AuthenticationError::from_msg(AuthenticationErrorKind::InvalidConfig, "Invalid HMAC key")
+    })
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> AuthenticationResult<Zeroizing<Vec<u8>>> {
+    let mut mac = new_hmac(key)?;
+    mac.update(data);
+    Ok(Zeroizing::new(mac.finalize().into_bytes().to_vec()))
+}
+
+/// The errors that can occur while authenticating
+pub mod errors;
+
+#[cfg(all(test, feature = "storage-memory"))]
+mod tests {
+    use super::*;
+    use crate::persistence::kv::memory::MemoryStore;
+    use std::path::PathBuf;
+
+    /// A key file removed when the test ends
+    struct KeyFile(PathBuf);
+
+    impl KeyFile {
+        fn new(name: &str, contents: &[u8], mode: u32) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("arieskms-key-{}-{}", name, std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+            }
+            #[cfg(not(unix))]
+            let _ = mode;
+            Self(path)
+        }
+    }
+
+    impl Drop for KeyFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn open<T: AuthenticatorLike>(
+        authenticator: &T,
+        store: MemoryStore,
+    ) -> AuthenticationResult<MemoryStore> {
+        Session::open(authenticator, store).map(Session::into_persistence)
+    }
+
+    fn assert_kind<T>(result: AuthenticationResult<T>, kind: AuthenticationErrorKind) {
+        match result {
+            Err(e) => assert_eq!(e.kind(), kind),
+            Ok(_) => panic!("Expected {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn raw_keys() {
+        let key = RawKeyAuthenticator::new(&[1; KEY_SIZE]).unwrap();
+        let store = open(&key, MemoryStore::new()).unwrap();
+        let verifier = store.fetch(&verifier_category(), VERIFIER_NAME).unwrap();
+        assert!(!verifier.value.windows(KEY_SIZE).any(|w| w == [1; KEY_SIZE]));
+
+        let store = open(&key, store).unwrap();
+        let wrong = RawKeyAuthenticator::new(&[2; KEY_SIZE]).unwrap();
+        assert_kind(
+            open(&wrong, store),
+            AuthenticationErrorKind::InvalidCredentials,
+        );
+        assert_kind(
+            RawKeyAuthenticator::new(&[1; 16]),
+            AuthenticationErrorKind::InvalidConfig,
+        );
+    }
+
+    #[test]
+    fn passphrases() {
+        let passphrase = PassphraseAuthenticator::new("correct horse").level(KdfLevel::Interactive);
+        let store = open(&passphrase, MemoryStore::new()).unwrap();
+        let store = open(&passphrase, store).unwrap();
+
+        let wrong = PassphraseAuthenticator::new("wrong horse").level(KdfLevel::Interactive);
+        assert_kind(
+            open(&wrong, store),
+            AuthenticationErrorKind::InvalidCredentials,
+        );
+    }
+
+    #[test]
+    fn key_files() {
+        let raw = KeyFile::new("raw", &[3; KEY_SIZE], 0o600);
+        let hex = KeyFile::new(
+            "hex",
+            format!("{}\n", hex::encode([3; KEY_SIZE])).as_bytes(),
+            0o600,
+        );
+        let store = open(&KeyFileAuthenticator::new(&raw.0), MemoryStore::new()).unwrap();
+        // The same key encoded either way opens the store
+        let store = open(&KeyFileAuthenticator::new(&hex.0), store).unwrap();
+
+        let wrong = KeyFile::new("wrong", &[4; KEY_SIZE], 0o600);
+        assert_kind(
+            open(&KeyFileAuthenticator::new(&wrong.0), store),
+            AuthenticationErrorKind::InvalidCredentials,
+        );
+        let short = KeyFile::new("short", &[3; 16], 0o600);
+        assert_kind(
+            KeyFileAuthenticator::new(&short.0).store_key(&[0; SALT_SIZE]),
+            AuthenticationErrorKind::InvalidConfig,
+        );
+        assert_kind(
+            KeyFileAuthenticator::new(raw.0.with_extension("missing")).store_key(&[0; SALT_SIZE]),
+            AuthenticationErrorKind::IOError,
+        );
+        #[cfg(unix)]
+        {
+            let shared = KeyFile::new("shared", &[3; KEY_SIZE], 0o644);
+            assert_kind(
+                KeyFileAuthenticator::new(&shared.0).store_key(&[0; SALT_SIZE]),
+                AuthenticationErrorKind::InvalidConfig,
+            );
+        }
+    }
+
+    #[cfg(feature = "software-enclave")]
+    #[test]
+    fn enclave_keys() {
+        use crate::security::software::SoftwareEnclave;
+
+        let authenticator = OsKeyRingAuthenticator::new(SoftwareEnclave::new(), "auth");
+        assert_kind(
+            open(&authenticator, MemoryStore::new()),
+            AuthenticationErrorKind::Enclave(
+                crate::security::errors::EnclaveErrorKind::ItemNotFound,
+            ),
+        );
+        authenticator.generate_key().unwrap();
+        let store = open(&authenticator, MemoryStore::new()).unwrap();
+        let store = open(&authenticator, store).unwrap();
+
+        let other = OsKeyRingAuthenticator::new(SoftwareEnclave::new(), "auth");
+        other.generate_key().unwrap();
+        assert_kind(
+            open(&other, store),
+            AuthenticationErrorKind::InvalidCredentials,
+        );
+    }
+
+    #[test]
+    fn changed_credentials() {
+        let old = RawKeyAuthenticator::new(&[1; KEY_SIZE]).unwrap();
+        let new = RawKeyAuthenticator::new(&[2; KEY_SIZE]).unwrap();
+        let session = Session::open(&old, MemoryStore::new()).unwrap();
+        session.change_credentials(&new).unwrap();
+        let store = session.into_persistence();
+
+        let record = store.fetch(&verifier_category(), VERIFIER_NAME).unwrap();
+        assert_kind(
+            Verifier::from_record(&record).unwrap().verify(&old),
+            AuthenticationErrorKind::InvalidCredentials,
+        );
+        open(&new, store).unwrap();
+    }
+
+    #[test]
+    fn malformed_verifiers_are_rejected() {
+        let store = MemoryStore::new();
+        store
+            .insert(Record {
+                category: verifier_category(),
+                name: VERIFIER_NAME.to_vec(),
+                value: b"{\"salt\":\"zz\",\"verifier\":\"\"}".to_vec(),
+                tags: Vec::new(),
+            })
+            .unwrap();
+        let key = RawKeyAuthenticator::new(&[1; KEY_SIZE]).unwrap();
+        assert_kind(open(&key, store), AuthenticationErrorKind::InvalidConfig);
+    }
+}
@@ -0,0 +1,607 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Enclaves are shared by the keys and stores opened from them,
+//! so an enclave handle can be freed while those are still in use.
+
+use super::{
+    bytes, call, free_handle, handle, string, write, write_handle, ArieskmsBuffer,
+    ArieskmsErrorCode, ArieskmsSlice, FfiError, FfiResult,
+};
+use crate::{
+    registry::Registry,
+    security::{
+        shared::SharedEnclave, AesModes, AesSizes, EcCurves, EccCapability, EcdsaAlgorithm,
+        EnclaveKeyType, EnclaveLike, HmacAlgorithm, KeyCapabilities, SymmetricCapability,
+        WrappingKey,
+    },
+};
+
+use std::os::raw::c_char;
+
+/// Symmetric key capability `SymmetricCapability::ENCRYPT`
+pub const ARIESKMS_SYMMETRIC_ENCRYPT: u16 = 0x0001;
+/// Symmetric key capability `SymmetricCapability::DECRYPT`
+pub const ARIESKMS_SYMMETRIC_DECRYPT: u16 = 0x0002;
+/// Symmetric key capability `SymmetricCapability::HMAC_SIGN`
+pub const ARIESKMS_SYMMETRIC_HMAC_SIGN: u16 = 0x0004;
+/// Symmetric key capability `SymmetricCapability::HMAC_VERIFY`
+pub const ARIESKMS_SYMMETRIC_HMAC_VERIFY: u16 = 0x0008;
+/// Symmetric key capability `SymmetricCapability::EXPORT_WRAPPED`
+pub const ARIESKMS_SYMMETRIC_EXPORT_WRAPPED: u16 = 0x0010;
+/// Symmetric key capability `SymmetricCapability::IMPORT_WRAPPED`
+pub const ARIESKMS_SYMMETRIC_IMPORT_WRAPPED: u16 = 0x0020;
+/// Symmetric key capability `SymmetricCapability::EXPORTABLE_WHEN_WRAPPED`
+pub const ARIESKMS_SYMMETRIC_EXPORTABLE_WHEN_WRAPPED: u16 = 0x0100;
+/// Ecc key capability `EccCapability::SIGN`
+pub const ARIESKMS_ECC_SIGN: u16 = 0x0001;
+/// Ecc key capability `EccCapability::VERIFY`
+pub const ARIESKMS_ECC_VERIFY: u16 = 0x0002;
+/// Ecc key capability `EccCapability::DERIVE_DIFFIE_HELLMAN`
+pub const ARIESKMS_ECC_DERIVE_DIFFIE_HELLMAN: u16 = 0x0004;
+/// Ecc key capability `EccCapability::EXPORTABLE_WHEN_WRAPPED`
+pub const ARIESKMS_ECC_EXPORTABLE_WHEN_WRAPPED: u16 = 0x0100;
+
+/// The key types that can be generated with `arieskms_key_generate`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArieskmsKeyType {
+    /// `EnclaveKeyType::Ed25519`
+    Ed25519 = 0,
+    /// `EnclaveKeyType::X25519`
+    X25519 = 1,
+    /// ECDSA with SHA-256 over P-256
+    EcdsaP256 = 2,
+    /// ECDSA with SHA-384 over P-384
+    EcdsaP384 = 3,
+    /// ECDSA with SHA-256 over secp256k1
+    EcdsaSecp256k1 = 4,
+    /// XChaCha20-Poly1305 wrapping key
+    XChaCha20Poly1305 = 5,
+    /// AES-128-GCM wrapping key
+    Aes128Gcm = 6,
+    /// AES-256-GCM wrapping key
+    Aes256Gcm = 7,
+    /// AES-256-GCM-SIV wrapping key
+    Aes256GcmSiv = 8,
+    /// HMAC-SHA256 key
+    HmacSha256 = 9,
+    /// HMAC-SHA512 key
+    HmacSha512 = 10,
+}
+
+impl ArieskmsKeyType {
+    fn from_u32(value: u32) -> FfiResult<Self> {
+        use ArieskmsKeyType::*;
+        [
+            Ed25519,
+            X25519,
+            EcdsaP256,
+            EcdsaP384,
+            EcdsaSecp256k1,
+            XChaCha20Poly1305,
+            Aes128Gcm,
+            Aes256Gcm,
+            Aes256GcmSiv,
+            HmacSha256,
+            HmacSha512,
+        ]
+        .iter()
+        .copied()
+        .find(|t| *t as u32 == value)
+        .ok_or_else(|| FfiError::invalid_argument("Unknown key type"))
+    }
+
+    fn key_type(self, capabilities: u16) -> FfiResult<(EnclaveKeyType, KeyCapabilities)> {
+        use ArieskmsKeyType::*;
+        let key_type = match self {
+            Ed25519 => EnclaveKeyType::Ed25519,
+            X25519 => EnclaveKeyType::X25519,
+            EcdsaP256 => EnclaveKeyType::Ecdsa(EcCurves::Secp256r1, EcdsaAlgorithm::Sha256),
+            EcdsaP384 => EnclaveKeyType::Ecdsa(EcCurves::Secp384r1, EcdsaAlgorithm::Sha384),
+            EcdsaSecp256k1 => EnclaveKeyType::Ecdsa(EcCurves::Secp256k1, EcdsaAlgorithm::Sha256),
+            XChaCha20Poly1305 => EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305),
+            Aes128Gcm => EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes128, AesModes::Gcm)),
+            Aes256Gcm => EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::Gcm)),
+            Aes256GcmSiv => {
+                EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::GcmSiv))
+            }
+            HmacSha256 => EnclaveKeyType::Hmac(HmacAlgorithm::Sha256),
+            HmacSha512 => EnclaveKeyType::Hmac(HmacAlgorithm::Sha512),
+        };
+        let invalid = || FfiError::invalid_argument("Unknown capabilities for the key type");
+        let capabilities = match self {
+            Ed25519 | X25519 | EcdsaP256 | EcdsaP384 | EcdsaSecp256k1 => {
+                KeyCapabilities::Ecc(EccCapability::from_bits(capabilities).ok_or_else(invalid)?)
+            }
+            _ => KeyCapabilities::Symmetric(
+                SymmetricCapability::from_bits(capabilities).ok_or_else(invalid)?,
+            ),
+        };
+        Ok((key_type, capabilities))
+    }
+
+    /// The C representation of `key_type` and `capabilities` or `None`
+    /// if the key type has none
+    // Only plugins pass key types to C
+    #[allow(dead_code)]
+    pub(crate) fn from_key_type(
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+    ) -> Option<(Self, u16)> {
+        use ArieskmsKeyType::*;
+        let key_type = match key_type {
+            EnclaveKeyType::Ed25519 => Ed25519,
+            EnclaveKeyType::X25519 => X25519,
+            EnclaveKeyType::Ecdsa(EcCurves::Secp256r1, EcdsaAlgorithm::Sha256) => EcdsaP256,
+            EnclaveKeyType::Ecdsa(EcCurves::Secp384r1, EcdsaAlgorithm::Sha384) => EcdsaP384,
+            EnclaveKeyType::Ecdsa(EcCurves::Secp256k1, EcdsaAlgorithm::Sha256) => EcdsaSecp256k1,
+            EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305) => XChaCha20Poly1305,
+            EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes128, AesModes::Gcm)) => {
+                Aes128Gcm
+            }
+            EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::Gcm)) => {
+                Aes256Gcm
+            }
+            EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::GcmSiv)) => {
+                Aes256GcmSiv
+            }
+            EnclaveKeyType::Hmac(HmacAlgorithm::Sha256) => HmacSha256,
+            EnclaveKeyType::Hmac(HmacAlgorithm::Sha512) => HmacSha512,
+            _ => return None,
+        };
+        let capabilities = match capabilities {
+            KeyCapabilities::Symmetric(c) => c.bits(),
+            KeyCapabilities::Ecc(c) => c.bits(),
+            KeyCapabilities::Rsa(_) => return None,
+        };
+        Some((key_type, capabilities))
+    }
+}
+
+/// An enclave opened through the C API
+pub struct ArieskmsEnclave(pub(crate) SharedEnclave);
+
+/// A key held by an enclave
+pub struct ArieskmsKey {
+    enclave: SharedEnclave,
+    id: String,
+}
+
+/// Open the enclave named by `uri` with the drivers built into this library,
+/// like `software:` or `oskeyring:`. The null enclave, which passes data
+/// through unencrypted, can't be opened through the C API.
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_enclave_open(
+    uri: *const c_char,
+    out: *mut *mut ArieskmsEnclave,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let uri = string(uri)?;
+        let mut registry = Registry::default();
+        registry.remove_enclave("null");
+        write_handle(out, ArieskmsEnclave(registry.open_enclave(uri)?))
+    })
+}
+
+/// Open the OS keyring at `path` or the default keyring if `path` is null,
+/// unlocking it with `password` if it isn't null
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_enclave_open_os_keyring(
+    path: *const c_char,
+    password: *const c_char,
+    out: *mut *mut ArieskmsEnclave,
+) -> ArieskmsErrorCode {
+    use crate::security::{os::macos::MacOsKeyRing, EnclaveConnector, OsKeyRingConnector};
+    call(|| {
+        let mut config = OsKeyRingConnector::new(super::optional_string(path)?);
+        if let Some(password) = super::optional_string(password)? {
+            config = config.password(password.to_string());
+        }
+        let enclave = MacOsKeyRing::connect(EnclaveConnector::OsKeyRing(config))?;
+        write_handle(out, ArieskmsEnclave(SharedEnclave::new(enclave)))
+    })
+}
+
+/// Release `enclave`. Keys and stores opened from it remain usable.
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_enclave_free(enclave: *mut ArieskmsEnclave) {
+    free_handle(enclave)
+}
+
+/// Generate the key `id` of type `key_type` with the `ARIESKMS_SYMMETRIC_*`
+/// or `ARIESKMS_ECC_*` bits `capabilities` and open it
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_key_generate(
+    enclave: *const ArieskmsEnclave,
+    id: *const c_char,
+    key_type: u32,
+    capabilities: u16,
+    out: *mut *mut ArieskmsKey,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let enclave = handle(enclave)?;
+        let id = string(id)?;
+        let (key_type, capabilities) =
+            ArieskmsKeyType::from_u32(key_type)?.key_type(capabilities)?;
+        enclave.0.create_key(id, key_type, capabilities)?;
+        write_handle(
+            out,
+            ArieskmsKey {
+                enclave: enclave.0.clone(),
+                id: id.to_string(),
+            },
+        )
+    })
+}
+
+/// Open the existing key `id`. The enclave reports a missing
+/// key when the key is first used.
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_key_open(
+    enclave: *const ArieskmsEnclave,
+    id: *const c_char,
+    out: *mut *mut ArieskmsKey,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let key = ArieskmsKey {
+            enclave: handle(enclave)?.0.clone(),
+            id: string(id)?.to_string(),
+        };
+        write_handle(out, key)
+    })
+}
+
+/// Encrypt `plaintext` authenticating `aad`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_key_encrypt(
+    key: *const ArieskmsKey,
+    plaintext: ArieskmsSlice,
+    aad: ArieskmsSlice,
+    out: *mut ArieskmsBuffer,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let key = handle(key)?;
+        let ciphertext = key
+            .enclave
+            .encrypt(&key.id, bytes(plaintext)?, bytes(aad)?)?;
+        write(out, ciphertext.into())
+    })
+}
+
+/// Decrypt `ciphertext` authenticating `aad`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_key_decrypt(
+    key: *const ArieskmsKey,
+    ciphertext: ArieskmsSlice,
+    aad: ArieskmsSlice,
+    out: *mut ArieskmsBuffer,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let key = handle(key)?;
+        let plaintext = key
+            .enclave
+            .decrypt(&key.id, bytes(ciphertext)?, bytes(aad)?)?;
+        write(out, plaintext.into())
+    })
+}
+
+/// Compute the HMAC of `data`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_key_sign_hmac(
+    key: *const ArieskmsKey,
+    data: ArieskmsSlice,
+    out: *mut ArieskmsBuffer,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let key = handle(key)?;
+        let tag = key.enclave.sign_hmac(&key.id, bytes(data)?)?;
+        write(out, tag.into())
+    })
+}
+
+/// Release `key`. The key stays in the enclave.
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_key_free(key: *mut ArieskmsKey) {
+    free_handle(key)
+}
+
+#[cfg(all(test, feature = "software-enclave"))]
+mod tests {
+    use super::*;
+    use crate::ffi::{arieskms_buffer_free, tests::last_error};
+    use std::ptr;
+
+    fn c(s: &str) -> std::ffi::CString {
+        std::ffi::CString::new(s).unwrap()
+    }
+
+    fn slice(bytes: &[u8]) -> ArieskmsSlice {
+        ArieskmsSlice {
+            data: bytes.as_ptr(),
+            len: bytes.len(),
+        }
+    }
+
+    fn empty() -> ArieskmsBuffer {
+        ArieskmsBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    /// Copy and release `buffer`
+    unsafe fn take(buffer: ArieskmsBuffer) -> Vec<u8> {
+        let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+        arieskms_buffer_free(buffer);
+        bytes
+    }
+
+    unsafe fn open() -> *mut ArieskmsEnclave {
+        let mut enclave = ptr::null_mut();
+        let code = arieskms_enclave_open(c("software:").as_ptr(), &mut enclave);
+        assert_eq!(code, ArieskmsErrorCode::Success);
+        assert!(!enclave.is_null());
+        enclave
+    }
+
+    unsafe fn generate(
+        enclave: *const ArieskmsEnclave,
+        id: &str,
+        key_type: ArieskmsKeyType,
+        capabilities: u16,
+    ) -> *mut ArieskmsKey {
+        let mut key = ptr::null_mut();
+        let code = arieskms_key_generate(
+            enclave,
+            c(id).as_ptr(),
+            key_type as u32,
+            capabilities,
+            &mut key,
+        );
+        assert_eq!(code, ArieskmsErrorCode::Success, "{:?}", last_error());
+        key
+    }
+
+    #[test]
+    fn open_enclaves() {
+        unsafe {
+            let mut enclave = ptr::null_mut();
+            let table = [
+                ("", ArieskmsErrorCode::EnclaveConnectionFailure),
+                ("unknown:", ArieskmsErrorCode::EnclaveConnectionFailure),
+                // The null enclave doesn't protect anything
+                ("null:", ArieskmsErrorCode::EnclaveConnectionFailure),
+                ("software:path", ArieskmsErrorCode::EnclaveConnectionFailure),
+            ];
+            for (uri, code) in table.iter() {
+                assert_eq!(
+                    arieskms_enclave_open(c(uri).as_ptr(), &mut enclave),
+                    *code,
+                    "{}",
+                    uri
+                );
+                assert!(last_error().is_some());
+                assert!(enclave.is_null());
+            }
+            assert_eq!(
+                arieskms_enclave_open(ptr::null(), &mut enclave),
+                ArieskmsErrorCode::InvalidArgument
+            );
+            assert_eq!(
+                arieskms_enclave_open(c("software:").as_ptr(), ptr::null_mut()),
+                ArieskmsErrorCode::InvalidArgument
+            );
+            arieskms_enclave_free(open());
+            arieskms_enclave_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        unsafe {
+            let enclave = open();
+            let key = generate(
+                enclave,
+                "wrap",
+                ArieskmsKeyType::XChaCha20Poly1305,
+                ARIESKMS_SYMMETRIC_ENCRYPT | ARIESKMS_SYMMETRIC_DECRYPT,
+            );
+            // Keys outlive the enclave handle
+            arieskms_enclave_free(enclave);
+
+            let mut out = empty();
+            let code = arieskms_key_encrypt(key, slice(b"secret"), slice(b"aad"), &mut out);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            assert_eq!(last_error(), None);
+            let ciphertext = take(out);
+
+            let mut out = empty();
+            let code = arieskms_key_decrypt(key, slice(&ciphertext), slice(b"aad"), &mut out);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            assert_eq!(take(out), b"secret");
+
+            let table: [(&[u8], &[u8]); 4] = [
+                (&ciphertext, b"other"),
+                (&ciphertext[..ciphertext.len() - 1], b"aad"),
+                // Too small to hold a nonce
+                (&ciphertext[..4], b"aad"),
+                (&[], b"aad"),
+            ];
+            for (ciphertext, aad) in table.iter() {
+                let mut out = empty();
+                let code = arieskms_key_decrypt(key, slice(ciphertext), slice(aad), &mut out);
+                assert_eq!(code, ArieskmsErrorCode::EnclaveGeneralError);
+                assert!(last_error().is_some());
+                assert!(out.data.is_null());
+            }
+
+            // Empty inputs may be null
+            let none = ArieskmsSlice {
+                data: ptr::null(),
+                len: 0,
+            };
+            let mut out = empty();
+            assert_eq!(
+                arieskms_key_encrypt(key, none, none, &mut out),
+                ArieskmsErrorCode::Success
+            );
+            take(out);
+
+            let mut out = empty();
+            let code = arieskms_key_sign_hmac(key, slice(b"data"), &mut out);
+            assert_eq!(code, ArieskmsErrorCode::EnclaveAccessDenied);
+            assert!(out.data.is_null());
+            arieskms_key_free(key);
+        }
+    }
+
+    #[test]
+    fn hmac() {
+        unsafe {
+            let enclave = open();
+            let key = generate(
+                enclave,
+                "mac",
+                ArieskmsKeyType::HmacSha256,
+                ARIESKMS_SYMMETRIC_HMAC_SIGN,
+            );
+            let mut out = empty();
+            let code = arieskms_key_sign_hmac(key, slice(b"data"), &mut out);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            assert_eq!(take(out).len(), 32);
+
+            let mut other = ptr::null_mut();
+            let code = arieskms_key_open(enclave, c("mac").as_ptr(), &mut other);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            let mut out = empty();
+            arieskms_key_sign_hmac(other, slice(b"data"), &mut out);
+            let mut again = empty();
+            arieskms_key_sign_hmac(key, slice(b"data"), &mut again);
+            assert_eq!(take(out), take(again));
+
+            arieskms_key_free(other);
+            arieskms_key_free(key);
+            arieskms_key_free(ptr::null_mut());
+            arieskms_enclave_free(enclave);
+        }
+    }
+
+    #[test]
+    fn invalid_keys() {
+        unsafe {
+            let enclave = open();
+            let mut key = ptr::null_mut();
+            let table = [
+                (
+                    ArieskmsKeyType::HmacSha512 as u32 + 1,
+                    ARIESKMS_SYMMETRIC_ENCRYPT,
+                    ArieskmsErrorCode::InvalidArgument,
+                ),
+                (
+                    ArieskmsKeyType::XChaCha20Poly1305 as u32,
+                    0x8000,
+                    ArieskmsErrorCode::InvalidArgument,
+                ),
+                (
+                    ArieskmsKeyType::Ed25519 as u32,
+                    ARIESKMS_SYMMETRIC_EXPORT_WRAPPED,
+                    ArieskmsErrorCode::InvalidArgument,
+                ),
+                (
+                    ArieskmsKeyType::XChaCha20Poly1305 as u32,
+                    ARIESKMS_SYMMETRIC_HMAC_SIGN,
+                    ArieskmsErrorCode::EnclaveInvalidCapabilities,
+                ),
+            ];
+            for (key_type, capabilities, code) in table.iter() {
+                let result = arieskms_key_generate(
+                    enclave,
+                    c("key").as_ptr(),
+                    *key_type,
+                    *capabilities,
+                    &mut key,
+                );
+                assert_eq!(result, *code, "{} {:x}", key_type, capabilities);
+                assert!(key.is_null());
+            }
+
+            let id = c("key");
+            let chacha = ArieskmsKeyType::XChaCha20Poly1305 as u32;
+            let encrypt = ARIESKMS_SYMMETRIC_ENCRYPT;
+            for (enclave, id, out) in &[
+                (ptr::null(), id.as_ptr(), &mut key as *mut _),
+                (enclave as *const _, ptr::null(), &mut key as *mut _),
+            ] {
+                let code = arieskms_key_generate(*enclave, *id, chacha, encrypt, *out);
+                assert_eq!(code, ArieskmsErrorCode::InvalidArgument);
+                let code = arieskms_key_open(*enclave, *id, *out);
+                assert_eq!(code, ArieskmsErrorCode::InvalidArgument);
+            }
+            assert_eq!(
+                arieskms_key_open(enclave, id.as_ptr(), ptr::null_mut()),
+                ArieskmsErrorCode::InvalidArgument
+            );
+
+            // Missing keys are reported when they are used
+            let code = arieskms_key_open(enclave, c("missing").as_ptr(), &mut key);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            let mut out = empty();
+            assert_eq!(
+                arieskms_key_encrypt(key, slice(b"secret"), slice(b""), &mut out),
+                ArieskmsErrorCode::EnclaveItemNotFound
+            );
+            arieskms_key_free(key);
+            arieskms_enclave_free(enclave);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        unsafe {
+            let enclave = open();
+            let key = generate(
+                enclave,
+                "wrap",
+                ArieskmsKeyType::Aes256Gcm,
+                ARIESKMS_SYMMETRIC_ENCRYPT | ARIESKMS_SYMMETRIC_DECRYPT,
+            );
+            let data = slice(b"data");
+            let dangling = ArieskmsSlice {
+                data: ptr::null(),
+                len: 4,
+            };
+            let mut out = empty();
+            let calls = [
+                arieskms_key_encrypt(ptr::null(), data, data, &mut out),
+                arieskms_key_encrypt(key, dangling, data, &mut out),
+                arieskms_key_encrypt(key, data, dangling, &mut out),
+                arieskms_key_encrypt(key, data, data, ptr::null_mut()),
+                arieskms_key_decrypt(ptr::null(), data, data, &mut out),
+                arieskms_key_decrypt(key, dangling, data, &mut out),
+                arieskms_key_sign_hmac(ptr::null(), data, &mut out),
+                arieskms_key_sign_hmac(key, dangling, &mut out),
+            ];
+            for (i, code) in calls.iter().enumerate() {
+                assert_eq!(*code, ArieskmsErrorCode::InvalidArgument, "call {}", i);
+            }
+            assert!(out.data.is_null());
+            arieskms_key_free(key);
+            arieskms_enclave_free(enclave);
+        }
+    }
+}
@@ -0,0 +1,1415 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Loading the enclave and persistence plugins described in `ffi::plugin`
+//! from shared libraries.
+//!
+//! ```no_run
+//! # use arieskms::{ffi::loader::Plugin, registry::Registry};
+//! let mut registry = Registry::default();
+//! // Loading a library runs its initialization code
+//! let plugin = unsafe { Plugin::load("/usr/lib/arieskms/libacme_hsm.so") }.unwrap();
+//! registry.register_plugin(plugin);
+//! let enclave = registry.open_enclave("acme-hsm://rack-1?slot=2");
+//! ```
+//!
+//! The library stays loaded while the plugin or anything opened from it is alive.
+
+use super::{
+    enclave::ArieskmsKeyType,
+    plugin::{
+        ArieskmsEnclavePlugin, ArieskmsPersistencePlugin, ArieskmsPlugin,
+        ARIESKMS_PLUGIN_ABI_VERSION,
+    },
+    ArieskmsBuffer, ArieskmsErrorCode, ArieskmsSlice,
+};
+use crate::{
+    persistence::{
+        cursor::{Cursor, Page},
+        errors::{PersistenceError, PersistenceErrorKind},
+        transaction::Operation,
+        wql::Query,
+        PersistenceConnector, PersistenceLike, PersistenceResult, Record,
+    },
+    security::{
+        errors::{EnclaveError, EnclaveErrorKind},
+        validation::CheckedKey,
+        EnclaveCapabilities, EnclaveConnector, EnclaveKeyType, EnclaveLike, EnclaveResult,
+        KeyCapabilities,
+    },
+};
+
+use failure::{Backtrace, Context, Fail};
+use libloading::Library;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    convert::TryFrom,
+    ffi::{c_void, CStr, CString, OsStr},
+    fmt,
+    os::raw::c_char,
+    path::Path,
+    ptr,
+    sync::Arc,
+};
+use zeroize::Zeroizing;
+
+/// The name of the function every plugin exports
+const ENTRY_POINT: &[u8] = b"arieskms_plugin\0";
+
+/// Represents possible errors that could occur while loading a plugin.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum PluginErrorKind {
+    /// Occurs when the library can't be loaded or doesn't export `arieskms_plugin`
+    #[fail(display = "The library can't be loaded")]
+    LoadFailed,
+    /// Occurs when the plugin was built for another version of the ABI
+    #[fail(display = "The plugin ABI version is not supported")]
+    UnsupportedVersion,
+    /// Occurs when the plugin is missing required functions
+    #[fail(display = "The plugin is invalid")]
+    InvalidPlugin,
+}
+
+/// Represents a plugin error that includes a context and backtrace
+#[derive(Debug)]
+pub struct PluginError {
+    inner: Context<PluginErrorKind>,
+}
+
+impl PluginError {
+    /// Create from a message and kind
+    pub fn from_msg<D: fmt::Display + fmt::Debug + Send + Sync + 'static>(
+        kind: PluginErrorKind,
+        msg: D,
+    ) -> Self {
+        Self {
+            inner: Context::new(msg).context(kind),
+        }
+    }
+
+    /// Get `PluginErrorKind` wrapped by this error
+    pub fn kind(&self) -> PluginErrorKind {
+        self.inner.get_context().clone()
+    }
+}
+
+impl From<PluginErrorKind> for PluginError {
+    fn from(kind: PluginErrorKind) -> Self {
+        Self {
+            inner: Context::new("").context(kind),
+        }
+    }
+}
+
+impl Fail for PluginError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.inner.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.inner.backtrace()
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        for cause in Fail::iter_chain(&self.inner) {
+            if first {
+                first = false;
+                writeln!(f, "Error: {}", cause)?;
+            } else {
+                writeln!(f, "Caused by: {}", cause)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A plugin loaded from a shared library
+pub struct Plugin {
+    descriptor: &'static ArieskmsPlugin,
+    scheme: String,
+    // Dropped last so the descriptor stays valid
+    _library: Option<Library>,
+}
+
+impl Plugin {
+    /// Load the plugin in the shared library at `path`.
+    ///
+    /// # Safety
+    ///
+    /// Loading runs the initialization code of the library and the plugin
+    /// must implement the ABI in `ffi::plugin` correctly.
+    pub unsafe fn load<P: AsRef<OsStr>>(path: P) -> Result<Arc<Self>, PluginError> {
+        let library = Library::new(path.as_ref())
+            .map_err(|e| PluginError::from_msg(PluginErrorKind::LoadFailed, e.to_string()))?;
+        let descriptor: &'static ArieskmsPlugin = {
+            let entry = library
+                .get::<unsafe extern "C" fn() -> *const ArieskmsPlugin>(ENTRY_POINT)
+                .map_err(|e| PluginError::from_msg(PluginErrorKind::LoadFailed, e.to_string()))?;
+            // The descriptor lives as long as the library, which is kept loaded with it
+            entry().as_ref().ok_or_else(|| {
+                PluginError::from_msg(
+                    PluginErrorKind::InvalidPlugin,
+                    "arieskms_plugin returned null",
+                )
+            })?
+        };
+        Self::from_descriptor(descriptor, Some(library))
+    }
+
+    /// Check `descriptor` and keep `library` loaded with it
+    unsafe fn from_descriptor(
+        descriptor: &'static ArieskmsPlugin,
+        library: Option<Library>,
+    ) -> Result<Arc<Self>, PluginError> {
+        if descriptor.abi_version != ARIESKMS_PLUGIN_ABI_VERSION {
+            return Err(PluginError::from_msg(
+                PluginErrorKind::UnsupportedVersion,
+                format!(
+                    "The plugin uses version {} but {} is required",
+                    descriptor.abi_version, ARIESKMS_PLUGIN_ABI_VERSION
+                ),
+            ));
+        }
+        let invalid =
+            |msg: &'static str| PluginError::from_msg(PluginErrorKind::InvalidPlugin, msg);
+        if descriptor.scheme.is_null() {
+            return Err(invalid("The plugin has no scheme"));
+        }
+        let scheme = CStr::from_ptr(descriptor.scheme)
+            .to_str()
+            .map_err(|_| invalid("The scheme is not UTF-8"))?
+            .to_ascii_lowercase();
+        if descriptor.buffer_free.is_none() {
+            return Err(invalid("The plugin has no buffer_free"));
+        }
+        if let Some(e) = descriptor.enclave.as_ref() {
+            if e.open.is_none() || e.close.is_none() || e.capabilities.is_none() {
+                return Err(invalid(
+                    "The enclave driver needs open, close and capabilities",
+                ));
+            }
+        }
+        if let Some(p) = descriptor.persistence.as_ref() {
+            if p.open.is_none()
+                || p.close.is_none()
+                || p.insert.is_none()
+                || p.fetch.is_none()
+                || p.update.is_none()
+                || p.delete.is_none()
+                || p.search.is_none()
+                || p.categories.is_none()
+                || p.search_page.is_none()
+                || p.apply.is_none()
+            {
+                return Err(invalid("The persistence driver is missing functions"));
+            }
+        }
+        if descriptor.enclave.is_null() && descriptor.persistence.is_null() {
+            return Err(invalid("The plugin has no drivers"));
+        }
+        Ok(Arc::new(Self {
+            descriptor,
+            scheme,
+            _library: library,
+        }))
+    }
+
+    /// The URI scheme of the plugin's drivers
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Does the plugin have an enclave driver
+    pub fn has_enclave(&self) -> bool {
+        !self.descriptor.enclave.is_null()
+    }
+
+    /// Does the plugin have a persistence driver
+    pub fn has_persistence(&self) -> bool {
+        !self.descriptor.persistence.is_null()
+    }
+
+    /// Open the enclave named by `uri` with the plugin
+    pub fn open_enclave(self: &Arc<Self>, uri: &str) -> EnclaveResult<PluginEnclave> {
+        let vtable = self.enclave().ok_or_else(|| {
+            connection_failure(format!("The plugin {} has no enclave driver", self.scheme))
+        })?;
+        let uri = CString::new(uri).map_err(|_| connection_failure("The URI has a nul byte"))?;
+        let mut instance = ptr::null_mut();
+        let open = vtable.open.expect("checked when loading");
+        let code = unsafe { open(uri.as_ptr(), &mut instance) };
+        self.check(code).map_err(|(c, m)| enclave_error(c, m))?;
+        Ok(PluginEnclave {
+            instance: Instance::new(self.clone(), instance, vtable.close),
+        })
+    }
+
+    /// Open the persistence backend named by `uri` with the plugin
+    pub fn open_persistence(self: &Arc<Self>, uri: &str) -> PersistenceResult<PluginPersistence> {
+        let vtable = self.persistence().ok_or_else(|| {
+            PersistenceError::from_msg(
+                PersistenceErrorKind::InvalidConfig,
+                format!("The plugin {} has no persistence driver", self.scheme),
+            )
+        })?;
+        let uri = CString::new(uri).map_err(|_| {
+            PersistenceError::from_msg(
+                PersistenceErrorKind::InvalidConfig,
+                "The URI has a nul byte",
+            )
+        })?;
+        let mut instance = ptr::null_mut();
+        let open = vtable.open.expect("checked when loading");
+        let code = unsafe { open(uri.as_ptr(), &mut instance) };
+        self.check(code).map_err(|(c, m)| persistence_error(c, m))?;
+        Ok(PluginPersistence {
+            instance: Instance::new(self.clone(), instance, vtable.close),
+        })
+    }
+
+    fn enclave(&self) -> Option<&'static ArieskmsEnclavePlugin> {
+        unsafe { self.descriptor.enclave.as_ref() }
+    }
+
+    fn persistence(&self) -> Option<&'static ArieskmsPersistencePlugin> {
+        unsafe { self.descriptor.persistence.as_ref() }
+    }
+
+    /// The code and message of a failed call. The code is `None` if the
+    /// plugin returned a value that isn't an `ArieskmsErrorCode`.
+    fn check(&self, code: i32) -> Result<(), (Option<ArieskmsErrorCode>, String)> {
+        let code = ArieskmsErrorCode::try_from(code);
+        if code == Ok(ArieskmsErrorCode::Success) {
+            return Ok(());
+        }
+        let message = self
+            .descriptor
+            .last_error_message
+            .map(|f| unsafe { f() })
+            .filter(|m| !m.is_null())
+            .map(|m| unsafe { CStr::from_ptr(m) }.to_string_lossy().into_owned())
+            .unwrap_or_else(|| match code {
+                Ok(code) => format!("The plugin {} failed with {:?}", self.scheme, code),
+                Err(code) => format!(
+                    "The plugin {} failed with the unknown code {}",
+                    self.scheme, code
+                ),
+            });
+        Err((code.ok(), message))
+    }
+
+    /// Copy `buffer` and release it with the plugin
+    fn take(&self, buffer: ArieskmsBuffer) -> Zeroizing<Vec<u8>> {
+        let bytes = if buffer.data.is_null() {
+            Vec::new()
+        } else {
+            unsafe { std::slice::from_raw_parts(buffer.data, buffer.len) }.to_vec()
+        };
+        let free = self.descriptor.buffer_free.expect("checked when loading");
+        unsafe { free(buffer) };
+        Zeroizing::new(bytes)
+    }
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin")
+            .field("scheme", &self.scheme)
+            .field("enclave", &self.has_enclave())
+            .field("persistence", &self.has_persistence())
+            .finish()
+    }
+}
+
+/// An instance opened by a plugin that is closed when dropped
+struct Instance {
+    plugin: Arc<Plugin>,
+    ptr: *mut c_void,
+    close: Option<unsafe extern "C" fn(*mut c_void)>,
+}
+
+// Plugins must be thread safe as documented in `ffi::plugin`
+unsafe impl Send for Instance {}
+unsafe impl Sync for Instance {}
+
+impl Instance {
+    fn new(
+        plugin: Arc<Plugin>,
+        ptr: *mut c_void,
+        close: Option<unsafe extern "C" fn(*mut c_void)>,
+    ) -> Self {
+        Self { plugin, ptr, close }
+    }
+
+    /// Call a function of the plugin that writes its result to a buffer
+    fn read<F>(&self, f: F) -> Result<Zeroizing<Vec<u8>>, (Option<ArieskmsErrorCode>, String)>
+    where
+        F: FnOnce(*mut ArieskmsBuffer) -> i32,
+    {
+        let mut out = ArieskmsBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        let code = f(&mut out);
+        let result = self.plugin.check(code);
+        let bytes = self.plugin.take(out);
+        result.map(|_| bytes)
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        if let Some(close) = self.close {
+            unsafe { close(self.ptr) }
+        }
+    }
+}
+
+fn slice(bytes: &[u8]) -> ArieskmsSlice {
+    ArieskmsSlice {
+        data: bytes.as_ptr(),
+        len: bytes.len(),
+    }
+}
+
+fn connection_failure<M: Into<String>>(msg: M) -> EnclaveError {
+    EnclaveErrorKind::ConnectionFailure { msg: msg.into() }.into()
+}
+
+/// The error for a code returned by an enclave plugin
+fn enclave_error(code: Option<ArieskmsErrorCode>, msg: String) -> EnclaveError {
+    let kind = match code {
+        Some(ArieskmsErrorCode::EnclaveConnectionFailure) => {
+            EnclaveErrorKind::ConnectionFailure { msg }
+        }
+        Some(ArieskmsErrorCode::EnclaveAccessDenied) => EnclaveErrorKind::AccessDenied { msg },
+        Some(ArieskmsErrorCode::EnclaveItemNotFound) => EnclaveErrorKind::ItemNotFound,
+        Some(ArieskmsErrorCode::EnclaveUnsupportedOperation) => {
+            EnclaveErrorKind::UnsupportedOperation
+        }
+        Some(ArieskmsErrorCode::EnclaveInvalidCapabilities) => {
+            EnclaveErrorKind::InvalidCapabilities { msg }
+        }
+        _ => EnclaveErrorKind::GeneralError { msg },
+    };
+    kind.into()
+}
+
+/// The error for a code returned by a persistence plugin
+fn persistence_error(code: Option<ArieskmsErrorCode>, msg: String) -> PersistenceError {
+    let kind = match code {
+        Some(ArieskmsErrorCode::PersistenceInvalidConfig) => PersistenceErrorKind::InvalidConfig,
+        Some(ArieskmsErrorCode::PersistenceItemNotFound) => PersistenceErrorKind::ItemNotFound,
+        Some(ArieskmsErrorCode::PersistenceDuplicateItem) => PersistenceErrorKind::DuplicateItem,
+        Some(ArieskmsErrorCode::PersistenceSerializationError) => {
+            PersistenceErrorKind::SerializationError
+        }
+        Some(ArieskmsErrorCode::PersistenceInvalidQuery) => PersistenceErrorKind::InvalidQuery,
+        Some(ArieskmsErrorCode::PersistenceConflict) => PersistenceErrorKind::Conflict,
+        Some(ArieskmsErrorCode::PersistenceUnsupportedVersion) => {
+            PersistenceErrorKind::UnsupportedVersion
+        }
+        Some(ArieskmsErrorCode::PersistenceUnsupportedOperation) => {
+            PersistenceErrorKind::UnsupportedOperation
+        }
+        _ => PersistenceErrorKind::IOError,
+    };
+    PersistenceError::from_msg(kind, msg)
+}
+
+/// An enclave opened by a plugin
+#[derive(Debug)]
+pub struct PluginEnclave {
+    instance: Instance,
+}
+
+impl PluginEnclave {
+    fn vtable(&self) -> &'static ArieskmsEnclavePlugin {
+        self.instance
+            .plugin
+            .enclave()
+            .expect("opened by the enclave driver")
+    }
+
+    fn id(id: &str) -> EnclaveResult<CString> {
+        CString::new(id).map_err(|_| {
+            EnclaveErrorKind::GeneralError {
+                msg: "Ids can't have nul bytes".to_string(),
+            }
+            .into()
+        })
+    }
+
+    fn key_type(
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+    ) -> EnclaveResult<(u32, u16)> {
+        ArieskmsKeyType::from_key_type(key_type, capabilities)
+            .map(|(t, c)| (t as u32, c))
+            .ok_or_else(|| EnclaveErrorKind::UnsupportedOperation.into())
+    }
+
+    /// Call a function that writes its result to a buffer
+    fn read<F>(&self, id: &str, f: Option<F>) -> EnclaveResult<Zeroizing<Vec<u8>>>
+    where
+        F: FnOnce(*const c_void, *const c_char, *mut ArieskmsBuffer) -> i32,
+    {
+        let f = f.ok_or(EnclaveErrorKind::UnsupportedOperation)?;
+        let id = Self::id(id)?;
+        self.instance
+            .read(|out| f(self.instance.ptr, id.as_ptr(), out))
+            .map_err(|(c, m)| enclave_error(c, m))
+    }
+
+    fn call(&self, code: i32) -> EnclaveResult<()> {
+        self.instance
+            .plugin
+            .check(code)
+            .map_err(|(c, m)| enclave_error(c, m))
+    }
+}
+
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Instance")
+            .field("plugin", &self.plugin.scheme)
+            .finish()
+    }
+}
+
+impl EnclaveLike for PluginEnclave {
+    /// Plugin enclaves are opened with `Plugin::open_enclave`
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: EnclaveConnector<A, B>,
+    ) -> EnclaveResult<Self> {
+        Err(connection_failure(format!(
+            "{} is not a plugin URI. Open plugin enclaves with `Plugin::open_enclave`",
+            config
+        )))
+    }
+
+    fn close(self) {}
+
+    fn capabilities(&self) -> EnclaveCapabilities {
+        let f = self.vtable().capabilities.expect("checked when loading");
+        EnclaveCapabilities::from_bits_truncate(unsafe { f(self.instance.ptr) })
+    }
+
+    fn generate_key(&self, id: &str, checked: CheckedKey) -> EnclaveResult<()> {
+        let f = self
+            .vtable()
+            .generate_key
+            .ok_or(EnclaveErrorKind::UnsupportedOperation)?;
+        let (key_type, capabilities) = Self::key_type(checked.key_type(), checked.capabilities())?;
+        let id = Self::id(id)?;
+        self.call(unsafe { f(self.instance.ptr, id.as_ptr(), key_type, capabilities) })
+    }
+
+    fn put_key(&self, id: &str, checked: CheckedKey, key: &[u8]) -> EnclaveResult<()> {
+        let f = self
+            .vtable()
+            .put_key
+            .ok_or(EnclaveErrorKind::UnsupportedOperation)?;
+        let (key_type, capabilities) = Self::key_type(checked.key_type(), checked.capabilities())?;
+        let id = Self::id(id)?;
+        self.call(unsafe {
+            f(
+                self.instance.ptr,
+                id.as_ptr(),
+                key_type,
+                capabilities,
+                slice(key),
+            )
+        })
+    }
+
+    fn export_wrapped_key(&self, id: &str, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let f = self.vtable().export_wrapped_key;
+        self.read(
+            id,
+            f.map(|f| move |e, id, out| unsafe { f(e, id, slice(wrapping_key), out) }),
+        )
+        .map(|b| b.to_vec())
+    }
+
+    fn encrypt(&self, id: &str, plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let f = self.vtable().encrypt;
+        self.read(
+            id,
+            f.map(|f| move |e, id, out| unsafe { f(e, id, slice(plaintext), slice(aad), out) }),
+        )
+        .map(|b| b.to_vec())
+    }
+
+    fn encrypt_deterministic(
+        &self,
+        id: &str,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> EnclaveResult<Vec<u8>> {
+        let f = self.vtable().encrypt_deterministic;
+        self.read(
+            id,
+            f.map(|f| move |e, id, out| unsafe { f(e, id, slice(plaintext), slice(aad), out) }),
+        )
+        .map(|b| b.to_vec())
+    }
+
+    fn decrypt(&self, id: &str, ciphertext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let f = self.vtable().decrypt;
+        self.read(
+            id,
+            f.map(|f| move |e, id, out| unsafe { f(e, id, slice(ciphertext), slice(aad), out) }),
+        )
+        .map(|b| b.to_vec())
+    }
+
+    fn sign_hmac(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let f = self.vtable().sign_hmac;
+        self.read(
+            id,
+            f.map(|f| move |e, id, out| unsafe { f(e, id, slice(data), out) }),
+        )
+        .map(|b| b.to_vec())
+    }
+
+    fn put_secret(&self, id: &str, secret: &[u8]) -> EnclaveResult<()> {
+        let f = self
+            .vtable()
+            .put_secret
+            .ok_or(EnclaveErrorKind::UnsupportedOperation)?;
+        let id = Self::id(id)?;
+        self.call(unsafe { f(self.instance.ptr, id.as_ptr(), slice(secret)) })
+    }
+
+    fn fetch_secret(&self, id: &str) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        let f = self.vtable().fetch_secret;
+        self.read(id, f.map(|f| move |e, id, out| unsafe { f(e, id, out) }))
+    }
+}
+
+/// A persistence backend opened by a plugin
+#[derive(Debug)]
+pub struct PluginPersistence {
+    instance: Instance,
+}
+
+/// A page of search results returned by a plugin
+#[derive(Deserialize)]
+struct PluginPage {
+    records: Vec<Record>,
+    next: Option<Vec<u8>>,
+}
+
+impl PluginPersistence {
+    fn vtable(&self) -> &'static ArieskmsPersistencePlugin {
+        self.instance
+            .plugin
+            .persistence()
+            .expect("opened by the persistence driver")
+    }
+
+    fn call(&self, code: i32) -> PersistenceResult<()> {
+        self.instance
+            .plugin
+            .check(code)
+            .map_err(|(c, m)| persistence_error(c, m))
+    }
+
+    /// Call a function that writes JSON to a buffer
+    fn read<T, F>(&self, f: F) -> PersistenceResult<T>
+    where
+        T: DeserializeOwned,
+        F: FnOnce(*const c_void, *mut ArieskmsBuffer) -> i32,
+    {
+        let json = self
+            .instance
+            .read(|out| f(self.instance.ptr, out))
+            .map_err(|(c, m)| persistence_error(c, m))?;
+        serde_json::from_slice(&json).map_err(|e| {
+            PersistenceError::from_msg(
+                PersistenceErrorKind::SerializationError,
+                format!("The plugin returned invalid JSON: {}", e),
+            )
+        })
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> PersistenceResult<Zeroizing<Vec<u8>>> {
+    serde_json::to_vec(value).map(Zeroizing::new).map_err(|e| {
+        PersistenceError::from_msg(PersistenceErrorKind::SerializationError, e.to_string())
+    })
+}
+
+impl PersistenceLike for PluginPersistence {
+    /// Plugin backends are opened with `Plugin::open_persistence`
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: PersistenceConnector<A, B>,
+    ) -> PersistenceResult<Self> {
+        Err(PersistenceError::from_msg(
+            PersistenceErrorKind::InvalidConfig,
+            format!(
+                "{} is not a plugin URI. Open plugin backends with `Plugin::open_persistence`",
+                config
+            ),
+        ))
+    }
+
+    fn close(self) {}
+
+    fn insert(&self, record: Record) -> PersistenceResult<()> {
+        let f = self.vtable().insert.expect("checked when loading");
+        let record = to_json(&record)?;
+        self.call(unsafe { f(self.instance.ptr, slice(&record)) })
+    }
+
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        let f = self.vtable().fetch.expect("checked when loading");
+        self.read(|s, out| unsafe { f(s, slice(category), slice(name), out) })
+    }
+
+    fn update(&self, record: Record) -> PersistenceResult<()> {
+        let f = self.vtable().update.expect("checked when loading");
+        let record = to_json(&record)?;
+        self.call(unsafe { f(self.instance.ptr, slice(&record)) })
+    }
+
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+        let f = self.vtable().delete.expect("checked when loading");
+        self.call(unsafe { f(self.instance.ptr, slice(category), slice(name)) })
+    }
+
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>> {
+        let f = self.vtable().search.expect("checked when loading");
+        let query = to_json(query)?;
+        self.read(|s, out| unsafe { f(s, slice(category), slice(&query), out) })
+    }
+
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>> {
+        let f = self.vtable().categories.expect("checked when loading");
+        self.read(|s, out| unsafe { f(s, out) })
+    }
+
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page> {
+        let f = self.vtable().search_page.expect("checked when loading");
+        let query = to_json(query)?;
+        let after = cursor.map(Cursor::after).unwrap_or_default();
+        let page: PluginPage = self.read(|s, out| unsafe {
+            f(
+                s,
+                slice(category),
+                slice(&query),
+                cursor.is_some() as _,
+                slice(after),
+                limit,
+                out,
+            )
+        })?;
+        Ok(Page {
+            records: page.records,
+            next: page.next.map(Cursor::new),
+        })
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()> {
+        let f = self.vtable().apply.expect("checked when loading");
+        let operations = to_json(&operations)?;
+        self.call(unsafe { f(self.instance.ptr, slice(&operations)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ffi::enclave::ARIESKMS_SYMMETRIC_DECRYPT,
+        ffi::enclave::ARIESKMS_SYMMETRIC_ENCRYPT,
+        persistence::RecordTag,
+        registry::Registry,
+        security::{HmacAlgorithm, SymmetricCapability, WrappingKey},
+    };
+    use std::{cell::RefCell, collections::BTreeMap, os::raw::c_int, str::FromStr, sync::Mutex};
+
+    // A plugin written against the ABI like one in a shared library would be.
+    // Its enclave only xors data with a one byte key and its store keeps
+    // records in memory.
+
+    thread_local! {
+        #[allow(clippy::missing_const_for_thread_local)]
+        static PLUGIN_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+    }
+
+    /// Return `code`, leaving `message` for `last_error_message` or clearing it
+    fn done(code: i32, message: Option<&str>) -> i32 {
+        let message = message.map(|m| CString::new(m).unwrap());
+        PLUGIN_ERROR.with(|e| *e.borrow_mut() = message);
+        code
+    }
+
+    fn fail(code: ArieskmsErrorCode, message: &str) -> i32 {
+        done(code as i32, Some(message))
+    }
+
+    unsafe extern "C" fn last_error_message() -> *const c_char {
+        PLUGIN_ERROR.with(|e| {
+            e.borrow()
+                .as_ref()
+                .map(|m| m.as_ptr())
+                .unwrap_or_else(ptr::null)
+        })
+    }
+
+    unsafe extern "C" fn buffer_free(buffer: ArieskmsBuffer) {
+        crate::ffi::arieskms_buffer_free(buffer)
+    }
+
+    unsafe fn input<'a>(slice: ArieskmsSlice) -> &'a [u8] {
+        crate::ffi::bytes(slice).unwrap()
+    }
+
+    unsafe fn output(out: *mut ArieskmsBuffer, bytes: Vec<u8>) -> i32 {
+        out.write(bytes.into());
+        done(0, None)
+    }
+
+    /// Open `test:` or fail with the code in `test:?code=<code>` without a message
+    unsafe fn open<T: Default>(uri: *const c_char, out: *mut *mut c_void) -> i32 {
+        let uri = CStr::from_ptr(uri).to_str().unwrap().to_ascii_lowercase();
+        if let Some(code) = uri.strip_prefix("test:?code=") {
+            return done(code.parse().unwrap(), None);
+        }
+        if uri != "test:" {
+            return fail(
+                ArieskmsErrorCode::EnclaveConnectionFailure,
+                "The test plugin takes no options",
+            );
+        }
+        out.write(Box::into_raw(Box::<T>::default()) as *mut c_void);
+        done(0, None)
+    }
+
+    #[derive(Default)]
+    struct TestEnclave {
+        keys: Mutex<BTreeMap<String, u8>>,
+        secrets: Mutex<BTreeMap<String, Vec<u8>>>,
+    }
+
+    unsafe fn enclave<'a>(enclave: *const c_void) -> &'a TestEnclave {
+        &*(enclave as *const TestEnclave)
+    }
+
+    unsafe extern "C" fn enclave_open(uri: *const c_char, out: *mut *mut c_void) -> i32 {
+        open::<TestEnclave>(uri, out)
+    }
+
+    unsafe extern "C" fn enclave_close(enclave: *mut c_void) {
+        drop(Box::from_raw(enclave as *mut TestEnclave))
+    }
+
+    unsafe extern "C" fn capabilities(_: *const c_void) -> u64 {
+        (EnclaveCapabilities::GENERATE_XCHACHA20_POLY1305_KEY
+            | EnclaveCapabilities::ENCRYPT_XCHACHA20_POLY1305
+            | EnclaveCapabilities::DECRYPT_XCHACHA20_POLY1305)
+            .bits()
+    }
+
+    unsafe extern "C" fn generate_key(
+        e: *const c_void,
+        id: *const c_char,
+        key_type: u32,
+        capabilities: u16,
+    ) -> i32 {
+        if key_type != ArieskmsKeyType::XChaCha20Poly1305 as u32
+            || capabilities != ARIESKMS_SYMMETRIC_ENCRYPT | ARIESKMS_SYMMETRIC_DECRYPT
+        {
+            return fail(
+                ArieskmsErrorCode::EnclaveInvalidCapabilities,
+                "Unexpected key",
+            );
+        }
+        let id = CStr::from_ptr(id).to_str().unwrap();
+        let key = enclave(e).keys.lock().unwrap().len() as u8 + 1;
+        enclave(e).keys.lock().unwrap().insert(id.to_string(), key);
+        done(0, None)
+    }
+
+    /// Xor `data` with the key `id`, which is prepended to ciphertexts
+    unsafe fn xor(e: *const c_void, id: *const c_char, data: &[u8]) -> Result<Vec<u8>, i32> {
+        let id = CStr::from_ptr(id).to_str().unwrap();
+        if id == "unknown-code" {
+            return Err(done(42, Some("Failed with an unknown code")));
+        }
+        match enclave(e).keys.lock().unwrap().get(id) {
+            Some(key) => Ok(data.iter().map(|b| b ^ key).collect()),
+            None => Err(fail(ArieskmsErrorCode::EnclaveItemNotFound, "No such key")),
+        }
+    }
+
+    unsafe extern "C" fn encrypt(
+        e: *const c_void,
+        id: *const c_char,
+        plaintext: ArieskmsSlice,
+        _aad: ArieskmsSlice,
+        out: *mut ArieskmsBuffer,
+    ) -> i32 {
+        match xor(e, id, input(plaintext)) {
+            Ok(ciphertext) => output(out, ciphertext),
+            Err(code) => code,
+        }
+    }
+
+    unsafe extern "C" fn decrypt(
+        e: *const c_void,
+        id: *const c_char,
+        ciphertext: ArieskmsSlice,
+        aad: ArieskmsSlice,
+        out: *mut ArieskmsBuffer,
+    ) -> i32 {
+        encrypt(e, id, ciphertext, aad, out)
+    }
+
+    unsafe extern "C" fn put_secret(
+        e: *const c_void,
+        id: *const c_char,
+        secret: ArieskmsSlice,
+    ) -> i32 {
+        let id = CStr::from_ptr(id).to_str().unwrap().to_string();
+        enclave(e)
+            .secrets
+            .lock()
+            .unwrap()
+            .insert(id, input(secret).to_vec());
+        done(0, None)
+    }
+
+    unsafe extern "C" fn fetch_secret(
+        e: *const c_void,
+        id: *const c_char,
+        out: *mut ArieskmsBuffer,
+    ) -> i32 {
+        let id = CStr::from_ptr(id).to_str().unwrap();
+        match enclave(e).secrets.lock().unwrap().get(id) {
+            Some(secret) => output(out, secret.clone()),
+            None => fail(ArieskmsErrorCode::EnclaveItemNotFound, "No such secret"),
+        }
+    }
+
+    #[derive(Default)]
+    struct TestStore(Mutex<Vec<Record>>);
+
+    unsafe fn store<'a>(store: *const c_void) -> &'a TestStore {
+        &*(store as *const TestStore)
+    }
+
+    unsafe fn record(record: ArieskmsSlice) -> Record {
+        serde_json::from_slice(input(record)).unwrap()
+    }
+
+    unsafe fn json<T: Serialize>(out: *mut ArieskmsBuffer, value: &T) -> i32 {
+        output(out, serde_json::to_vec(value).unwrap())
+    }
+
+    fn not_found() -> i32 {
+        fail(ArieskmsErrorCode::PersistenceItemNotFound, "No such record")
+    }
+
+    unsafe extern "C" fn store_open(uri: *const c_char, out: *mut *mut c_void) -> i32 {
+        open::<TestStore>(uri, out)
+    }
+
+    unsafe extern "C" fn store_close(store: *mut c_void) {
+        drop(Box::from_raw(store as *mut TestStore))
+    }
+
+    unsafe extern "C" fn insert(s: *const c_void, r: ArieskmsSlice) -> i32 {
+        let r = record(r);
+        let mut records = store(s).0.lock().unwrap();
+        if records
+            .iter()
+            .any(|x| x.category == r.category && x.name == r.name)
+        {
+            return fail(ArieskmsErrorCode::PersistenceDuplicateItem, "Duplicate");
+        }
+        records.push(r);
+        done(0, None)
+    }
+
+    unsafe extern "C" fn fetch(
+        s: *const c_void,
+        category: ArieskmsSlice,
+        name: ArieskmsSlice,
+        out: *mut ArieskmsBuffer,
+    ) -> i32 {
+        let (category, name) = (input(category), input(name));
+        let records = store(s).0.lock().unwrap();
+        match records
+            .iter()
+            .find(|r| r.category == category && r.name == name)
+        {
+            Some(r) => json(out, r),
+            None => not_found(),
+        }
+    }
+
+    unsafe extern "C" fn update(s: *const c_void, r: ArieskmsSlice) -> i32 {
+        let r = record(r);
+        let mut records = store(s).0.lock().unwrap();
+        match records
+            .iter_mut()
+            .find(|x| x.category == r.category && x.name == r.name)
+        {
+            Some(x) => {
+                *x = r;
+                done(0, None)
+            }
+            None => not_found(),
+        }
+    }
+
+    unsafe extern "C" fn delete(
+        s: *const c_void,
+        category: ArieskmsSlice,
+        name: ArieskmsSlice,
+    ) -> i32 {
+        let (category, name) = (input(category), input(name));
+        let mut records = store(s).0.lock().unwrap();
+        match records
+            .iter()
+            .position(|r| r.category == category && r.name == name)
+        {
+            Some(i) => {
+                records.remove(i);
+                done(0, None)
+            }
+            None => not_found(),
+        }
+    }
+
+    /// The records in `category` ordered by name. Queries are ignored.
+    unsafe fn in_category(s: *const c_void, category: ArieskmsSlice) -> Vec<Record> {
+        let category = input(category);
+        let mut found: Vec<_> = store(s)
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.category == category)
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        found
+    }
+
+    unsafe extern "C" fn search(
+        s: *const c_void,
+        category: ArieskmsSlice,
+        _query: ArieskmsSlice,
+        out: *mut ArieskmsBuffer,
+    ) -> i32 {
+        json(out, &in_category(s, category))
+    }
+
+    unsafe extern "C" fn categories(s: *const c_void, out: *mut ArieskmsBuffer) -> i32 {
+        let mut categories: Vec<_> = store(s)
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.category.clone())
+            .collect();
+        categories.sort();
+        categories.dedup();
+        json(out, &categories)
+    }
+
+    unsafe extern "C" fn search_page(
+        s: *const c_void,
+        category: ArieskmsSlice,
+        _query: ArieskmsSlice,
+        has_after: c_int,
+        after: ArieskmsSlice,
+        limit: usize,
+        out: *mut ArieskmsBuffer,
+    ) -> i32 {
+        let after = input(after);
+        let mut records: Vec<_> = in_category(s, category)
+            .into_iter()
+            .filter(|r| has_after == 0 || r.name.as_slice() > after)
+            .collect();
+        let more = records.len() > limit;
+        records.truncate(limit);
+        let next = if more {
+            records.last().map(|r| r.name.clone())
+        } else {
+            None
+        };
+        json(
+            out,
+            &serde_json::json!({ "records": records, "next": next }),
+        )
+    }
+
+    unsafe extern "C" fn apply(_: *const c_void, _: ArieskmsSlice) -> i32 {
+        fail(
+            ArieskmsErrorCode::PersistenceUnsupportedOperation,
+            "Transactions are not supported",
+        )
+    }
+
+    fn enclave_plugin() -> ArieskmsEnclavePlugin {
+        ArieskmsEnclavePlugin {
+            open: Some(enclave_open),
+            close: Some(enclave_close),
+            capabilities: Some(capabilities),
+            generate_key: Some(generate_key),
+            put_key: None,
+            export_wrapped_key: None,
+            encrypt: Some(encrypt),
+            encrypt_deterministic: None,
+            decrypt: Some(decrypt),
+            sign_hmac: None,
+            put_secret: Some(put_secret),
+            fetch_secret: Some(fetch_secret),
+        }
+    }
+
+    fn persistence_plugin() -> ArieskmsPersistencePlugin {
+        ArieskmsPersistencePlugin {
+            open: Some(store_open),
+            close: Some(store_close),
+            insert: Some(insert),
+            fetch: Some(fetch),
+            update: Some(update),
+            delete: Some(delete),
+            search: Some(search),
+            categories: Some(categories),
+            search_page: Some(search_page),
+            apply: Some(apply),
+        }
+    }
+
+    /// The descriptor of the test plugin after `change`
+    fn descriptor<F: FnOnce(&mut ArieskmsPlugin)>(change: F) -> &'static ArieskmsPlugin {
+        let mut plugin = ArieskmsPlugin {
+            abi_version: ARIESKMS_PLUGIN_ABI_VERSION,
+            scheme: b"Test\0".as_ptr() as *const c_char,
+            enclave: Box::leak(Box::new(enclave_plugin())),
+            persistence: Box::leak(Box::new(persistence_plugin())),
+            buffer_free: Some(buffer_free),
+            last_error_message: Some(last_error_message),
+        };
+        change(&mut plugin);
+        Box::leak(Box::new(plugin))
+    }
+
+    fn registry() -> Registry {
+        let plugin = unsafe { Plugin::from_descriptor(descriptor(|_| {}), None) }.unwrap();
+        let mut registry = Registry::empty();
+        registry.register_plugin(plugin);
+        registry
+    }
+
+    fn record_named(name: &[u8]) -> Record {
+        Record {
+            category: b"category".to_vec(),
+            name: name.to_vec(),
+            value: b"value".to_vec(),
+            tags: vec![RecordTag::Plaintext(b"~tag".to_vec(), vec![0, 255])],
+        }
+    }
+
+    #[test]
+    fn descriptors() {
+        let plugin = unsafe { Plugin::from_descriptor(descriptor(|_| {}), None) }.unwrap();
+        assert_eq!(plugin.scheme(), "test");
+        assert!(plugin.has_enclave());
+        assert!(plugin.has_persistence());
+        assert_eq!(
+            format!("{:?}", plugin),
+            r#"Plugin { scheme: "test", enclave: true, persistence: true }"#
+        );
+        let plugin =
+            unsafe { Plugin::from_descriptor(descriptor(|p| p.persistence = ptr::null()), None) }
+                .unwrap();
+        assert!(plugin.has_enclave());
+        assert!(!plugin.has_persistence());
+        let err = plugin.open_persistence("test:").unwrap_err();
+        assert_eq!(err.kind(), PersistenceErrorKind::InvalidConfig);
+    }
+
+    #[test]
+    fn invalid_descriptors_are_rejected() {
+        let no_open = Box::leak(Box::new(ArieskmsEnclavePlugin {
+            open: None,
+            ..enclave_plugin()
+        }));
+        let no_capabilities = Box::leak(Box::new(ArieskmsEnclavePlugin {
+            capabilities: None,
+            ..enclave_plugin()
+        }));
+        let no_apply = Box::leak(Box::new(ArieskmsPersistencePlugin {
+            apply: None,
+            ..persistence_plugin()
+        }));
+        let table: Vec<(&'static ArieskmsPlugin, PluginErrorKind)> = vec![
+            (
+                descriptor(|p| p.abi_version = ARIESKMS_PLUGIN_ABI_VERSION + 1),
+                PluginErrorKind::UnsupportedVersion,
+            ),
+            (
+                descriptor(|p| p.abi_version = 0),
+                PluginErrorKind::UnsupportedVersion,
+            ),
+            (
+                descriptor(|p| p.scheme = ptr::null()),
+                PluginErrorKind::InvalidPlugin,
+            ),
+            (
+                descriptor(|p| p.scheme = b"\xff\0".as_ptr() as *const c_char),
+                PluginErrorKind::InvalidPlugin,
+            ),
+            (
+                descriptor(|p| p.buffer_free = None),
+                PluginErrorKind::InvalidPlugin,
+            ),
+            (
+                descriptor(|p| p.enclave = no_open),
+                PluginErrorKind::InvalidPlugin,
+            ),
+            (
+                descriptor(|p| p.enclave = no_capabilities),
+                PluginErrorKind::InvalidPlugin,
+            ),
+            (
+                descriptor(|p| p.persistence = no_apply),
+                PluginErrorKind::InvalidPlugin,
+            ),
+            (
+                descriptor(|p| {
+                    p.enclave = ptr::null();
+                    p.persistence = ptr::null();
+                }),
+                PluginErrorKind::InvalidPlugin,
+            ),
+        ];
+        for (i, (descriptor, kind)) in table.into_iter().enumerate() {
+            let err = unsafe { Plugin::from_descriptor(descriptor, None) }.unwrap_err();
+            assert_eq!(err.kind(), kind, "descriptor {}: {}", i, err);
+        }
+
+        let err = unsafe { Plugin::load("/nonexistent/libarieskms_plugin.so") }.unwrap_err();
+        assert_eq!(err.kind(), PluginErrorKind::LoadFailed);
+    }
+
+    #[test]
+    fn enclaves() {
+        let registry = registry();
+        let enclave = registry.open_enclave("TEST:").unwrap();
+        assert_eq!(enclave.capabilities().bits(), unsafe {
+            capabilities(ptr::null())
+        });
+        let capabilities =
+            KeyCapabilities::Symmetric(SymmetricCapability::ENCRYPT | SymmetricCapability::DECRYPT);
+        let key_type = EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305);
+        enclave.create_key("key", key_type, capabilities).unwrap();
+
+        let ciphertext = enclave.encrypt("key", b"secret", b"aad").unwrap();
+        assert_ne!(ciphertext, b"secret");
+        assert_eq!(
+            enclave.decrypt("key", &ciphertext, b"aad").unwrap(),
+            b"secret"
+        );
+        assert_eq!(enclave.encrypt("key", b"", b"").unwrap(), b"");
+
+        // The host rejects keys the enclave can't create before calling the plugin
+        let err = enclave
+            .create_key(
+                "mac",
+                EnclaveKeyType::Hmac(HmacAlgorithm::Sha256),
+                KeyCapabilities::Symmetric(SymmetricCapability::HMAC_SIGN),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), EnclaveErrorKind::UnsupportedOperation);
+        // Missing functions are unsupported operations
+        for err in [
+            enclave.sign_hmac("key", b"data").unwrap_err(),
+            enclave
+                .encrypt_deterministic("key", b"secret", b"")
+                .unwrap_err(),
+            enclave.export_wrapped_key("key", &[0; 32]).unwrap_err(),
+        ]
+        .iter()
+        {
+            assert_eq!(err.kind(), EnclaveErrorKind::UnsupportedOperation);
+        }
+
+        enclave.put_secret("password", b"hunter2").unwrap();
+        assert_eq!(&*enclave.fetch_secret("password").unwrap(), b"hunter2");
+    }
+
+    #[test]
+    fn enclave_error_codes() {
+        let registry = registry();
+        let enclave = registry.open_enclave("test:").unwrap();
+
+        let err = enclave.encrypt("missing", b"secret", b"").unwrap_err();
+        assert_eq!(err.kind(), EnclaveErrorKind::ItemNotFound);
+        let err = enclave.fetch_secret("missing").unwrap_err();
+        assert_eq!(err.kind(), EnclaveErrorKind::ItemNotFound);
+        let err = enclave.encrypt("unknown-code", b"secret", b"").unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EnclaveErrorKind::GeneralError { msg } if msg == "Failed with an unknown code"
+        ));
+        let err = enclave.encrypt("nul\0id", b"secret", b"").unwrap_err();
+        assert!(matches!(err.kind(), EnclaveErrorKind::GeneralError { .. }));
+
+        let err = registry.open_enclave("test:?port=1").unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EnclaveErrorKind::ConnectionFailure { msg } if msg == "The test plugin takes no options"
+        ));
+        let table = vec![
+            ("101", EnclaveErrorKind::AccessDenied { msg: String::new() }),
+            ("102", EnclaveErrorKind::ItemNotFound),
+            ("103", EnclaveErrorKind::UnsupportedOperation),
+            (
+                "105",
+                EnclaveErrorKind::InvalidCapabilities { msg: String::new() },
+            ),
+            // Codes of other kinds of errors and unknown codes are general errors
+            ("202", EnclaveErrorKind::GeneralError { msg: String::new() }),
+            ("42", EnclaveErrorKind::GeneralError { msg: String::new() }),
+        ];
+        for (code, kind) in table {
+            let err = registry
+                .open_enclave(&format!("test:?code={}", code))
+                .unwrap_err();
+            assert_eq!(
+                std::mem::discriminant(&err.kind()),
+                std::mem::discriminant(&kind),
+                "{}",
+                code
+            );
+        }
+        let err = registry.open_enclave("test:?code=42").unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EnclaveErrorKind::GeneralError { msg } if msg == "The plugin test failed with the unknown code 42"
+        ));
+        let err = registry.open_enclave("test:?code=101").unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EnclaveErrorKind::AccessDenied { msg } if msg == "The plugin test failed with EnclaveAccessDenied"
+        ));
+        let err = registry.open_enclave("test:\0").unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            EnclaveErrorKind::ConnectionFailure { .. }
+        ));
+    }
+
+    #[test]
+    fn persistence() {
+        let registry = registry();
+        let store = registry.open_persistence("test:").unwrap();
+        for name in &[b"c", b"a", b"b"] {
+            store.insert(record_named(&name[..])).unwrap();
+        }
+        assert_eq!(store.fetch(b"category", b"a").unwrap(), record_named(b"a"));
+
+        let mut changed = record_named(b"a");
+        changed.value = vec![0, 1, 2];
+        changed.tags.clear();
+        store.update(changed.clone()).unwrap();
+        assert_eq!(store.fetch(b"category", b"a").unwrap(), changed);
+
+        let query = Query::from_str("{}").unwrap();
+        let found = store.search(b"category", &query).unwrap();
+        assert_eq!(found.len(), 3);
+        assert!(store.search(b"other", &query).unwrap().is_empty());
+        assert_eq!(store.categories().unwrap(), vec![b"category".to_vec()]);
+
+        let first = store.search_page(b"category", &query, None, 2).unwrap();
+        assert_eq!(first.records.len(), 2);
+        let next = first.next.expect("there is a second page");
+        let second = store
+            .search_page(b"category", &query, Some(&next), 2)
+            .unwrap();
+        assert_eq!(second.records, vec![record_named(b"c")]);
+        assert!(second.next.is_none());
+
+        store.delete(b"category", b"b").unwrap();
+        let err = store.fetch(b"category", b"b").unwrap_err();
+        assert_eq!(err.kind(), PersistenceErrorKind::ItemNotFound);
+    }
+
+    #[test]
+    fn persistence_error_codes() {
+        let registry = registry();
+        let store = registry.open_persistence("test:").unwrap();
+        store.insert(record_named(b"a")).unwrap();
+        let table = vec![
+            (
+                store.insert(record_named(b"a")).unwrap_err(),
+                PersistenceErrorKind::DuplicateItem,
+            ),
+            (
+                store.update(record_named(b"b")).unwrap_err(),
+                PersistenceErrorKind::ItemNotFound,
+            ),
+            (
+                store.delete(b"category", b"b").unwrap_err(),
+                PersistenceErrorKind::ItemNotFound,
+            ),
+            (
+                store.apply(Vec::new()).unwrap_err(),
+                PersistenceErrorKind::UnsupportedOperation,
+            ),
+        ];
+        for (err, kind) in table {
+            assert_eq!(err.kind(), kind, "{}", err);
+        }
+        let err = store.apply(Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("Transactions are not supported"));
+
+        let table = vec![
+            ("200", PersistenceErrorKind::InvalidConfig),
+            ("203", PersistenceErrorKind::DuplicateItem),
+            ("206", PersistenceErrorKind::Conflict),
+            ("207", PersistenceErrorKind::UnsupportedVersion),
+            // Codes of other kinds of errors and unknown codes are I/O errors
+            ("102", PersistenceErrorKind::IOError),
+            ("42", PersistenceErrorKind::IOError),
+        ];
+        for (code, kind) in table {
+            let err = registry
+                .open_persistence(&format!("test:?code={}", code))
+                .unwrap_err();
+            assert_eq!(err.kind(), kind, "{}", code);
+        }
+    }
+}
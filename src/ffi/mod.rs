@@ -0,0 +1,587 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! A C API for the `cdylib` and `staticlib` outputs.
+//!
+//! Enclaves, keys and stores are opaque handles created by an
+//! `arieskms_*_open` function and released with the matching
+//! `arieskms_*_free` function. Records and categories read from a store
+//! are handles too, released with `arieskms_records_free` and
+//! `arieskms_categories_free`. Every fallible function returns an
+//! `ArieskmsErrorCode` and writes its result through an out pointer. The
+//! message of the last error on the calling thread is available from
+//! `arieskms_last_error_message`.
+//!
+//! Inputs are borrowed for the duration of the call. Data returned in an
+//! `ArieskmsBuffer` is owned by the caller and must be released with
+//! `arieskms_buffer_free`, which zeroizes it first.
+//!
+//! Handles can be used from any thread. The header `include/arieskms.h`
+//! is generated from this module with
+//! `cbindgen --config cbindgen.toml --output include/arieskms.h`.
+//!
+//! # Safety
+//!
+//! Every function trusts that handles were returned by this library and
+//! not yet freed, that strings are nul terminated, that slices point to
+//! `len` readable bytes and that out pointers are writable. Null pointers
+//! are reported as `InvalidArgument`.
+#![allow(unsafe_code, clippy::missing_safety_doc)]
+
+use crate::{
+    persistence::errors::{PersistenceError, PersistenceErrorKind},
+    protection::errors::{ProtectionError, ProtectionErrorKind},
+    security::errors::{EnclaveError, EnclaveErrorKind},
+};
+
+use std::{
+    cell::RefCell,
+    convert::TryFrom,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+use zeroize::Zeroize;
+
+/// Enclave and key handles
+pub mod enclave;
+/// Loading plugins from shared libraries
+#[cfg(feature = "plugins")]
+pub mod loader;
+/// The ABI of enclave and persistence plugins
+pub mod plugin;
+/// Store handles
+pub mod store;
+
+/// The result of every fallible function. Zero is success.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArieskmsErrorCode {
+    /// The call succeeded
+    Success = 0,
+    /// A pointer was null, a string was not UTF-8 or a value was out of range
+    InvalidArgument = 1,
+    /// The library panicked. The handles used in the call should be freed.
+    Panic = 2,
+    /// `EnclaveErrorKind::ConnectionFailure`
+    EnclaveConnectionFailure = 100,
+    /// `EnclaveErrorKind::AccessDenied`
+    EnclaveAccessDenied = 101,
+    /// `EnclaveErrorKind::ItemNotFound`
+    EnclaveItemNotFound = 102,
+    /// `EnclaveErrorKind::UnsupportedOperation`
+    EnclaveUnsupportedOperation = 103,
+    /// `EnclaveErrorKind::GeneralError`
+    EnclaveGeneralError = 104,
+    /// `EnclaveErrorKind::InvalidCapabilities`
+    EnclaveInvalidCapabilities = 105,
+    /// `PersistenceErrorKind::InvalidConfig`
+    PersistenceInvalidConfig = 200,
+    /// `PersistenceErrorKind::IOError`
+    PersistenceIOError = 201,
+    /// `PersistenceErrorKind::ItemNotFound`
+    PersistenceItemNotFound = 202,
+    /// `PersistenceErrorKind::DuplicateItem`
+    PersistenceDuplicateItem = 203,
+    /// `PersistenceErrorKind::SerializationError`
+    PersistenceSerializationError = 204,
+    /// `PersistenceErrorKind::InvalidQuery`
+    PersistenceInvalidQuery = 205,
+    /// `PersistenceErrorKind::Conflict`
+    PersistenceConflict = 206,
+    /// `PersistenceErrorKind::UnsupportedVersion`
+    PersistenceUnsupportedVersion = 207,
+    /// `PersistenceErrorKind::UnsupportedOperation`
+    PersistenceUnsupportedOperation = 208,
+    /// `ProtectionErrorKind::InvalidData`
+    ProtectionInvalidData = 300,
+}
+
+/// Values that aren't known codes are returned as the error
+impl TryFrom<i32> for ArieskmsErrorCode {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, i32> {
+        Ok(match value {
+            0 => Self::Success,
+            1 => Self::InvalidArgument,
+            2 => Self::Panic,
+            100 => Self::EnclaveConnectionFailure,
+            101 => Self::EnclaveAccessDenied,
+            102 => Self::EnclaveItemNotFound,
+            103 => Self::EnclaveUnsupportedOperation,
+            104 => Self::EnclaveGeneralError,
+            105 => Self::EnclaveInvalidCapabilities,
+            200 => Self::PersistenceInvalidConfig,
+            201 => Self::PersistenceIOError,
+            202 => Self::PersistenceItemNotFound,
+            203 => Self::PersistenceDuplicateItem,
+            204 => Self::PersistenceSerializationError,
+            205 => Self::PersistenceInvalidQuery,
+            206 => Self::PersistenceConflict,
+            207 => Self::PersistenceUnsupportedVersion,
+            208 => Self::PersistenceUnsupportedOperation,
+            300 => Self::ProtectionInvalidData,
+            _ => return Err(value),
+        })
+    }
+}
+
+impl From<EnclaveErrorKind> for ArieskmsErrorCode {
+    fn from(kind: EnclaveErrorKind) -> Self {
+        match kind {
+            EnclaveErrorKind::ConnectionFailure { .. } => Self::EnclaveConnectionFailure,
+            EnclaveErrorKind::AccessDenied { .. } => Self::EnclaveAccessDenied,
+            EnclaveErrorKind::ItemNotFound => Self::EnclaveItemNotFound,
+            EnclaveErrorKind::UnsupportedOperation => Self::EnclaveUnsupportedOperation,
+            EnclaveErrorKind::GeneralError { .. } => Self::EnclaveGeneralError,
+            EnclaveErrorKind::InvalidCapabilities { .. } => Self::EnclaveInvalidCapabilities,
+        }
+    }
+}
+
+impl From<PersistenceErrorKind> for ArieskmsErrorCode {
+    fn from(kind: PersistenceErrorKind) -> Self {
+        match kind {
+            PersistenceErrorKind::InvalidConfig => Self::PersistenceInvalidConfig,
+            PersistenceErrorKind::IOError => Self::PersistenceIOError,
+            PersistenceErrorKind::ItemNotFound => Self::PersistenceItemNotFound,
+            PersistenceErrorKind::DuplicateItem => Self::PersistenceDuplicateItem,
+            PersistenceErrorKind::SerializationError => Self::PersistenceSerializationError,
+            PersistenceErrorKind::InvalidQuery => Self::PersistenceInvalidQuery,
+            PersistenceErrorKind::Conflict => Self::PersistenceConflict,
+            PersistenceErrorKind::UnsupportedVersion => Self::PersistenceUnsupportedVersion,
+            PersistenceErrorKind::UnsupportedOperation => Self::PersistenceUnsupportedOperation,
+        }
+    }
+}
+
+impl From<ProtectionErrorKind> for ArieskmsErrorCode {
+    fn from(kind: ProtectionErrorKind) -> Self {
+        match kind {
+            ProtectionErrorKind::Enclave(kind) => kind.into(),
+            ProtectionErrorKind::Persistence(kind) => kind.into(),
+            ProtectionErrorKind::InvalidData => Self::ProtectionInvalidData,
+        }
+    }
+}
+
+/// Bytes borrowed from the caller. `data` may be null when `len` is zero.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ArieskmsSlice {
+    /// The first byte
+    pub data: *const u8,
+    /// The number of bytes
+    pub len: usize,
+}
+
+/// Bytes owned by the caller that must be released with `arieskms_buffer_free`
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArieskmsBuffer {
+    /// The first byte
+    pub data: *mut u8,
+    /// The number of bytes
+    pub len: usize,
+}
+
+impl From<Vec<u8>> for ArieskmsBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        let bytes = bytes.into_boxed_slice();
+        let len = bytes.len();
+        Self {
+            data: Box::into_raw(bytes) as *mut u8,
+            len,
+        }
+    }
+}
+
+/// Zeroize and release `buffer`. Does nothing if it is empty.
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_buffer_free(buffer: ArieskmsBuffer) {
+    if !buffer.data.is_null() {
+        let mut bytes = Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len));
+        bytes.zeroize();
+    }
+}
+
+/// The message of the last error on the calling thread, or null if the
+/// last call succeeded. The string is valid until the next call on the thread.
+#[no_mangle]
+pub extern "C" fn arieskms_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map(|message| message.as_ptr())
+            .unwrap_or_else(ptr::null)
+    })
+}
+
+/// The version of this library
+#[no_mangle]
+pub extern "C" fn arieskms_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+thread_local! {
+    // const initializers need Rust 1.59
+    #[allow(clippy::missing_const_for_thread_local)]
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// An error returned to C callers
+#[derive(Debug)]
+pub(crate) struct FfiError {
+    code: ArieskmsErrorCode,
+    message: String,
+}
+
+impl FfiError {
+    pub(crate) fn invalid_argument(message: &str) -> Self {
+        Self {
+            code: ArieskmsErrorCode::InvalidArgument,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<EnclaveError> for FfiError {
+    fn from(e: EnclaveError) -> Self {
+        Self {
+            code: e.kind().into(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<PersistenceError> for FfiError {
+    fn from(e: PersistenceError) -> Self {
+        Self {
+            code: e.kind().into(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<ProtectionError> for FfiError {
+    fn from(e: ProtectionError) -> Self {
+        Self {
+            code: e.kind().into(),
+            message: e.to_string(),
+        }
+    }
+}
+
+pub(crate) type FfiResult<T> = Result<T, FfiError>;
+
+/// Run `f`, record its error for `arieskms_last_error_message`
+/// and keep panics from unwinding into C
+pub(crate) fn call<F: FnOnce() -> FfiResult<()>>(f: F) -> ArieskmsErrorCode {
+    let (code, message) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (ArieskmsErrorCode::Success, None),
+        Ok(Err(e)) => (e.code, Some(e.message)),
+        Err(_) => (
+            ArieskmsErrorCode::Panic,
+            Some("arieskms panicked".to_string()),
+        ),
+    };
+    let message =
+        message.map(|m| CString::new(m.replace('\0', " ")).expect("nul bytes were replaced"));
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+    code
+}
+
+/// Borrow the bytes in `slice`
+pub(crate) unsafe fn bytes<'a>(slice: ArieskmsSlice) -> FfiResult<&'a [u8]> {
+    if slice.len == 0 {
+        Ok(&[])
+    } else if slice.data.is_null() {
+        Err(FfiError::invalid_argument(
+            "A slice with data has a null pointer",
+        ))
+    } else {
+        Ok(std::slice::from_raw_parts(slice.data, slice.len))
+    }
+}
+
+/// Borrow the nul terminated UTF-8 string `s`
+pub(crate) unsafe fn string<'a>(s: *const c_char) -> FfiResult<&'a str> {
+    if s.is_null() {
+        return Err(FfiError::invalid_argument("A required string is null"));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| FfiError::invalid_argument("Strings must be UTF-8"))
+}
+
+/// Borrow the nul terminated UTF-8 string `s` if it isn't null
+// Unused when no storage feature or OS keyring is available
+#[allow(dead_code)]
+pub(crate) unsafe fn optional_string<'a>(s: *const c_char) -> FfiResult<Option<&'a str>> {
+    if s.is_null() {
+        Ok(None)
+    } else {
+        string(s).map(Some)
+    }
+}
+
+/// Borrow the object behind the handle `handle`
+pub(crate) unsafe fn handle<'a, T>(handle: *const T) -> FfiResult<&'a T> {
+    handle
+        .as_ref()
+        .ok_or_else(|| FfiError::invalid_argument("A handle is null"))
+}
+
+/// Write `value` to the out pointer `out`
+pub(crate) unsafe fn write<T>(out: *mut T, value: T) -> FfiResult<()> {
+    if out.is_null() {
+        return Err(FfiError::invalid_argument("An out pointer is null"));
+    }
+    out.write(value);
+    Ok(())
+}
+
+/// Move `value` to the heap and write the handle to `out`
+pub(crate) unsafe fn write_handle<T>(out: *mut *mut T, value: T) -> FfiResult<()> {
+    if out.is_null() {
+        return Err(FfiError::invalid_argument("An out pointer is null"));
+    }
+    out.write(Box::into_raw(Box::new(value)));
+    Ok(())
+}
+
+/// Release the handle `handle` created by `write_handle`
+pub(crate) unsafe fn free_handle<T>(handle: *mut T) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The message `arieskms_last_error_message` returns on this thread
+    pub(crate) fn last_error() -> Option<String> {
+        let message = arieskms_last_error_message();
+        if message.is_null() {
+            None
+        } else {
+            Some(
+                unsafe { CStr::from_ptr(message) }
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            )
+        }
+    }
+
+    #[test]
+    fn error_codes() {
+        for value in (0..3).chain(100..106).chain(200..209).chain(300..301) {
+            let code = ArieskmsErrorCode::try_from(value).unwrap();
+            assert_eq!(code as i32, value);
+        }
+        for value in &[-1, 3, 99, 106, 199, 209, 299, 301, i32::MAX] {
+            assert_eq!(ArieskmsErrorCode::try_from(*value), Err(*value));
+        }
+
+        let msg = String::new();
+        let enclave = [
+            (
+                EnclaveErrorKind::ConnectionFailure { msg: msg.clone() },
+                ArieskmsErrorCode::EnclaveConnectionFailure,
+            ),
+            (
+                EnclaveErrorKind::AccessDenied { msg: msg.clone() },
+                ArieskmsErrorCode::EnclaveAccessDenied,
+            ),
+            (
+                EnclaveErrorKind::ItemNotFound,
+                ArieskmsErrorCode::EnclaveItemNotFound,
+            ),
+            (
+                EnclaveErrorKind::UnsupportedOperation,
+                ArieskmsErrorCode::EnclaveUnsupportedOperation,
+            ),
+            (
+                EnclaveErrorKind::GeneralError { msg: msg.clone() },
+                ArieskmsErrorCode::EnclaveGeneralError,
+            ),
+            (
+                EnclaveErrorKind::InvalidCapabilities { msg },
+                ArieskmsErrorCode::EnclaveInvalidCapabilities,
+            ),
+        ];
+        for (kind, code) in enclave.iter() {
+            assert_eq!(ArieskmsErrorCode::from(kind.clone()), *code);
+            assert_eq!(
+                ArieskmsErrorCode::from(ProtectionErrorKind::Enclave(kind.clone())),
+                *code
+            );
+        }
+
+        let persistence = [
+            (
+                PersistenceErrorKind::InvalidConfig,
+                ArieskmsErrorCode::PersistenceInvalidConfig,
+            ),
+            (
+                PersistenceErrorKind::IOError,
+                ArieskmsErrorCode::PersistenceIOError,
+            ),
+            (
+                PersistenceErrorKind::ItemNotFound,
+                ArieskmsErrorCode::PersistenceItemNotFound,
+            ),
+            (
+                PersistenceErrorKind::DuplicateItem,
+                ArieskmsErrorCode::PersistenceDuplicateItem,
+            ),
+            (
+                PersistenceErrorKind::SerializationError,
+                ArieskmsErrorCode::PersistenceSerializationError,
+            ),
+            (
+                PersistenceErrorKind::InvalidQuery,
+                ArieskmsErrorCode::PersistenceInvalidQuery,
+            ),
+            (
+                PersistenceErrorKind::Conflict,
+                ArieskmsErrorCode::PersistenceConflict,
+            ),
+            (
+                PersistenceErrorKind::UnsupportedVersion,
+                ArieskmsErrorCode::PersistenceUnsupportedVersion,
+            ),
+            (
+                PersistenceErrorKind::UnsupportedOperation,
+                ArieskmsErrorCode::PersistenceUnsupportedOperation,
+            ),
+        ];
+        for (kind, code) in persistence.iter() {
+            assert_eq!(ArieskmsErrorCode::from(*kind), *code);
+            assert_eq!(
+                ArieskmsErrorCode::from(ProtectionErrorKind::Persistence(*kind)),
+                *code
+            );
+        }
+        assert_eq!(
+            ArieskmsErrorCode::from(ProtectionErrorKind::InvalidData),
+            ArieskmsErrorCode::ProtectionInvalidData
+        );
+    }
+
+    #[test]
+    fn last_error_messages() {
+        let code = call(|| Err(FfiError::invalid_argument("first")));
+        assert_eq!(code, ArieskmsErrorCode::InvalidArgument);
+        assert_eq!(last_error().as_deref(), Some("first"));
+
+        // Messages are per thread
+        let other = std::thread::spawn(|| {
+            call(|| Err(FfiError::invalid_argument("other")));
+            last_error()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(other.as_deref(), Some("other"));
+        assert_eq!(last_error().as_deref(), Some("first"));
+
+        let code = call(|| Err(EnclaveError::from(EnclaveErrorKind::ItemNotFound).into()));
+        assert_eq!(code, ArieskmsErrorCode::EnclaveItemNotFound);
+        assert!(last_error().is_some());
+
+        call(|| Err(FfiError::invalid_argument("a\0b")));
+        assert_eq!(last_error().as_deref(), Some("a b"));
+
+        assert_eq!(call(|| Ok(())), ArieskmsErrorCode::Success);
+        assert_eq!(last_error(), None);
+    }
+
+    #[test]
+    fn panics_are_caught() {
+        let code = call(|| panic!("in a test"));
+        assert_eq!(code, ArieskmsErrorCode::Panic);
+        assert_eq!(last_error().as_deref(), Some("arieskms panicked"));
+    }
+
+    #[test]
+    fn null_pointers_are_invalid_arguments() {
+        unsafe {
+            let empty = ArieskmsSlice {
+                data: ptr::null(),
+                len: 0,
+            };
+            assert_eq!(bytes(empty).unwrap(), &[] as &[u8]);
+            let dangling = ArieskmsSlice {
+                data: ptr::null(),
+                len: 1,
+            };
+            assert_eq!(
+                bytes(dangling).unwrap_err().code,
+                ArieskmsErrorCode::InvalidArgument
+            );
+
+            assert_eq!(
+                string(ptr::null()).unwrap_err().code,
+                ArieskmsErrorCode::InvalidArgument
+            );
+            let not_utf8 = b"\xff\0";
+            assert_eq!(
+                string(not_utf8.as_ptr() as *const c_char).unwrap_err().code,
+                ArieskmsErrorCode::InvalidArgument
+            );
+            assert_eq!(string(b"ok\0".as_ptr() as *const c_char).unwrap(), "ok");
+            assert_eq!(optional_string(ptr::null()).unwrap(), None);
+
+            assert_eq!(
+                handle::<u8>(ptr::null()).unwrap_err().code,
+                ArieskmsErrorCode::InvalidArgument
+            );
+            assert_eq!(
+                write::<u8>(ptr::null_mut(), 1).unwrap_err().code,
+                ArieskmsErrorCode::InvalidArgument
+            );
+            assert_eq!(
+                write_handle::<u8>(ptr::null_mut(), 1).unwrap_err().code,
+                ArieskmsErrorCode::InvalidArgument
+            );
+            free_handle::<u8>(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn buffers() {
+        let buffer = ArieskmsBuffer::from(vec![1, 2, 3]);
+        assert_eq!(buffer.len, 3);
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(buffer.data, buffer.len) },
+            &[1, 2, 3]
+        );
+        unsafe {
+            arieskms_buffer_free(buffer);
+            arieskms_buffer_free(ArieskmsBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            });
+        }
+        let version = unsafe { CStr::from_ptr(arieskms_version()) };
+        assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    }
+}
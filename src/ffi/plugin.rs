@@ -0,0 +1,243 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! The ABI of enclave and persistence plugins loaded from shared libraries.
+//!
+//! A plugin exports the function `arieskms_plugin` that returns a pointer to
+//! an `ArieskmsPlugin` living as long as the library. The host checks that
+//! `abi_version` is `ARIESKMS_PLUGIN_ABI_VERSION` and registers the drivers
+//! in it under `scheme`, so `hsm://rack-1?slot=2` opens the enclave of the
+//! plugin with the scheme `hsm`. New versions of the ABI only append fields
+//! and bump the version, so plugins built against an older header keep working
+//! until the version they were built for is dropped.
+//!
+//! Every function receives the whole URI and parses its options itself. It
+//! returns zero on success or the `ArieskmsErrorCode` describing the error and
+//! may make a message available from `last_error_message` on the calling
+//! thread. Codes the host doesn't know are reported as general enclave errors
+//! or I/O errors of the store. Results are written to `ArieskmsBuffer`s allocated by the plugin and
+//! released by the host with the plugin's `buffer_free`. Inputs are borrowed
+//! for the duration of the call.
+//!
+//! Enclave functions other than `open`, `close` and `capabilities` may be null
+//! when the enclave doesn't support the operation. The host only generates or
+//! imports keys through the plugin if their capabilities fit their type and
+//! the enclave capabilities it reports. Key types are `ArieskmsKeyType` values and key
+//! capabilities the `ARIESKMS_SYMMETRIC_*` or `ARIESKMS_ECC_*` bits. Enclave
+//! capabilities are the bits of `EnclaveCapabilities`.
+//!
+//! Persistence plugins exchange records, queries and transactions as the JSON
+//! encoding of `Record`, `wql::Query` and `transaction::Operation` where byte
+//! strings are arrays of numbers, for example
+//!
+//! ```json
+//! {"category": [107], "name": [49], "value": [1, 2], "tags": [{"Plaintext": [[116], [118]]}]}
+//! {"Eq": [{"Encrypted": [[116]]}, [118]]}
+//! {"Delete": {"category": [107], "name": [49]}}
+//! ```
+//!
+//! A page of search results is `{"records": [...], "next": [49]}` where `next`
+//! is the name of the last record if there are more pages and `null` otherwise.
+//!
+//! Instances are used from many threads at once, so plugins must be thread safe.
+
+use super::{ArieskmsBuffer, ArieskmsSlice};
+
+use std::{
+    ffi::c_void,
+    os::raw::{c_char, c_int},
+};
+
+/// The version of the plugin ABI described in this header
+pub const ARIESKMS_PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Returned by the function `arieskms_plugin` a plugin exports
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArieskmsPlugin {
+    /// `ARIESKMS_PLUGIN_ABI_VERSION` of the header the plugin was built with
+    pub abi_version: u32,
+    /// The URI scheme the drivers are registered under, like `hsm`
+    pub scheme: *const c_char,
+    /// The enclave driver or null
+    pub enclave: *const ArieskmsEnclavePlugin,
+    /// The persistence driver or null
+    pub persistence: *const ArieskmsPersistencePlugin,
+    /// Release a buffer the plugin returned
+    pub buffer_free: Option<unsafe extern "C" fn(buffer: ArieskmsBuffer)>,
+    /// The message of the last error on the calling thread or null
+    pub last_error_message: Option<unsafe extern "C" fn() -> *const c_char>,
+}
+
+/// The enclave functions of a plugin. `enclave` is the instance `open` returned.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArieskmsEnclavePlugin {
+    /// Open the enclave named by `uri`
+    pub open: Option<unsafe extern "C" fn(uri: *const c_char, out: *mut *mut c_void) -> i32>,
+    /// Close the enclave
+    pub close: Option<unsafe extern "C" fn(enclave: *mut c_void)>,
+    /// The `EnclaveCapabilities` bits of the enclave
+    pub capabilities: Option<unsafe extern "C" fn(enclave: *const c_void) -> u64>,
+    /// Create a new key
+    pub generate_key: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            key_type: u32,
+            capabilities: u16,
+        ) -> i32,
+    >,
+    /// Save an existing unwrapped key
+    pub put_key: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            key_type: u32,
+            capabilities: u16,
+            key: ArieskmsSlice,
+        ) -> i32,
+    >,
+    /// Export a key wrapped with XChaCha20-Poly1305 as described by `EnclaveLike::export_wrapped_key`
+    pub export_wrapped_key: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            wrapping_key: ArieskmsSlice,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+    /// Encrypt `plaintext` with a fresh nonce authenticating `aad`
+    pub encrypt: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            plaintext: ArieskmsSlice,
+            aad: ArieskmsSlice,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+    /// Encrypt `plaintext` so the same inputs always give the same ciphertext
+    pub encrypt_deterministic: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            plaintext: ArieskmsSlice,
+            aad: ArieskmsSlice,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+    /// Decrypt `ciphertext` authenticating `aad`
+    pub decrypt: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            ciphertext: ArieskmsSlice,
+            aad: ArieskmsSlice,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+    /// Compute the HMAC of `data`
+    pub sign_hmac: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            data: ArieskmsSlice,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+    /// Save a secret like a password or PIN
+    pub put_secret: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            secret: ArieskmsSlice,
+        ) -> i32,
+    >,
+    /// Retrieve a secret saved with `put_secret`
+    pub fetch_secret: Option<
+        unsafe extern "C" fn(
+            enclave: *const c_void,
+            id: *const c_char,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+}
+
+/// The persistence functions of a plugin. All of them are required.
+/// `store` is the instance `open` returned.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArieskmsPersistencePlugin {
+    /// Open the store named by `uri`
+    pub open: Option<unsafe extern "C" fn(uri: *const c_char, out: *mut *mut c_void) -> i32>,
+    /// Close the store
+    pub close: Option<unsafe extern "C" fn(store: *mut c_void)>,
+    /// Save the new JSON `record`
+    pub insert: Option<unsafe extern "C" fn(store: *const c_void, record: ArieskmsSlice) -> i32>,
+    /// Write the JSON record `name` in `category` to `out`
+    pub fetch: Option<
+        unsafe extern "C" fn(
+            store: *const c_void,
+            category: ArieskmsSlice,
+            name: ArieskmsSlice,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+    /// Replace the value and tags of the existing JSON `record`
+    pub update: Option<unsafe extern "C" fn(store: *const c_void, record: ArieskmsSlice) -> i32>,
+    /// Remove the record `name` in `category`
+    pub delete: Option<
+        unsafe extern "C" fn(
+            store: *const c_void,
+            category: ArieskmsSlice,
+            name: ArieskmsSlice,
+        ) -> i32,
+    >,
+    /// Write the JSON array of records in `category` matching the JSON `query` to `out`
+    pub search: Option<
+        unsafe extern "C" fn(
+            store: *const c_void,
+            category: ArieskmsSlice,
+            query: ArieskmsSlice,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+    /// Write the JSON array of categories that have records to `out`
+    pub categories:
+        Option<unsafe extern "C" fn(store: *const c_void, out: *mut ArieskmsBuffer) -> i32>,
+    /// Write the JSON page of up to `limit` records in `category` matching the
+    /// JSON `query` ordered by name to `out`. The page starts after the record
+    /// named `after` if `has_after` isn't zero.
+    pub search_page: Option<
+        unsafe extern "C" fn(
+            store: *const c_void,
+            category: ArieskmsSlice,
+            query: ArieskmsSlice,
+            has_after: c_int,
+            after: ArieskmsSlice,
+            limit: usize,
+            out: *mut ArieskmsBuffer,
+        ) -> i32,
+    >,
+    /// Apply the JSON array of `operations` atomically
+    pub apply: Option<unsafe extern "C" fn(store: *const c_void, operations: ArieskmsSlice) -> i32>,
+}
+
+// The descriptors are immutable, so plugins written in Rust can keep them in statics
+unsafe impl Sync for ArieskmsPlugin {}
+unsafe impl Sync for ArieskmsEnclavePlugin {}
+unsafe impl Sync for ArieskmsPersistencePlugin {}
@@ -0,0 +1,853 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Stores keep their records encrypted by the enclave they were opened with.
+//!
+//! Fetches and searches return an `ArieskmsRecords` handle. The bytes of
+//! its records are borrowed from the handle until it is freed, which
+//! zeroizes them. Large searches are read a page at a time with
+//! `arieskms_store_search_page`, passing back the continuation token of
+//! the previous page.
+
+use super::{
+    bytes, call, enclave::ArieskmsEnclave, handle, string, write, write_handle, ArieskmsBuffer,
+    ArieskmsErrorCode, ArieskmsSlice, FfiError, FfiResult,
+};
+use crate::{
+    persistence::{
+        cursor::Cursor, shared::BoxedPersistence, wql::Query, PersistenceConnector,
+        PersistenceLike, Record, RecordTag,
+    },
+    protection::{ProtectedStore, ProtectionKeys},
+    security::shared::SharedEnclave,
+};
+
+use std::{os::raw::c_char, ptr, str::FromStr};
+use zeroize::Zeroize;
+
+/// A tag borrowed from the caller
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ArieskmsTag {
+    /// The tag name
+    pub name: ArieskmsSlice,
+    /// The tag value
+    pub value: ArieskmsSlice,
+    /// Persist the tag as is so it can be used for range and pattern
+    /// lookups instead of encrypting it
+    pub plaintext: bool,
+}
+
+/// A record borrowed from an `ArieskmsRecords` handle
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ArieskmsRecord {
+    /// The category of the record
+    pub category: ArieskmsSlice,
+    /// The name of the record
+    pub name: ArieskmsSlice,
+    /// The decrypted value
+    pub value: ArieskmsSlice,
+    /// The first of `tags_len` decrypted tags
+    pub tags: *const ArieskmsTag,
+    /// The number of tags
+    pub tags_len: usize,
+}
+
+/// A store whose records are protected by an enclave
+pub struct ArieskmsStore(ProtectedStore<SharedEnclave, BoxedPersistence>);
+
+/// Records returned by a fetch or search
+pub struct ArieskmsRecords {
+    records: Vec<Record>,
+    /// The tags of each record pointing into `records`, whose
+    /// buffers don't move while the handle is alive
+    tags: Vec<Vec<ArieskmsTag>>,
+}
+
+impl From<Vec<Record>> for ArieskmsRecords {
+    fn from(records: Vec<Record>) -> Self {
+        let tags = records
+            .iter()
+            .map(|r| {
+                r.tags
+                    .iter()
+                    .map(|t| ArieskmsTag {
+                        name: slice(t.name()),
+                        value: slice(t.value()),
+                        plaintext: matches!(t, RecordTag::Plaintext(..)),
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { records, tags }
+    }
+}
+
+impl Drop for ArieskmsRecords {
+    fn drop(&mut self) {
+        for record in self.records.iter_mut() {
+            record.value.zeroize();
+            for tag in record.tags.iter_mut() {
+                match tag {
+                    RecordTag::Encrypted(name, value) | RecordTag::Plaintext(name, value) => {
+                        name.zeroize();
+                        value.zeroize();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Categories returned by `arieskms_store_categories`
+pub struct ArieskmsCategories(Vec<Vec<u8>>);
+
+/// Borrow `bytes` for as long as they aren't moved or freed
+fn slice(bytes: &[u8]) -> ArieskmsSlice {
+    ArieskmsSlice {
+        data: bytes.as_ptr(),
+        len: bytes.len(),
+    }
+}
+
+/// Connect `config` and protect it with the keys starting with `key_prefix`,
+/// generating them first if `generate_keys` is set
+// Unused when no storage feature is enabled
+#[allow(dead_code)]
+unsafe fn open<P: PersistenceLike + Send + Sync + 'static>(
+    enclave: *const ArieskmsEnclave,
+    config: PersistenceConnector<&str, String>,
+    key_prefix: *const c_char,
+    generate_keys: bool,
+    out: *mut *mut ArieskmsStore,
+) -> FfiResult<()> {
+    let enclave = handle(enclave)?.0.clone();
+    let key_prefix = string(key_prefix)?;
+    if out.is_null() {
+        return Err(FfiError::invalid_argument("An out pointer is null"));
+    }
+    let keys = if generate_keys {
+        ProtectionKeys::generate(&enclave, key_prefix)?
+    } else {
+        ProtectionKeys::with_prefix(key_prefix)
+    };
+    let persistence = BoxedPersistence::new(P::connect(config)?);
+    write_handle(
+        out,
+        ArieskmsStore(ProtectedStore::new(enclave, persistence, keys)),
+    )
+}
+
+/// Open the sled database at `path`, or a temporary one if `path` is null
+#[cfg(feature = "storage-sled")]
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_open_sled(
+    enclave: *const ArieskmsEnclave,
+    path: *const c_char,
+    key_prefix: *const c_char,
+    generate_keys: bool,
+    out: *mut *mut ArieskmsStore,
+) -> ArieskmsErrorCode {
+    use crate::persistence::{kv::sled::SledStore, KeyValueConnector};
+    call(|| {
+        let config = KeyValueConnector::new(super::optional_string(path)?);
+        open::<SledStore>(
+            enclave,
+            PersistenceConnector::KeyValue(config),
+            key_prefix,
+            generate_keys,
+            out,
+        )
+    })
+}
+
+/// Open the SQLite database at `path`, or an in memory one if `path` is null
+#[cfg(feature = "storage-sqlite")]
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_open_sqlite(
+    enclave: *const ArieskmsEnclave,
+    path: *const c_char,
+    key_prefix: *const c_char,
+    generate_keys: bool,
+    out: *mut *mut ArieskmsStore,
+) -> ArieskmsErrorCode {
+    use crate::persistence::{sql::sqlite::SqliteStore, SqliteConnector};
+    call(|| {
+        let config = SqliteConnector::new(super::optional_string(path)?);
+        open::<SqliteStore>(
+            enclave,
+            PersistenceConnector::Sqlite(config),
+            key_prefix,
+            generate_keys,
+            out,
+        )
+    })
+}
+
+/// Open the S3 bucket `bucket` at `endpoint`
+#[cfg(feature = "storage-s3")]
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_open_s3(
+    enclave: *const ArieskmsEnclave,
+    endpoint: *const c_char,
+    bucket: *const c_char,
+    region: *const c_char,
+    access_key_id: *const c_char,
+    secret_access_key: *const c_char,
+    key_prefix: *const c_char,
+    generate_keys: bool,
+    out: *mut *mut ArieskmsStore,
+) -> ArieskmsErrorCode {
+    use crate::persistence::{object::s3::S3Store, ObjectStoreConnector};
+    call(|| {
+        let config = ObjectStoreConnector::new(
+            string(endpoint)?.to_string(),
+            string(bucket)?.to_string(),
+            string(region)?.to_string(),
+            string(access_key_id)?.to_string(),
+            string(secret_access_key)?.to_string(),
+        );
+        open::<S3Store>(
+            enclave,
+            PersistenceConnector::ObjectStore(config),
+            key_prefix,
+            generate_keys,
+            out,
+        )
+    })
+}
+
+/// Build a record from the arguments of insert and update
+unsafe fn record(
+    category: ArieskmsSlice,
+    name: ArieskmsSlice,
+    value: ArieskmsSlice,
+    tags: *const ArieskmsTag,
+    tags_len: usize,
+) -> FfiResult<Record> {
+    let tags = if tags_len == 0 {
+        &[][..]
+    } else if tags.is_null() {
+        return Err(FfiError::invalid_argument("Tags have a null pointer"));
+    } else {
+        std::slice::from_raw_parts(tags, tags_len)
+    };
+    Ok(Record {
+        category: bytes(category)?.to_vec(),
+        name: bytes(name)?.to_vec(),
+        value: bytes(value)?.to_vec(),
+        tags: tags
+            .iter()
+            .map(|t| {
+                let name = bytes(t.name)?.to_vec();
+                let value = bytes(t.value)?.to_vec();
+                Ok(if t.plaintext {
+                    RecordTag::Plaintext(name, value)
+                } else {
+                    RecordTag::Encrypted(name, value)
+                })
+            })
+            .collect::<FfiResult<_>>()?,
+    })
+}
+
+/// Save a new record with the `tags_len` tags in `tags`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_insert(
+    store: *const ArieskmsStore,
+    category: ArieskmsSlice,
+    name: ArieskmsSlice,
+    value: ArieskmsSlice,
+    tags: *const ArieskmsTag,
+    tags_len: usize,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let store = handle(store)?;
+        Ok(store
+            .0
+            .insert(record(category, name, value, tags, tags_len)?)?)
+    })
+}
+
+/// Replace the value and tags of an existing record
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_update(
+    store: *const ArieskmsStore,
+    category: ArieskmsSlice,
+    name: ArieskmsSlice,
+    value: ArieskmsSlice,
+    tags: *const ArieskmsTag,
+    tags_len: usize,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let store = handle(store)?;
+        Ok(store
+            .0
+            .update(record(category, name, value, tags, tags_len)?)?)
+    })
+}
+
+/// Fetch the value of a record. Use `arieskms_store_fetch_record`
+/// to also read its tags.
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_fetch(
+    store: *const ArieskmsStore,
+    category: ArieskmsSlice,
+    name: ArieskmsSlice,
+    out: *mut ArieskmsBuffer,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let store = handle(store)?;
+        let record = store.0.fetch(bytes(category)?, bytes(name)?)?;
+        write(out, record.value.into())
+    })
+}
+
+/// Fetch a record with its tags as the only record in `out`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_fetch_record(
+    store: *const ArieskmsStore,
+    category: ArieskmsSlice,
+    name: ArieskmsSlice,
+    out: *mut *mut ArieskmsRecords,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let store = handle(store)?;
+        let record = store.0.fetch(bytes(category)?, bytes(name)?)?;
+        write_handle(out, vec![record].into())
+    })
+}
+
+/// Find every record in `category` whose tags match the WQL query `query`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_search(
+    store: *const ArieskmsStore,
+    category: ArieskmsSlice,
+    query: *const c_char,
+    out: *mut *mut ArieskmsRecords,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let store = handle(store)?;
+        let query = Query::from_str(string(query)?)?;
+        let found = store.0.search(bytes(category)?, &query)?;
+        write_handle(out, found.into())
+    })
+}
+
+/// Find up to `limit` records in `category` whose tags match the WQL query
+/// `query`, starting after the continuation token `cursor` unless it is empty.
+/// The token of the next page is written to `next`, which is left empty after
+/// the last page.
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_search_page(
+    store: *const ArieskmsStore,
+    category: ArieskmsSlice,
+    query: *const c_char,
+    cursor: ArieskmsSlice,
+    limit: usize,
+    out: *mut *mut ArieskmsRecords,
+    next: *mut ArieskmsBuffer,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let store = handle(store)?;
+        let query = Query::from_str(string(query)?)?;
+        let cursor = match bytes(cursor)? {
+            [] => None,
+            token => Some(Cursor::from_str(std::str::from_utf8(token).map_err(
+                |_| FfiError::invalid_argument("Continuation tokens must be UTF-8"),
+            )?)?),
+        };
+        if out.is_null() || next.is_null() {
+            return Err(FfiError::invalid_argument("An out pointer is null"));
+        }
+        let page = store
+            .0
+            .search_page(bytes(category)?, &query, cursor.as_ref(), limit)?;
+        let token = page
+            .next
+            .map(|c| c.to_string().into_bytes())
+            .unwrap_or_default();
+        write(next, token.into())?;
+        write_handle(out, page.records.into())
+    })
+}
+
+/// List every category that has records
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_categories(
+    store: *const ArieskmsStore,
+    out: *mut *mut ArieskmsCategories,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let store = handle(store)?;
+        write_handle(out, ArieskmsCategories(store.0.categories()?))
+    })
+}
+
+/// Delete a record
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_delete(
+    store: *const ArieskmsStore,
+    category: ArieskmsSlice,
+    name: ArieskmsSlice,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let store = handle(store)?;
+        Ok(store.0.delete(bytes(category)?, bytes(name)?)?)
+    })
+}
+
+/// Close and release `store`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_store_free(store: *mut ArieskmsStore) {
+    if !store.is_null() {
+        Box::from_raw(store).0.close()
+    }
+}
+
+/// The number of records in `records`, or zero if it is null
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_records_len(records: *const ArieskmsRecords) -> usize {
+    records.as_ref().map(|r| r.records.len()).unwrap_or(0)
+}
+
+/// Borrow the record at `index` until `records` is freed
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_records_get(
+    records: *const ArieskmsRecords,
+    index: usize,
+    out: *mut ArieskmsRecord,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let records = handle(records)?;
+        let (record, tags) = records
+            .records
+            .get(index)
+            .zip(records.tags.get(index))
+            .ok_or_else(|| FfiError::invalid_argument("The record index is out of range"))?;
+        write(
+            out,
+            ArieskmsRecord {
+                category: slice(&record.category),
+                name: slice(&record.name),
+                value: slice(&record.value),
+                tags: if tags.is_empty() {
+                    ptr::null()
+                } else {
+                    tags.as_ptr()
+                },
+                tags_len: tags.len(),
+            },
+        )
+    })
+}
+
+/// Zeroize and release `records`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_records_free(records: *mut ArieskmsRecords) {
+    super::free_handle(records)
+}
+
+/// The number of categories in `categories`, or zero if it is null
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_categories_len(categories: *const ArieskmsCategories) -> usize {
+    categories.as_ref().map(|c| c.0.len()).unwrap_or(0)
+}
+
+/// Borrow the category at `index` until `categories` is freed
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_categories_get(
+    categories: *const ArieskmsCategories,
+    index: usize,
+    out: *mut ArieskmsSlice,
+) -> ArieskmsErrorCode {
+    call(|| {
+        let category = handle(categories)?
+            .0
+            .get(index)
+            .ok_or_else(|| FfiError::invalid_argument("The category index is out of range"))?;
+        write(out, slice(category))
+    })
+}
+
+/// Release `categories`
+#[no_mangle]
+pub unsafe extern "C" fn arieskms_categories_free(categories: *mut ArieskmsCategories) {
+    super::free_handle(categories)
+}
+
+#[cfg(all(test, feature = "software-enclave", feature = "storage-sqlite"))]
+mod tests {
+    use super::*;
+    use crate::ffi::{
+        arieskms_buffer_free,
+        enclave::{arieskms_enclave_free, arieskms_enclave_open},
+        tests::last_error,
+    };
+    use std::ffi::CString;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    fn empty() -> ArieskmsBuffer {
+        ArieskmsBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn tag(name: &'static [u8], value: &'static [u8], plaintext: bool) -> ArieskmsTag {
+        ArieskmsTag {
+            name: slice(name),
+            value: slice(value),
+            plaintext,
+        }
+    }
+
+    unsafe fn borrowed<'a>(slice: ArieskmsSlice) -> &'a [u8] {
+        bytes(slice).unwrap()
+    }
+
+    unsafe fn open() -> *mut ArieskmsStore {
+        let mut enclave = ptr::null_mut();
+        let code = arieskms_enclave_open(c("software:").as_ptr(), &mut enclave);
+        assert_eq!(code, ArieskmsErrorCode::Success);
+        let mut store = ptr::null_mut();
+        let code =
+            arieskms_store_open_sqlite(enclave, ptr::null(), c("ffi").as_ptr(), true, &mut store);
+        assert_eq!(code, ArieskmsErrorCode::Success, "{:?}", last_error());
+        // Stores outlive the enclave handle
+        arieskms_enclave_free(enclave);
+        store
+    }
+
+    unsafe fn insert(store: *const ArieskmsStore, name: &[u8], tags: &[ArieskmsTag]) {
+        let code = arieskms_store_insert(
+            store,
+            slice(b"category"),
+            slice(name),
+            slice(b"value"),
+            tags.as_ptr(),
+            tags.len(),
+        );
+        assert_eq!(code, ArieskmsErrorCode::Success, "{:?}", last_error());
+    }
+
+    unsafe fn names(records: *const ArieskmsRecords) -> Vec<Vec<u8>> {
+        let mut record = std::mem::MaybeUninit::uninit();
+        (0..arieskms_records_len(records))
+            .map(|i| {
+                let code = arieskms_records_get(records, i, record.as_mut_ptr());
+                assert_eq!(code, ArieskmsErrorCode::Success);
+                borrowed(record.assume_init().name).to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records() {
+        unsafe {
+            let store = open();
+            let tags = [tag(b"color", b"red", false), tag(b"~size", b"2", true)];
+            insert(store, b"one", &tags);
+
+            let mut value = empty();
+            let code = arieskms_store_fetch(store, slice(b"category"), slice(b"one"), &mut value);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            assert_eq!(std::slice::from_raw_parts(value.data, value.len), b"value");
+            arieskms_buffer_free(value);
+
+            let mut records = ptr::null_mut();
+            let code =
+                arieskms_store_fetch_record(store, slice(b"category"), slice(b"one"), &mut records);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            assert_eq!(arieskms_records_len(records), 1);
+            let mut record = std::mem::MaybeUninit::uninit();
+            assert_eq!(
+                arieskms_records_get(records, 0, record.as_mut_ptr()),
+                ArieskmsErrorCode::Success
+            );
+            let record = record.assume_init();
+            assert_eq!(borrowed(record.category), b"category");
+            assert_eq!(borrowed(record.value), b"value");
+            let fetched = std::slice::from_raw_parts(record.tags, record.tags_len);
+            assert_eq!(fetched.len(), 2);
+            for (fetched, tag) in fetched.iter().zip(tags.iter()) {
+                assert_eq!(borrowed(fetched.name), borrowed(tag.name));
+                assert_eq!(borrowed(fetched.value), borrowed(tag.value));
+                assert_eq!(fetched.plaintext, tag.plaintext);
+            }
+            let mut out = std::mem::MaybeUninit::uninit();
+            assert_eq!(
+                arieskms_records_get(records, 1, out.as_mut_ptr()),
+                ArieskmsErrorCode::InvalidArgument
+            );
+            arieskms_records_free(records);
+
+            let code = arieskms_store_update(
+                store,
+                slice(b"category"),
+                slice(b"one"),
+                slice(b"changed"),
+                ptr::null(),
+                0,
+            );
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            let mut records = ptr::null_mut();
+            arieskms_store_fetch_record(store, slice(b"category"), slice(b"one"), &mut records);
+            let mut record = std::mem::MaybeUninit::uninit();
+            arieskms_records_get(records, 0, record.as_mut_ptr());
+            let record = record.assume_init();
+            assert_eq!(borrowed(record.value), b"changed");
+            assert!(record.tags.is_null());
+            assert_eq!(record.tags_len, 0);
+            arieskms_records_free(records);
+
+            let code = arieskms_store_delete(store, slice(b"category"), slice(b"one"));
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            let mut value = empty();
+            let code = arieskms_store_fetch(store, slice(b"category"), slice(b"one"), &mut value);
+            assert_eq!(code, ArieskmsErrorCode::PersistenceItemNotFound);
+            assert!(last_error().is_some());
+            assert!(value.data.is_null());
+            arieskms_store_free(store);
+        }
+    }
+
+    #[test]
+    fn searches() {
+        unsafe {
+            let store = open();
+            for (name, color) in &[(b"a", b"red"), (b"b", b"red"), (b"c", b"red")] {
+                insert(store, &name[..], &[tag(b"color", &color[..], false)]);
+            }
+            insert(store, b"d", &[tag(b"color", b"blue", false)]);
+
+            let query = c(r#"{"color": "red"}"#);
+            let mut records = ptr::null_mut();
+            let code =
+                arieskms_store_search(store, slice(b"category"), query.as_ptr(), &mut records);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            let mut found = names(records);
+            found.sort();
+            assert_eq!(found, [b"a", b"b", b"c"]);
+            arieskms_records_free(records);
+
+            let mut pages = Vec::new();
+            let mut cursor = Vec::new();
+            loop {
+                let mut records = ptr::null_mut();
+                let mut next = empty();
+                let code = arieskms_store_search_page(
+                    store,
+                    slice(b"category"),
+                    query.as_ptr(),
+                    slice(&cursor),
+                    2,
+                    &mut records,
+                    &mut next,
+                );
+                assert_eq!(code, ArieskmsErrorCode::Success, "{:?}", last_error());
+                pages.push(names(records));
+                arieskms_records_free(records);
+                cursor = if next.data.is_null() {
+                    Vec::new()
+                } else {
+                    std::slice::from_raw_parts(next.data, next.len).to_vec()
+                };
+                arieskms_buffer_free(next);
+                if cursor.is_empty() {
+                    break;
+                }
+            }
+            // Pages are ordered by the protected names
+            assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), [2, 1]);
+            let mut found = pages.concat();
+            found.sort();
+            assert_eq!(found, [b"a", b"b", b"c"]);
+
+            let mut categories = ptr::null_mut();
+            let code = arieskms_store_categories(store, &mut categories);
+            assert_eq!(code, ArieskmsErrorCode::Success);
+            assert_eq!(arieskms_categories_len(categories), 1);
+            let mut category = ArieskmsSlice {
+                data: ptr::null(),
+                len: 0,
+            };
+            assert_eq!(
+                arieskms_categories_get(categories, 0, &mut category),
+                ArieskmsErrorCode::Success
+            );
+            assert_eq!(borrowed(category), b"category");
+            assert_eq!(
+                arieskms_categories_get(categories, 1, &mut category),
+                ArieskmsErrorCode::InvalidArgument
+            );
+            arieskms_categories_free(categories);
+            arieskms_store_free(store);
+        }
+    }
+
+    #[test]
+    fn error_codes() {
+        unsafe {
+            let store = open();
+            insert(store, b"one", &[]);
+            let code = arieskms_store_insert(
+                store,
+                slice(b"category"),
+                slice(b"one"),
+                slice(b"value"),
+                ptr::null(),
+                0,
+            );
+            assert_eq!(code, ArieskmsErrorCode::PersistenceDuplicateItem);
+            let code = arieskms_store_update(
+                store,
+                slice(b"category"),
+                slice(b"missing"),
+                slice(b"value"),
+                ptr::null(),
+                0,
+            );
+            assert_eq!(code, ArieskmsErrorCode::PersistenceItemNotFound);
+            let code = arieskms_store_delete(store, slice(b"category"), slice(b"missing"));
+            assert_eq!(code, ArieskmsErrorCode::PersistenceItemNotFound);
+
+            let mut records = ptr::null_mut();
+            let mut next = empty();
+            let bad_query = c("{not wql");
+            let code =
+                arieskms_store_search(store, slice(b"category"), bad_query.as_ptr(), &mut records);
+            assert_ne!(code, ArieskmsErrorCode::Success);
+            assert!(last_error().is_some());
+            let query = c("{}");
+            for cursor in &[&b"not a token"[..], &b"\xff"[..]] {
+                let code = arieskms_store_search_page(
+                    store,
+                    slice(b"category"),
+                    query.as_ptr(),
+                    slice(cursor),
+                    10,
+                    &mut records,
+                    &mut next,
+                );
+                assert_ne!(code, ArieskmsErrorCode::Success);
+                assert!(records.is_null());
+                assert!(next.data.is_null());
+            }
+            arieskms_store_free(store);
+        }
+    }
+
+    #[test]
+    fn null_pointers() {
+        unsafe {
+            let store = open();
+            let data = slice(b"data");
+            let dangling = ArieskmsSlice {
+                data: ptr::null(),
+                len: 1,
+            };
+            let query = c("{}");
+            let mut value = empty();
+            let mut records = ptr::null_mut();
+            let mut next = empty();
+            let mut categories = ptr::null_mut();
+            let calls = [
+                arieskms_store_insert(ptr::null(), data, data, data, ptr::null(), 0),
+                arieskms_store_insert(store, dangling, data, data, ptr::null(), 0),
+                arieskms_store_insert(store, data, data, data, ptr::null(), 1),
+                arieskms_store_insert(
+                    store,
+                    data,
+                    data,
+                    data,
+                    [ArieskmsTag {
+                        name: dangling,
+                        value: data,
+                        plaintext: false,
+                    }]
+                    .as_ptr(),
+                    1,
+                ),
+                arieskms_store_update(store, data, dangling, data, ptr::null(), 0),
+                arieskms_store_fetch(ptr::null(), data, data, &mut value),
+                arieskms_store_fetch(store, data, dangling, &mut value),
+                arieskms_store_fetch_record(store, dangling, data, &mut records),
+                arieskms_store_search(ptr::null(), data, query.as_ptr(), &mut records),
+                arieskms_store_search(store, data, ptr::null(), &mut records),
+                arieskms_store_search(store, data, query.as_ptr(), ptr::null_mut()),
+                arieskms_store_search_page(
+                    store,
+                    data,
+                    query.as_ptr(),
+                    dangling,
+                    1,
+                    &mut records,
+                    &mut next,
+                ),
+                arieskms_store_search_page(
+                    store,
+                    data,
+                    query.as_ptr(),
+                    slice(b""),
+                    1,
+                    &mut records,
+                    ptr::null_mut(),
+                ),
+                arieskms_store_search_page(
+                    store,
+                    data,
+                    query.as_ptr(),
+                    slice(b""),
+                    1,
+                    ptr::null_mut(),
+                    &mut next,
+                ),
+                arieskms_store_categories(ptr::null(), &mut categories),
+                arieskms_store_categories(store, ptr::null_mut()),
+                arieskms_store_delete(ptr::null(), data, data),
+                arieskms_records_get(ptr::null(), 0, &mut std::mem::zeroed()),
+                arieskms_categories_get(ptr::null(), 0, &mut std::mem::zeroed()),
+            ];
+            for (i, code) in calls.iter().enumerate() {
+                assert_eq!(*code, ArieskmsErrorCode::InvalidArgument, "call {}", i);
+            }
+            assert!(value.data.is_null() && records.is_null() && categories.is_null());
+            assert!(next.data.is_null());
+            assert_eq!(arieskms_records_len(ptr::null()), 0);
+            assert_eq!(arieskms_categories_len(ptr::null()), 0);
+            arieskms_records_free(ptr::null_mut());
+            arieskms_categories_free(ptr::null_mut());
+            arieskms_store_free(ptr::null_mut());
+
+            let mut other = ptr::null_mut();
+            let code = arieskms_store_open_sqlite(
+                ptr::null(),
+                ptr::null(),
+                c("ffi").as_ptr(),
+                true,
+                &mut other,
+            );
+            assert_eq!(code, ArieskmsErrorCode::InvalidArgument);
+            assert!(other.is_null());
+            arieskms_store_free(store);
+        }
+    }
+}
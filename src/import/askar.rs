@@ -0,0 +1,707 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Import Aries Askar SQLite stores.
+//!
+//! An Askar store holds any number of profiles. The `config` table names the
+//! default profile and how the store key is derived from the pass key. Each
+//! row in `profiles` has a profile key encrypted with the store key, which
+//! is CBOR holding the keys for categories, names, tag names and tag values
+//! and the HMAC key the value keys are derived from. Rows in `items` belong
+//! to a profile and have their category and name encrypted with
+//! ChaCha20-Poly1305. Values are encrypted with a key derived from the
+//! plaintext category and name. Tags are in `items_tags` where plaintext
+//! tags only have their name encrypted. Every ciphertext is the 12 byte nonce
+//! followed by the encrypted data, the same as Indy-SDK wallets.
+//!
+//! The store is only read and expired items are skipped. Items are saved as
+//! records with the same category, name and tags. Key entries are saved in
+//! the enclave named after the entry and replaced by a record in
+//! `cryptokey` holding the public JWK and the key metadata. Imported keys
+//! can be exported when wrapped since they already existed outside the
+//! enclave. Keys of a type the enclave can't hold, or that arieskms doesn't
+//! know, are left out with their record and listed in the `ImportSummary`.
+
+use super::{
+    errors::{ImportError, ImportErrorKind},
+    indy::{blob, decrypt, IndyKeyDerivation, KeyBytes},
+    ImportResult, ImportSummary,
+};
+use crate::{
+    persistence::{PersistenceLike, Record, RecordTag},
+    protection::ProtectedStore,
+    security::{
+        errors::EnclaveErrorKind, validation::KeyOrigin, AesModes, AesSizes, EcCurves,
+        EccCapability, EcdsaAlgorithm, EnclaveKeyType, EnclaveLike, KeyCapabilities,
+        SymmetricCapability, WrappingKey,
+    },
+};
+
+use hmac::{Hmac, Mac, NewMac};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, NO_PARAMS};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{fmt, path::Path};
+use zeroize::Zeroizing;
+
+/// The category of records holding the public part of imported keys
+pub const KEY_CATEGORY: &str = "cryptokey";
+
+const KEY_SIZE: usize = 32;
+/// The only store version Askar has released
+const STORE_VERSION: &str = "1";
+
+/// The kind of an entry in an Askar store
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AskarEntryKind {
+    /// A key managed by Askar. The value is the CBOR encoded key parameters.
+    Key,
+    /// A regular item
+    Item,
+}
+
+impl AskarEntryKind {
+    fn from_column(kind: i64) -> ImportResult<Self> {
+        match kind {
+            1 => Ok(AskarEntryKind::Key),
+            2 => Ok(AskarEntryKind::Item),
+            _ => Err(invalid_data(format!("Unknown entry kind {}", kind))),
+        }
+    }
+}
+
+/// The keys for one profile. Keys are encoded as CBOR bytes.
+#[derive(Deserialize)]
+struct ProfileKey {
+    #[serde(rename = "ick")]
+    category_key: KeyBytes,
+    #[serde(rename = "ink")]
+    name_key: KeyBytes,
+    #[serde(rename = "ihk")]
+    item_hmac_key: KeyBytes,
+    #[serde(rename = "tnk")]
+    tag_name_key: KeyBytes,
+    #[serde(rename = "tvk")]
+    tag_value_key: KeyBytes,
+}
+
+impl ProfileKey {
+    fn from_cbor(data: &[u8]) -> ImportResult<Self> {
+        let key: Self = serde_cbor::from_slice(data)
+            .map_err(|e| invalid_data(format!("Unable to read a profile key: {}", e)))?;
+        let keys = [
+            &key.category_key,
+            &key.name_key,
+            &key.item_hmac_key,
+            &key.tag_name_key,
+            &key.tag_value_key,
+        ];
+        if keys.iter().any(|k| k.0.len() != KEY_SIZE) {
+            return Err(invalid_data("A profile key is malformed"));
+        }
+        Ok(key)
+    }
+
+    /// The key for one value is the HMAC of its length prefixed category and name
+    fn value_key(&self, category: &[u8], name: &[u8]) -> ImportResult<Zeroizing<Vec<u8>>> {
+        let mut hmac = Hmac::<Sha256>::new_varkey(&self.item_hmac_key.0)
+            .map_err(|_| invalid_data("A profile key is malformed"))?;
+        hmac.update(&(category.len() as u32).to_be_bytes());
+        hmac.update(category);
+        hmac.update(&(name.len() as u32).to_be_bytes());
+        hmac.update(name);
+        Ok(Zeroizing::new(hmac.finalize().into_bytes().to_vec()))
+    }
+}
+
+/// The parameters saved as the value of a key entry
+#[derive(Deserialize)]
+struct KeyParams {
+    #[serde(default, rename = "meta")]
+    metadata: Option<String>,
+    #[serde(default)]
+    data: Option<KeyBytes>,
+}
+
+/// An Aries Askar store opened for reading
+pub struct AskarStore {
+    conn: Connection,
+    store_key: Option<Zeroizing<Vec<u8>>>,
+    default_profile: String,
+}
+
+impl AskarStore {
+    /// Open the store at `path` with its `pass_key`. The key derivation
+    /// method is read from the store.
+    pub fn open<A: AsRef<Path>>(path: A, pass_key: &str) -> ImportResult<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let config = |name: &str| -> ImportResult<String> {
+            conn.query_row("SELECT value FROM config WHERE name = ?1", &[name], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| invalid_data(format!("The store has no {} in its config", name)))
+        };
+        let version = config("version")?;
+        if version != STORE_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported Askar store version {}",
+                version
+            )));
+        }
+        let default_profile = config("default_profile")?;
+        let store_key = store_key(&config("key")?, pass_key)?;
+        Ok(Self {
+            conn,
+            store_key,
+            default_profile,
+        })
+    }
+
+    /// The profile used when none is named
+    pub fn default_profile(&self) -> &str {
+        &self.default_profile
+    }
+
+    /// The names of all profiles in the store
+    pub fn profiles(&self) -> ImportResult<Vec<String>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM profiles ORDER BY id")?;
+        let names = stmt
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(names)
+    }
+
+    /// Decrypt each entry in `profile` and pass it to `f` as a record so
+    /// only one entry is held in memory at a time
+    pub fn for_each_entry<F>(&self, profile: &str, mut f: F) -> ImportResult<()>
+    where
+        F: FnMut(AskarEntryKind, Record) -> ImportResult<()>,
+    {
+        let (profile_id, key) = self.profile_key(profile)?;
+        let mut items = self.conn.prepare(
+            "SELECT id, kind, category, name, value FROM items \
+             WHERE profile_id = ?1 AND (expiry IS NULL OR expiry > DATETIME('now')) \
+             ORDER BY id",
+        )?;
+        let mut item_tags = self
+            .conn
+            .prepare("SELECT name, value, plaintext FROM items_tags WHERE item_id = ?1")?;
+        let mut rows = items.query(params![profile_id])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let kind = AskarEntryKind::from_column(row.get(1)?)?;
+            let category = decrypt(&key.category_key.0, &blob(row, 2)?)?;
+            let name = decrypt(&key.name_key.0, &blob(row, 3)?)?;
+            let value_key = key.value_key(&category, &name)?;
+            let value = decrypt(&value_key, &blob(row, 4)?)?;
+
+            let mut tags = Vec::new();
+            let mut tag_rows = item_tags.query(params![id])?;
+            while let Some(t) = tag_rows.next()? {
+                let tag_name = decrypt(&key.tag_name_key.0, &blob(t, 0)?)?.to_vec();
+                let plaintext: bool = t.get(2)?;
+                tags.push(if plaintext {
+                    RecordTag::Plaintext(tag_name, blob(t, 1)?)
+                } else {
+                    RecordTag::Encrypted(
+                        tag_name,
+                        decrypt(&key.tag_value_key.0, &blob(t, 1)?)?.to_vec(),
+                    )
+                });
+            }
+
+            f(
+                kind,
+                Record {
+                    category: category.to_vec(),
+                    name: name.to_vec(),
+                    value: value.to_vec(),
+                    tags,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Move every entry in `profile` into `store`. Keys are saved in the
+    /// store's enclave named `key_prefix` followed by the entry name. Keys
+    /// the enclave can't hold are skipped.
+    pub fn import<E: EnclaveLike, P: PersistenceLike>(
+        &self,
+        profile: &str,
+        store: &ProtectedStore<E, P>,
+        key_prefix: &str,
+    ) -> ImportResult<ImportSummary> {
+        let mut summary = ImportSummary::default();
+        self.for_each_entry(profile, |kind, mut record| {
+            if kind == AskarEntryKind::Key {
+                let params = Zeroizing::new(record.value);
+                record.category = KEY_CATEGORY.as_bytes().to_vec();
+                match import_key(store.enclave(), key_prefix, &record.name, &params)? {
+                    Some(value) => record.value = value,
+                    None => {
+                        let name = String::from_utf8_lossy(&record.name).into_owned();
+                        summary.skipped_keys.push(name);
+                        return Ok(());
+                    }
+                }
+                summary.keys += 1;
+            }
+            store.insert(record)?;
+            summary.records += 1;
+            Ok(())
+        })?;
+        Ok(summary)
+    }
+
+    fn profile_key(&self, profile: &str) -> ImportResult<(i64, ProfileKey)> {
+        let (id, key): (i64, Option<Vec<u8>>) = self
+            .conn
+            .query_row(
+                "SELECT id, profile_key FROM profiles WHERE name = ?1",
+                &[profile],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| invalid_data(format!("The store has no profile {}", profile)))?;
+        let key = key.ok_or_else(|| invalid_data(format!("Profile {} has no key", profile)))?;
+        let key = match &self.store_key {
+            Some(store_key) => decrypt(store_key, &key).map_err(|_| {
+                ImportError::from_msg(
+                    ImportErrorKind::InvalidKey,
+                    "Unable to decrypt the profile key. The pass key is wrong",
+                )
+            })?,
+            None => Zeroizing::new(key),
+        };
+        Ok((id, ProfileKey::from_cbor(&key)?))
+    }
+}
+
+/// Derive the store key described by the `key` config value. Unprotected
+/// stores have no store key.
+fn store_key(method: &str, pass_key: &str) -> ImportResult<Option<Zeroizing<Vec<u8>>>> {
+    let (derivation, salt) = match method {
+        "none" => return Ok(None),
+        "raw" => (IndyKeyDerivation::Raw, None),
+        _ => {
+            let (level, salt) = method
+                .strip_prefix("kdf:argon2i:13:")
+                .and_then(|m| {
+                    let i = m.find("?salt=")?;
+                    Some((&m[..i], &m[i + "?salt=".len()..]))
+                })
+                .ok_or_else(|| unknown_method(method))?;
+            let derivation = match level {
+                "mod" => IndyKeyDerivation::Argon2iMod,
+                "int" => IndyKeyDerivation::Argon2iInt,
+                _ => return Err(unknown_method(method)),
+            };
+            let salt = hex::decode(salt).map_err(|_| invalid_data("The store salt is not hex"))?;
+            (derivation, Some(salt))
+        }
+    };
+    derivation.derive(pass_key, salt.as_deref()).map(Some)
+}
+
+/// Save the private key in a key entry in `enclave` and return the value of
+/// the record that replaces the entry, or `None` if the enclave can't hold it
+fn import_key<E: EnclaveLike>(
+    enclave: &E,
+    key_prefix: &str,
+    name: &[u8],
+    params: &[u8],
+) -> ImportResult<Option<Vec<u8>>> {
+    let name = std::str::from_utf8(name).map_err(|_| invalid_data("A key name is not UTF-8"))?;
+    let params: KeyParams = serde_cbor::from_slice(params)
+        .map_err(|e| invalid_data(format!("Unable to read key {}: {}", name, e)))?;
+    let data = params
+        .data
+        .ok_or_else(|| invalid_data(format!("Key {} has no key data", name)))?;
+    let mut jwk: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&data.0)
+        .map_err(|e| invalid_data(format!("Unable to read key {}: {}", name, e)))?;
+
+    let (key_type, capabilities, secret_param) = match jwk_key_type(&jwk) {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    match enclave
+        .capabilities()
+        .check_key(key_type, capabilities, KeyOrigin::Imported)
+    {
+        Err(ref e) if e.kind() == EnclaveErrorKind::UnsupportedOperation => return Ok(None),
+        result => result?,
+    }
+    let secret = match jwk.remove(secret_param) {
+        Some(serde_json::Value::String(s)) => Zeroizing::new(s),
+        _ => return Err(invalid_data(format!("Key {} has no private key", name))),
+    };
+    let secret = Zeroizing::new(
+        base64::decode_config(secret.as_str(), base64::URL_SAFE_NO_PAD)
+            .map_err(|_| invalid_data(format!("Key {} is not base64url", name)))?,
+    );
+    enclave.import_key(
+        &format!("{}{}", key_prefix, name),
+        key_type,
+        capabilities,
+        &secret,
+    )?;
+
+    let mut value = serde_json::Map::new();
+    value.insert("jwk".to_string(), serde_json::Value::Object(jwk));
+    if let Some(metadata) = params.metadata {
+        value.insert("meta".to_string(), serde_json::Value::String(metadata));
+    }
+    serde_json::to_vec(&value)
+        .map(Some)
+        .map_err(|e| invalid_data(e.to_string()))
+}
+
+/// The enclave key type and capabilities for a private JWK and the
+/// parameter holding its private key
+fn jwk_key_type(
+    jwk: &serde_json::Map<String, serde_json::Value>,
+) -> Option<(EnclaveKeyType, KeyCapabilities, &'static str)> {
+    let param = |name: &str| jwk.get(name).and_then(serde_json::Value::as_str);
+    // Signatures are checked with the public key so keys can't verify,
+    // like the handles in `security::keys`
+    let signing =
+        KeyCapabilities::Ecc(EccCapability::SIGN | EccCapability::EXPORTABLE_WHEN_WRAPPED);
+    let symmetric = KeyCapabilities::Symmetric(
+        SymmetricCapability::ENCRYPT
+            | SymmetricCapability::DECRYPT
+            | SymmetricCapability::EXPORTABLE_WHEN_WRAPPED,
+    );
+    match (param("kty")?, param("crv"), param("alg")) {
+        ("OKP", Some("Ed25519"), _) => Some((EnclaveKeyType::Ed25519, signing, "d")),
+        ("OKP", Some("X25519"), _) => Some((
+            EnclaveKeyType::X25519,
+            KeyCapabilities::Ecc(
+                EccCapability::DERIVE_DIFFIE_HELLMAN | EccCapability::EXPORTABLE_WHEN_WRAPPED,
+            ),
+            "d",
+        )),
+        ("EC", Some("P-256"), _) => Some((
+            EnclaveKeyType::Ecdsa(EcCurves::Secp256r1, EcdsaAlgorithm::Sha256),
+            signing,
+            "d",
+        )),
+        ("EC", Some("P-384"), _) => Some((
+            EnclaveKeyType::Ecdsa(EcCurves::Secp384r1, EcdsaAlgorithm::Sha384),
+            signing,
+            "d",
+        )),
+        ("EC", Some("secp256k1"), _) => Some((
+            EnclaveKeyType::Ecdsa(EcCurves::Secp256k1, EcdsaAlgorithm::Sha256),
+            signing,
+            "d",
+        )),
+        ("oct", _, Some("A128GCM")) => Some((
+            EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes128, AesModes::Gcm)),
+            symmetric,
+            "k",
+        )),
+        ("oct", _, Some("A256GCM")) => Some((
+            EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::Gcm)),
+            symmetric,
+            "k",
+        )),
+        ("oct", _, Some("XC20P")) => Some((
+            EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305),
+            symmetric,
+            "k",
+        )),
+        _ => None,
+    }
+}
+
+fn unknown_method(method: &str) -> ImportError {
+    ImportError::from_msg(
+        ImportErrorKind::InvalidKey,
+        format!("Unknown store key method {}", method),
+    )
+}
+
+fn invalid_data<D: fmt::Display + fmt::Debug + Send + Sync + 'static>(msg: D) -> ImportError {
+    ImportError::from_msg(ImportErrorKind::InvalidData, msg)
+}
+
+#[cfg(all(test, feature = "software-enclave", feature = "storage-memory"))]
+mod tests {
+    use super::*;
+    use crate::{
+        import::indy::tests::encrypt, persistence::kv::memory::MemoryStore,
+        protection::ProtectionKeys, security::software::SoftwareEnclave,
+    };
+    use serde_cbor::Value;
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    /// The Askar SQLite schema
+    const SCHEMA: &str = "
+        CREATE TABLE config (name TEXT NOT NULL, value TEXT, PRIMARY KEY (name));
+        CREATE TABLE profiles (id INTEGER NOT NULL, name TEXT NOT NULL, reference TEXT NULL, profile_key BLOB NULL, PRIMARY KEY (id));
+        CREATE UNIQUE INDEX ix_profile_name ON profiles (name);
+        CREATE TABLE items (id INTEGER NOT NULL, profile_id INTEGER NOT NULL, kind INTEGER NOT NULL, category BLOB NOT NULL, name BLOB NOT NULL, value BLOB NOT NULL, expiry DATETIME NULL, PRIMARY KEY (id), FOREIGN KEY (profile_id) REFERENCES profiles (id) ON DELETE CASCADE ON UPDATE CASCADE);
+        CREATE UNIQUE INDEX ix_items_uniq ON items (profile_id, kind, category, name);
+        CREATE TABLE items_tags (id INTEGER NOT NULL, item_id INTEGER NOT NULL, name BLOB NOT NULL, value BLOB NOT NULL, plaintext BOOLEAN NOT NULL, PRIMARY KEY (id), FOREIGN KEY (item_id) REFERENCES items (id) ON DELETE CASCADE ON UPDATE CASCADE);
+    ";
+
+    // RFC 8032 test 1
+    const ED25519_SEED: &str = "nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A";
+    const ED25519_PUBLIC: &str = "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo";
+
+    /// A store file removed when the test ends
+    struct StoreFile(PathBuf);
+
+    impl Drop for StoreFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// The category, name, value, tag name and tag value keys of a profile
+    fn profile_keys(profile: u8) -> Vec<Vec<u8>> {
+        (1..=5).map(|i| vec![profile * 10 + i; KEY_SIZE]).collect()
+    }
+
+    fn cbor_map(entries: Vec<(&str, Value)>) -> Vec<u8> {
+        let map: BTreeMap<_, _> = entries
+            .into_iter()
+            .map(|(k, v)| (Value::Text(k.to_string()), v))
+            .collect();
+        serde_cbor::to_vec(&Value::Map(map)).unwrap()
+    }
+
+    /// The CBOR profile key Askar encrypts with the store key
+    fn profile_key(profile: u8) -> Vec<u8> {
+        let keys = profile_keys(profile);
+        cbor_map(
+            ["ick", "ink", "ihk", "tnk", "tvk"]
+                .iter()
+                .zip(keys)
+                .map(|(name, key)| (*name, Value::Bytes(key)))
+                .collect(),
+        )
+    }
+
+    /// The CBOR parameters of a key entry holding `jwk`
+    fn key_params(jwk: serde_json::Value) -> Vec<u8> {
+        cbor_map(vec![
+            ("meta", Value::Text("metadata".to_string())),
+            ("data", Value::Bytes(serde_json::to_vec(&jwk).unwrap())),
+        ])
+    }
+
+    fn record(name: &str, tags: Vec<RecordTag>) -> Record {
+        Record {
+            category: b"connections".to_vec(),
+            name: name.as_bytes().to_vec(),
+            value: format!(r#"{{"name":"{}"}}"#, name).into_bytes(),
+            tags,
+        }
+    }
+
+    fn alice() -> Record {
+        record(
+            "alice",
+            vec![
+                RecordTag::Encrypted(b"state".to_vec(), b"active".to_vec()),
+                RecordTag::Encrypted(b"role".to_vec(), b"holder".to_vec()),
+                RecordTag::Plaintext(b"issued".to_vec(), b"2020-01".to_vec()),
+            ],
+        )
+    }
+
+    /// Write a store laid out like Askar writes them with the entries
+    /// encrypted by the keys of their profile
+    fn write_store(name: &str, method: &str, store_key: &[u8]) -> StoreFile {
+        let path =
+            std::env::temp_dir().join(format!("arieskms-askar-{}-{}.db", name, std::process::id()));
+        let file = StoreFile(path);
+        let _ = std::fs::remove_file(&file.0);
+        let conn = Connection::open(&file.0).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        for (name, value) in &[
+            ("default_profile", "agent"),
+            ("key", method),
+            ("version", STORE_VERSION),
+        ] {
+            conn.execute(
+                "INSERT INTO config (name, value) VALUES (?1, ?2)",
+                params![name, value],
+            )
+            .unwrap();
+        }
+        for (id, profile) in &[(1u8, "agent"), (2, "other")] {
+            conn.execute(
+                "INSERT INTO profiles (id, name, profile_key) VALUES (?1, ?2, ?3)",
+                params![*id as i64, profile, encrypt(store_key, &profile_key(*id))],
+            )
+            .unwrap();
+        }
+
+        let ed25519 = serde_json::json!({
+            "kty": "OKP", "crv": "Ed25519", "x": ED25519_PUBLIC, "d": ED25519_SEED,
+        });
+        let aes = serde_json::json!({
+            "kty": "oct", "alg": "A256GCM", "k": base64::encode_config([6u8; 32], base64::URL_SAFE_NO_PAD),
+        });
+        let p256 = serde_json::json!({
+            "kty": "EC", "crv": "P-256", "x": "AA", "y": "AA",
+            "d": base64::encode_config([1u8; 32], base64::URL_SAFE_NO_PAD),
+        });
+        let rsa = serde_json::json!({ "kty": "RSA", "n": "AA", "e": "AQAB", "d": "AA" });
+        let key = |name: &str, jwk| {
+            let mut r = record(name, Vec::new());
+            r.category = b"".to_vec();
+            r.value = key_params(jwk);
+            r
+        };
+        let entries = vec![
+            (1u8, 2i64, alice(), None),
+            (1, 2, record("bob", Vec::new()), Some("2000-01-01 00:00:00")),
+            (1, 1, key("ed25519", ed25519), None),
+            (1, 1, key("aes", aes), None),
+            (1, 1, key("p256", p256), None),
+            (1, 1, key("rsa", rsa), None),
+            (2, 2, record("carol", Vec::new()), None),
+        ];
+        for (profile, kind, record, expiry) in entries {
+            let keys = profile_keys(profile);
+            // Values are encrypted with the HMAC of their length prefixed category and name
+            let mut hmac = Hmac::<Sha256>::new_varkey(&keys[2]).unwrap();
+            for part in &[&record.category, &record.name] {
+                hmac.update(&(part.len() as u32).to_be_bytes());
+                hmac.update(part);
+            }
+            let value_key = hmac.finalize().into_bytes();
+            conn.execute(
+                "INSERT INTO items (profile_id, kind, category, name, value, expiry) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    profile as i64,
+                    kind,
+                    encrypt(&keys[0], &record.category),
+                    encrypt(&keys[1], &record.name),
+                    encrypt(&value_key, &record.value),
+                    expiry
+                ],
+            )
+            .unwrap();
+            let id = conn.last_insert_rowid();
+            for tag in &record.tags {
+                let (name, value, plaintext) = match tag {
+                    RecordTag::Encrypted(n, v) => (n, encrypt(&keys[4], v), false),
+                    RecordTag::Plaintext(n, v) => (n, v.clone(), true),
+                };
+                conn.execute(
+                    "INSERT INTO items_tags (item_id, name, value, plaintext) \
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![id, encrypt(&keys[3], name), value, plaintext],
+                )
+                .unwrap();
+            }
+        }
+        file
+    }
+
+    fn protected_store() -> ProtectedStore<SoftwareEnclave, MemoryStore> {
+        let enclave = SoftwareEnclave::new();
+        let keys = ProtectionKeys::generate(&enclave, "test").unwrap();
+        ProtectedStore::new(enclave, MemoryStore::new(), keys)
+    }
+
+    #[test]
+    fn imports_stores() {
+        let salt = [8u8; 16];
+        let store_key = IndyKeyDerivation::Argon2iInt
+            .derive("pass key", Some(&salt))
+            .unwrap();
+        let method = format!("kdf:argon2i:13:int?salt={}", hex::encode(salt));
+        let file = write_store("argon2i", &method, &store_key);
+
+        // The pass key is only checked when a profile key is decrypted
+        let wrong = AskarStore::open(&file.0, "wrong").unwrap();
+        assert_eq!(
+            wrong
+                .import("agent", &protected_store(), "askar-")
+                .unwrap_err()
+                .kind(),
+            ImportErrorKind::InvalidKey
+        );
+        let askar = AskarStore::open(&file.0, "pass key").unwrap();
+        assert_eq!(askar.default_profile(), "agent");
+        assert_eq!(askar.profiles().unwrap(), vec!["agent", "other"]);
+
+        let store = protected_store();
+        assert_eq!(
+            askar.import("agent", &store, "askar-").unwrap(),
+            ImportSummary {
+                records: 3,
+                keys: 2,
+                skipped_keys: vec!["p256".to_string(), "rsa".to_string()],
+            }
+        );
+        assert_eq!(store.fetch(b"connections", b"alice").unwrap(), alice());
+        for (category, name) in &[
+            (&b"connections"[..], &b"bob"[..]),
+            (b"connections", b"carol"),
+            (KEY_CATEGORY.as_bytes(), b"p256"),
+            (KEY_CATEGORY.as_bytes(), b"rsa"),
+        ] {
+            assert!(store.fetch(category, name).is_err());
+        }
+
+        let ed25519 = store.fetch(KEY_CATEGORY.as_bytes(), b"ed25519").unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&ed25519.value).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "jwk": { "kty": "OKP", "crv": "Ed25519", "x": ED25519_PUBLIC },
+                "meta": "metadata",
+            })
+        );
+        let enclave = store.enclave();
+        assert_eq!(
+            base64::encode_config(
+                enclave.public_key("askar-ed25519").unwrap(),
+                base64::URL_SAFE_NO_PAD
+            ),
+            ED25519_PUBLIC
+        );
+        let ciphertext = enclave.encrypt("askar-aes", b"plaintext", b"").unwrap();
+        assert_eq!(
+            enclave.decrypt("askar-aes", &ciphertext, b"").unwrap(),
+            b"plaintext"
+        );
+        assert!(enclave.sign("askar-p256", b"").is_err());
+
+        let other = protected_store();
+        askar.import("other", &other, "askar-").unwrap();
+        assert!(other.fetch(b"connections", b"carol").is_ok());
+    }
+
+    #[test]
+    fn opens_raw_stores() {
+        let store_key = [4u8; KEY_SIZE];
+        let file = write_store("raw", "raw", &store_key);
+        let askar = AskarStore::open(&file.0, &bs58::encode(store_key).into_string()).unwrap();
+        let store = protected_store();
+        askar.import("other", &store, "").unwrap();
+        assert!(store.fetch(b"connections", b"carol").is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+use crate::{
+    persistence::errors::{PersistenceError, PersistenceErrorKind},
+    protection::errors::{ProtectionError, ProtectionErrorKind},
+    security::errors::{EnclaveError, EnclaveErrorKind},
+};
+use failure::{Backtrace, Context, Fail};
+use std::fmt;
+
+/// Represents possible errors that could occur while importing a wallet.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum ImportErrorKind {
+    /// Occurs when the wallet can't be read
+    #[fail(display = "IO Error")]
+    IOError,
+    /// Occurs when the wallet key is wrong or in the wrong format
+    #[fail(display = "The wallet key is invalid")]
+    InvalidKey,
+    /// Occurs when the wallet contents are not in the expected format
+    #[fail(display = "The wallet is malformed")]
+    InvalidData,
+    /// Occurs when the enclave fails to save an imported key
+    #[fail(display = "Enclave Error: {}", _0)]
+    Enclave(EnclaveErrorKind),
+    /// Occurs when the persistence backend fails
+    #[fail(display = "Persistence Error: {}", _0)]
+    Persistence(PersistenceErrorKind),
+    /// Occurs when imported records can't be protected
+    #[fail(display = "Protection Error: {}", _0)]
+    Protection(ProtectionErrorKind),
+}
+
+/// Represents an import error that includes a context and backtrace
+#[derive(Debug)]
+pub struct ImportError {
+    inner: Context<ImportErrorKind>,
+}
+
+impl ImportError {
+    /// Create from a message and kind
+    pub fn from_msg<D: fmt::Display + fmt::Debug + Send + Sync + 'static>(
+        kind: ImportErrorKind,
+        msg: D,
+    ) -> Self {
+        Self {
+            inner: Context::new(msg).context(kind),
+        }
+    }
+
+    /// Get `ImportErrorKind` wrapped by this error
+    pub fn kind(&self) -> ImportErrorKind {
+        self.inner.get_context().clone()
+    }
+}
+
+impl From<ImportErrorKind> for ImportError {
+    fn from(kind: ImportErrorKind) -> Self {
+        Self {
+            inner: Context::new("").context(kind),
+        }
+    }
+}
+
+impl From<Context<ImportErrorKind>> for ImportError {
+    fn from(inner: Context<ImportErrorKind>) -> Self {
+        ImportError { inner }
+    }
+}
+
+impl From<EnclaveError> for ImportError {
+    fn from(e: EnclaveError) -> Self {
+        let kind = ImportErrorKind::Enclave(e.kind());
+        e.context(kind).into()
+    }
+}
+
+impl From<PersistenceError> for ImportError {
+    fn from(e: PersistenceError) -> Self {
+        let kind = ImportErrorKind::Persistence(e.kind());
+        e.context(kind).into()
+    }
+}
+
+impl From<ProtectionError> for ImportError {
+    fn from(e: ProtectionError) -> Self {
+        let kind = ImportErrorKind::Protection(e.kind());
+        e.context(kind).into()
+    }
+}
+
+#[cfg(feature = "import-indy")]
+impl From<rusqlite::Error> for ImportError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::SqliteFailure(ref f, _)
+                if f.code == rusqlite::ErrorCode::NotADatabase =>
+            {
+                ImportError::from_msg(ImportErrorKind::InvalidData, e.to_string())
+            }
+            _ => ImportError::from_msg(ImportErrorKind::IOError, e.to_string()),
+        }
+    }
+}
+
+impl Fail for ImportError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.inner.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.inner.backtrace()
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        for cause in Fail::iter_chain(&self.inner) {
+            if first {
+                first = false;
+                writeln!(f, "Error: {}", cause)?;
+            } else {
+                writeln!(f, "Caused by: {}", cause)?;
+            }
+        }
+        Ok(())
+    }
+}
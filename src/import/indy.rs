@@ -0,0 +1,602 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Import Indy-SDK wallets.
+//!
+//! An Indy-SDK wallet is a SQLite file. The `metadata` table holds the wallet
+//! keys encrypted with a master key derived from the wallet key. Each row in
+//! `items` has its type and name encrypted with ChaCha20-Poly1305 and its
+//! value encrypted with a random per-item key that is itself encrypted with
+//! the wallet value key. Tags are in `tags_encrypted` where both name and
+//! value are encrypted and in `tags_plaintext` where only the name is.
+//! Every ciphertext is the 12 byte nonce followed by the encrypted data.
+//!
+//! The wallet is only read. Each item is decrypted and saved as a record with
+//! the item type as category, so DIDs stay in `Indy::Did` and so on. Private
+//! keys in `Indy::Key` items are saved in the enclave as Ed25519 keys named
+//! after their verkey and removed from the record. They can be exported when
+//! wrapped since they already existed outside the enclave and must still be
+//! included in Indy-SDK export files.
+
+use super::{
+    errors::{ImportError, ImportErrorKind},
+    ImportResult, ImportSummary,
+};
+use crate::{
+    persistence::{PersistenceLike, Record, RecordTag},
+    protection::ProtectedStore,
+    security::{
+        keys::{KeyExport, SigningAlgorithm, SigningKey},
+        EnclaveLike,
+    },
+};
+
+use argon2::{Config, ThreadMode, Variant, Version};
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rusqlite::{params, types::Value, Connection, OpenFlags, Row, NO_PARAMS};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{fmt, path::Path, str::FromStr};
+use zeroize::Zeroizing;
+
+/// The category of items holding DIDs
+pub const DID_CATEGORY: &str = "Indy::Did";
+/// The category of items holding Ed25519 keys
+pub const KEY_CATEGORY: &str = "Indy::Key";
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+/// The number of keys in the wallet metadata
+const WALLET_KEYS: usize = 7;
+
+/// How the master key is derived from the wallet key.
+/// This is the `key_derivation_method` the wallet was opened with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndyKeyDerivation {
+    /// Argon2i with libsodium's moderate limits. The Indy-SDK default.
+    Argon2iMod,
+    /// Argon2i with libsodium's interactive limits
+    Argon2iInt,
+    /// The wallet key is the base58 encoded master key
+    Raw,
+}
+
+impl IndyKeyDerivation {
+    pub(super) fn derive(self, key: &str, salt: Option<&[u8]>) -> ImportResult<Zeroizing<Vec<u8>>> {
+        // libsodium's crypto_pwhash_argon2i limits. Memory is in KiB.
+        let (time_cost, mem_cost) = match self {
+            IndyKeyDerivation::Argon2iMod => (6, 131_072),
+            IndyKeyDerivation::Argon2iInt => (4, 32_768),
+            IndyKeyDerivation::Raw => {
+                let master = Zeroizing::new(bs58::decode(key).into_vec().map_err(|_| {
+                    ImportError::from_msg(ImportErrorKind::InvalidKey, "Raw keys must be base58")
+                })?);
+                if master.len() != KEY_SIZE {
+                    return Err(ImportError::from_msg(
+                        ImportErrorKind::InvalidKey,
+                        format!("Raw keys must be {} bytes", KEY_SIZE),
+                    ));
+                }
+                return Ok(master);
+            }
+        };
+        let salt = salt.ok_or_else(|| invalid_data("The wallet metadata has no salt"))?;
+        let config = Config {
+            variant: Variant::Argon2i,
+            version: Version::Version13,
+            mem_cost,
+            time_cost,
+            lanes: 1,
+            thread_mode: ThreadMode::Sequential,
+            secret: &[],
+            ad: &[],
+            hash_length: KEY_SIZE as u32,
+        };
+        argon2::hash_raw(key.as_bytes(), salt, &config)
+            .map(Zeroizing::new)
+            .map_err(|e| ImportError::from_msg(ImportErrorKind::InvalidKey, e.to_string()))
+    }
+}
+
+impl FromStr for IndyKeyDerivation {
+    type Err = ImportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ARGON2I_MOD" => Ok(IndyKeyDerivation::Argon2iMod),
+            "ARGON2I_INT" => Ok(IndyKeyDerivation::Argon2iInt),
+            "RAW" => Ok(IndyKeyDerivation::Raw),
+            _ => Err(ImportError::from_msg(
+                ImportErrorKind::InvalidKey,
+                format!("Unknown key derivation method {}", s),
+            )),
+        }
+    }
+}
+
+/// The contents of the `metadata` table
+#[derive(Deserialize)]
+struct Metadata {
+    keys: Vec<u8>,
+    master_key_salt: Option<Vec<u8>>,
+}
+
+/// A key in the msgpack encoded wallet keys. Depending on the
+/// Indy-SDK version keys are encoded as binary or as a list of bytes.
+pub(super) struct KeyBytes(pub(super) Zeroizing<Vec<u8>>);
+
+impl<'de> Deserialize<'de> for KeyBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = KeyBytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a key as bytes")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<KeyBytes, E> {
+                Ok(KeyBytes(Zeroizing::new(v.to_vec())))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<KeyBytes, A::Error> {
+                let mut key = Zeroizing::new(Vec::with_capacity(KEY_SIZE));
+                while let Some(b) = seq.next_element::<u8>()? {
+                    key.push(b);
+                }
+                Ok(KeyBytes(key))
+            }
+        }
+
+        deserializer.deserialize_any(KeyVisitor)
+    }
+}
+
+/// The wallet keys needed to decrypt items. The HMAC keys
+/// are only needed to search the wallet so they are dropped.
+struct WalletKeys {
+    type_key: Zeroizing<Vec<u8>>,
+    name_key: Zeroizing<Vec<u8>>,
+    value_key: Zeroizing<Vec<u8>>,
+    tag_name_key: Zeroizing<Vec<u8>>,
+    tag_value_key: Zeroizing<Vec<u8>>,
+}
+
+impl WalletKeys {
+    fn from_msgpack(data: &[u8]) -> ImportResult<Self> {
+        let keys: Vec<KeyBytes> = rmp_serde::from_slice(data)
+            .map_err(|e| invalid_data(format!("Unable to read the wallet keys: {}", e)))?;
+        if keys.len() != WALLET_KEYS || keys.iter().any(|k| k.0.len() != KEY_SIZE) {
+            return Err(invalid_data("The wallet keys are malformed"));
+        }
+        let mut keys = keys.into_iter().map(|k| k.0);
+        let mut next = || keys.next().unwrap();
+        let type_key = next();
+        let name_key = next();
+        let value_key = next();
+        let _item_hmac_key = next();
+        let tag_name_key = next();
+        let tag_value_key = next();
+        Ok(Self {
+            type_key,
+            name_key,
+            value_key,
+            tag_name_key,
+            tag_value_key,
+        })
+    }
+}
+
+/// An Indy-SDK wallet opened for reading
+pub struct IndyWallet {
+    conn: Connection,
+    keys: WalletKeys,
+}
+
+impl IndyWallet {
+    /// Open the wallet at `path` with its wallet `key`
+    pub fn open<A: AsRef<Path>>(
+        path: A,
+        key: &str,
+        method: IndyKeyDerivation,
+    ) -> ImportResult<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let metadata =
+            conn.query_row("SELECT value FROM metadata", NO_PARAMS, |row| blob(row, 0))?;
+        let metadata: Metadata = serde_json::from_slice(&metadata)
+            .map_err(|e| invalid_data(format!("Unable to read the wallet metadata: {}", e)))?;
+        let master = method.derive(key, metadata.master_key_salt.as_deref())?;
+        let keys = decrypt(&master, &metadata.keys).map_err(|_| {
+            ImportError::from_msg(
+                ImportErrorKind::InvalidKey,
+                "Unable to decrypt the wallet keys. The wallet key or method is wrong",
+            )
+        })?;
+        Ok(Self {
+            conn,
+            keys: WalletKeys::from_msgpack(&keys)?,
+        })
+    }
+
+    /// Decrypt each item in the wallet and pass it to `f` as a record
+    /// so only one item is held in memory at a time
+    pub fn for_each_record<F>(&self, mut f: F) -> ImportResult<()>
+    where
+        F: FnMut(Record) -> ImportResult<()>,
+    {
+        let mut items = self
+            .conn
+            .prepare("SELECT id, type, name, value, key FROM items ORDER BY id")?;
+        let mut encrypted_tags = self
+            .conn
+            .prepare("SELECT name, value FROM tags_encrypted WHERE item_id = ?1")?;
+        let mut plaintext_tags = self
+            .conn
+            .prepare("SELECT name, value FROM tags_plaintext WHERE item_id = ?1")?;
+        let mut rows = items.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let category = decrypt(&self.keys.type_key, &blob(row, 1)?)?;
+            let name = decrypt(&self.keys.name_key, &blob(row, 2)?)?;
+            let item_key = decrypt(&self.keys.value_key, &blob(row, 4)?)?;
+            let value = decrypt(&item_key, &blob(row, 3)?)?;
+
+            let mut tags = Vec::new();
+            let mut tag_rows = encrypted_tags.query(params![id])?;
+            while let Some(t) = tag_rows.next()? {
+                tags.push(RecordTag::Encrypted(
+                    decrypt(&self.keys.tag_name_key, &blob(t, 0)?)?.to_vec(),
+                    decrypt(&self.keys.tag_value_key, &blob(t, 1)?)?.to_vec(),
+                ));
+            }
+            let mut tag_rows = plaintext_tags.query(params![id])?;
+            while let Some(t) = tag_rows.next()? {
+                tags.push(RecordTag::Plaintext(
+                    decrypt(&self.keys.tag_name_key, &blob(t, 0)?)?.to_vec(),
+                    blob(t, 1)?,
+                ));
+            }
+
+            f(Record {
+                category: category.to_vec(),
+                name: name.to_vec(),
+                value: value.to_vec(),
+                tags,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Move every item into `store`. Private keys are saved in the store's
+    /// enclave named `key_prefix` followed by their verkey.
+    pub fn import<E: EnclaveLike, P: PersistenceLike>(
+        &self,
+        store: &ProtectedStore<E, P>,
+        key_prefix: &str,
+    ) -> ImportResult<ImportSummary> {
+        let mut summary = ImportSummary::default();
+        self.for_each_record(|mut record| {
+            if record.category == KEY_CATEGORY.as_bytes() {
+                record.value = import_key(store.enclave(), key_prefix, &record.value)?;
+                summary.keys += 1;
+            }
+            store.insert(record)?;
+            summary.records += 1;
+            Ok(())
+        })?;
+        Ok(summary)
+    }
+}
+
+/// Save the signing key in an `Indy::Key` value in `enclave`
+/// and return the value without it
+pub(super) fn import_key<E: EnclaveLike>(
+    enclave: &E,
+    key_prefix: &str,
+    value: &[u8],
+) -> ImportResult<Vec<u8>> {
+    let mut value: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(value)
+        .map_err(|e| invalid_data(format!("Unable to read a key: {}", e)))?;
+    let verkey = match value.get("verkey") {
+        Some(serde_json::Value::String(v)) => v.clone(),
+        _ => return Err(invalid_data("A key has no verkey")),
+    };
+    let signkey = match value.remove("signkey") {
+        Some(serde_json::Value::String(s)) => Zeroizing::new(s),
+        _ => return Err(invalid_data("A key has no signkey")),
+    };
+    // libsodium signing keys are the 32 byte seed followed by the public key
+    let secret = Zeroizing::new(
+        bs58::decode(signkey.as_str())
+            .into_vec()
+            .map_err(|_| invalid_data("A signkey is not base58"))?,
+    );
+    if secret.len() != 2 * KEY_SIZE {
+        return Err(invalid_data("A signkey is not an Ed25519 key"));
+    }
+    SigningKey::import(
+        enclave,
+        format!("{}{}", key_prefix, verkey),
+        SigningAlgorithm::Ed25519,
+        KeyExport::WhenWrapped,
+        &secret[..KEY_SIZE],
+    )?;
+    serde_json::to_vec(&value).map_err(|e| invalid_data(e.to_string()))
+}
+
+pub(super) fn decrypt(key: &[u8], data: &[u8]) -> ImportResult<Zeroizing<Vec<u8>>> {
+    if key.len() != KEY_SIZE || data.len() < NONCE_SIZE {
+        return Err(invalid_data("Ciphertext is too short"));
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+    ChaCha20Poly1305::new(<&Key>::from(key))
+        .decrypt(<&Nonce>::from(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| invalid_data("Unable to decrypt wallet data"))
+}
+
+/// Read a column that older Indy-SDK versions may have stored as text
+pub(super) fn blob(row: &Row<'_>, idx: usize) -> rusqlite::Result<Vec<u8>> {
+    match row.get(idx)? {
+        Value::Blob(b) => Ok(b),
+        Value::Text(t) => Ok(t.into_bytes()),
+        v => Err(rusqlite::Error::InvalidColumnType(
+            idx,
+            "value".to_string(),
+            v.data_type(),
+        )),
+    }
+}
+
+fn invalid_data<D: fmt::Display + fmt::Debug + Send + Sync + 'static>(msg: D) -> ImportError {
+    ImportError::from_msg(ImportErrorKind::InvalidData, msg)
+}
+
+#[cfg(all(test, feature = "software-enclave", feature = "storage-memory"))]
+pub(super) mod tests {
+    use super::*;
+    use crate::{
+        persistence::kv::memory::MemoryStore, protection::ProtectionKeys,
+        security::software::SoftwareEnclave,
+    };
+    use ed25519_dalek::{PublicKey, SecretKey, Signature, Verifier};
+    use rand::{rngs::OsRng, RngCore};
+    use std::{convert::TryFrom, path::PathBuf};
+
+    /// The Indy-SDK SQLite schema
+    const SCHEMA: &str = "
+        CREATE TABLE metadata (id INTEGER NOT NULL, value NOT NULL, PRIMARY KEY(id));
+        CREATE TABLE items(id INTEGER NOT NULL, type NOT NULL, name NOT NULL, value NOT NULL, key NOT NULL, PRIMARY KEY(id));
+        CREATE UNIQUE INDEX ux_items_type_name ON items(type, name);
+        CREATE TABLE tags_encrypted(name NOT NULL, value NOT NULL, item_id INTEGER NOT NULL, PRIMARY KEY(name, item_id), FOREIGN KEY(item_id) REFERENCES items(id) ON DELETE CASCADE ON UPDATE CASCADE);
+        CREATE INDEX ix_tags_encrypted_name_value ON tags_encrypted(name, value);
+        CREATE INDEX ix_tags_encrypted_item_id ON tags_encrypted(item_id);
+        CREATE TABLE tags_plaintext(name NOT NULL, value NOT NULL, item_id INTEGER NOT NULL, PRIMARY KEY(name, item_id), FOREIGN KEY(item_id) REFERENCES items(id) ON DELETE CASCADE ON UPDATE CASCADE);
+        CREATE INDEX ix_tags_plaintext_name_value ON tags_plaintext(name, value);
+        CREATE INDEX ix_tags_plaintext_item_id ON tags_plaintext(item_id);
+    ";
+
+    pub const SEED: [u8; KEY_SIZE] = [5; KEY_SIZE];
+
+    /// A wallet file removed when the test ends
+    struct WalletFile(PathBuf);
+
+    impl Drop for WalletFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    pub fn encrypt(key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        let mut data = nonce.to_vec();
+        data.extend(
+            ChaCha20Poly1305::new(<&Key>::from(key))
+                .encrypt(<&Nonce>::from(&nonce[..]), plaintext)
+                .unwrap(),
+        );
+        data
+    }
+
+    /// The type, name, value, ..., tag value keys in the order Indy-SDK keeps them
+    fn wallet_keys() -> Vec<Vec<u8>> {
+        (1..=WALLET_KEYS as u8).map(|i| vec![i; KEY_SIZE]).collect()
+    }
+
+    /// The verkey of `SEED`
+    pub fn verkey() -> String {
+        let public = PublicKey::from(&SecretKey::from_bytes(&SEED).unwrap());
+        bs58::encode(public.as_bytes()).into_string()
+    }
+
+    /// What the fixture wallets hold
+    pub fn records() -> Vec<Record> {
+        let public = bs58::decode(verkey()).into_vec().unwrap();
+        let mut signkey = SEED.to_vec();
+        signkey.extend(public);
+        vec![
+            Record {
+                category: DID_CATEGORY.as_bytes().to_vec(),
+                name: b"did".to_vec(),
+                value: format!(r#"{{"did":"did","verkey":"{}"}}"#, verkey()).into_bytes(),
+                tags: Vec::new(),
+            },
+            Record {
+                category: KEY_CATEGORY.as_bytes().to_vec(),
+                name: verkey().into_bytes(),
+                value: format!(
+                    r#"{{"verkey":"{}","signkey":"{}"}}"#,
+                    verkey(),
+                    bs58::encode(signkey).into_string()
+                )
+                .into_bytes(),
+                tags: Vec::new(),
+            },
+            Record {
+                category: b"connections".to_vec(),
+                name: b"alice".to_vec(),
+                value: b"{}".to_vec(),
+                tags: vec![
+                    RecordTag::Encrypted(b"state".to_vec(), b"active".to_vec()),
+                    RecordTag::Encrypted(b"role".to_vec(), b"holder".to_vec()),
+                    RecordTag::Plaintext(b"~issued".to_vec(), b"2020-01".to_vec()),
+                ],
+            },
+        ]
+    }
+
+    /// Write a wallet laid out like Indy-SDK writes them. `keys` are the
+    /// msgpack encoded `wallet_keys`.
+    fn write_wallet(name: &str, master: &[u8], salt: Option<&[u8]>, keys: &[u8]) -> WalletFile {
+        let path =
+            std::env::temp_dir().join(format!("arieskms-indy-{}-{}.db", name, std::process::id()));
+        let file = WalletFile(path);
+        let _ = std::fs::remove_file(&file.0);
+        let conn = Connection::open(&file.0).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let mut metadata = serde_json::json!({ "keys": encrypt(master, keys) });
+        if let Some(salt) = salt {
+            metadata["master_key_salt"] = salt.to_vec().into();
+        }
+        conn.execute(
+            "INSERT INTO metadata (value) VALUES (?1)",
+            params![serde_json::to_vec(&metadata).unwrap()],
+        )
+        .unwrap();
+
+        let keys = wallet_keys();
+        for record in records() {
+            let item_key = [9u8; KEY_SIZE];
+            conn.execute(
+                "INSERT INTO items (type, name, value, key) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    encrypt(&keys[0], &record.category),
+                    encrypt(&keys[1], &record.name),
+                    encrypt(&item_key, &record.value),
+                    encrypt(&keys[2], &item_key)
+                ],
+            )
+            .unwrap();
+            let id = conn.last_insert_rowid();
+            for tag in &record.tags {
+                match tag {
+                    RecordTag::Encrypted(n, v) => conn.execute(
+                        "INSERT INTO tags_encrypted (name, value, item_id) VALUES (?1, ?2, ?3)",
+                        params![encrypt(&keys[4], n), encrypt(&keys[5], v), id],
+                    ),
+                    // Plaintext tag values are kept as text
+                    RecordTag::Plaintext(n, v) => conn.execute(
+                        "INSERT INTO tags_plaintext (name, value, item_id) VALUES (?1, ?2, ?3)",
+                        params![
+                            encrypt(&keys[4], n),
+                            String::from_utf8(v.clone()).unwrap(),
+                            id
+                        ],
+                    ),
+                }
+                .unwrap();
+            }
+        }
+        file
+    }
+
+    /// Import `wallet` and check the records and key it held
+    fn check_import(wallet: &IndyWallet) {
+        let enclave = SoftwareEnclave::new();
+        let keys = ProtectionKeys::generate(&enclave, "test").unwrap();
+        let store = ProtectedStore::new(enclave, MemoryStore::new(), keys);
+        assert_eq!(
+            wallet.import(&store, "indy-").unwrap(),
+            ImportSummary {
+                records: 3,
+                keys: 1,
+                skipped_keys: Vec::new(),
+            }
+        );
+
+        for record in records() {
+            let imported = store.fetch(&record.category, &record.name).unwrap();
+            if record.category == KEY_CATEGORY.as_bytes() {
+                assert_eq!(
+                    imported.value,
+                    format!(r#"{{"verkey":"{}"}}"#, verkey()).into_bytes()
+                );
+            } else {
+                assert_eq!(imported, record);
+            }
+        }
+
+        let id = format!("indy-{}", verkey());
+        let public = store.enclave().public_key(&id).unwrap();
+        assert_eq!(bs58::encode(&public).into_string(), verkey());
+        let signature = store.enclave().sign(&id, b"message").unwrap();
+        PublicKey::from_bytes(&public)
+            .unwrap()
+            .verify(b"message", &Signature::try_from(&signature[..]).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn imports_argon2i_wallets() {
+        let salt = [7u8; 16];
+        let master = IndyKeyDerivation::Argon2iMod
+            .derive("wallet key", Some(&salt))
+            .unwrap();
+        // Older Indy-SDK versions encode the keys as lists of bytes
+        let keys = rmp_serde::to_vec(&wallet_keys()).unwrap();
+        let file = write_wallet("argon2i", &master, Some(&salt), &keys);
+
+        for (key, method) in &[
+            ("wrong key", IndyKeyDerivation::Argon2iMod),
+            ("wallet key", IndyKeyDerivation::Argon2iInt),
+        ] {
+            match IndyWallet::open(&file.0, key, *method) {
+                Err(e) => assert_eq!(e.kind(), ImportErrorKind::InvalidKey),
+                Ok(_) => panic!("Opened with {} and {:?}", key, method),
+            }
+        }
+        let wallet =
+            IndyWallet::open(&file.0, "wallet key", IndyKeyDerivation::Argon2iMod).unwrap();
+        check_import(&wallet);
+    }
+
+    #[test]
+    fn imports_raw_wallets() {
+        let master = [3u8; KEY_SIZE];
+        // Newer Indy-SDK versions encode the keys as binary
+        let mut keys = vec![0x90 | WALLET_KEYS as u8];
+        for key in wallet_keys() {
+            keys.extend(&[0xc4, KEY_SIZE as u8]);
+            keys.extend(key);
+        }
+        let file = write_wallet("raw", &master, None, &keys);
+
+        let wrong = bs58::encode([4u8; KEY_SIZE]).into_string();
+        match IndyWallet::open(&file.0, &wrong, IndyKeyDerivation::Raw) {
+            Err(e) => assert_eq!(e.kind(), ImportErrorKind::InvalidKey),
+            Ok(_) => panic!("Opened with the wrong key"),
+        }
+        let key = bs58::encode(master).into_string();
+        check_import(&IndyWallet::open(&file.0, &key, IndyKeyDerivation::Raw).unwrap());
+    }
+}
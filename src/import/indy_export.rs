@@ -0,0 +1,891 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Read and write Indy-SDK wallet export files.
+//!
+//! These are the files created by `indy_export_wallet` and read by
+//! `indy_import_wallet`, so backups can move between this crate and any
+//! other Aries or Indy tooling.
+//!
+//! A file starts with the length of the header as a little endian u32 and
+//! the msgpack encoded header. The header names the key derivation method
+//! and holds the salt, the first nonce and the chunk size. Everything after
+//! it is encrypted with ChaCha20-Poly1305 in chunks of that size, where the
+//! nonce is incremented after every chunk. The encrypted stream starts with
+//! the SHA-256 hash of the header so it can't be changed, followed by each
+//! record as a little endian u32 length and a msgpack encoded record. A zero
+//! length marks the end.
+//!
+//! Records in the file are plaintext inside the encryption, including the
+//! private keys in `Indy::Key` records. Exporting asks the enclave for each
+//! of those keys wrapped under a random key, which only works for keys that
+//! are `EXPORTABLE_WHEN_WRAPPED`, and unwraps it in memory just long enough
+//! to encrypt it into the file. Importing saves them in the enclave again.
+
+use super::{
+    errors::{ImportError, ImportErrorKind},
+    indy::{import_key, IndyKeyDerivation, KEY_CATEGORY},
+    ImportResult, ImportSummary,
+};
+use crate::{
+    persistence::{wql::Query, PersistenceLike, Record, RecordTag},
+    protection::ProtectedStore,
+    security::EnclaveLike,
+};
+
+use chacha20poly1305::{
+    aead::{self, Aead, NewAead, Payload},
+    ChaCha20Poly1305, Key, Nonce, XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    io::{Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroizing;
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const SALT_SIZE: usize = 16;
+const TAG_SIZE: usize = 16;
+const HASH_SIZE: usize = 32;
+const WRAPPING_NONCE_SIZE: usize = 24;
+/// The chunk size Indy-SDK uses
+const CHUNK_SIZE: usize = 1024;
+/// The largest chunk accepted when reading so a bad header can't exhaust memory
+const MAX_CHUNK_SIZE: usize = 1 << 20;
+/// The largest record accepted when reading
+const MAX_RECORD_SIZE: usize = 1 << 28;
+const HEADER_VERSION: u64 = 0;
+
+/// Encryption methods in the order of the Indy-SDK `EncryptionMethod` enum
+const METHOD_ARGON2I_MOD: u64 = 0;
+const METHOD_ARGON2I_INT: u64 = 1;
+const METHOD_RAW: u64 = 2;
+
+/// The page size used to read records from the store while exporting
+const EXPORT_PAGE_SIZE: usize = 100;
+
+/// Encrypts a stream in chunks
+struct ChunkWriter<W: Write> {
+    writer: W,
+    cipher: ChaCha20Poly1305,
+    nonce: [u8; NONCE_SIZE],
+    buffer: Zeroizing<Vec<u8>>,
+    chunk_size: usize,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    fn write_all(&mut self, mut data: &[u8]) -> ImportResult<()> {
+        while !data.is_empty() {
+            let n = (self.chunk_size - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buffer.len() == self.chunk_size {
+                self.flush_chunk()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> ImportResult<()> {
+        let chunk = self
+            .cipher
+            .encrypt(<&Nonce>::from(&self.nonce[..]), self.buffer.as_slice())
+            .map_err(|_| invalid_data("Unable to encrypt the export"))?;
+        self.writer.write_all(&chunk).map_err(io_error)?;
+        self.buffer.clear();
+        increment(&mut self.nonce);
+        Ok(())
+    }
+
+    fn finish(mut self) -> ImportResult<W> {
+        if !self.buffer.is_empty() {
+            self.flush_chunk()?;
+        }
+        self.writer.flush().map_err(io_error)?;
+        Ok(self.writer)
+    }
+}
+
+/// Decrypts a stream written in chunks
+struct ChunkReader<R: Read> {
+    reader: R,
+    cipher: ChaCha20Poly1305,
+    nonce: [u8; NONCE_SIZE],
+    buffer: Zeroizing<Vec<u8>>,
+    position: usize,
+    chunk_size: usize,
+}
+
+impl<R: Read> ChunkReader<R> {
+    fn read_exact(&mut self, mut out: &mut [u8]) -> ImportResult<()> {
+        while !out.is_empty() {
+            if self.position == self.buffer.len() {
+                self.read_chunk()?;
+            }
+            let n = (self.buffer.len() - self.position).min(out.len());
+            out[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+            self.position += n;
+            out = &mut out[n..];
+        }
+        Ok(())
+    }
+
+    fn read_chunk(&mut self) -> ImportResult<()> {
+        let chunk = self.read_ciphertext()?;
+        self.decrypt_chunk(&chunk)
+            .map_err(|_| invalid_data("Unable to decrypt the export"))
+    }
+
+    /// Read the next encrypted chunk, which is shorter at the end of the stream
+    fn read_ciphertext(&mut self) -> ImportResult<Vec<u8>> {
+        let mut chunk = vec![0u8; self.chunk_size + TAG_SIZE];
+        let mut len = 0;
+        while len < chunk.len() {
+            match self.reader.read(&mut chunk[len..]).map_err(io_error)? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        if len <= TAG_SIZE {
+            return Err(invalid_data("The export ended unexpectedly"));
+        }
+        chunk.truncate(len);
+        Ok(chunk)
+    }
+
+    fn decrypt_chunk(&mut self, chunk: &[u8]) -> Result<(), aead::Error> {
+        self.buffer = Zeroizing::new(
+            self.cipher
+                .decrypt(<&Nonce>::from(&self.nonce[..]), chunk)?,
+        );
+        self.position = 0;
+        increment(&mut self.nonce);
+        Ok(())
+    }
+
+    fn read_u32(&mut self) -> ImportResult<u32> {
+        let mut bytes = [0u8; 4];
+        self.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+/// Writes records to an Indy-SDK export file
+pub struct IndyExportWriter<W: Write> {
+    writer: ChunkWriter<W>,
+}
+
+impl<W: Write> IndyExportWriter<W> {
+    /// Start an export file in `writer` encrypted with the export `key`
+    pub fn new(mut writer: W, key: &str, method: IndyKeyDerivation) -> ImportResult<Self> {
+        let mut salt = [0u8; SALT_SIZE];
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let master = method.derive(key, Some(&salt))?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        // Header { encryption_method: EncryptionMethod, time: u64, version: u32 }
+        // with the enum encoded as [variant index, [fields]]
+        let mut header = Vec::new();
+        write_array_len(&mut header, 3);
+        write_array_len(&mut header, 2);
+        match method {
+            IndyKeyDerivation::Raw => {
+                write_uint(&mut header, METHOD_RAW);
+                write_array_len(&mut header, 2);
+            }
+            IndyKeyDerivation::Argon2iMod | IndyKeyDerivation::Argon2iInt => {
+                let index = if method == IndyKeyDerivation::Argon2iMod {
+                    METHOD_ARGON2I_MOD
+                } else {
+                    METHOD_ARGON2I_INT
+                };
+                write_uint(&mut header, index);
+                write_array_len(&mut header, 3);
+                write_bytes(&mut header, &salt);
+            }
+        }
+        write_bytes(&mut header, &nonce);
+        write_uint(&mut header, CHUNK_SIZE as u64);
+        write_uint(&mut header, time);
+        write_uint(&mut header, HEADER_VERSION);
+
+        writer
+            .write_all(&(header.len() as u32).to_le_bytes())
+            .map_err(io_error)?;
+        writer.write_all(&header).map_err(io_error)?;
+        let mut writer = ChunkWriter {
+            writer,
+            cipher: ChaCha20Poly1305::new(<&Key>::from(master.as_slice())),
+            nonce,
+            buffer: Zeroizing::new(Vec::with_capacity(CHUNK_SIZE)),
+            chunk_size: CHUNK_SIZE,
+        };
+        writer.write_all(&Sha256::digest(&header))?;
+        Ok(Self { writer })
+    }
+
+    /// Append `record`. Indy-SDK only supports UTF-8 categories, names,
+    /// values and tags so other records can't be exported.
+    pub fn write_record(&mut self, record: &Record) -> ImportResult<()> {
+        let data = encode_record(record)?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&data)
+    }
+
+    /// Mark the end of the records and return the underlying writer
+    pub fn finish(mut self) -> ImportResult<W> {
+        self.writer.write_all(&0u32.to_le_bytes())?;
+        self.writer.finish()
+    }
+}
+
+/// Reads records from an Indy-SDK export file
+pub struct IndyExportReader<R: Read> {
+    reader: ChunkReader<R>,
+    done: bool,
+}
+
+impl<R: Read> IndyExportReader<R> {
+    /// Open the export file in `reader` with the export `key`
+    pub fn new(mut reader: R, key: &str) -> ImportResult<Self> {
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len).map_err(io_error)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_CHUNK_SIZE {
+            return Err(invalid_data("The export header is too large"));
+        }
+        let mut header = vec![0u8; len];
+        reader.read_exact(&mut header).map_err(io_error)?;
+
+        let mut rd = header.as_slice();
+        if read_array_len(&mut rd)? != 3 || read_array_len(&mut rd)? != 2 {
+            return Err(invalid_data("The export header is malformed"));
+        }
+        let (method, salt) = match (read_uint(&mut rd)?, read_array_len(&mut rd)?) {
+            (METHOD_ARGON2I_MOD, 3) => (IndyKeyDerivation::Argon2iMod, Some(read_bytes(&mut rd)?)),
+            (METHOD_ARGON2I_INT, 3) => (IndyKeyDerivation::Argon2iInt, Some(read_bytes(&mut rd)?)),
+            (METHOD_RAW, 2) => (IndyKeyDerivation::Raw, None),
+            _ => return Err(invalid_data("Unknown export encryption method")),
+        };
+        let nonce = read_bytes(&mut rd)?;
+        let chunk_size = read_uint(&mut rd)? as usize;
+        if nonce.len() != NONCE_SIZE || chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(invalid_data("The export header is malformed"));
+        }
+        let _time = read_uint(&mut rd)?;
+        if read_uint(&mut rd)? != HEADER_VERSION {
+            return Err(invalid_data("Unsupported export version"));
+        }
+
+        let master = method.derive(key, salt.as_deref())?;
+        let mut start = [0u8; NONCE_SIZE];
+        start.copy_from_slice(&nonce);
+        let mut reader = ChunkReader {
+            reader,
+            cipher: ChaCha20Poly1305::new(<&Key>::from(master.as_slice())),
+            nonce: start,
+            buffer: Zeroizing::new(Vec::new()),
+            position: 0,
+            chunk_size,
+        };
+        // The first chunk holds at least the hash and the end marker, so a
+        // whole chunk that fails to decrypt means the export key is wrong
+        let chunk = reader.read_ciphertext()?;
+        if chunk.len() < TAG_SIZE + chunk_size.min(HASH_SIZE + 4) {
+            return Err(invalid_data("The export ended unexpectedly"));
+        }
+        reader.decrypt_chunk(&chunk).map_err(|_| {
+            ImportError::from_msg(
+                ImportErrorKind::InvalidKey,
+                "Unable to decrypt the export. The export key is wrong",
+            )
+        })?;
+        let mut hash = [0u8; HASH_SIZE];
+        reader.read_exact(&mut hash)?;
+        if hash[..] != Sha256::digest(&header)[..] {
+            return Err(invalid_data("The export header was modified"));
+        }
+        Ok(Self {
+            reader,
+            done: false,
+        })
+    }
+
+    /// Read the next record or `None` after the last one
+    pub fn read_record(&mut self) -> ImportResult<Option<Record>> {
+        if self.done {
+            return Ok(None);
+        }
+        let len = self.reader.read_u32()? as usize;
+        if len == 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        if len > MAX_RECORD_SIZE {
+            return Err(invalid_data("A record in the export is too large"));
+        }
+        let mut data = Zeroizing::new(vec![0u8; len]);
+        self.reader.read_exact(&mut data)?;
+
+        let mut rd = data.as_slice();
+        if read_array_len(&mut rd)? != 4 {
+            return Err(invalid_data("A record in the export is malformed"));
+        }
+        let category = read_str(&mut rd)?;
+        let name = read_str(&mut rd)?;
+        let value = read_str(&mut rd)?;
+        let count = read_map_len(&mut rd)?;
+        let mut tags = Vec::with_capacity(count.min(rd.len()));
+        for _ in 0..count {
+            let mut tag_name = read_str(&mut rd)?;
+            let tag_value = read_str(&mut rd)?;
+            tags.push(if tag_name.first() == Some(&b'~') {
+                tag_name.remove(0);
+                RecordTag::Plaintext(tag_name, tag_value)
+            } else {
+                RecordTag::Encrypted(tag_name, tag_value)
+            });
+        }
+        Ok(Some(Record {
+            category,
+            name,
+            value,
+            tags,
+        }))
+    }
+}
+
+/// Write every record in `store` to an export file in `writer` encrypted
+/// with the export `key`. Private keys are read from the store's enclave
+/// where they are named `key_prefix` followed by their verkey.
+pub fn export<E: EnclaveLike, P: PersistenceLike, W: Write>(
+    store: &ProtectedStore<E, P>,
+    key_prefix: &str,
+    writer: W,
+    key: &str,
+    method: IndyKeyDerivation,
+) -> ImportResult<(W, ImportSummary)> {
+    let mut export = IndyExportWriter::new(writer, key, method)?;
+    let mut summary = ImportSummary::default();
+    let everything = Query::And(Vec::new());
+    for category in store.categories()? {
+        for record in store.scan(&category, &everything, EXPORT_PAGE_SIZE)? {
+            let record = record?;
+            if record.category == KEY_CATEGORY.as_bytes() {
+                let value = export_key(store.enclave(), key_prefix, &record.value)?;
+                export.write_record(&Record {
+                    value: value.to_vec(),
+                    ..record
+                })?;
+                summary.keys += 1;
+            } else {
+                export.write_record(&record)?;
+            }
+            summary.records += 1;
+        }
+    }
+    Ok((export.finish()?, summary))
+}
+
+/// Save every record in the export file in `reader` to `store`. Private
+/// keys are saved in the store's enclave named `key_prefix` followed by
+/// their verkey.
+pub fn import<E: EnclaveLike, P: PersistenceLike, R: Read>(
+    store: &ProtectedStore<E, P>,
+    key_prefix: &str,
+    reader: R,
+    key: &str,
+) -> ImportResult<ImportSummary> {
+    let mut export = IndyExportReader::new(reader, key)?;
+    let mut summary = ImportSummary::default();
+    while let Some(mut record) = export.read_record()? {
+        if record.category == KEY_CATEGORY.as_bytes() {
+            let value = Zeroizing::new(record.value);
+            record.value = import_key(store.enclave(), key_prefix, &value)?;
+            summary.keys += 1;
+        }
+        store.insert(record)?;
+        summary.records += 1;
+    }
+    Ok(summary)
+}
+
+/// Encode `record` like Indy-SDK's
+/// `Record { type_: String, id: String, value: String, tags: HashMap<String, String> }`
+/// where plaintext tag names start with `~`
+fn encode_record(record: &Record) -> ImportResult<Zeroizing<Vec<u8>>> {
+    let mut data = Zeroizing::new(Vec::new());
+    write_array_len(&mut data, 4);
+    write_str(&mut data, utf8(&record.category)?);
+    write_str(&mut data, utf8(&record.name)?);
+    write_str(&mut data, utf8(&record.value)?);
+    write_map_len(&mut data, record.tags.len());
+    for tag in &record.tags {
+        match tag {
+            RecordTag::Encrypted(n, _) => write_str(&mut data, utf8(n)?),
+            RecordTag::Plaintext(n, _) => write_str(&mut data, &format!("~{}", utf8(n)?)),
+        }
+        write_str(&mut data, utf8(tag.value())?);
+    }
+    Ok(data)
+}
+
+/// Add the signing key held by `enclave` to an `Indy::Key` value
+fn export_key<E: EnclaveLike>(
+    enclave: &E,
+    key_prefix: &str,
+    value: &[u8],
+) -> ImportResult<Zeroizing<Vec<u8>>> {
+    let mut value: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(value)
+        .map_err(|e| invalid_data(format!("Unable to read a key: {}", e)))?;
+    let verkey = match value.get("verkey") {
+        Some(serde_json::Value::String(v)) => v.clone(),
+        _ => return Err(invalid_data("A key has no verkey")),
+    };
+    let id = format!("{}{}", key_prefix, verkey);
+
+    let mut wrapping_key = Zeroizing::new([0u8; KEY_SIZE]);
+    OsRng.fill_bytes(&mut wrapping_key[..]);
+    let wrapped = enclave.export_wrapped_key(&id, &wrapping_key[..])?;
+    if wrapped.len() < WRAPPING_NONCE_SIZE {
+        return Err(invalid_data("The enclave returned a malformed wrapped key"));
+    }
+    let (nonce, ciphertext) = wrapped.split_at(WRAPPING_NONCE_SIZE);
+    let seed = Zeroizing::new(
+        XChaCha20Poly1305::new(<&Key>::from(&wrapping_key[..]))
+            .decrypt(
+                <&XNonce>::from(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: id.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid_data("Unable to unwrap the exported key"))?,
+    );
+    let public = bs58::decode(&verkey)
+        .into_vec()
+        .map_err(|_| invalid_data("A verkey is not base58"))?;
+    if seed.len() != KEY_SIZE || public.len() != KEY_SIZE {
+        return Err(invalid_data("A key is not an Ed25519 key"));
+    }
+    // libsodium signing keys are the 32 byte seed followed by the public key
+    let mut secret = Zeroizing::new(Vec::with_capacity(2 * KEY_SIZE));
+    secret.extend_from_slice(&seed);
+    secret.extend_from_slice(&public);
+    value.insert(
+        "signkey".to_string(),
+        serde_json::Value::String(bs58::encode(secret.as_slice()).into_string()),
+    );
+    let encoded = serde_json::to_vec(&value).map_err(|e| invalid_data(e.to_string()));
+    if let Some(serde_json::Value::String(signkey)) = value.remove("signkey") {
+        drop(Zeroizing::new(signkey));
+    }
+    Ok(Zeroizing::new(encoded?))
+}
+
+// The subset of msgpack used by Indy-SDK export files
+
+fn write_array_len(wr: &mut Vec<u8>, len: usize) {
+    write_len(wr, len, 0x90, 0xdc);
+}
+
+fn write_map_len(wr: &mut Vec<u8>, len: usize) {
+    write_len(wr, len, 0x80, 0xde);
+}
+
+fn write_len(wr: &mut Vec<u8>, len: usize, fixed: u8, marker16: u8) {
+    if len < 16 {
+        wr.push(fixed | len as u8);
+    } else if len <= 0xffff {
+        wr.push(marker16);
+        wr.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        wr.push(marker16 + 1);
+        wr.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+fn write_uint(wr: &mut Vec<u8>, n: u64) {
+    if n < 0x80 {
+        wr.push(n as u8);
+    } else if n <= 0xff {
+        wr.extend_from_slice(&[0xcc, n as u8]);
+    } else if n <= 0xffff {
+        wr.push(0xcd);
+        wr.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= 0xffff_ffff {
+        wr.push(0xce);
+        wr.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        wr.push(0xcf);
+        wr.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn write_str(wr: &mut Vec<u8>, s: &str) {
+    let len = s.len();
+    if len < 32 {
+        wr.push(0xa0 | len as u8);
+    } else if len <= 0xff {
+        wr.extend_from_slice(&[0xd9, len as u8]);
+    } else if len <= 0xffff {
+        wr.push(0xda);
+        wr.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        wr.push(0xdb);
+        wr.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    wr.extend_from_slice(s.as_bytes());
+}
+
+/// Indy-SDK encodes byte vectors as a list of integers
+fn write_bytes(wr: &mut Vec<u8>, bytes: &[u8]) {
+    write_array_len(wr, bytes.len());
+    for b in bytes {
+        write_uint(wr, u64::from(*b));
+    }
+}
+
+fn take<'a>(rd: &mut &'a [u8], n: usize) -> ImportResult<&'a [u8]> {
+    if rd.len() < n {
+        return Err(invalid_data("Unexpected end of msgpack data"));
+    }
+    let (head, tail) = rd.split_at(n);
+    *rd = tail;
+    Ok(head)
+}
+
+fn read_be(rd: &mut &[u8], n: usize) -> ImportResult<u64> {
+    Ok(take(rd, n)?
+        .iter()
+        .fold(0u64, |acc, b| (acc << 8) | u64::from(*b)))
+}
+
+fn read_array_len(rd: &mut &[u8]) -> ImportResult<usize> {
+    match take(rd, 1)?[0] {
+        m @ 0x90..=0x9f => Ok((m & 0x0f) as usize),
+        0xdc => Ok(read_be(rd, 2)? as usize),
+        0xdd => Ok(read_be(rd, 4)? as usize),
+        _ => Err(invalid_data("Expected a msgpack array")),
+    }
+}
+
+fn read_map_len(rd: &mut &[u8]) -> ImportResult<usize> {
+    match take(rd, 1)?[0] {
+        m @ 0x80..=0x8f => Ok((m & 0x0f) as usize),
+        0xde => Ok(read_be(rd, 2)? as usize),
+        0xdf => Ok(read_be(rd, 4)? as usize),
+        _ => Err(invalid_data("Expected a msgpack map")),
+    }
+}
+
+fn read_uint(rd: &mut &[u8]) -> ImportResult<u64> {
+    match take(rd, 1)?[0] {
+        m @ 0x00..=0x7f => Ok(u64::from(m)),
+        0xcc => read_be(rd, 1),
+        0xcd => read_be(rd, 2),
+        0xce => read_be(rd, 4),
+        0xcf => read_be(rd, 8),
+        _ => Err(invalid_data("Expected a msgpack unsigned integer")),
+    }
+}
+
+/// Read a string or binary value
+fn read_str(rd: &mut &[u8]) -> ImportResult<Vec<u8>> {
+    let len = match take(rd, 1)?[0] {
+        m @ 0xa0..=0xbf => (m & 0x1f) as usize,
+        0xc4 | 0xd9 => read_be(rd, 1)? as usize,
+        0xc5 | 0xda => read_be(rd, 2)? as usize,
+        0xc6 | 0xdb => read_be(rd, 4)? as usize,
+        _ => return Err(invalid_data("Expected a msgpack string")),
+    };
+    Ok(take(rd, len)?.to_vec())
+}
+
+/// Read a byte vector written as binary or as a list of integers
+fn read_bytes(rd: &mut &[u8]) -> ImportResult<Vec<u8>> {
+    if let Some(0xc4..=0xc6) = rd.first() {
+        return read_str(rd);
+    }
+    let len = read_array_len(rd)?;
+    let mut bytes = Vec::with_capacity(len.min(rd.len()));
+    for _ in 0..len {
+        match read_uint(rd)? {
+            b if b <= 0xff => bytes.push(b as u8),
+            _ => return Err(invalid_data("Expected a byte")),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Increment a nonce as a little endian number like libsodium's `sodium_increment`
+fn increment(nonce: &mut [u8]) {
+    for b in nonce.iter_mut() {
+        *b = b.wrapping_add(1);
+        if *b != 0 {
+            break;
+        }
+    }
+}
+
+fn utf8(bytes: &[u8]) -> ImportResult<&str> {
+    std::str::from_utf8(bytes)
+        .map_err(|_| invalid_data("Indy-SDK exports only support UTF-8 records"))
+}
+
+fn io_error(e: std::io::Error) -> ImportError {
+    ImportError::from_msg(ImportErrorKind::IOError, e.to_string())
+}
+
+fn invalid_data<D: fmt::Display + fmt::Debug + Send + Sync + 'static>(msg: D) -> ImportError {
+    ImportError::from_msg(ImportErrorKind::InvalidData, msg)
+}
+
+#[cfg(all(test, feature = "software-enclave", feature = "storage-memory"))]
+mod tests {
+    use super::*;
+    use crate::{
+        import::indy::tests::{records, verkey},
+        persistence::kv::memory::MemoryStore,
+        protection::ProtectionKeys,
+        security::software::SoftwareEnclave,
+    };
+    use std::io::Cursor;
+
+    fn raw_key(b: u8) -> String {
+        bs58::encode([b; KEY_SIZE]).into_string()
+    }
+
+    fn record(name: &str, value: &str) -> Record {
+        Record {
+            category: b"notes".to_vec(),
+            name: name.as_bytes().to_vec(),
+            value: value.as_bytes().to_vec(),
+            tags: vec![
+                RecordTag::Plaintext(b"kind".to_vec(), b"note".to_vec()),
+                RecordTag::Encrypted(b"title".to_vec(), name.as_bytes().to_vec()),
+            ],
+        }
+    }
+
+    /// Records filling several chunks where the first ends exactly on the
+    /// end of the first chunk
+    fn chunked_records() -> Vec<Record> {
+        let overhead = encode_record(&record("first", &"x".repeat(300)))
+            .unwrap()
+            .len()
+            - 300;
+        let mut records = vec![record(
+            "first",
+            &"x".repeat(CHUNK_SIZE - HASH_SIZE - 4 - overhead),
+        )];
+        records.extend((0..5).map(|i| record(&format!("note {}", i), &"y".repeat(700))));
+        records
+    }
+
+    fn write_export(records: &[Record], key: &str, method: IndyKeyDerivation) -> Vec<u8> {
+        let mut export = IndyExportWriter::new(Vec::new(), key, method).unwrap();
+        for record in records {
+            export.write_record(record).unwrap();
+        }
+        export.finish().unwrap()
+    }
+
+    fn read_export(data: &[u8], key: &str) -> ImportResult<Vec<Record>> {
+        let mut export = IndyExportReader::new(data, key)?;
+        let mut records = Vec::new();
+        while let Some(record) = export.read_record()? {
+            records.push(record);
+        }
+        assert!(export.read_record()?.is_none());
+        Ok(records)
+    }
+
+    /// Sort `records` and the JSON fields in their values
+    fn normalize(mut records: Vec<Record>) -> Vec<Record> {
+        for record in &mut records {
+            let value: serde_json::Value = serde_json::from_slice(&record.value).unwrap();
+            record.value = serde_json::to_vec(&value).unwrap();
+        }
+        records.sort_by(|a, b| a.category.cmp(&b.category));
+        records
+    }
+
+    fn new_store() -> ProtectedStore<SoftwareEnclave, MemoryStore> {
+        let enclave = SoftwareEnclave::new();
+        let keys = ProtectionKeys::generate(&enclave, "test").unwrap();
+        ProtectedStore::new(enclave, MemoryStore::new(), keys)
+    }
+
+    /// An export laid out like Indy-SDK writes them, with a smaller chunk
+    /// size and a nonce that carries into its second byte
+    fn fixture(key: &[u8]) -> Vec<u8> {
+        let chunk_size = 64;
+        let nonce = [0xfe, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        // [[RAW, [nonce, chunk_size]], time, version] with the nonce as a list of bytes
+        let mut header = vec![0x93, 0x92, 0x02, 0x92, 0x9c];
+        for b in &nonce {
+            if *b < 0x80 {
+                header.push(*b);
+            } else {
+                header.extend(&[0xcc, *b]);
+            }
+        }
+        header.extend(&[0x40, 0xce, 0x5f, 0x5e, 0x10, 0x00, 0x00]);
+
+        let mut stream = Sha256::digest(&header).to_vec();
+        for record in records() {
+            let mut data = vec![0x94];
+            for field in &[&record.category, &record.name, &record.value] {
+                data.extend(&[0xd9, field.len() as u8]);
+                data.extend(field.iter());
+            }
+            data.push(0x80 | record.tags.len() as u8);
+            for tag in &record.tags {
+                let name = match tag {
+                    RecordTag::Encrypted(n, _) => n.clone(),
+                    RecordTag::Plaintext(n, _) => [b"~", &n[..]].concat(),
+                };
+                for field in &[&name, tag.value()] {
+                    data.push(0xa0 | field.len() as u8);
+                    data.extend(field.iter());
+                }
+            }
+            stream.extend(&(data.len() as u32).to_le_bytes());
+            stream.extend(data);
+        }
+        stream.extend(&[0, 0, 0, 0]);
+
+        let mut file = (header.len() as u32).to_le_bytes().to_vec();
+        file.extend(&header);
+        let cipher = ChaCha20Poly1305::new(<&Key>::from(key));
+        let start = u64::from_le_bytes([0xfe, 0xff, 0, 0, 0, 0, 0, 0]);
+        for (i, chunk) in stream.chunks(chunk_size).enumerate() {
+            let mut nonce = (start + i as u64).to_le_bytes().to_vec();
+            nonce.extend(&[0, 0, 0, 0]);
+            file.extend(cipher.encrypt(<&Nonce>::from(&nonce[..]), chunk).unwrap());
+        }
+        file
+    }
+
+    #[test]
+    fn round_trip_over_chunks() {
+        let records = chunked_records();
+        let mut export =
+            IndyExportWriter::new(Vec::new(), &raw_key(1), IndyKeyDerivation::Raw).unwrap();
+        export.write_record(&records[0]).unwrap();
+        assert!(export.writer.buffer.is_empty());
+        for record in &records[1..] {
+            export.write_record(record).unwrap();
+        }
+        let data = export.finish().unwrap();
+        assert_eq!(read_export(&data, &raw_key(1)).unwrap(), records);
+
+        let data = write_export(&records, "export key", IndyKeyDerivation::Argon2iInt);
+        assert_eq!(read_export(&data, "export key").unwrap(), records);
+    }
+
+    #[test]
+    fn wrong_keys_are_rejected() {
+        let data = write_export(&chunked_records(), &raw_key(1), IndyKeyDerivation::Raw);
+        match read_export(&data, &raw_key(2)) {
+            Err(e) => assert_eq!(e.kind(), ImportErrorKind::InvalidKey),
+            Ok(_) => panic!("Read with the wrong key"),
+        }
+    }
+
+    #[test]
+    fn modified_headers_are_rejected() {
+        let mut data = write_export(&chunked_records(), &raw_key(1), IndyKeyDerivation::Raw);
+        let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        // The last byte of the time
+        data[4 + len - 2] ^= 1;
+        match read_export(&data, &raw_key(1)) {
+            Err(e) => {
+                assert_eq!(e.kind(), ImportErrorKind::InvalidData);
+                assert!(e.to_string().contains("modified"), "{}", e);
+            }
+            Ok(_) => panic!("Read a modified header"),
+        }
+    }
+
+    #[test]
+    fn truncated_exports_are_rejected() {
+        let data = write_export(&chunked_records(), &raw_key(1), IndyKeyDerivation::Raw);
+        let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let start = 4 + len;
+        let chunk = CHUNK_SIZE + TAG_SIZE;
+        for (end, kind) in &[
+            (2, ImportErrorKind::IOError),
+            (start - 1, ImportErrorKind::IOError),
+            (start, ImportErrorKind::InvalidData),
+            (start + TAG_SIZE + HASH_SIZE, ImportErrorKind::InvalidData),
+            (start + chunk, ImportErrorKind::InvalidData),
+            (start + chunk + 100, ImportErrorKind::InvalidData),
+            (data.len() - 1, ImportErrorKind::InvalidData),
+        ] {
+            match read_export(&data[..*end], &raw_key(1)) {
+                Err(e) => assert_eq!(e.kind(), *kind, "Truncated at {}", end),
+                Ok(_) => panic!("Read an export truncated at {}", end),
+            }
+        }
+    }
+
+    #[test]
+    fn imports_indy_exports() {
+        let data = fixture(&[9; KEY_SIZE]);
+        assert_eq!(read_export(&data, &raw_key(9)).unwrap(), records());
+
+        let store = new_store();
+        let summary = import(&store, "indy-", Cursor::new(&data), &raw_key(9)).unwrap();
+        assert_eq!((summary.records, summary.keys), (3, 1));
+        let id = format!("indy-{}", verkey());
+        let public = store.enclave().public_key(&id).unwrap();
+        assert_eq!(bs58::encode(&public).into_string(), verkey());
+
+        // Exporting puts the signing key back in the key record
+        let (data, summary) = export(
+            &store,
+            "indy-",
+            Vec::new(),
+            "export key",
+            IndyKeyDerivation::Argon2iInt,
+        )
+        .unwrap();
+        assert_eq!((summary.records, summary.keys), (3, 1));
+        assert_eq!(
+            normalize(read_export(&data, "export key").unwrap()),
+            normalize(records())
+        );
+
+        let other = new_store();
+        import(&other, "indy-", Cursor::new(&data), "export key").unwrap();
+        assert_eq!(other.enclave().public_key(&id).unwrap(), public);
+        assert_eq!(
+            other.enclave().sign(&id, b"message").unwrap(),
+            store.enclave().sign(&id, b"message").unwrap()
+        );
+    }
+}
@@ -0,0 +1,54 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Importers move the contents of wallets created by other libraries into
+//! an enclave and a protected persistence backend.
+//!
+//! Records keep their category, name, value and tags. Private keys found in
+//! the wallet are saved in the enclave and removed from the records that
+//! held them so they never reach the persistence backend.
+
+use errors::ImportError;
+
+/// Typical result from importing a wallet
+pub type ImportResult<T> = Result<T, ImportError>;
+
+/// What was moved by an import
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// The number of records saved in the persistence backend
+    pub records: usize,
+    /// The number of private keys saved in the enclave
+    pub keys: usize,
+    /// The names of keys that were left out because the enclave
+    /// can't hold their type. Their records are left out too.
+    pub skipped_keys: Vec<String>,
+}
+
+/// Importing Aries Askar stores
+#[cfg(feature = "import-askar")]
+pub mod askar;
+
+/// Importing Indy-SDK wallets
+#[cfg(feature = "import-indy")]
+pub mod indy;
+
+/// Reading and writing Indy-SDK wallet export files
+#[cfg(feature = "import-indy")]
+pub mod indy_export;
+
+/// The errors that can occur while importing a wallet
+pub mod errors;
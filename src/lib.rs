@@ -49,6 +49,8 @@
 //! The storage layer is how the agent will store state and other information long term.
 //! This should be separate from the backend system but doesn't have to be.
 //! Again, this must be configurable via the `--features=` option at compile time.
+//! Which of the compiled in enclaves and stores is used can then be picked at runtime
+//! by URI scheme with the `registry` module.
 //! The focus of this project is to enable secure, misuse-resistant agent storage.
 //! 
 //! Storage is composed of the authentication layer
@@ -69,7 +71,21 @@
 
 #[macro_use] extern crate bitflags;
 
+/// The authentication modules
+#[cfg(feature = "authentication")]
+pub mod authentication;
+/// The C API
+#[cfg(feature = "ffi")]
+pub mod ffi;
 /// The security modules
 pub mod security;
 /// The persistence modules
 pub mod persistence;
+/// The data protection modules
+pub mod protection;
+/// Drivers chosen at runtime by URI scheme
+pub mod registry;
+/// Importing wallets from other libraries
+pub mod import;
+/// Splitting URIs
+mod uri;
@@ -0,0 +1,149 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Cursors read large result sets one page at a time.
+//!
+//! Every backend returns search results ordered by record name. A page ends
+//! with a `Cursor` that remembers the last name returned so the next page
+//! starts right after it. Cursors hold no backend resources, so they can be
+//! given to a client as an opaque continuation token with `to_string` and
+//! parsed back with `from_str` in another request or on another worker.
+//!
+//! Records inserted or removed between pages are only seen if they sort
+//! after the cursor.
+
+use super::{
+    errors::{PersistenceError, PersistenceErrorKind},
+    wql::Query,
+    PersistenceLike, PersistenceResult, Record,
+};
+
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+/// Where the next page of a search starts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    after: Vec<u8>,
+}
+
+impl Cursor {
+    /// A cursor that continues after the record named `after`.
+    /// Only backends need to create cursors.
+    pub fn new(after: Vec<u8>) -> Self {
+        Self { after }
+    }
+
+    /// The name of the last record returned
+    pub fn after(&self) -> &[u8] {
+        &self.after
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.after))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = PersistenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode(s).map(Self::new).map_err(|_| {
+            PersistenceError::from_msg(
+                PersistenceErrorKind::InvalidQuery,
+                "Invalid continuation token",
+            )
+        })
+    }
+}
+
+/// One page of search results
+#[derive(Clone, Debug)]
+pub struct Page {
+    /// The records in this page ordered by name
+    pub records: Vec<Record>,
+    /// Where the next page starts or `None` if this is the last page
+    pub next: Option<Cursor>,
+}
+
+impl Page {
+    /// Build a page from up to `limit + 1` records ordered by name.
+    /// The extra record only signals there is another page.
+    pub fn new(mut records: Vec<Record>, limit: usize) -> Self {
+        let next = if records.len() > limit {
+            records.truncate(limit);
+            records.last().map(|r| Cursor::new(r.name.clone()))
+        } else {
+            None
+        };
+        Self { records, next }
+    }
+}
+
+/// Iterates over every match of a search while holding
+/// at most one page of records in memory
+pub struct Scan<'a, P: PersistenceLike> {
+    store: &'a P,
+    category: Vec<u8>,
+    query: Query,
+    page_size: usize,
+    buffer: VecDeque<Record>,
+    next: Option<Cursor>,
+    done: bool,
+}
+
+impl<'a, P: PersistenceLike> Scan<'a, P> {
+    /// Scan `store` for records in `category` matching `query`
+    /// reading `page_size` records at a time
+    pub fn new(store: &'a P, category: &[u8], query: Query, page_size: usize) -> Self {
+        Self {
+            store,
+            category: category.to_vec(),
+            query,
+            page_size: page_size.max(1),
+            buffer: VecDeque::new(),
+            next: None,
+            done: false,
+        }
+    }
+}
+
+impl<'a, P: PersistenceLike> Iterator for Scan<'a, P> {
+    type Item = PersistenceResult<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.done {
+            match self.store.search_page(
+                &self.category,
+                &self.query,
+                self.next.as_ref(),
+                self.page_size,
+            ) {
+                Ok(page) => {
+                    self.buffer.extend(page.records);
+                    self.done = page.next.is_none();
+                    self.next = page.next;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
@@ -25,7 +25,30 @@ pub enum PersistenceErrorKind {
     InvalidConfig,
     /// Occurs during an IO error
     #[fail(display = "IO Error")]
-    IOError
+    IOError,
+    /// When a record does not exist
+    #[fail(display = "The specified item is not found in the store")]
+    ItemNotFound,
+    /// When a record with the same category and name already exists
+    #[fail(display = "The specified item already exists in the store")]
+    DuplicateItem,
+    /// Occurs when stored data cannot be encoded or decoded
+    #[fail(display = "Stored data is malformed")]
+    SerializationError,
+    /// Occurs when a query is malformed or not supported
+    #[fail(display = "The query is invalid")]
+    InvalidQuery,
+    /// Occurs when another writer changed the same data first like
+    /// a record read in a transaction. The operation or transaction
+    /// can be retried.
+    #[fail(display = "The item was modified concurrently")]
+    Conflict,
+    /// Occurs when a store was written by a newer version of this library
+    #[fail(display = "The store schema version is not supported")]
+    UnsupportedVersion,
+    /// Occurs when the backend can't perform the operation
+    #[fail(display = "The operation is not supported by this backend")]
+    UnsupportedOperation,
 }
 
 /// Represents a Persistence error that includes a context and backtrace
@@ -35,9 +58,19 @@ pub struct PersistenceError {
 }
 
 impl PersistenceError {
+    /// Create from a message and kind
+    pub fn from_msg<D: fmt::Display + fmt::Debug + Send + Sync + 'static>(
+        kind: PersistenceErrorKind,
+        msg: D,
+    ) -> Self {
+        Self {
+            inner: Context::new(msg).context(kind)
+        }
+    }
+
     /// Get `PersistenceErrorKind` wrapped by this error
     pub fn kind(&self) -> PersistenceErrorKind {
-        self.inner.get_context().clone()
+        *self.inner.get_context()
     }
 }
 
@@ -81,3 +114,47 @@ impl fmt::Display for PersistenceError {
     }
 }
 
+
+#[cfg(feature = "storage-sled")]
+impl From<sled::Error> for PersistenceError {
+    fn from(e: sled::Error) -> Self {
+        match e {
+            sled::Error::Unsupported(_) | sled::Error::CollectionNotFound(_) => {
+                PersistenceError::from_msg(PersistenceErrorKind::InvalidConfig, e.to_string())
+            }
+            // Io, Corruption and ReportableBug all mean the files on disk
+            // could not be read or written as expected
+            _ => PersistenceError::from_msg(PersistenceErrorKind::IOError, e.to_string()),
+        }
+    }
+}
+
+#[cfg(any(feature = "storage-memory", feature = "storage-sled"))]
+impl From<bincode::Error> for PersistenceError {
+    fn from(e: bincode::Error) -> Self {
+        PersistenceError::from_msg(PersistenceErrorKind::SerializationError, e.to_string())
+    }
+}
+
+#[cfg(feature = "storage-sqlite")]
+impl From<rusqlite::Error> for PersistenceError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => PersistenceErrorKind::ItemNotFound.into(),
+            rusqlite::Error::SqliteFailure(ref f, _)
+                if f.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                PersistenceError::from_msg(PersistenceErrorKind::DuplicateItem, e.to_string())
+            }
+            // Another connection holds the lock and didn't release it within
+            // the busy timeout. Retrying later will usually succeed.
+            rusqlite::Error::SqliteFailure(ref f, _)
+                if f.code == rusqlite::ErrorCode::DatabaseBusy
+                    || f.code == rusqlite::ErrorCode::DatabaseLocked =>
+            {
+                PersistenceError::from_msg(PersistenceErrorKind::Conflict, e.to_string())
+            }
+            _ => PersistenceError::from_msg(PersistenceErrorKind::IOError, e.to_string()),
+        }
+    }
+}
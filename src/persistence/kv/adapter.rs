@@ -0,0 +1,314 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Browser storage like IndexedDB only has asynchronous APIs while
+//! `PersistenceLike` is synchronous. An `AdapterStore` bridges the two. Every
+//! record is read from an `AsyncKeyValue` adapter once when the store is
+//! opened and served from a `MemoryStore` after that. Changed records are
+//! remembered and written back to the adapter in a single batch by `flush`.
+//!
+//! Adapters only keep opaque keys and values so they take a few lines of
+//! JavaScript glue to write. Values are bincode encoded records and keys
+//! encode their category and name.
+//!
+//! Changes that were not flushed are lost when the store is closed or the
+//! page is unloaded, so flush after every change that has to survive like
+//! a committed transaction.
+
+use super::memory::MemoryStore;
+use crate::persistence::{
+    cursor::{Cursor, Page},
+    errors::{PersistenceError, PersistenceErrorKind},
+    transaction::Operation,
+    wql::Query,
+    PersistenceConnector, PersistenceLike, PersistenceResult, Record,
+};
+
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{Mutex, MutexGuard},
+};
+
+/// The future returned by an `AsyncKeyValue` adapter. Browser futures
+/// can't be sent to other threads so this is not `Send`.
+pub type AdapterFuture<'a, T> = Pin<Box<dyn Future<Output = PersistenceResult<T>> + 'a>>;
+
+/// An asynchronous key-value store like IndexedDB
+pub trait AsyncKeyValue {
+    /// Read every value in the store
+    fn load(&self) -> AdapterFuture<'_, Vec<Vec<u8>>>;
+    /// Save each `Some` value under its key and remove each key with
+    /// `None`, all in one transaction
+    fn write(&self, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> AdapterFuture<'_, ()>;
+}
+
+/// A persistence backend kept in memory and written
+/// to an asynchronous key-value store when flushed
+pub struct AdapterStore<K: AsyncKeyValue> {
+    adapter: K,
+    memory: MemoryStore,
+    changed: Mutex<BTreeSet<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl<K: AsyncKeyValue> AdapterStore<K> {
+    /// Read every record kept by `adapter`
+    pub async fn open(adapter: K) -> PersistenceResult<Self> {
+        let mut records = Vec::new();
+        for value in adapter.load().await? {
+            records.push(bincode::deserialize::<Record>(&value)?);
+        }
+        Ok(Self {
+            adapter,
+            memory: MemoryStore::with_records(records),
+            changed: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    /// The adapter records are written to
+    pub fn adapter(&self) -> &K {
+        &self.adapter
+    }
+
+    /// Are there changes that have not been flushed
+    pub fn has_changes(&self) -> bool {
+        !self.changed().is_empty()
+    }
+
+    /// Write every record changed since the last flush to the adapter.
+    /// If the adapter fails the changes are kept for the next flush.
+    pub async fn flush(&self) -> PersistenceResult<()> {
+        let keys = std::mem::take(&mut *self.changed());
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let result = self.write(&keys).await;
+        if result.is_err() {
+            self.changed().extend(keys);
+        }
+        result
+    }
+
+    async fn write(&self, keys: &BTreeSet<(Vec<u8>, Vec<u8>)>) -> PersistenceResult<()> {
+        let mut changes = Vec::with_capacity(keys.len());
+        for (category, name) in keys {
+            let value = match self.memory.fetch(category, name) {
+                Ok(r) => Some(bincode::serialize(&r)?),
+                Err(ref e) if e.kind() == PersistenceErrorKind::ItemNotFound => None,
+                Err(e) => return Err(e),
+            };
+            changes.push((bincode::serialize(&(category, name))?, value));
+        }
+        self.adapter.write(changes).await
+    }
+
+    fn changed(&self) -> MutexGuard<'_, BTreeSet<(Vec<u8>, Vec<u8>)>> {
+        self.changed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn mark(&self, category: &[u8], name: &[u8]) {
+        self.changed().insert((category.to_vec(), name.to_vec()));
+    }
+}
+
+impl<K: AsyncKeyValue> PersistenceLike for AdapterStore<K> {
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: PersistenceConnector<A, B>,
+    ) -> PersistenceResult<Self> {
+        Err(PersistenceError::from_msg(
+            PersistenceErrorKind::InvalidConfig,
+            format!(
+                "An adapter store is opened with AdapterStore::open not {}",
+                config
+            ),
+        ))
+    }
+
+    /// Close the store. Changes that were not flushed are lost.
+    fn close(self) {
+        self.memory.close();
+    }
+
+    fn insert(&self, record: Record) -> PersistenceResult<()> {
+        let (category, name) = (record.category.clone(), record.name.clone());
+        self.memory.insert(record)?;
+        self.mark(&category, &name);
+        Ok(())
+    }
+
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        self.memory.fetch(category, name)
+    }
+
+    fn update(&self, record: Record) -> PersistenceResult<()> {
+        let (category, name) = (record.category.clone(), record.name.clone());
+        self.memory.update(record)?;
+        self.mark(&category, &name);
+        Ok(())
+    }
+
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+        self.memory.delete(category, name)?;
+        self.mark(category, name);
+        Ok(())
+    }
+
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>> {
+        self.memory.categories()
+    }
+
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>> {
+        self.memory.search(category, query)
+    }
+
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page> {
+        self.memory.search_page(category, query, cursor, limit)
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()> {
+        let keys: Vec<_> = operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Check { .. } => None,
+                Operation::Insert(r) | Operation::Update(r) => {
+                    Some((r.category.clone(), r.name.clone()))
+                }
+                Operation::Delete { category, name } => Some((category.clone(), name.clone())),
+            })
+            .collect();
+        self.memory.apply(operations)?;
+        self.changed().extend(keys);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::suite;
+    use std::{
+        collections::BTreeMap,
+        ptr,
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    };
+
+    /// Keeps the values in memory like IndexedDB would
+    #[derive(Default)]
+    struct MemoryAdapter(Mutex<BTreeMap<Vec<u8>, Vec<u8>>>);
+
+    impl AsyncKeyValue for MemoryAdapter {
+        fn load(&self) -> AdapterFuture<'_, Vec<Vec<u8>>> {
+            Box::pin(async move { Ok(self.0.lock().unwrap().values().cloned().collect()) })
+        }
+
+        fn write(&self, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> AdapterFuture<'_, ()> {
+            Box::pin(async move {
+                let mut values = self.0.lock().unwrap();
+                for (key, value) in changes {
+                    match value {
+                        Some(v) => values.insert(key, v),
+                        None => values.remove(&key),
+                    };
+                }
+                Ok(())
+            })
+        }
+    }
+
+    /// The adapter futures never wait so polling them once completes them
+    #[allow(unsafe_code)]
+    fn block_on<F: Future>(future: F) -> F::Output {
+        fn raw() -> RawWaker {
+            RawWaker::new(ptr::null(), &VTABLE)
+        }
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(|_| raw(), |_| {}, |_| {}, |_| {});
+        let waker = unsafe { Waker::from_raw(raw()) };
+        let mut future = Box::pin(future);
+        match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("The adapter future is not ready"),
+        }
+    }
+
+    fn store() -> AdapterStore<MemoryAdapter> {
+        block_on(AdapterStore::open(MemoryAdapter::default())).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        suite::round_trip(&store());
+    }
+
+    #[test]
+    fn search_records() {
+        suite::search_records(&store());
+    }
+
+    #[test]
+    fn apply_rolls_back_on_conflict() {
+        suite::apply_rolls_back_on_conflict(&store());
+    }
+
+    #[test]
+    fn keeps_tag_order() {
+        suite::keeps_tag_order(&store());
+    }
+
+    #[test]
+    fn flushed_records_are_reopened() {
+        let store = store();
+        store
+            .insert(suite::record("alice", "1", "active", "2020-01"))
+            .unwrap();
+        store
+            .insert(suite::record("bob", "2", "active", "2020-02"))
+            .unwrap();
+        assert!(store.has_changes());
+        assert!(store.adapter().0.lock().unwrap().is_empty());
+        block_on(store.flush()).unwrap();
+        assert!(!store.has_changes());
+
+        store.delete(b"connections", b"bob").unwrap();
+        store
+            .update(suite::record("alice", "3", "revoked", "2020-01"))
+            .unwrap();
+        block_on(store.flush()).unwrap();
+        // Changes made after the last flush are lost
+        store
+            .insert(suite::record("carol", "4", "active", "2020-03"))
+            .unwrap();
+
+        let reopened = block_on(AdapterStore::open(store.adapter)).unwrap();
+        assert_eq!(
+            reopened.fetch(b"connections", b"alice").unwrap(),
+            suite::record("alice", "3", "revoked", "2020-01")
+        );
+        for name in &[&b"bob"[..], b"carol"] {
+            assert_eq!(
+                reopened.fetch(b"connections", name).unwrap_err().kind(),
+                PersistenceErrorKind::ItemNotFound
+            );
+        }
+    }
+}
@@ -0,0 +1,263 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Persistence kept in memory.
+//!
+//! Nothing is written anywhere, so this backend needs no file system or
+//! network and also works in WebAssembly. Records are lost when the store
+//! is dropped unless they are written through an `AdapterStore`.
+//!
+//! Records are kept in a map sorted by category and name so pages resume
+//! right after the cursor. There is no tag index, every search filters the
+//! records in the category which is fine for the small stores this is meant
+//! for like browser wallets and tests.
+//!
+//! Transactions committed with `apply` hold the write lock while every check
+//! and change runs and undo the changes already made when one fails, so they
+//! are serializable.
+
+use crate::persistence::{
+    cursor::{Cursor, Page},
+    errors::{PersistenceError, PersistenceErrorKind},
+    transaction::Operation,
+    wql::Query,
+    PersistenceConnector, PersistenceLike, PersistenceResult, Record,
+};
+
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    path::Path,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+type Records = BTreeMap<(Vec<u8>, Vec<u8>), Record>;
+
+/// A persistence backend kept in memory
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    records: RwLock<Records>,
+}
+
+impl MemoryStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a store holding `records`. Later records replace
+    /// earlier ones with the same category and name.
+    pub fn with_records<I: IntoIterator<Item = Record>>(records: I) -> Self {
+        let records = records
+            .into_iter()
+            .map(|r| ((r.category.clone(), r.name.clone()), r))
+            .collect();
+        Self {
+            records: RwLock::new(records),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Records> {
+        // A panic while holding the lock can't leave a half written record
+        self.records.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Records> {
+        self.records.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Find up to `limit` matches ordered by name starting after the record `after`
+    fn search_from(
+        &self,
+        category: &[u8],
+        query: &Query,
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<Record> {
+        let start = match after {
+            Some(a) => Bound::Excluded((category.to_vec(), a.to_vec())),
+            None => Bound::Included((category.to_vec(), Vec::new())),
+        };
+        self.read()
+            .range((start, Bound::Unbounded))
+            .take_while(|((c, _), _)| c.as_slice() == category)
+            .map(|(_, r)| r)
+            .filter(|r| query.matches(&r.tags))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Run `op` on `records` and return the previous record
+/// it changed so the change can be undone
+fn apply_one(records: &mut Records, op: &Operation) -> PersistenceResult<Option<Change>> {
+    match op {
+        Operation::Check {
+            category,
+            name,
+            expected,
+        } => {
+            if records.get(&(category.clone(), name.clone())) != expected.as_ref() {
+                return Err(PersistenceErrorKind::Conflict.into());
+            }
+            Ok(None)
+        }
+        Operation::Insert(r) => {
+            let key = (r.category.clone(), r.name.clone());
+            if records.contains_key(&key) {
+                return Err(PersistenceErrorKind::DuplicateItem.into());
+            }
+            records.insert(key.clone(), r.clone());
+            Ok(Some((key, None)))
+        }
+        Operation::Update(r) => {
+            let key = (r.category.clone(), r.name.clone());
+            match records.get_mut(&key) {
+                Some(old) => Ok(Some((key, Some(std::mem::replace(old, r.clone()))))),
+                None => Err(PersistenceErrorKind::ItemNotFound.into()),
+            }
+        }
+        Operation::Delete { category, name } => {
+            let key = (category.clone(), name.clone());
+            match records.remove(&key) {
+                Some(old) => Ok(Some((key, Some(old)))),
+                None => Err(PersistenceErrorKind::ItemNotFound.into()),
+            }
+        }
+    }
+}
+
+/// A record key and the record it held before it was changed
+type Change = ((Vec<u8>, Vec<u8>), Option<Record>);
+
+impl PersistenceLike for MemoryStore {
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: PersistenceConnector<A, B>,
+    ) -> PersistenceResult<Self> {
+        match config {
+            PersistenceConnector::Memory => Ok(Self::new()),
+            _ => Err(PersistenceError::from_msg(
+                PersistenceErrorKind::InvalidConfig,
+                format!(
+                    "Invalid configuration type. Expected Memory but found {}",
+                    config
+                ),
+            )),
+        }
+    }
+
+    fn close(self) {}
+
+    fn insert(&self, record: Record) -> PersistenceResult<()> {
+        apply_one(&mut self.write(), &Operation::Insert(record)).map(|_| ())
+    }
+
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        self.read()
+            .get(&(category.to_vec(), name.to_vec()))
+            .cloned()
+            .ok_or_else(|| PersistenceErrorKind::ItemNotFound.into())
+    }
+
+    fn update(&self, record: Record) -> PersistenceResult<()> {
+        apply_one(&mut self.write(), &Operation::Update(record)).map(|_| ())
+    }
+
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+        let op = Operation::Delete {
+            category: category.to_vec(),
+            name: name.to_vec(),
+        };
+        apply_one(&mut self.write(), &op).map(|_| ())
+    }
+
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>> {
+        let mut categories: Vec<Vec<u8>> = Vec::new();
+        for (category, _) in self.read().keys() {
+            if categories.last() != Some(category) {
+                categories.push(category.clone());
+            }
+        }
+        Ok(categories)
+    }
+
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>> {
+        Ok(self.search_from(category, query, None, usize::MAX))
+    }
+
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page> {
+        let found = self.search_from(
+            category,
+            query,
+            cursor.map(Cursor::after),
+            limit.saturating_add(1),
+        );
+        Ok(Page::new(found, limit))
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()> {
+        let mut records = self.write();
+        let mut undo = Vec::new();
+        for op in &operations {
+            match apply_one(&mut records, op) {
+                Ok(change) => undo.extend(change),
+                Err(e) => {
+                    for (key, old) in undo.into_iter().rev() {
+                        match old {
+                            Some(r) => records.insert(key, r),
+                            None => records.remove(&key),
+                        };
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::suite;
+
+    #[test]
+    fn round_trip() {
+        suite::round_trip(&MemoryStore::new());
+    }
+
+    #[test]
+    fn search_records() {
+        suite::search_records(&MemoryStore::new());
+    }
+
+    #[test]
+    fn apply_rolls_back_on_conflict() {
+        suite::apply_rolls_back_on_conflict(&MemoryStore::new());
+    }
+
+    #[test]
+    fn keeps_tag_order() {
+        suite::keeps_tag_order(&MemoryStore::new());
+    }
+}
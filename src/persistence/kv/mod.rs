@@ -0,0 +1,9 @@
+/// Asynchronous key-value stores like IndexedDB behind an in-memory store
+#[cfg(feature = "storage-memory")]
+pub mod adapter;
+/// Persistence kept in memory
+#[cfg(feature = "storage-memory")]
+pub mod memory;
+/// Persistence backed by the sled embedded database
+#[cfg(feature = "storage-sled")]
+pub mod sled;
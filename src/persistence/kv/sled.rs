@@ -0,0 +1,467 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Persistence on top of the sled embedded database.
+//!
+//! sled is a lock-free, log-structured store which makes it a good fit for
+//! agents that write a lot like mediators queuing messages. Unlike SQLite,
+//! writers do not block each other on a single database lock.
+//!
+//! Records are kept in the `records` tree keyed by category and name.
+//! Tags are kept as a secondary index in the `tags` tree keyed by
+//! category, tag kind, tag name, tag value and record name so an equality
+//! lookup is a single prefix scan. Searches that require a tag to equal a
+//! value scan that part of the index and filter the records found with the
+//! rest of the query, all other searches filter every record in the category.
+//! Each key component is escaped and terminated so keys sort in the same
+//! order as the raw bytes they contain.
+//!
+//! Keys sort by record name within a category and tag, so pages resume
+//! with a range scan that starts right after the cursor.
+//!
+//! The schema version is kept in the default tree and migrated when the
+//! store is opened.
+//!
+//! Both trees are always updated in the same transaction. Transactions
+//! committed with `apply` run every check and change in a single sled
+//! transaction so they are serializable.
+
+use crate::persistence::{
+    cursor::{Cursor, Page},
+    errors::{PersistenceError, PersistenceErrorKind},
+    migration::{self, Migration},
+    transaction::Operation,
+    wql::{Query, TagName},
+    PersistenceConnector, PersistenceLike, PersistenceResult, Record, RecordTag,
+};
+
+use sled::{
+    transaction::{abort, ConflictableTransactionResult, TransactionError, TransactionalTree},
+    Db, Transactional, Tree,
+};
+use std::{ops::Bound, path::Path};
+
+const RECORDS_TREE: &[u8] = b"records";
+const TAGS_TREE: &[u8] = b"tags";
+
+/// The key in the default tree holding the schema version as a big endian u32
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// The schema migrations in order
+static MIGRATIONS: &[Migration<Db>] = &[Migration {
+    version: 1,
+    description: "Create the records and tags trees",
+    up: |db| {
+        db.open_tree(RECORDS_TREE)?;
+        db.open_tree(TAGS_TREE)?;
+        Ok(())
+    },
+}];
+
+const ENCRYPTED_TAG: u8 = 0x01;
+const PLAINTEXT_TAG: u8 = 0x02;
+
+/// A persistence backend stored in a sled database
+pub struct SledStore {
+    db: Db,
+    records: Tree,
+    tags: Tree,
+}
+
+impl SledStore {
+    /// Run `f` as a single transaction over the record and tag trees.
+    /// Aborting with an error kind rolls back all writes staged by `f`.
+    fn transaction<T, F>(&self, f: F) -> PersistenceResult<T>
+    where
+        F: Fn(&TransactionalTree, &TransactionalTree) -> TxResult<T>,
+    {
+        match (&self.records, &self.tags).transaction(|(records, tags)| f(records, tags)) {
+            Ok(t) => Ok(t),
+            Err(TransactionError::Abort(kind)) => Err(kind.into()),
+            Err(TransactionError::Storage(e)) => Err(e.into()),
+        }
+    }
+
+    /// Find up to `limit` matches ordered by name starting after the record `after`
+    fn search_from(
+        &self,
+        category: &[u8],
+        query: &Query,
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> PersistenceResult<Vec<Record>> {
+        let mut found = Vec::new();
+        match indexed_tag(query) {
+            Some((name, value)) => {
+                let prefix = tag_prefix(category, name, value);
+                // Index keys end with the record name so they are ordered by it
+                let start = match after {
+                    Some(a) => {
+                        let mut key = prefix.clone();
+                        push_component(&mut key, a);
+                        Bound::Excluded(key)
+                    }
+                    None => Bound::Included(prefix.clone()),
+                };
+                for entry in self.tags.range((start, Bound::Unbounded)) {
+                    if found.len() >= limit {
+                        break;
+                    }
+                    let (key, record_name) = entry?;
+                    if !key.starts_with(&prefix) {
+                        break;
+                    }
+                    // The record may have been removed since the index was read
+                    match self.fetch(category, &record_name) {
+                        Ok(r) if query.matches(&r.tags) => found.push(r),
+                        Ok(_) => {}
+                        Err(ref e) if e.kind() == PersistenceErrorKind::ItemNotFound => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            None => {
+                let mut prefix = Vec::new();
+                push_component(&mut prefix, category);
+                let start = match after {
+                    Some(a) => Bound::Excluded(record_key(category, a)),
+                    None => Bound::Included(prefix.clone()),
+                };
+                for entry in self.records.range((start, Bound::Unbounded)) {
+                    if found.len() >= limit {
+                        break;
+                    }
+                    let (key, data) = entry?;
+                    if !key.starts_with(&prefix) {
+                        break;
+                    }
+                    let record: Record = bincode::deserialize(&data)?;
+                    if query.matches(&record.tags) {
+                        found.push(record);
+                    }
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// Run every pending migration. sled allows only one process
+/// to open a database so no other writer can run them concurrently.
+fn migrate(db: &Db) -> PersistenceResult<()> {
+    let current = match db.get(SCHEMA_VERSION_KEY)? {
+        Some(v) if v.len() == 4 => u32::from_be_bytes([v[0], v[1], v[2], v[3]]),
+        Some(_) => return Err(PersistenceErrorKind::SerializationError.into()),
+        None => 0,
+    };
+    for m in migration::pending(current, MIGRATIONS)? {
+        (m.up)(db)?;
+        db.insert(SCHEMA_VERSION_KEY, &m.version.to_be_bytes())?;
+        db.flush()?;
+    }
+    Ok(())
+}
+
+type TxResult<T> = ConflictableTransactionResult<T, PersistenceErrorKind>;
+
+fn tx_get(records: &TransactionalTree, key: &[u8]) -> TxResult<Option<Record>> {
+    match records.get(key)? {
+        Some(d) => match bincode::deserialize(&d) {
+            Ok(r) => Ok(Some(r)),
+            Err(_) => abort(PersistenceErrorKind::SerializationError),
+        },
+        None => Ok(None),
+    }
+}
+
+fn tx_insert(
+    records: &TransactionalTree,
+    tags: &TransactionalTree,
+    record: &Record,
+) -> TxResult<()> {
+    let key = record_key(&record.category, &record.name);
+    if records.get(key.as_slice())?.is_some() {
+        return abort(PersistenceErrorKind::DuplicateItem);
+    }
+    tx_write(records, tags, key, record)
+}
+
+fn tx_update(
+    records: &TransactionalTree,
+    tags: &TransactionalTree,
+    record: &Record,
+) -> TxResult<()> {
+    let key = record_key(&record.category, &record.name);
+    let old = match tx_get(records, &key)? {
+        Some(r) => r,
+        None => return abort(PersistenceErrorKind::ItemNotFound),
+    };
+    for tag in &old.tags {
+        tags.remove(tag_key(&old.category, tag, &old.name))?;
+    }
+    tx_write(records, tags, key, record)
+}
+
+fn tx_write(
+    records: &TransactionalTree,
+    tags: &TransactionalTree,
+    key: Vec<u8>,
+    record: &Record,
+) -> TxResult<()> {
+    let data = match bincode::serialize(record) {
+        Ok(d) => d,
+        Err(_) => return abort(PersistenceErrorKind::SerializationError),
+    };
+    records.insert(key, data)?;
+    for tag in &record.tags {
+        tags.insert(
+            tag_key(&record.category, tag, &record.name),
+            record.name.as_slice(),
+        )?;
+    }
+    Ok(())
+}
+
+fn tx_delete(
+    records: &TransactionalTree,
+    tags: &TransactionalTree,
+    category: &[u8],
+    name: &[u8],
+) -> TxResult<()> {
+    let key = record_key(category, name);
+    let old = match tx_get(records, &key)? {
+        Some(r) => r,
+        None => return abort(PersistenceErrorKind::ItemNotFound),
+    };
+    records.remove(key)?;
+    for tag in &old.tags {
+        tags.remove(tag_key(category, tag, name))?;
+    }
+    Ok(())
+}
+
+impl PersistenceLike for SledStore {
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: PersistenceConnector<A, B>,
+    ) -> PersistenceResult<Self> {
+        match config {
+            PersistenceConnector::KeyValue(c) => {
+                let mut options = sled::Config::new().flush_every_ms(c.flush_every_ms);
+                options = match c.path {
+                    Some(p) => options.path(p.as_ref()),
+                    None => options.temporary(true),
+                };
+                if let Some(capacity) = c.cache_capacity {
+                    options = options.cache_capacity(capacity);
+                }
+                let db = options.open()?;
+                migrate(&db)?;
+                let records = db.open_tree(RECORDS_TREE)?;
+                let tags = db.open_tree(TAGS_TREE)?;
+                Ok(Self { db, records, tags })
+            }
+            _ => Err(PersistenceError::from_msg(
+                PersistenceErrorKind::InvalidConfig,
+                format!(
+                    "Invalid configuration type. Expected KeyValue but found {}",
+                    config
+                ),
+            )),
+        }
+    }
+
+    fn close(self) {
+        let _ = self.db.flush();
+    }
+
+    fn insert(&self, record: Record) -> PersistenceResult<()> {
+        self.transaction(|records, tags| tx_insert(records, tags, &record))
+    }
+
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        match self.records.get(record_key(category, name))? {
+            Some(data) => Ok(bincode::deserialize(&data)?),
+            None => Err(PersistenceErrorKind::ItemNotFound.into()),
+        }
+    }
+
+    fn update(&self, record: Record) -> PersistenceResult<()> {
+        self.transaction(|records, tags| tx_update(records, tags, &record))
+    }
+
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+        self.transaction(|records, tags| tx_delete(records, tags, category, name))
+    }
+
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>> {
+        let mut categories = Vec::new();
+        let mut start = Vec::new();
+        // Ending the category with `00 02` instead of `00 01` sorts after all its
+        // keys and before the next category, so each category is read once
+        while let Some(entry) = self.records.range(start.as_slice()..).next() {
+            let (_, data) = entry?;
+            let record: Record = bincode::deserialize(&data)?;
+            start.clear();
+            push_component(&mut start, &record.category);
+            *start.last_mut().unwrap() += 1;
+            categories.push(record.category);
+        }
+        Ok(categories)
+    }
+
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>> {
+        self.search_from(category, query, None, usize::MAX)
+    }
+
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page> {
+        let found = self.search_from(
+            category,
+            query,
+            cursor.map(Cursor::after),
+            limit.saturating_add(1),
+        )?;
+        Ok(Page::new(found, limit))
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()> {
+        self.transaction(|records, tags| {
+            for op in &operations {
+                match op {
+                    Operation::Check {
+                        category,
+                        name,
+                        expected,
+                    } => {
+                        if tx_get(records, &record_key(category, name))? != *expected {
+                            return abort(PersistenceErrorKind::Conflict);
+                        }
+                    }
+                    Operation::Insert(r) => tx_insert(records, tags, r)?,
+                    Operation::Update(r) => tx_update(records, tags, r)?,
+                    Operation::Delete { category, name } => {
+                        tx_delete(records, tags, category, name)?
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Find a tag that every match must have so the tag index
+/// can be scanned instead of every record in the category
+fn indexed_tag(query: &Query) -> Option<(&TagName, &[u8])> {
+    match query {
+        Query::Eq(name, value) => Some((name, value.as_slice())),
+        Query::And(queries) => queries.iter().find_map(indexed_tag),
+        _ => None,
+    }
+}
+
+/// Append `part` to `key` so that it can't be confused with the
+/// component that follows it and still sorts like the raw bytes.
+/// Zero bytes are escaped as `00 FF` and the part ends with `00 01`.
+fn push_component(key: &mut Vec<u8>, part: &[u8]) {
+    for b in part {
+        if *b == 0 {
+            key.extend_from_slice(&[0x00, 0xFF]);
+        } else {
+            key.push(*b);
+        }
+    }
+    key.extend_from_slice(&[0x00, 0x01]);
+}
+
+fn record_key(category: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(category.len() + name.len() + 4);
+    push_component(&mut key, category);
+    push_component(&mut key, name);
+    key
+}
+
+fn tag_prefix(category: &[u8], name: &TagName, value: &[u8]) -> Vec<u8> {
+    let mut key = Vec::new();
+    push_component(&mut key, category);
+    match name {
+        TagName::Encrypted(n) => {
+            key.push(ENCRYPTED_TAG);
+            push_component(&mut key, n);
+        }
+        TagName::Plaintext(n) => {
+            key.push(PLAINTEXT_TAG);
+            push_component(&mut key, n);
+        }
+    }
+    push_component(&mut key, value);
+    key
+}
+
+fn tag_key(category: &[u8], tag: &RecordTag, name: &[u8]) -> Vec<u8> {
+    let mut key = tag_prefix(category, &tag.into(), tag.value());
+    push_component(&mut key, name);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{suite, KeyValueConnector};
+
+    fn store() -> SledStore {
+        let config = KeyValueConnector::<&str>::new(None);
+        SledStore::connect(PersistenceConnector::<_, &str>::KeyValue(config)).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        suite::round_trip(&store());
+    }
+
+    #[test]
+    fn search_records() {
+        suite::search_records(&store());
+    }
+
+    #[test]
+    fn apply_rolls_back_on_conflict() {
+        suite::apply_rolls_back_on_conflict(&store());
+    }
+
+    #[test]
+    fn keeps_tag_order() {
+        suite::keeps_tag_order(&store());
+    }
+
+    #[test]
+    fn migrate_refuses_newer_versions() {
+        let store = store();
+        migrate(&store.db).unwrap();
+        store
+            .db
+            .insert(SCHEMA_VERSION_KEY, &99u32.to_be_bytes())
+            .unwrap();
+        assert_eq!(
+            migrate(&store.db).unwrap_err().kind(),
+            PersistenceErrorKind::UnsupportedVersion
+        );
+    }
+}
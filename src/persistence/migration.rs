@@ -0,0 +1,120 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Schema migrations keep stores written by older versions of this library
+//! readable by newer ones.
+//!
+//! Each backend records the schema version in the store itself and keeps
+//! an ordered list of migrations, one per version starting at 1. When a
+//! store is opened every migration newer than the recorded version runs in
+//! order and the version is updated after each one, so a store interrupted
+//! while migrating continues where it stopped the next time it is opened.
+//! A store with a version newer than the latest migration was written by a
+//! newer library and is refused with `UnsupportedVersion` instead of being
+//! misread.
+//!
+//! Migrations are never changed or removed once released. A change to the
+//! layout is always a new migration at the end of the list.
+
+use super::{
+    errors::{PersistenceError, PersistenceErrorKind},
+    PersistenceResult,
+};
+
+/// Upgrades a store of type `S` from the previous schema version to `version`
+pub struct Migration<S: ?Sized> {
+    /// The schema version after this migration has run
+    pub version: u32,
+    /// What this migration changes
+    pub description: &'static str,
+    /// Changes the layout of the store
+    pub up: fn(&S) -> PersistenceResult<()>,
+}
+
+/// The schema version written by the last migration in `migrations`
+pub fn latest_version<S: ?Sized>(migrations: &[Migration<S>]) -> u32 {
+    migrations.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Find the migrations that must run on a store at version `current`
+pub fn pending<S: ?Sized>(
+    current: u32,
+    migrations: &[Migration<S>],
+) -> PersistenceResult<&[Migration<S>]> {
+    for (i, m) in migrations.iter().enumerate() {
+        if m.version as usize != i + 1 {
+            return Err(PersistenceError::from_msg(
+                PersistenceErrorKind::InvalidConfig,
+                format!(
+                    "Migration {} \"{}\" is out of order. Expected version {}",
+                    m.version,
+                    m.description,
+                    i + 1
+                ),
+            ));
+        }
+    }
+    let latest = latest_version(migrations);
+    if current > latest {
+        return Err(PersistenceError::from_msg(
+            PersistenceErrorKind::UnsupportedVersion,
+            format!(
+                "The store has schema version {} but this library only supports up to {}",
+                current, latest
+            ),
+        ));
+    }
+    Ok(&migrations[current as usize..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MIGRATIONS: &[Migration<()>] = &[
+        Migration {
+            version: 1,
+            description: "First",
+            up: |_| Ok(()),
+        },
+        Migration {
+            version: 2,
+            description: "Second",
+            up: |_| Ok(()),
+        },
+    ];
+
+    #[test]
+    fn pending_migrations() {
+        assert_eq!(latest_version(MIGRATIONS), 2);
+        assert_eq!(pending(0, MIGRATIONS).unwrap().len(), 2);
+        assert_eq!(pending(1, MIGRATIONS).unwrap()[0].version, 2);
+        assert!(pending(2, MIGRATIONS).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_versions_are_refused() {
+        assert_eq!(
+            pending(3, MIGRATIONS).err().map(|e| e.kind()),
+            Some(PersistenceErrorKind::UnsupportedVersion)
+        );
+        // Versions must start at 1 without gaps
+        assert_eq!(
+            pending(0, &MIGRATIONS[1..]).err().map(|e| e.kind()),
+            Some(PersistenceErrorKind::InvalidConfig)
+        );
+    }
+}
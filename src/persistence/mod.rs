@@ -1,2 +1,332 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Data persistence is just putting the data where it can be retrieved later.
+//!
+//! Persistence backends do not know or care whether the bytes they are handed
+//! are encrypted. Every value, name and tag is treated as an opaque byte string
+//! so the same backend can sit below any data protection layer.
+//!
+//! Records are grouped by category (e.g. connections, credentials, messages)
+//! and are unique by name within a category. Each record can carry tags which
+//! backends index so records can be looked up without scanning every value.
+
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::Path};
+
+/// Typical result from performing a persistence operation
+pub type PersistenceResult<T> = Result<T, errors::PersistenceError>;
+
+/// Categories starting with this prefix hold records written by this library
+/// itself, like the authentication verifier, instead of by callers
+pub const RESERVED_CATEGORY_PREFIX: &[u8] = b"arieskms:";
+
+/// Configuration options for connecting to persistence backends
+///
+/// Each backend has its own unique configuration requirements
+/// but are wrapped by this config to enable generic interfaces
+#[derive(Debug)]
+pub enum PersistenceConnector<A, B>
+where
+    A: AsRef<Path>,
+    B: Into<String>,
+{
+    /// Connect to an embedded key-value store
+    KeyValue(KeyValueConnector<A>),
+    /// Keep records in memory
+    Memory,
+    /// Connect to an S3-compatible object store
+    ObjectStore(ObjectStoreConnector<B>),
+    /// Connect to a SQLite database
+    Sqlite(SqliteConnector<A>),
+}
+
+impl<A, B> fmt::Display for PersistenceConnector<A, B>
+where
+    A: AsRef<Path>,
+    B: Into<String>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistenceConnector::KeyValue(c) => write!(f, "PersistenceConfig ({})", c),
+            PersistenceConnector::Memory => write!(f, "PersistenceConfig (MemoryConfig)"),
+            PersistenceConnector::ObjectStore(c) => write!(f, "PersistenceConfig ({})", c),
+            PersistenceConnector::Sqlite(c) => write!(f, "PersistenceConfig ({})", c),
+        }
+    }
+}
+
+/// Configuration options for opening an embedded key-value store
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyValueConnector<A: AsRef<Path>> {
+    /// Path to the store. If `None`, a temporary store is created
+    /// that is deleted when it is closed
+    path: Option<A>,
+    /// Maximum size in bytes of the in-memory page cache
+    cache_capacity: Option<u64>,
+    /// Milliseconds between flushing writes to disk. If `None`,
+    /// writes are only flushed when the store is closed
+    flush_every_ms: Option<u64>,
+}
+
+impl<A: AsRef<Path>> KeyValueConnector<A> {
+    /// Create a new configuration for the store at `path`
+    pub fn new(path: Option<A>) -> Self {
+        Self {
+            path,
+            cache_capacity: None,
+            flush_every_ms: Some(500),
+        }
+    }
+
+    /// Set the maximum size in bytes of the in-memory page cache
+    pub fn cache_capacity(mut self, bytes: u64) -> Self {
+        self.cache_capacity = Some(bytes);
+        self
+    }
+
+    /// Set the milliseconds between flushing writes to disk
+    pub fn flush_every_ms(mut self, ms: Option<u64>) -> Self {
+        self.flush_every_ms = ms;
+        self
+    }
+}
+
+impl<A> fmt::Display for KeyValueConnector<A>
+where
+    A: AsRef<Path>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "KeyValueConfig (path: {:?}, cache_capacity: {:?}, flush_every_ms: {:?})",
+            self.path.as_ref().map(|p| p.as_ref().as_os_str()),
+            self.cache_capacity,
+            self.flush_every_ms
+        )
+    }
+}
+
+/// Configuration options for connecting to an S3-compatible object store
+/// like AWS S3 or MinIO
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectStoreConnector<B: Into<String>> {
+    /// Base URL of the service e.g. `https://s3.us-east-1.amazonaws.com`
+    /// or `http://localhost:9000`
+    endpoint: B,
+    /// Name of the bucket to store objects in
+    bucket: B,
+    /// Region used when signing requests
+    region: B,
+    /// Access key id for signing requests
+    access_key_id: B,
+    /// Secret access key for signing requests
+    secret_access_key: B,
+    /// Prefix prepended to all object keys so multiple
+    /// stores can share a bucket
+    prefix: Option<B>,
+}
+
+impl<B: Into<String>> ObjectStoreConnector<B> {
+    /// Create a new configuration for the `bucket` at `endpoint`
+    pub fn new(endpoint: B, bucket: B, region: B, access_key_id: B, secret_access_key: B) -> Self {
+        Self {
+            endpoint,
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key,
+            prefix: None,
+        }
+    }
+
+    /// Set the prefix prepended to all object keys
+    pub fn prefix(mut self, prefix: B) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+}
+
+impl<B> fmt::Display for ObjectStoreConnector<B>
+where
+    B: Into<String>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ObjectStoreConfig (endpoint: *********, bucket: *********, region: *********, access_key_id: *********, secret_access_key: *********, prefix: {:?})",
+            self.prefix.as_ref().map(|_| "*********")
+        )
+    }
+}
+
+/// Configuration options for opening a SQLite database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SqliteConnector<A: AsRef<Path>> {
+    /// Path to the database file. If `None`, the database
+    /// is kept in memory and lost when it is closed
+    path: Option<A>,
+}
+
+impl<A: AsRef<Path>> SqliteConnector<A> {
+    /// Create a new configuration for the database at `path`
+    pub fn new(path: Option<A>) -> Self {
+        Self { path }
+    }
+}
+
+impl<A> fmt::Display for SqliteConnector<A>
+where
+    A: AsRef<Path>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SqliteConfig (path: {:?})",
+            self.path.as_ref().map(|p| p.as_ref().as_os_str())
+        )
+    }
+}
+
+/// A tag attached to a record used for looking it up later
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RecordTag {
+    /// A tag that only supports equality lookups. The data protection
+    /// layer may encrypt its name and value before it is persisted.
+    Encrypted(Vec<u8>, Vec<u8>),
+    /// A tag that is always persisted as is and
+    /// can be used for range and pattern lookups
+    Plaintext(Vec<u8>, Vec<u8>),
+}
+
+impl RecordTag {
+    /// The tag name
+    pub fn name(&self) -> &[u8] {
+        match self {
+            RecordTag::Encrypted(n, _) | RecordTag::Plaintext(n, _) => n.as_slice(),
+        }
+    }
+
+    /// The tag value
+    pub fn value(&self) -> &[u8] {
+        match self {
+            RecordTag::Encrypted(_, v) | RecordTag::Plaintext(_, v) => v.as_slice(),
+        }
+    }
+}
+
+/// A single item kept by a persistence backend
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// The group this record belongs to
+    pub category: Vec<u8>,
+    /// The unique name of this record within its category
+    pub name: Vec<u8>,
+    /// The record data
+    pub value: Vec<u8>,
+    /// Tags for looking up the record
+    pub tags: Vec<RecordTag>,
+}
+
+/// All persistence backends should use this trait so the callers
+/// can simply use them without diving into the details
+/// for each unique configuration.
+pub trait PersistenceLike: Sized {
+    /// Establish a connection to the backend
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: PersistenceConnector<A, B>,
+    ) -> PersistenceResult<Self>;
+    /// Close the connection to the backend
+    fn close(self);
+    /// Save a new record. Fails with `DuplicateItem` if a record
+    /// with the same category and name already exists.
+    fn insert(&self, record: Record) -> PersistenceResult<()>;
+    /// Retrieve the record with `name` in `category`
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record>;
+    /// Replace the value and tags of an existing record
+    fn update(&self, record: Record) -> PersistenceResult<()>;
+    /// Remove the record with `name` in `category`
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()>;
+    /// Find all records in `category` whose tags match `query`
+    fn search(&self, category: &[u8], query: &wql::Query) -> PersistenceResult<Vec<Record>>;
+    /// Find all records in `category` that have `tag`
+    fn find_by_tag(&self, category: &[u8], tag: &RecordTag) -> PersistenceResult<Vec<Record>> {
+        self.search(category, &wql::Query::Eq(tag.into(), tag.value().to_vec()))
+    }
+    /// List every category that has records
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>>;
+    /// Find up to `limit` records in `category` whose tags match `query`
+    /// ordered by name, starting after `cursor` if given
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &wql::Query,
+        cursor: Option<&cursor::Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<cursor::Page>;
+    /// Iterate over all records in `category` whose tags match `query`
+    /// reading `page_size` records at a time
+    fn scan(&self, category: &[u8], query: wql::Query, page_size: usize) -> cursor::Scan<'_, Self> {
+        cursor::Scan::new(self, category, query, page_size)
+    }
+    /// Apply all `operations` atomically in order. Fails with `Conflict`
+    /// if a checked record has changed and nothing is applied. Backends that
+    /// can't change several records atomically fail with `UnsupportedOperation`
+    /// when more than one operation is a write.
+    fn apply(&self, operations: Vec<transaction::Operation>) -> PersistenceResult<()>;
+    /// Start a transaction to change multiple records atomically
+    fn transaction(&self) -> transaction::Transaction<'_, Self> {
+        transaction::Transaction::new(self)
+    }
+}
+
+/// Reading search results one page at a time
+pub mod cursor;
+
+/// Embedded key-value persistence backends
+pub mod kv;
+
+/// Upgrading the layout of stores written by older versions
+pub mod migration;
+
+/// Object storage persistence backends
+pub mod object;
+
+/// Persistence backends boxed behind a trait object
+pub mod shared;
+
+/// SQL database persistence backends
+pub mod sql;
+
+/// Behavior every persistence backend shares, checked by their tests
+#[cfg(all(
+    test,
+    any(
+        feature = "storage-memory",
+        feature = "storage-sled",
+        feature = "storage-sqlite"
+    )
+))]
+pub(crate) mod suite;
+
+/// Changing multiple records atomically
+pub mod transaction;
+
+/// Wallet Query Language for searching records by their tags
+pub mod wql;
+
 /// The errors that can occur during a persistence operation
-pub mod errors;
\ No newline at end of file
+pub mod errors;
@@ -0,0 +1,3 @@
+/// Persistence in S3-compatible object storage
+#[cfg(feature = "storage-s3")]
+pub mod s3;
@@ -0,0 +1,913 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Persistence in any S3-compatible object store.
+//!
+//! Agent workers using this backend keep no local state so any number of them
+//! can serve the same store. Requests use path-style addressing and AWS
+//! Signature Version 4 so the same code works against AWS S3 and a local MinIO
+//! instance, e.g. `docker run -p 9000:9000 minio/minio server /data`.
+//!
+//! Each record is an object at `<prefix><hex(category)>/records/<hex(name)>`
+//! holding the record as JSON with its byte strings base64 encoded.
+//! Objects are created with `If-None-Match: *` and replaced or deleted with
+//! `If-Match` on the ETag that was read so concurrent writers are detected
+//! instead of silently overwriting each other. Those cases fail with
+//! `DuplicateItem` and `Conflict` respectively. Servers that ignore `If-Match`
+//! on `DELETE` let a delete win over an update made after it read the record.
+//!
+//! Object stores can't look up objects by their content, so each category has
+//! a manifest at `<prefix><hex(category)>/manifest.json` listing its record
+//! names and their tags which searches are matched against. The manifest is
+//! updated with the same conditional writes and retried when another writer
+//! updated it first. A record object is always written before the manifest,
+//! so a worker that crashes in between leaves a record that can be fetched by
+//! name but is not yet listed. The manifest is sorted by name so pages resume
+//! right after the cursor, but it is read in full for every page. Only the
+//! records in the page are fetched.
+//!
+//! Categories are listed with `ListObjectsV2` and only those with a manifest
+//! are returned. A category stays listed after all its records are deleted
+//! since its manifest remains.
+//!
+//! The schema version is kept in the `<prefix>schema_version` object and
+//! migrated when the store is opened.
+//!
+//! Objects can't be changed together atomically so transactions committed
+//! with `apply` may change at most one record. The records read in the
+//! transaction are checked first and then the change is written. Another
+//! writer can change a checked record in between which is not detected,
+//! unless it is the record being updated or deleted since that still uses
+//! `If-Match`.
+
+use crate::persistence::{
+    cursor::{Cursor, Page},
+    errors::{PersistenceError, PersistenceErrorKind},
+    migration::{self, Migration},
+    transaction::Operation,
+    wql::Query,
+    PersistenceConnector, PersistenceLike, PersistenceResult, Record, RecordTag,
+};
+
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroizing;
+
+/// The number of times a manifest update is retried before giving up
+const MAX_MANIFEST_RETRIES: usize = 16;
+
+/// The object under the prefix holding the schema version as a decimal number
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// The schema migrations in order. Another worker may run the same
+/// migration concurrently so they must be safe to run more than once.
+static MIGRATIONS: &[Migration<S3Store>] = &[Migration {
+    version: 1,
+    description: "Keep records and a manifest per category",
+    up: |_| Ok(()),
+}];
+
+/// A persistence backend stored in an S3-compatible bucket
+pub struct S3Store {
+    agent: ureq::Agent,
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: Zeroizing<String>,
+    prefix: String,
+}
+
+/// Conditions under which an object may be written
+enum Precondition<'a> {
+    /// Only write if the object does not exist
+    IfNoneMatch,
+    /// Only write if the object still has this ETag
+    IfMatch(&'a str),
+}
+
+/// The record names and tags in a category
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    /// Tags keyed by the hex encoded record name
+    records: BTreeMap<String, Vec<StoredTag>>,
+}
+
+/// A record as it is written to its object
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    #[serde(with = "base64_bytes")]
+    category: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    name: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    value: Vec<u8>,
+    tags: Vec<StoredTag>,
+}
+
+/// A tag as it is written to record objects and manifests
+#[derive(Serialize, Deserialize)]
+enum StoredTag {
+    Encrypted(
+        #[serde(with = "base64_bytes")] Vec<u8>,
+        #[serde(with = "base64_bytes")] Vec<u8>,
+    ),
+    Plaintext(
+        #[serde(with = "base64_bytes")] Vec<u8>,
+        #[serde(with = "base64_bytes")] Vec<u8>,
+    ),
+}
+
+impl From<&RecordTag> for StoredTag {
+    fn from(tag: &RecordTag) -> Self {
+        match tag.clone() {
+            RecordTag::Encrypted(name, value) => StoredTag::Encrypted(name, value),
+            RecordTag::Plaintext(name, value) => StoredTag::Plaintext(name, value),
+        }
+    }
+}
+
+impl From<&StoredTag> for RecordTag {
+    fn from(tag: &StoredTag) -> Self {
+        match tag {
+            StoredTag::Encrypted(name, value) => RecordTag::Encrypted(name.clone(), value.clone()),
+            StoredTag::Plaintext(name, value) => RecordTag::Plaintext(name.clone(), value.clone()),
+        }
+    }
+}
+
+fn stored_tags(tags: &[RecordTag]) -> Vec<StoredTag> {
+    tags.iter().map(StoredTag::from).collect()
+}
+
+fn encode_record(record: &Record) -> PersistenceResult<Vec<u8>> {
+    let stored = StoredRecord {
+        category: record.category.clone(),
+        name: record.name.clone(),
+        value: record.value.clone(),
+        tags: stored_tags(&record.tags),
+    };
+    serde_json::to_vec(&stored).map_err(serialization)
+}
+
+fn decode_record(data: &[u8]) -> PersistenceResult<Record> {
+    let stored: StoredRecord = serde_json::from_slice(data).map_err(serialization)?;
+    Ok(Record {
+        category: stored.category,
+        name: stored.name,
+        value: stored.value,
+        tags: stored.tags.iter().map(RecordTag::from).collect(),
+    })
+}
+
+/// Byte strings as base64 instead of arrays of numbers
+mod base64_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::decode(&encoded).map_err(D::Error::custom)
+    }
+}
+
+impl S3Store {
+    fn record_key(&self, category: &[u8], name: &[u8]) -> String {
+        format!(
+            "{}{}/records/{}",
+            self.prefix,
+            hex::encode(category),
+            hex::encode(name)
+        )
+    }
+
+    fn manifest_key(&self, category: &[u8]) -> String {
+        format!("{}{}/manifest.json", self.prefix, hex::encode(category))
+    }
+
+    /// Send a signed request for the object at `key`
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        body: &[u8],
+        precondition: Option<Precondition>,
+    ) -> PersistenceResult<ureq::Response> {
+        self.send_query(method, key, &[], body, precondition)
+    }
+
+    /// Send a signed request for the object at `key` with the query parameters `query`
+    fn send_query(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        body: &[u8],
+        precondition: Option<Precondition>,
+    ) -> PersistenceResult<ureq::Response> {
+        let path = format!("/{}/{}", uri_encode(&self.bucket), uri_encode(key));
+        let query = canonical_query(query);
+        let payload_hash = hex::encode(Sha256::digest(body));
+        let timestamp = amz_date(SystemTime::now());
+        let (scope, signature) = SignedRequest {
+            method,
+            path: &path,
+            query: &query,
+            host: &self.host,
+            payload_hash: &payload_hash,
+            timestamp: &timestamp,
+        }
+        .sign(&self.region, &self.secret_access_key);
+
+        let url = if query.is_empty() {
+            format!("{}{}", self.endpoint, path)
+        } else {
+            format!("{}{}?{}", self.endpoint, path, query)
+        };
+        let mut request = self.agent.request(method, &url);
+        request
+            .set("Host", &self.host)
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("x-amz-date", &timestamp)
+            .set(
+                "Authorization",
+                &format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                    self.access_key_id, scope, signature
+                ),
+            );
+        match precondition {
+            Some(Precondition::IfNoneMatch) => {
+                request.set("If-None-Match", "*");
+            }
+            Some(Precondition::IfMatch(etag)) => {
+                request.set("If-Match", etag);
+            }
+            None => {}
+        }
+        let response = if body.is_empty() {
+            request.call()
+        } else {
+            request.send_bytes(body)
+        };
+        match response.synthetic_error() {
+            Some(e) => Err(PersistenceError::from_msg(
+                PersistenceErrorKind::IOError,
+                e.to_string(),
+            )),
+            None => Ok(response),
+        }
+    }
+
+    /// Read an object and its ETag. Returns `None` if it doesn't exist.
+    fn get_object(&self, key: &str) -> PersistenceResult<Option<(Vec<u8>, String)>> {
+        let response = self.send("GET", key, &[], None)?;
+        match response.status() {
+            200 => {
+                let etag = response.header("ETag").unwrap_or_default().to_string();
+                let mut data = Vec::new();
+                std::io::copy(&mut response.into_reader(), &mut data).map_err(|e| {
+                    PersistenceError::from_msg(PersistenceErrorKind::IOError, e.to_string())
+                })?;
+                Ok(Some((data, etag)))
+            }
+            404 => Ok(None),
+            _ => Err(unexpected(response)),
+        }
+    }
+
+    /// Check whether an object exists without reading it
+    fn object_exists(&self, key: &str) -> PersistenceResult<bool> {
+        let response = self.send("HEAD", key, &[], None)?;
+        match response.status() {
+            200 => Ok(true),
+            404 => Ok(false),
+            _ => Err(unexpected(response)),
+        }
+    }
+
+    /// Write an object. Returns `false` if the precondition failed.
+    fn put_object(
+        &self,
+        key: &str,
+        data: &[u8],
+        precondition: Precondition,
+    ) -> PersistenceResult<bool> {
+        let response = self.send("PUT", key, data, Some(precondition))?;
+        match response.status() {
+            200 => Ok(true),
+            409 | 412 => Ok(false),
+            _ => Err(unexpected(response)),
+        }
+    }
+
+    /// Delete an object. Returns `false` if the precondition failed
+    /// or the object was already deleted.
+    fn delete_object(&self, key: &str, precondition: Precondition) -> PersistenceResult<bool> {
+        let response = self.send("DELETE", key, &[], Some(precondition))?;
+        match response.status() {
+            200 | 204 => Ok(true),
+            404 | 409 | 412 => Ok(false),
+            _ => Err(unexpected(response)),
+        }
+    }
+
+    /// List the "directories" directly below `prefix`
+    fn list_prefixes(&self, prefix: &str) -> PersistenceResult<Vec<String>> {
+        let mut prefixes = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("delimiter", "/"), ("list-type", "2"), ("prefix", prefix)];
+            if let Some(ref t) = token {
+                query.push(("continuation-token", t.as_str()));
+            }
+            let response = self.send_query("GET", "", &query, &[], None)?;
+            if response.status() != 200 {
+                return Err(unexpected(response));
+            }
+            let body = response.into_string().map_err(|e| {
+                PersistenceError::from_msg(PersistenceErrorKind::IOError, e.to_string())
+            })?;
+            prefixes.extend(xml_values(&body, "CommonPrefixes", "Prefix"));
+            token = xml_value(&body, "NextContinuationToken");
+            if token.is_none() {
+                return Ok(prefixes);
+            }
+        }
+    }
+
+    /// Read, change and conditionally write back the manifest for `category`,
+    /// starting over whenever another writer changed it in the meantime
+    fn modify_manifest<F>(&self, category: &[u8], f: F) -> PersistenceResult<()>
+    where
+        F: Fn(&mut Manifest),
+    {
+        let key = self.manifest_key(category);
+        for _ in 0..MAX_MANIFEST_RETRIES {
+            let (mut manifest, etag) = match self.get_object(&key)? {
+                Some((data, etag)) => (
+                    serde_json::from_slice(&data).map_err(serialization)?,
+                    Some(etag),
+                ),
+                None => (Manifest::default(), None),
+            };
+            f(&mut manifest);
+            let data = serde_json::to_vec(&manifest).map_err(serialization)?;
+            let precondition = match etag {
+                Some(ref e) => Precondition::IfMatch(e),
+                None => Precondition::IfNoneMatch,
+            };
+            if self.put_object(&key, &data, precondition)? {
+                return Ok(());
+            }
+        }
+        Err(PersistenceError::from_msg(
+            PersistenceErrorKind::Conflict,
+            "Too many concurrent updates to the manifest",
+        ))
+    }
+
+    fn update_if_match(&self, record: Record, etag: &str) -> PersistenceResult<()> {
+        let data = encode_record(&record)?;
+        let key = self.record_key(&record.category, &record.name);
+        if !self.put_object(&key, &data, Precondition::IfMatch(etag))? {
+            return Err(PersistenceErrorKind::Conflict.into());
+        }
+        let name = hex::encode(&record.name);
+        self.modify_manifest(&record.category, |m| {
+            m.records.insert(name.clone(), stored_tags(&record.tags));
+        })
+    }
+
+    fn delete_if_match(&self, category: &[u8], name: &[u8], etag: &str) -> PersistenceResult<()> {
+        let key = self.record_key(category, name);
+        if !self.delete_object(&key, Precondition::IfMatch(etag))? {
+            return Err(PersistenceErrorKind::Conflict.into());
+        }
+        let name = hex::encode(name);
+        self.modify_manifest(category, |m| {
+            m.records.remove(&name);
+        })
+    }
+
+    /// Run every pending migration. The version is bumped with a conditional
+    /// write after each one and reread when another worker bumped it first.
+    fn migrate(&self) -> PersistenceResult<()> {
+        let key = format!("{}{}", self.prefix, SCHEMA_VERSION_KEY);
+        loop {
+            let (current, etag) = match self.get_object(&key)? {
+                Some((data, etag)) => {
+                    let version = std::str::from_utf8(&data)
+                        .map_err(serialization)?
+                        .trim()
+                        .parse::<u32>()
+                        .map_err(serialization)?;
+                    (version, Some(etag))
+                }
+                None => (0, None),
+            };
+            let m = match migration::pending(current, MIGRATIONS)?.first() {
+                Some(m) => m,
+                None => return Ok(()),
+            };
+            (m.up)(self)?;
+            let precondition = match etag {
+                Some(ref e) => Precondition::IfMatch(e),
+                None => Precondition::IfNoneMatch,
+            };
+            self.put_object(&key, m.version.to_string().as_bytes(), precondition)?;
+        }
+    }
+
+    /// Find up to `limit` matches ordered by name starting after the record `after`
+    fn search_from(
+        &self,
+        category: &[u8],
+        query: &Query,
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> PersistenceResult<Vec<Record>> {
+        let manifest: Manifest = match self.get_object(&self.manifest_key(category))? {
+            Some((data, _)) => serde_json::from_slice(&data).map_err(serialization)?,
+            None => return Ok(Vec::new()),
+        };
+        // Hex encoded names sort in the same order as the names
+        let start = match after {
+            Some(a) => Bound::Excluded(hex::encode(a)),
+            None => Bound::Unbounded,
+        };
+        let mut found = Vec::new();
+        for (name, tags) in manifest.records.range((start, Bound::Unbounded)) {
+            if found.len() >= limit {
+                break;
+            }
+            let tags: Vec<RecordTag> = tags.iter().map(RecordTag::from).collect();
+            if !query.matches(&tags) {
+                continue;
+            }
+            let name = hex::decode(name).map_err(serialization)?;
+            // The record may have been removed since the manifest was read
+            match self.fetch(category, &name) {
+                Ok(r) => found.push(r),
+                Err(ref e) if e.kind() == PersistenceErrorKind::ItemNotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(found)
+    }
+
+    fn fetch_with_etag(&self, category: &[u8], name: &[u8]) -> PersistenceResult<(Record, String)> {
+        match self.get_object(&self.record_key(category, name))? {
+            Some((data, etag)) => Ok((decode_record(&data)?, etag)),
+            None => Err(PersistenceErrorKind::ItemNotFound.into()),
+        }
+    }
+}
+
+impl PersistenceLike for S3Store {
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: PersistenceConnector<A, B>,
+    ) -> PersistenceResult<Self> {
+        match config {
+            PersistenceConnector::ObjectStore(c) => {
+                let endpoint = c.endpoint.into().trim_end_matches('/').to_string();
+                let host = endpoint_host(&endpoint)
+                    .ok_or_else(|| {
+                        PersistenceError::from_msg(
+                            PersistenceErrorKind::InvalidConfig,
+                            "The endpoint must be a URL like https://host[:port]",
+                        )
+                    })?
+                    .to_string();
+                let mut prefix = c.prefix.map(|p| p.into()).unwrap_or_default();
+                if !prefix.is_empty() && !prefix.ends_with('/') {
+                    prefix.push('/');
+                }
+                let store = Self {
+                    agent: ureq::agent(),
+                    endpoint,
+                    host,
+                    bucket: c.bucket.into(),
+                    region: c.region.into(),
+                    access_key_id: c.access_key_id.into(),
+                    secret_access_key: Zeroizing::new(c.secret_access_key.into()),
+                    prefix,
+                };
+                store.migrate()?;
+                Ok(store)
+            }
+            _ => Err(PersistenceError::from_msg(
+                PersistenceErrorKind::InvalidConfig,
+                format!(
+                    "Invalid configuration type. Expected ObjectStore but found {}",
+                    config
+                ),
+            )),
+        }
+    }
+
+    fn close(self) {}
+
+    fn insert(&self, record: Record) -> PersistenceResult<()> {
+        let data = encode_record(&record)?;
+        let key = self.record_key(&record.category, &record.name);
+        if !self.put_object(&key, &data, Precondition::IfNoneMatch)? {
+            return Err(PersistenceErrorKind::DuplicateItem.into());
+        }
+        let name = hex::encode(&record.name);
+        self.modify_manifest(&record.category, |m| {
+            m.records.insert(name.clone(), stored_tags(&record.tags));
+        })
+    }
+
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        self.fetch_with_etag(category, name).map(|(r, _)| r)
+    }
+
+    fn update(&self, record: Record) -> PersistenceResult<()> {
+        let (_, etag) = self.fetch_with_etag(&record.category, &record.name)?;
+        self.update_if_match(record, &etag)
+    }
+
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+        let (_, etag) = self.fetch_with_etag(category, name)?;
+        self.delete_if_match(category, name, &etag)
+    }
+
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>> {
+        let mut categories = Vec::new();
+        for p in self.list_prefixes(&self.prefix)? {
+            let name = p[self.prefix.len()..].trim_end_matches('/');
+            // Skip anything under the prefix that wasn't written by this
+            // backend and categories whose first record was written by a
+            // worker that stopped before creating the manifest
+            if let Ok(category) = hex::decode(name) {
+                if self.object_exists(&self.manifest_key(&category))? {
+                    categories.push(category);
+                }
+            }
+        }
+        Ok(categories)
+    }
+
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>> {
+        self.search_from(category, query, None, usize::MAX)
+    }
+
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page> {
+        let found = self.search_from(
+            category,
+            query,
+            cursor.map(Cursor::after),
+            limit.saturating_add(1),
+        )?;
+        Ok(Page::new(found, limit))
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()> {
+        if operations.iter().filter(|op| op.is_write()).count() > 1 {
+            return Err(PersistenceError::from_msg(
+                PersistenceErrorKind::UnsupportedOperation,
+                "Object stores can only change one record in a transaction",
+            ));
+        }
+        // Check every record read before writing anything
+        let (checks, writes): (Vec<_>, Vec<_>) =
+            operations.into_iter().partition(|op| !op.is_write());
+        let mut etags = BTreeMap::new();
+        for op in checks.iter().chain(writes.iter()) {
+            match op {
+                Operation::Check {
+                    category,
+                    name,
+                    expected,
+                } => {
+                    let current = match self.fetch_with_etag(category, name) {
+                        Ok((r, etag)) => {
+                            etags.insert(self.record_key(category, name), etag);
+                            Some(r)
+                        }
+                        Err(ref e) if e.kind() == PersistenceErrorKind::ItemNotFound => None,
+                        Err(e) => return Err(e),
+                    };
+                    if current != *expected {
+                        return Err(PersistenceErrorKind::Conflict.into());
+                    }
+                }
+                Operation::Insert(r) => self.insert(r.clone())?,
+                Operation::Update(r) => match etags.get(&self.record_key(&r.category, &r.name)) {
+                    Some(etag) => self.update_if_match(r.clone(), etag)?,
+                    None => self.update(r.clone())?,
+                },
+                Operation::Delete { category, name } => {
+                    match etags.get(&self.record_key(category, name)) {
+                        Some(etag) => self.delete_if_match(category, name, etag)?,
+                        None => self.delete(category, name)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The host and port of an endpoint like `https://host[:port]`
+fn endpoint_host(endpoint: &str) -> Option<&str> {
+    let start = endpoint.find("://")? + 3;
+    endpoint[start..]
+        .split('/')
+        .next()
+        .filter(|h| !h.is_empty())
+}
+
+fn serialization<E: std::fmt::Display>(e: E) -> PersistenceError {
+    PersistenceError::from_msg(PersistenceErrorKind::SerializationError, e.to_string())
+}
+
+fn unexpected(response: ureq::Response) -> PersistenceError {
+    let status = response.status();
+    let kind = match status {
+        400 | 403 | 404 => PersistenceErrorKind::InvalidConfig,
+        _ => PersistenceErrorKind::IOError,
+    };
+    let body = response.into_string().unwrap_or_default();
+    PersistenceError::from_msg(kind, format!("Unexpected response {}: {}", status, body))
+}
+
+/// The parts of a request covered by its Signature Version 4 signature.
+/// Only the `host`, `x-amz-content-sha256` and `x-amz-date` headers are signed.
+struct SignedRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    query: &'a str,
+    host: &'a str,
+    payload_hash: &'a str,
+    timestamp: &'a str,
+}
+
+impl SignedRequest<'_> {
+    /// The credential scope and signature of the request sent to `region`
+    fn sign(&self, region: &str, secret_access_key: &str) -> (String, String) {
+        let date = &self.timestamp[..8];
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            self.method,
+            self.path,
+            self.query,
+            self.host,
+            self.payload_hash,
+            self.timestamp,
+            self.payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            self.timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let secret = Zeroizing::new(format!("AWS4{}", secret_access_key));
+        let mut signing_key = hmac_sha256(secret.as_bytes(), date.as_bytes());
+        for part in &[region, "s3", "aws4_request"] {
+            signing_key = hmac_sha256(&signing_key, part.as_bytes());
+        }
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+        (scope, signature)
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Encode query parameters sorted by name as Signature Version 4 requires
+/// both in the canonical request and the URL that was signed
+fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut pairs = query
+        .iter()
+        .map(|(k, v)| (query_encode(k), query_encode(v)))
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Percent encode everything except unreserved characters and `/`
+fn uri_encode(s: &str) -> String {
+    percent_encode(s, b"/")
+}
+
+/// Percent encode everything except unreserved characters
+fn query_encode(s: &str) -> String {
+    percent_encode(s, b"")
+}
+
+fn percent_encode(s: &str, keep: &[u8]) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ if keep.contains(&b) => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// The text of every `<tag>` element in `xml` that is inside a `<parent>` element.
+/// S3 list responses are flat enough that this avoids a full XML parser.
+fn xml_values(xml: &str, parent: &str, tag: &str) -> Vec<String> {
+    let parent_close = format!("</{}>", parent);
+    xml.split(&format!("<{}>", parent))
+        .skip(1)
+        .filter_map(|p| p.split(&parent_close).next())
+        .filter_map(|p| xml_value(p, tag))
+        .collect()
+}
+
+/// The text of the first `<tag>` element in `xml`
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(xml_unescape(&xml[start..end]))
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Format `time` as the `YYYYMMDD'T'HHMMSS'Z'` timestamp used by
+/// Signature Version 4. The first eight characters are the date.
+fn amz_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // Convert days since the epoch to a civil date.
+    // See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        (rem % 3_600) / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const SECRET_ACCESS_KEY: &str = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+    const EMPTY_PAYLOAD_HASH: &str =
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    // The examples of the Amazon S3 documentation for signing requests
+    // with the payload in a single chunk
+    fn sign(query: &[(&str, &str)]) -> (String, String) {
+        SignedRequest {
+            method: "GET",
+            path: "/",
+            query: &canonical_query(query),
+            host: "examplebucket.s3.amazonaws.com",
+            payload_hash: EMPTY_PAYLOAD_HASH,
+            timestamp: "20130524T000000Z",
+        }
+        .sign("us-east-1", SECRET_ACCESS_KEY)
+    }
+
+    #[test]
+    fn signature_v4_get_bucket_lifecycle() {
+        let (scope, signature) = sign(&[("lifecycle", "")]);
+        assert_eq!(scope, "20130524/us-east-1/s3/aws4_request");
+        assert_eq!(
+            signature,
+            "fea454ca298b7da1c68078a5d1bdbfbbe0d65c699e0f91ac7a200a0136783543"
+        );
+    }
+
+    #[test]
+    fn signature_v4_list_objects() {
+        // The query is signed sorted by name whatever order it is given in
+        let (_, signature) = sign(&[("prefix", "J"), ("max-keys", "2")]);
+        assert_eq!(
+            signature,
+            "34b48302e7b5fa45bde8084f4b7868a86f0a534bc59db6670ed5711ef69dc6f7"
+        );
+    }
+
+    #[test]
+    fn query_encoding() {
+        assert_eq!(
+            canonical_query(&[("prefix", "a/b c"), ("list-type", "2")]),
+            "list-type=2&prefix=a%2Fb%20c"
+        );
+        assert_eq!(uri_encode("p/a b~"), "p/a%20b~");
+    }
+
+    #[test]
+    fn records_are_base64_encoded() {
+        let record = Record {
+            category: b"category".to_vec(),
+            name: vec![0, 255],
+            value: vec![1, 2, 3],
+            tags: vec![
+                RecordTag::Encrypted(b"e".to_vec(), vec![]),
+                RecordTag::Plaintext(b"p".to_vec(), b"v".to_vec()),
+            ],
+        };
+        let data = encode_record(&record).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&data).unwrap(),
+            r#"{"category":"Y2F0ZWdvcnk=","name":"AP8=","value":"AQID","tags":[{"Encrypted":["ZQ==",""]},{"Plaintext":["cA==","dg=="]}]}"#
+        );
+        assert_eq!(decode_record(&data).unwrap(), record);
+
+        for data in &[
+            &br#"{"category":[1],"name":"","value":"","tags":[]}"#[..],
+            br#"{"category":"not base64!","name":"","value":"","tags":[]}"#,
+            br#"{"category":"","name":"","value":"","tags":[{"Encrypted":["@@"," "]}]}"#,
+        ] {
+            let err = decode_record(data).unwrap_err();
+            assert_eq!(err.kind(), PersistenceErrorKind::SerializationError);
+        }
+    }
+
+    #[test]
+    fn endpoint_hosts() {
+        let table = [
+            ("https://s3.amazonaws.com", Some("s3.amazonaws.com")),
+            ("http://127.0.0.1:9000", Some("127.0.0.1:9000")),
+            ("http://minio:9000/path", Some("minio:9000")),
+            ("http://", None),
+            ("s3.amazonaws.com", None),
+        ];
+        for (endpoint, host) in table.iter() {
+            assert_eq!(endpoint_host(endpoint), *host, "{}", endpoint);
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_369_353_600);
+        assert_eq!(amz_date(time), "20130524T000000Z");
+        let time = UNIX_EPOCH + Duration::from_secs(951_825_599);
+        assert_eq!(amz_date(time), "20000229T115959Z");
+    }
+}
@@ -0,0 +1,159 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Persistence backends whose type is only known at runtime, like the
+//! ones opened through language bindings, are boxed behind a trait object.
+
+use super::{
+    cursor::{Cursor, Page},
+    errors::PersistenceErrorKind,
+    transaction::Operation,
+    wql::Query,
+    PersistenceConnector, PersistenceLike, PersistenceResult, Record,
+};
+
+use std::{fmt, path::Path};
+
+/// The persistence methods that can be called through a trait object
+trait DynPersistenceLike {
+    fn close(self: Box<Self>);
+    fn insert(&self, record: Record) -> PersistenceResult<()>;
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record>;
+    fn update(&self, record: Record) -> PersistenceResult<()>;
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()>;
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>>;
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>>;
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page>;
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()>;
+}
+
+impl<P: PersistenceLike> DynPersistenceLike for P {
+    fn close(self: Box<Self>) {
+        PersistenceLike::close(*self)
+    }
+
+    fn insert(&self, record: Record) -> PersistenceResult<()> {
+        PersistenceLike::insert(self, record)
+    }
+
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        PersistenceLike::fetch(self, category, name)
+    }
+
+    fn update(&self, record: Record) -> PersistenceResult<()> {
+        PersistenceLike::update(self, record)
+    }
+
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+        PersistenceLike::delete(self, category, name)
+    }
+
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>> {
+        PersistenceLike::search(self, category, query)
+    }
+
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>> {
+        PersistenceLike::categories(self)
+    }
+
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page> {
+        PersistenceLike::search_page(self, category, query, cursor, limit)
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()> {
+        PersistenceLike::apply(self, operations)
+    }
+}
+
+/// A persistence backend of any type that can be used from any thread
+pub struct BoxedPersistence(Box<dyn DynPersistenceLike + Send + Sync>);
+
+impl BoxedPersistence {
+    /// Box `persistence`
+    pub fn new<P: PersistenceLike + Send + Sync + 'static>(persistence: P) -> Self {
+        Self(Box::new(persistence))
+    }
+}
+
+impl fmt::Debug for BoxedPersistence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxedPersistence").finish()
+    }
+}
+
+impl PersistenceLike for BoxedPersistence {
+    /// Boxed backends are created from connected backends with `new`
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: PersistenceConnector<A, B>,
+    ) -> PersistenceResult<Self> {
+        let _ = config;
+        Err(PersistenceErrorKind::InvalidConfig.into())
+    }
+
+    fn close(self) {
+        self.0.close()
+    }
+
+    fn insert(&self, record: Record) -> PersistenceResult<()> {
+        self.0.insert(record)
+    }
+
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        self.0.fetch(category, name)
+    }
+
+    fn update(&self, record: Record) -> PersistenceResult<()> {
+        self.0.update(record)
+    }
+
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+        self.0.delete(category, name)
+    }
+
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>> {
+        self.0.search(category, query)
+    }
+
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>> {
+        self.0.categories()
+    }
+
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page> {
+        self.0.search_page(category, query, cursor, limit)
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()> {
+        self.0.apply(operations)
+    }
+}
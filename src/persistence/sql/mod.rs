@@ -0,0 +1,3 @@
+/// Persistence in a SQLite database
+#[cfg(feature = "storage-sqlite")]
+pub mod sqlite;
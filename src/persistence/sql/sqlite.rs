@@ -0,0 +1,487 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Persistence in a single SQLite database file.
+//!
+//! Records are rows in the `records` table and each tag is a row in the
+//! `tags` table that refers to its record. WQL queries are translated into
+//! a `WHERE` clause with one subquery on `tags` per comparison so SQLite
+//! can use the tag index to find matches. SQLite's `LIKE` matches characters
+//! rather than bytes so `$like` is only narrowed down in SQL and then matched
+//! in process like the other backends do. Results are ordered by name and
+//! pages continue with `name > cursor`, which the `UNIQUE (category, name)`
+//! index serves without sorting.
+//!
+//! The schema version is kept in SQLite's `user_version` and
+//! migrated when the store is opened.
+//!
+//! Transactions committed with `apply` run in a single `IMMEDIATE` SQLite
+//! transaction. It takes the database write lock before the first check so
+//! they are serializable.
+
+use crate::persistence::{
+    cursor::{Cursor, Page},
+    errors::{PersistenceError, PersistenceErrorKind},
+    migration::{self, Migration},
+    transaction::Operation,
+    wql::{Query, TagName},
+    PersistenceConnector, PersistenceLike, PersistenceResult, Record, RecordTag,
+};
+
+use rusqlite::{params, Connection, ToSql, TransactionBehavior, NO_PARAMS};
+use std::{
+    convert::TryFrom,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+/// Settings that only last for a connection so they are applied on every open
+const PRAGMAS: &str = "
+    PRAGMA foreign_keys = ON;
+";
+
+/// The tables every store starts with
+const SCHEMA_V1: &str = "
+    CREATE TABLE records (
+        id INTEGER PRIMARY KEY,
+        category BLOB NOT NULL,
+        name BLOB NOT NULL,
+        value BLOB NOT NULL,
+        UNIQUE (category, name)
+    );
+    CREATE TABLE tags (
+        record_id INTEGER NOT NULL REFERENCES records (id) ON DELETE CASCADE,
+        plaintext INTEGER NOT NULL,
+        name BLOB NOT NULL,
+        value BLOB NOT NULL
+    );
+    CREATE INDEX ix_tags_name_value ON tags (plaintext, name, value);
+    CREATE INDEX ix_tags_record_id ON tags (record_id);
+";
+
+/// The schema migrations in order
+static MIGRATIONS: &[Migration<Connection>] = &[Migration {
+    version: 1,
+    description: "Create the records and tags tables",
+    up: |conn| Ok(conn.execute_batch(SCHEMA_V1)?),
+}];
+
+/// A persistence backend stored in a SQLite database
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        // A panic while holding the lock can't leave a transaction
+        // half applied since it is rolled back when dropped
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl PersistenceLike for SqliteStore {
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: PersistenceConnector<A, B>,
+    ) -> PersistenceResult<Self> {
+        match config {
+            PersistenceConnector::Sqlite(c) => {
+                let mut conn = match c.path {
+                    Some(p) => Connection::open(p.as_ref())?,
+                    None => Connection::open_in_memory()?,
+                };
+                conn.execute_batch(PRAGMAS)?;
+                migrate(&mut conn)?;
+                Ok(Self {
+                    conn: Mutex::new(conn),
+                })
+            }
+            _ => Err(PersistenceError::from_msg(
+                PersistenceErrorKind::InvalidConfig,
+                format!(
+                    "Invalid configuration type. Expected Sqlite but found {}",
+                    config
+                ),
+            )),
+        }
+    }
+
+    fn close(self) {}
+
+    fn insert(&self, record: Record) -> PersistenceResult<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        insert_record(&tx, &record)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn fetch(&self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        fetch_record(&self.conn(), category, name)
+    }
+
+    fn update(&self, record: Record) -> PersistenceResult<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        update_record(&tx, &record)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete(&self, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+        delete_record(&self.conn(), category, name)
+    }
+
+    fn categories(&self) -> PersistenceResult<Vec<Vec<u8>>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT DISTINCT category FROM records ORDER BY category")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
+        let mut categories = Vec::new();
+        for category in rows {
+            categories.push(category?);
+        }
+        Ok(categories)
+    }
+
+    fn search(&self, category: &[u8], query: &Query) -> PersistenceResult<Vec<Record>> {
+        search_from(&self.conn(), category, query, None, -1)
+    }
+
+    fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> PersistenceResult<Page> {
+        // One extra row tells whether there is another page
+        let rows = i64::try_from(limit).unwrap_or(i64::MAX).saturating_add(1);
+        let found = search_from(
+            &self.conn(),
+            category,
+            query,
+            cursor.map(Cursor::after),
+            rows,
+        )?;
+        Ok(Page::new(found, limit))
+    }
+
+    fn apply(&self, operations: Vec<Operation>) -> PersistenceResult<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for op in &operations {
+            match op {
+                Operation::Check {
+                    category,
+                    name,
+                    expected,
+                } => {
+                    let current = match fetch_record(&tx, category, name) {
+                        Ok(r) => Some(r),
+                        Err(ref e) if e.kind() == PersistenceErrorKind::ItemNotFound => None,
+                        Err(e) => return Err(e),
+                    };
+                    if current != *expected {
+                        return Err(PersistenceErrorKind::Conflict.into());
+                    }
+                }
+                Operation::Insert(r) => insert_record(&tx, r)?,
+                Operation::Update(r) => update_record(&tx, r)?,
+                Operation::Delete { category, name } => delete_record(&tx, category, name)?,
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Run every pending migration. Each one runs in its own transaction that
+/// also reads and bumps `user_version` so concurrent openers don't both run it.
+fn migrate(conn: &mut Connection) -> PersistenceResult<()> {
+    loop {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current: u32 = tx.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+        let m = match migration::pending(current, MIGRATIONS)?.first() {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        (m.up)(&tx)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", m.version))?;
+        tx.commit()?;
+    }
+}
+
+/// Find up to `limit` matches ordered by name starting after the record
+/// `after`. A negative `limit` returns all matches.
+fn search_from(
+    conn: &Connection,
+    category: &[u8],
+    query: &Query,
+    after: Option<&[u8]>,
+    limit: i64,
+) -> PersistenceResult<Vec<Record>> {
+    let mut values = vec![category.to_vec()];
+    let mut sql = format!(
+        "SELECT id, name, value FROM records WHERE category = ? AND ({})",
+        query_to_sql(query, false, &mut values)
+    );
+    if let Some(a) = after {
+        sql.push_str(" AND name > ?");
+        values.push(a.to_vec());
+    }
+    // Rows that only might match can't count towards the limit in SQL
+    let exact = !contains_like(query);
+    if exact {
+        sql.push_str(&format!(" ORDER BY name LIMIT {}", limit));
+    } else {
+        sql.push_str(" ORDER BY name");
+    }
+    let mut stmt = conn.prepare(&sql)?;
+    let params: Vec<&dyn ToSql> = values.iter().map(|v| v as &dyn ToSql).collect();
+    let rows = stmt.query_map(&params[..], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?))
+    })?;
+    let mut found = Vec::new();
+    for row in rows {
+        if !exact && limit >= 0 && found.len() as i64 >= limit {
+            break;
+        }
+        let (id, name, value) = row?;
+        let tags = fetch_tags(conn, id)?;
+        if exact || query.matches(&tags) {
+            found.push(Record {
+                category: category.to_vec(),
+                name,
+                value,
+                tags,
+            });
+        }
+    }
+    Ok(found)
+}
+
+fn insert_record(conn: &Connection, record: &Record) -> PersistenceResult<()> {
+    conn.execute(
+        "INSERT INTO records (category, name, value) VALUES (?1, ?2, ?3)",
+        params![record.category, record.name, record.value],
+    )?;
+    let id = conn.last_insert_rowid();
+    insert_tags(conn, id, &record.tags)
+}
+
+fn fetch_record(conn: &Connection, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+    let (id, value): (i64, Vec<u8>) = conn.query_row(
+        "SELECT id, value FROM records WHERE category = ?1 AND name = ?2",
+        params![category, name],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(Record {
+        category: category.to_vec(),
+        name: name.to_vec(),
+        value,
+        tags: fetch_tags(conn, id)?,
+    })
+}
+
+fn update_record(conn: &Connection, record: &Record) -> PersistenceResult<()> {
+    let id: i64 = conn.query_row(
+        "SELECT id FROM records WHERE category = ?1 AND name = ?2",
+        params![record.category, record.name],
+        |row| row.get(0),
+    )?;
+    conn.execute(
+        "UPDATE records SET value = ?1 WHERE id = ?2",
+        params![record.value, id],
+    )?;
+    conn.execute("DELETE FROM tags WHERE record_id = ?1", params![id])?;
+    insert_tags(conn, id, &record.tags)
+}
+
+fn delete_record(conn: &Connection, category: &[u8], name: &[u8]) -> PersistenceResult<()> {
+    let deleted = conn.execute(
+        "DELETE FROM records WHERE category = ?1 AND name = ?2",
+        params![category, name],
+    )?;
+    if deleted == 0 {
+        return Err(PersistenceErrorKind::ItemNotFound.into());
+    }
+    Ok(())
+}
+
+fn insert_tags(conn: &Connection, id: i64, tags: &[RecordTag]) -> PersistenceResult<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO tags (record_id, plaintext, name, value) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for tag in tags {
+        let plaintext = match tag {
+            RecordTag::Encrypted(..) => 0,
+            RecordTag::Plaintext(..) => 1,
+        };
+        stmt.execute(params![id, plaintext, tag.name(), tag.value()])?;
+    }
+    Ok(())
+}
+
+fn fetch_tags(conn: &Connection, id: i64) -> PersistenceResult<Vec<RecordTag>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT plaintext, name, value FROM tags WHERE record_id = ?1 ORDER BY rowid",
+    )?;
+    let rows = stmt.query_map(params![id], |row| {
+        let plaintext: bool = row.get(0)?;
+        Ok(if plaintext {
+            RecordTag::Plaintext(row.get(1)?, row.get(2)?)
+        } else {
+            RecordTag::Encrypted(row.get(1)?, row.get(2)?)
+        })
+    })?;
+    let mut tags = Vec::new();
+    for tag in rows {
+        tags.push(tag?);
+    }
+    Ok(tags)
+}
+
+/// Translate `query` into a SQL condition on the `records` table.
+/// Values are added to `values` in the order of their placeholders.
+///
+/// `$like` becomes a condition that holds for every match, or for none when
+/// `negated` by an enclosing `$not`, so the whole condition holds for at least
+/// every match and the rows found must be checked with `Query::matches`.
+fn query_to_sql(query: &Query, negated: bool, values: &mut Vec<Vec<u8>>) -> String {
+    match query {
+        Query::And(qs) if qs.is_empty() => "1".to_string(),
+        Query::Or(qs) if qs.is_empty() => "0".to_string(),
+        Query::And(qs) => join(qs, " AND ", negated, values),
+        Query::Or(qs) => join(qs, " OR ", negated, values),
+        Query::Not(q) => format!("NOT ({})", query_to_sql(q, !negated, values)),
+        Query::Eq(n, v) => compare(n, "= ?", &[v], values),
+        Query::Neq(n, v) => compare(n, "!= ?", &[v], values),
+        Query::In(_, vs) if vs.is_empty() => "0".to_string(),
+        Query::In(n, vs) => {
+            let placeholders = vec!["?"; vs.len()].join(", ");
+            let refs = vs.iter().map(|v| v.as_slice()).collect::<Vec<_>>();
+            compare(n, &format!("IN ({})", placeholders), &refs, values)
+        }
+        Query::Gt(n, v) => plaintext(n, "> ?", v, values),
+        Query::Gte(n, v) => plaintext(n, ">= ?", v, values),
+        Query::Lt(n, v) => plaintext(n, "< ?", v, values),
+        Query::Lte(n, v) => plaintext(n, "<= ?", v, values),
+        Query::Like(_, _) if negated => "0".to_string(),
+        Query::Like(n, _) => compare(&TagName::Plaintext(n.clone()), "IS NOT NULL", &[], values),
+    }
+}
+
+/// Does `query` use `$like` anywhere
+fn contains_like(query: &Query) -> bool {
+    match query {
+        Query::And(qs) | Query::Or(qs) => qs.iter().any(contains_like),
+        Query::Not(q) => contains_like(q),
+        Query::Like(_, _) => true,
+        _ => false,
+    }
+}
+
+fn join(queries: &[Query], separator: &str, negated: bool, values: &mut Vec<Vec<u8>>) -> String {
+    let clauses = queries
+        .iter()
+        .map(|q| format!("({})", query_to_sql(q, negated, values)))
+        .collect::<Vec<_>>();
+    clauses.join(separator)
+}
+
+fn compare(
+    name: &TagName,
+    condition: &str,
+    operands: &[&[u8]],
+    values: &mut Vec<Vec<u8>>,
+) -> String {
+    let (plaintext, name) = match name {
+        TagName::Encrypted(n) => (0, n),
+        TagName::Plaintext(n) => (1, n),
+    };
+    values.push(name.clone());
+    values.extend(operands.iter().map(|v| v.to_vec()));
+    format!(
+        "id IN (SELECT record_id FROM tags WHERE plaintext = {} AND name = ? AND value {})",
+        plaintext, condition
+    )
+}
+
+fn plaintext(name: &[u8], condition: &str, operand: &[u8], values: &mut Vec<Vec<u8>>) -> String {
+    compare(
+        &TagName::Plaintext(name.to_vec()),
+        condition,
+        &[operand],
+        values,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{suite, SqliteConnector};
+
+    fn store() -> SqliteStore {
+        let config = SqliteConnector::<&str>::new(None);
+        SqliteStore::connect(PersistenceConnector::<_, &str>::Sqlite(config)).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        suite::round_trip(&store());
+    }
+
+    #[test]
+    fn search_records() {
+        suite::search_records(&store());
+    }
+
+    #[test]
+    fn apply_rolls_back_on_conflict() {
+        suite::apply_rolls_back_on_conflict(&store());
+    }
+
+    #[test]
+    fn keeps_tag_order() {
+        suite::keeps_tag_order(&store());
+    }
+
+    #[test]
+    fn like_matches_bytes() {
+        let store = store();
+        let mut record = suite::record("alice", "", "active", "2020-01");
+        record.tags = vec![RecordTag::Plaintext(
+            b"name".to_vec(),
+            "é".as_bytes().to_vec(),
+        )];
+        store.insert(record).unwrap();
+        // "é" is two bytes, so one `_` doesn't match it
+        let one: Query = r#"{"~name": {"$like": "_"}}"#.parse().unwrap();
+        let two: Query = r#"{"~name": {"$like": "__"}}"#.parse().unwrap();
+        assert!(store.search(b"connections", &one).unwrap().is_empty());
+        assert_eq!(store.search(b"connections", &two).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn migrate_refuses_newer_versions() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn.execute_batch("PRAGMA user_version = 99").unwrap();
+        assert_eq!(
+            migrate(&mut conn).unwrap_err().kind(),
+            PersistenceErrorKind::UnsupportedVersion
+        );
+    }
+}
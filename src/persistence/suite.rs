@@ -0,0 +1,210 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Behavior every persistence backend shares. Each backend runs these
+//! against a fresh store in its own tests.
+
+use super::{
+    errors::PersistenceErrorKind, transaction::Operation, wql::Query, PersistenceLike, Record,
+    RecordTag,
+};
+
+pub fn record(name: &str, value: &str, state: &str, issued: &str) -> Record {
+    Record {
+        category: b"connections".to_vec(),
+        name: name.as_bytes().to_vec(),
+        value: value.as_bytes().to_vec(),
+        tags: vec![
+            RecordTag::Encrypted(b"state".to_vec(), state.as_bytes().to_vec()),
+            RecordTag::Plaintext(b"issued".to_vec(), issued.as_bytes().to_vec()),
+        ],
+    }
+}
+
+fn names(records: &[Record]) -> Vec<&[u8]> {
+    records.iter().map(|r| r.name.as_slice()).collect()
+}
+
+fn search<P: PersistenceLike>(store: &P, query: &str) -> Vec<Record> {
+    store
+        .search(b"connections", &query.parse::<Query>().unwrap())
+        .unwrap()
+}
+
+/// Insert, fetch, update and delete a record
+pub fn round_trip<P: PersistenceLike>(store: &P) {
+    let find = |state: &str| {
+        let tag = RecordTag::Encrypted(b"state".to_vec(), state.as_bytes().to_vec());
+        store.find_by_tag(b"connections", &tag).unwrap()
+    };
+    let alice = record("alice", "{}", "active", "2020-01");
+    store.insert(alice.clone()).unwrap();
+    assert_eq!(store.fetch(b"connections", b"alice").unwrap(), alice);
+    assert_eq!(find("active"), vec![alice.clone()]);
+    assert_eq!(
+        store.insert(alice).unwrap_err().kind(),
+        PersistenceErrorKind::DuplicateItem
+    );
+
+    let updated = record("alice", "{\"did\":1}", "revoked", "2020-02");
+    store.update(updated.clone()).unwrap();
+    assert_eq!(store.fetch(b"connections", b"alice").unwrap(), updated);
+    assert!(find("active").is_empty());
+    assert_eq!(find("revoked"), vec![updated]);
+    assert_eq!(
+        store
+            .update(record("bob", "{}", "active", "2020-01"))
+            .unwrap_err()
+            .kind(),
+        PersistenceErrorKind::ItemNotFound
+    );
+
+    store.delete(b"connections", b"alice").unwrap();
+    assert_eq!(
+        store.fetch(b"connections", b"alice").unwrap_err().kind(),
+        PersistenceErrorKind::ItemNotFound
+    );
+    assert!(find("revoked").is_empty());
+}
+
+/// Search by tags, list categories and read pages
+pub fn search_records<P: PersistenceLike>(store: &P) {
+    store
+        .insert(record("alice", "", "active", "2020-01"))
+        .unwrap();
+    store
+        .insert(record("bob", "", "revoked", "2020-06"))
+        .unwrap();
+    store
+        .insert(record("carol", "", "active", "2021-03"))
+        .unwrap();
+    store
+        .insert(Record {
+            category: b"credentials".to_vec(),
+            ..record("alice", "", "active", "2020-01")
+        })
+        .unwrap();
+
+    assert_eq!(
+        names(&search(store, r#"{"state": "active"}"#)),
+        vec![&b"alice"[..], b"carol"]
+    );
+    assert_eq!(
+        names(&search(store, r#"{"~issued": {"$gte": "2020-06"}}"#)),
+        vec![&b"bob"[..], b"carol"]
+    );
+    assert_eq!(
+        names(&search(store, r#"{"~issued": {"$like": "2020-%"}}"#)),
+        vec![&b"alice"[..], b"bob"]
+    );
+    assert_eq!(
+        names(&search(
+            store,
+            r#"{"$or": [{"state": "revoked"}, {"~issued": {"$lt": "2020-02"}}]}"#
+        )),
+        vec![&b"alice"[..], b"bob"]
+    );
+    assert_eq!(
+        names(&search(store, r#"{"$not": {"state": "active"}}"#)),
+        vec![&b"bob"[..]]
+    );
+    // A plaintext tag doesn't match an encrypted tag of the same name
+    assert!(search(store, r#"{"~state": "active"}"#).is_empty());
+    assert_eq!(
+        store.categories().unwrap(),
+        vec![b"connections".to_vec(), b"credentials".to_vec()]
+    );
+
+    let all = Query::And(Vec::new());
+    let first = store.search_page(b"connections", &all, None, 2).unwrap();
+    assert_eq!(names(&first.records), vec![&b"alice"[..], b"bob"]);
+    let second = store
+        .search_page(b"connections", &all, first.next.as_ref(), 2)
+        .unwrap();
+    assert_eq!(names(&second.records), vec![&b"carol"[..]]);
+    assert!(second.next.is_none());
+    let scanned = store
+        .scan(b"connections", all, 1)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(names(&scanned), vec![&b"alice"[..], b"bob", b"carol"]);
+}
+
+/// A failed check undoes every change made before it
+pub fn apply_rolls_back_on_conflict<P: PersistenceLike>(store: &P) {
+    let alice = record("alice", "1", "active", "2020-01");
+    store.insert(alice.clone()).unwrap();
+
+    let stale = record("alice", "0", "active", "2020-01");
+    let result = store.apply(vec![
+        Operation::Insert(record("bob", "", "active", "2020-01")),
+        Operation::Update(record("alice", "2", "revoked", "2020-01")),
+        Operation::Check {
+            category: b"connections".to_vec(),
+            name: b"alice".to_vec(),
+            expected: Some(stale),
+        },
+    ]);
+    assert_eq!(result.unwrap_err().kind(), PersistenceErrorKind::Conflict);
+    assert_eq!(store.fetch(b"connections", b"alice").unwrap(), alice);
+    assert_eq!(
+        store.fetch(b"connections", b"bob").unwrap_err().kind(),
+        PersistenceErrorKind::ItemNotFound
+    );
+    assert_eq!(search(store, r#"{"state": "active"}"#), vec![alice]);
+
+    let mut tx = store.transaction();
+    let read = tx.fetch(b"connections", b"alice").unwrap();
+    tx.update(Record {
+        value: b"3".to_vec(),
+        ..read
+    });
+    store
+        .update(record("alice", "4", "active", "2020-01"))
+        .unwrap();
+    assert_eq!(
+        tx.commit().unwrap_err().kind(),
+        PersistenceErrorKind::Conflict
+    );
+    assert_eq!(store.fetch(b"connections", b"alice").unwrap().value, b"4");
+
+    let mut tx = store.transaction();
+    assert!(tx.fetch(b"connections", b"carol").is_err());
+    tx.insert(record("carol", "", "active", "2020-01"));
+    tx.delete(b"connections", b"alice");
+    tx.commit().unwrap();
+    assert_eq!(
+        names(&search(store, r#"{"state": "active"}"#)),
+        vec![&b"carol"[..]]
+    );
+}
+
+/// Tags come back in the order they were saved in, whatever their kind
+pub fn keeps_tag_order<P: PersistenceLike>(store: &P) {
+    let alice = Record {
+        tags: vec![
+            RecordTag::Plaintext(b"issued".to_vec(), b"2020-01".to_vec()),
+            RecordTag::Encrypted(b"state".to_vec(), b"active".to_vec()),
+            RecordTag::Plaintext(b"expires".to_vec(), b"2021-01".to_vec()),
+            RecordTag::Encrypted(b"role".to_vec(), b"holder".to_vec()),
+            RecordTag::Encrypted(b"alias".to_vec(), b"al".to_vec()),
+        ],
+        ..record("alice", "{}", "active", "2020-01")
+    };
+    store.insert(alice.clone()).unwrap();
+    assert_eq!(store.fetch(b"connections", b"alice").unwrap(), alice);
+    assert_eq!(search(store, r#"{"role": "holder"}"#), vec![alice]);
+}
@@ -0,0 +1,173 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Transactions group changes to multiple records so they are applied
+//! all together or not at all.
+//!
+//! Transactions are optimistic. Nothing is locked while a transaction is
+//! open. Records read through the transaction are remembered and changes
+//! are only buffered. When the transaction is committed the backend checks
+//! that none of the records read have changed since and applies every change
+//! atomically. If another writer changed one of them first, the commit fails
+//! with `PersistenceErrorKind::Conflict` and nothing is applied. The caller
+//! can then start a new transaction and try again.
+//!
+//! This gives serializable isolation for the records that were read. Searches
+//! are not part of the transaction so records added by other writers that
+//! would have matched are not detected.
+//!
+//! The isolation each backend provides when applying the changes:
+//!
+//! - Memory: serializable. All checks and changes run while holding the
+//!   write lock and are undone when one fails.
+//! - sled: serializable. All checks and changes run in one sled transaction.
+//! - SQLite: serializable. All checks and changes run in one `IMMEDIATE`
+//!   transaction which holds the database write lock until it is done.
+//!   When another connection holds the lock for too long the commit fails
+//!   with `Conflict` as well.
+//! - S3: Object stores can't change multiple objects atomically so only
+//!   transactions with at most one change are supported, others fail with
+//!   `UnsupportedOperation`. Records read are checked before the change is
+//!   written, so there is a small window where another writer can change
+//!   them unnoticed.
+
+use super::{errors::PersistenceErrorKind, PersistenceLike, PersistenceResult, Record};
+
+use serde::{Deserialize, Serialize};
+
+/// A single step in a transaction
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    /// The record must not have changed since it was read.
+    /// `None` means the record must not exist.
+    Check {
+        /// The category of the record
+        category: Vec<u8>,
+        /// The name of the record
+        name: Vec<u8>,
+        /// The record as it was read
+        expected: Option<Record>,
+    },
+    /// Save a new record
+    Insert(Record),
+    /// Replace the value and tags of an existing record
+    Update(Record),
+    /// Remove a record
+    Delete {
+        /// The category of the record
+        category: Vec<u8>,
+        /// The name of the record
+        name: Vec<u8>,
+    },
+}
+
+impl Operation {
+    /// Does this operation change a record
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Operation::Insert(_) | Operation::Update(_) | Operation::Delete { .. }
+        )
+    }
+}
+
+/// Buffers reads and changes until they are committed or rolled back.
+/// Dropping a transaction without committing it rolls it back.
+pub struct Transaction<'a, P: PersistenceLike> {
+    store: &'a P,
+    operations: Vec<Operation>,
+}
+
+impl<'a, P: PersistenceLike> Transaction<'a, P> {
+    /// Start a new transaction on `store`
+    pub fn new(store: &'a P) -> Self {
+        Self {
+            store,
+            operations: Vec::new(),
+        }
+    }
+
+    /// Retrieve the record with `name` in `category` including
+    /// any changes already made in this transaction
+    pub fn fetch(&mut self, category: &[u8], name: &[u8]) -> PersistenceResult<Record> {
+        for op in self.operations.iter().rev() {
+            match op {
+                Operation::Insert(r) | Operation::Update(r)
+                    if r.category == category && r.name == name =>
+                {
+                    return Ok(r.clone())
+                }
+                Operation::Check {
+                    category: c,
+                    name: n,
+                    expected,
+                } if c.as_slice() == category && n.as_slice() == name => {
+                    return expected
+                        .clone()
+                        .ok_or_else(|| PersistenceErrorKind::ItemNotFound.into())
+                }
+                Operation::Delete {
+                    category: c,
+                    name: n,
+                } if c.as_slice() == category && n.as_slice() == name => {
+                    return Err(PersistenceErrorKind::ItemNotFound.into())
+                }
+                _ => {}
+            }
+        }
+        let expected = match self.store.fetch(category, name) {
+            Ok(r) => Some(r),
+            Err(ref e) if e.kind() == PersistenceErrorKind::ItemNotFound => None,
+            Err(e) => return Err(e),
+        };
+        self.operations.push(Operation::Check {
+            category: category.to_vec(),
+            name: name.to_vec(),
+            expected: expected.clone(),
+        });
+        expected.ok_or_else(|| PersistenceErrorKind::ItemNotFound.into())
+    }
+
+    /// Save a new record when the transaction is committed
+    pub fn insert(&mut self, record: Record) {
+        self.operations.push(Operation::Insert(record));
+    }
+
+    /// Replace an existing record when the transaction is committed
+    pub fn update(&mut self, record: Record) {
+        self.operations.push(Operation::Update(record));
+    }
+
+    /// Remove the record with `name` in `category` when the transaction is committed
+    pub fn delete(&mut self, category: &[u8], name: &[u8]) {
+        self.operations.push(Operation::Delete {
+            category: category.to_vec(),
+            name: name.to_vec(),
+        });
+    }
+
+    /// Apply all changes atomically
+    pub fn commit(self) -> PersistenceResult<()> {
+        if self.operations.iter().any(Operation::is_write) {
+            self.store.apply(self.operations)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Discard all changes
+    pub fn rollback(self) {}
+}
@@ -0,0 +1,371 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Wallet Query Language (WQL) is how Aries agents look up records by their tags.
+//!
+//! A query is a JSON object where each key is a tag name or an operator
+//!
+//! ```json
+//! {
+//!     "schema_id": "WgWxqztrNooG92RXvxSTWv:2:degree:1.0",
+//!     "$or": [{"~issued": {"$gt": "2020"}}, {"$not": {"status": "revoked"}}],
+//!     "~name": {"$like": "Alice%"},
+//!     "state": {"$in": ["active", "pending"]}
+//! }
+//! ```
+//!
+//! All keys in an object must match (`$and`). Tag names starting with `~`
+//! are plaintext tags, all others are encrypted tags. Encrypted tags may only
+//! be compared for equality with a value, `$neq` or `$in`. Plaintext tags
+//! additionally support `$gt`, `$gte`, `$lt`, `$lte` and `$like`. Values are
+//! compared bytewise.
+//!
+//! Backends either translate a `Query` into their own query language or
+//! filter records in process with `Query::matches`.
+
+use super::{
+    errors::{PersistenceError, PersistenceErrorKind},
+    RecordTag,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// The name of a tag in a query
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TagName {
+    /// The name of an encrypted tag
+    Encrypted(Vec<u8>),
+    /// The name of a plaintext tag
+    Plaintext(Vec<u8>),
+}
+
+impl TagName {
+    /// Does `tag` have this name
+    pub fn is_name_of(&self, tag: &RecordTag) -> bool {
+        match (self, tag) {
+            (TagName::Encrypted(n), RecordTag::Encrypted(t, _))
+            | (TagName::Plaintext(n), RecordTag::Plaintext(t, _)) => n == t,
+            _ => false,
+        }
+    }
+}
+
+impl From<&RecordTag> for TagName {
+    fn from(tag: &RecordTag) -> Self {
+        match tag {
+            RecordTag::Encrypted(n, _) => TagName::Encrypted(n.clone()),
+            RecordTag::Plaintext(n, _) => TagName::Plaintext(n.clone()),
+        }
+    }
+}
+
+/// A parsed WQL query.
+///
+/// Comparisons other than equality only take plaintext tag names
+/// so they can't be used by mistake with encrypted tags.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Query {
+    /// All subqueries must match. Matches everything when empty.
+    And(Vec<Query>),
+    /// At least one subquery must match. Matches nothing when empty.
+    Or(Vec<Query>),
+    /// The subquery must not match
+    Not(Box<Query>),
+    /// The tag has the value
+    Eq(TagName, Vec<u8>),
+    /// The tag exists and does not have the value
+    Neq(TagName, Vec<u8>),
+    /// The tag has one of the values
+    In(TagName, Vec<Vec<u8>>),
+    /// The plaintext tag is greater than the value
+    Gt(Vec<u8>, Vec<u8>),
+    /// The plaintext tag is greater than or equal to the value
+    Gte(Vec<u8>, Vec<u8>),
+    /// The plaintext tag is less than the value
+    Lt(Vec<u8>, Vec<u8>),
+    /// The plaintext tag is less than or equal to the value
+    Lte(Vec<u8>, Vec<u8>),
+    /// The plaintext tag matches the SQL `LIKE` pattern where `%` matches
+    /// any number of bytes and `_` matches exactly one byte
+    Like(Vec<u8>, Vec<u8>),
+}
+
+impl Query {
+    /// Parse a query from a JSON value
+    pub fn from_json(value: &Value) -> Result<Self, PersistenceError> {
+        match value {
+            Value::Object(map) => parse_object(map),
+            _ => Err(invalid("A query must be a JSON object")),
+        }
+    }
+
+    /// Does a record with `tags` match this query
+    pub fn matches(&self, tags: &[RecordTag]) -> bool {
+        match self {
+            Query::And(qs) => qs.iter().all(|q| q.matches(tags)),
+            Query::Or(qs) => qs.iter().any(|q| q.matches(tags)),
+            Query::Not(q) => !q.matches(tags),
+            Query::Eq(n, v) => find(tags, n).any(|t| t == v.as_slice()),
+            Query::Neq(n, v) => find(tags, n).any(|t| t != v.as_slice()),
+            Query::In(n, vs) => find(tags, n).any(|t| vs.iter().any(|v| t == v.as_slice())),
+            Query::Gt(n, v) => find_plaintext(tags, n).any(|t| t > v.as_slice()),
+            Query::Gte(n, v) => find_plaintext(tags, n).any(|t| t >= v.as_slice()),
+            Query::Lt(n, v) => find_plaintext(tags, n).any(|t| t < v.as_slice()),
+            Query::Lte(n, v) => find_plaintext(tags, n).any(|t| t <= v.as_slice()),
+            Query::Like(n, p) => find_plaintext(tags, n).any(|t| like(t, p)),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = PersistenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value: Value = serde_json::from_str(s).map_err(|e| invalid(e.to_string()))?;
+        Self::from_json(&value)
+    }
+}
+
+fn invalid<D: std::fmt::Display>(msg: D) -> PersistenceError {
+    PersistenceError::from_msg(PersistenceErrorKind::InvalidQuery, msg.to_string())
+}
+
+fn find<'a>(tags: &'a [RecordTag], name: &'a TagName) -> impl Iterator<Item = &'a [u8]> {
+    tags.iter()
+        .filter(move |t| name.is_name_of(t))
+        .map(|t| t.value())
+}
+
+fn find_plaintext<'a>(tags: &'a [RecordTag], name: &'a [u8]) -> impl Iterator<Item = &'a [u8]> {
+    tags.iter()
+        .filter(move |t| match t {
+            RecordTag::Plaintext(n, _) => n.as_slice() == name,
+            _ => false,
+        })
+        .map(|t| t.value())
+}
+
+/// Match `value` against a SQL `LIKE` pattern. When a byte doesn't match,
+/// backtrack to the last `%` and let it consume one more byte.
+fn like(value: &[u8], pattern: &[u8]) -> bool {
+    let (mut v, mut p) = (0, 0);
+    let mut wildcard: Option<(usize, usize)> = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(b'%') => {
+                wildcard = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == b'_' || *c == value[v] => {
+                v += 1;
+                p += 1;
+            }
+            _ => match wildcard {
+                Some((wp, wv)) => {
+                    wildcard = Some((wp, wv + 1));
+                    p = wp + 1;
+                    v = wv + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'%')
+}
+
+fn parse_object(map: &Map<String, Value>) -> Result<Query, PersistenceError> {
+    let mut queries = Vec::with_capacity(map.len());
+    for (key, value) in map {
+        queries.push(match key.as_str() {
+            "$and" => Query::And(parse_list(value)?),
+            "$or" => Query::Or(parse_list(value)?),
+            "$not" => Query::Not(Box::new(Query::from_json(value)?)),
+            _ if key.starts_with('$') => return Err(invalid(format!("Unknown operator {}", key))),
+            _ => parse_tag(key, value)?,
+        });
+    }
+    if queries.len() == 1 {
+        Ok(queries.remove(0))
+    } else {
+        Ok(Query::And(queries))
+    }
+}
+
+fn parse_list(value: &Value) -> Result<Vec<Query>, PersistenceError> {
+    match value {
+        Value::Array(values) => values.iter().map(Query::from_json).collect(),
+        _ => Err(invalid("$and and $or take a list of queries")),
+    }
+}
+
+fn parse_tag(key: &str, value: &Value) -> Result<Query, PersistenceError> {
+    let name = match key.strip_prefix('~') {
+        Some(plaintext) => TagName::Plaintext(plaintext.as_bytes().to_vec()),
+        None => TagName::Encrypted(key.as_bytes().to_vec()),
+    };
+    let (op, operand) = match value {
+        Value::String(s) => return Ok(Query::Eq(name, s.as_bytes().to_vec())),
+        Value::Object(map) if map.len() == 1 => map.iter().next().unwrap(),
+        _ => {
+            return Err(invalid(format!(
+                "Tag {} must be compared to a string or a single operator",
+                key
+            )))
+        }
+    };
+    if op == "$in" {
+        let values = match operand {
+            Value::Array(vs) => vs
+                .iter()
+                .map(|v| string(key, v))
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(invalid("$in takes a list of strings")),
+        };
+        return Ok(Query::In(name, values));
+    }
+    let operand = string(key, operand)?;
+    match (op.as_str(), name) {
+        ("$eq", name) => Ok(Query::Eq(name, operand)),
+        ("$neq", name) => Ok(Query::Neq(name, operand)),
+        ("$gt", TagName::Plaintext(n)) => Ok(Query::Gt(n, operand)),
+        ("$gte", TagName::Plaintext(n)) => Ok(Query::Gte(n, operand)),
+        ("$lt", TagName::Plaintext(n)) => Ok(Query::Lt(n, operand)),
+        ("$lte", TagName::Plaintext(n)) => Ok(Query::Lte(n, operand)),
+        ("$like", TagName::Plaintext(n)) => Ok(Query::Like(n, operand)),
+        ("$gt", _) | ("$gte", _) | ("$lt", _) | ("$lte", _) | ("$like", _) => {
+            Err(invalid(format!(
+                "{} can only be used with plaintext tags but found {}",
+                op, key
+            )))
+        }
+        _ => Err(invalid(format!("Unknown operator {}", op))),
+    }
+}
+
+fn string(key: &str, value: &Value) -> Result<Vec<u8>, PersistenceError> {
+    match value {
+        Value::String(s) => Ok(s.as_bytes().to_vec()),
+        _ => Err(invalid(format!(
+            "Tag {} can only be compared to strings",
+            key
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> Query {
+        query.parse().unwrap()
+    }
+
+    fn tags() -> Vec<RecordTag> {
+        vec![
+            RecordTag::Encrypted(b"state".to_vec(), b"active".to_vec()),
+            RecordTag::Plaintext(b"issued".to_vec(), b"2020-06".to_vec()),
+            RecordTag::Plaintext(b"name".to_vec(), b"Alice".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn parse_operators() {
+        assert_eq!(
+            parse(r#"{"state": "active"}"#),
+            Query::Eq(TagName::Encrypted(b"state".to_vec()), b"active".to_vec())
+        );
+        assert_eq!(
+            parse(r#"{"~issued": {"$gt": "2020"}}"#),
+            Query::Gt(b"issued".to_vec(), b"2020".to_vec())
+        );
+        assert_eq!(
+            parse(r#"{"state": {"$in": ["active", "pending"]}}"#),
+            Query::In(
+                TagName::Encrypted(b"state".to_vec()),
+                vec![b"active".to_vec(), b"pending".to_vec()]
+            )
+        );
+        assert_eq!(
+            parse(r#"{"$not": {"~name": {"$like": "A%"}}}"#),
+            Query::Not(Box::new(Query::Like(b"name".to_vec(), b"A%".to_vec())))
+        );
+        assert_eq!(
+            parse(r#"{"$or": [{"a": "1"}, {"~b": {"$neq": "2"}}]}"#),
+            Query::Or(vec![
+                Query::Eq(TagName::Encrypted(b"a".to_vec()), b"1".to_vec()),
+                Query::Neq(TagName::Plaintext(b"b".to_vec()), b"2".to_vec()),
+            ])
+        );
+        assert_eq!(parse("{}"), Query::And(Vec::new()));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_queries() {
+        for query in &[
+            "[]",
+            r#"{"$xor": []}"#,
+            r#"{"state": {"$gt": "a"}}"#,
+            r#"{"state": {"$like": "a%"}}"#,
+            r#"{"state": 1}"#,
+            r#"{"state": {"$eq": "a", "$neq": "b"}}"#,
+            r#"{"state": {"$in": "a"}}"#,
+            r#"{"$and": {"a": "1"}}"#,
+        ] {
+            assert_eq!(
+                query.parse::<Query>().unwrap_err().kind(),
+                PersistenceErrorKind::InvalidQuery,
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn matches_tags() {
+        let tags = tags();
+        for (query, expected) in &[
+            (r#"{"state": "active"}"#, true),
+            (r#"{"~state": "active"}"#, false),
+            (r#"{"state": {"$neq": "active"}}"#, false),
+            (r#"{"missing": {"$neq": "active"}}"#, false),
+            (r#"{"state": {"$in": ["revoked", "active"]}}"#, true),
+            (r#"{"~issued": {"$gte": "2020-06"}}"#, true),
+            (r#"{"~issued": {"$lt": "2020-06"}}"#, false),
+            (r#"{"~name": {"$like": "A%e"}}"#, true),
+            (r#"{"~name": {"$like": "_lice"}}"#, true),
+            (r#"{"~name": {"$like": "%x%"}}"#, false),
+            (r#"{"state": "active", "~name": "Bob"}"#, false),
+            (r#"{"$or": [{"~name": "Bob"}, {"state": "active"}]}"#, true),
+            (r#"{"$or": []}"#, false),
+            (r#"{"$not": {"state": "active"}}"#, false),
+            ("{}", true),
+        ] {
+            assert_eq!(parse(query).matches(&tags), *expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert!(like(b"", b"%"));
+        assert!(like(b"abc", b"a%%c"));
+        assert!(like(b"aXbXc", b"a%b%c"));
+        assert!(like(b"abcbc", b"a%bc"));
+        assert!(!like(b"abc", b"a_"));
+        assert!(!like(b"ab", b"a_c%"));
+    }
+}
@@ -0,0 +1,113 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+use crate::{
+    persistence::errors::{PersistenceError, PersistenceErrorKind},
+    security::errors::{EnclaveError, EnclaveErrorKind},
+};
+use failure::{Backtrace, Context, Fail};
+use std::fmt;
+
+/// Represents possible errors that could occur in the data protection layer.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum ProtectionErrorKind {
+    /// Occurs when the enclave fails to protect or unprotect data
+    #[fail(display = "Enclave Error: {}", _0)]
+    Enclave(EnclaveErrorKind),
+    /// Occurs when the persistence backend fails
+    #[fail(display = "Persistence Error: {}", _0)]
+    Persistence(PersistenceErrorKind),
+    /// Occurs when unprotected data is not in the expected format
+    #[fail(display = "Protected data is malformed")]
+    InvalidData,
+}
+
+/// Represents a data protection error that includes a context and backtrace
+#[derive(Debug)]
+pub struct ProtectionError {
+    inner: Context<ProtectionErrorKind>,
+}
+
+impl ProtectionError {
+    /// Create from a message and kind
+    pub fn from_msg<D: fmt::Display + fmt::Debug + Send + Sync + 'static>(
+        kind: ProtectionErrorKind,
+        msg: D,
+    ) -> Self {
+        Self {
+            inner: Context::new(msg).context(kind),
+        }
+    }
+
+    /// Get `ProtectionErrorKind` wrapped by this error
+    pub fn kind(&self) -> ProtectionErrorKind {
+        self.inner.get_context().clone()
+    }
+}
+
+impl From<ProtectionErrorKind> for ProtectionError {
+    fn from(kind: ProtectionErrorKind) -> Self {
+        Self {
+            inner: Context::new("").context(kind),
+        }
+    }
+}
+
+impl From<Context<ProtectionErrorKind>> for ProtectionError {
+    fn from(inner: Context<ProtectionErrorKind>) -> Self {
+        ProtectionError { inner }
+    }
+}
+
+impl From<EnclaveError> for ProtectionError {
+    fn from(e: EnclaveError) -> Self {
+        let kind = ProtectionErrorKind::Enclave(e.kind());
+        e.context(kind).into()
+    }
+}
+
+impl From<PersistenceError> for ProtectionError {
+    fn from(e: PersistenceError) -> Self {
+        let kind = ProtectionErrorKind::Persistence(e.kind());
+        e.context(kind).into()
+    }
+}
+
+impl Fail for ProtectionError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.inner.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.inner.backtrace()
+    }
+}
+
+impl fmt::Display for ProtectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        for cause in Fail::iter_chain(&self.inner) {
+            if first {
+                first = false;
+                writeln!(f, "Error: {}", cause)?;
+            } else {
+                writeln!(f, "Caused by: {}", cause)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,650 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Data protection sits between the caller and a persistence backend
+//! and encrypts everything before it is persisted.
+//!
+//! The keys never leave the enclave. Any `EnclaveLike` can be combined
+//! with any `PersistenceLike` so the persistence backend only ever sees
+//! ciphertext.
+//!
+//! Values are encrypted with a fresh nonce every time. Categories and names
+//! must still be found by equality so they are encrypted deterministically.
+//! Every ciphertext is bound to where it belongs with associated data so
+//! values can't be swapped between records.
+//!
+//! Encrypted tags are searched with blind indexes. The persisted tag name
+//! and value are HMACs computed in the enclave over the tag name and value,
+//! so an equality lookup only needs the HMAC of the tag being searched for.
+//! The HMACs can't be reversed, so the actual tags are encrypted together
+//! with the record value along with their position among the tags. Blind
+//! indexes only support equality lookups, use plaintext tags for range and
+//! pattern lookups. Plaintext tags are persisted as is.
+
+use crate::{
+    persistence::{
+        cursor::{Cursor, Page},
+        transaction::Transaction,
+        wql::{Query, TagName},
+        PersistenceLike, Record, RecordTag, RESERVED_CATEGORY_PREFIX,
+    },
+    security::{
+        keys::{AeadKey, HmacKey, KeyExport},
+        AesModes, AesSizes, EnclaveLike, EnclaveResult, HmacAlgorithm, WrappingKey,
+    },
+};
+
+use errors::ProtectionErrorKind;
+use serde::{Deserialize, Serialize};
+
+/// Typical result from performing a data protection operation
+pub type ProtectionResult<T> = Result<T, errors::ProtectionError>;
+
+const CATEGORY_LABEL: &[u8] = b"category";
+const NAME_LABEL: &[u8] = b"name";
+const VALUE_LABEL: &[u8] = b"value";
+const TAG_NAME_LABEL: &[u8] = b"tag-name";
+const TAG_VALUE_LABEL: &[u8] = b"tag-value";
+
+/// Ids of the enclave keys used to protect a store
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtectionKeys {
+    /// Key for encrypting record values
+    pub value_key: String,
+    /// Key for deterministically encrypting categories and names
+    pub index_key: String,
+    /// HMAC key for computing blind indexes of encrypted tags
+    pub tag_key: String,
+}
+
+impl ProtectionKeys {
+    /// The ids of keys starting with `prefix` that were already generated
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            value_key: format!("{}-value", prefix),
+            index_key: format!("{}-index", prefix),
+            tag_key: format!("{}-tag", prefix),
+        }
+    }
+
+    /// Generate new keys in `enclave` with ids starting with `prefix`
+    pub fn generate<E: EnclaveLike>(enclave: &E, prefix: &str) -> EnclaveResult<Self> {
+        let keys = Self::with_prefix(prefix);
+        AeadKey::generate(
+            enclave,
+            keys.value_key.as_str(),
+            WrappingKey::XChaChaPoly1305,
+            KeyExport::Never,
+        )?;
+        AeadKey::generate(
+            enclave,
+            keys.index_key.as_str(),
+            WrappingKey::Aes(AesSizes::Aes256, AesModes::GcmSiv),
+            KeyExport::Never,
+        )?;
+        HmacKey::generate(
+            enclave,
+            keys.tag_key.as_str(),
+            HmacAlgorithm::Sha256,
+            KeyExport::Never,
+        )?;
+        Ok(keys)
+    }
+}
+
+/// A persistence backend whose records are protected by an enclave
+pub struct ProtectedStore<E: EnclaveLike, P: PersistenceLike> {
+    enclave: E,
+    persistence: P,
+    keys: ProtectionKeys,
+}
+
+impl<E: EnclaveLike, P: PersistenceLike> ProtectedStore<E, P> {
+    /// Protect `persistence` with `keys` held by `enclave`. The records are
+    /// only as protected as the enclave makes them, a `NullEnclave` persists
+    /// everything in the clear.
+    pub fn new(enclave: E, persistence: P, keys: ProtectionKeys) -> Self {
+        Self {
+            enclave,
+            persistence,
+            keys,
+        }
+    }
+
+    /// The enclave protecting the records
+    pub fn enclave(&self) -> &E {
+        &self.enclave
+    }
+
+    /// The backend where protected records are persisted
+    pub fn persistence(&self) -> &P {
+        &self.persistence
+    }
+
+    /// Close the persistence backend and the enclave
+    pub fn close(self) {
+        self.persistence.close();
+        self.enclave.close();
+    }
+
+    /// Protect and save a new record
+    pub fn insert(&self, record: Record) -> ProtectionResult<()> {
+        let record = self.protect(record)?;
+        Ok(self.persistence.insert(record)?)
+    }
+
+    /// Retrieve and unprotect the record with `name` in `category`
+    pub fn fetch(&self, category: &[u8], name: &[u8]) -> ProtectionResult<Record> {
+        let record = self.persistence.fetch(
+            &self.protect_category(category)?,
+            &self.protect_name(category, name)?,
+        )?;
+        self.unprotect(category, record)
+    }
+
+    /// Protect and replace the value and tags of an existing record
+    pub fn update(&self, record: Record) -> ProtectionResult<()> {
+        let record = self.protect(record)?;
+        Ok(self.persistence.update(record)?)
+    }
+
+    /// Remove the record with `name` in `category`
+    pub fn delete(&self, category: &[u8], name: &[u8]) -> ProtectionResult<()> {
+        Ok(self.persistence.delete(
+            &self.protect_category(category)?,
+            &self.protect_name(category, name)?,
+        )?)
+    }
+
+    /// List and unprotect every category that has records. Reserved
+    /// categories are never protected so they are skipped.
+    pub fn categories(&self) -> ProtectionResult<Vec<Vec<u8>>> {
+        self.persistence
+            .categories()?
+            .iter()
+            .filter(|c| !c.starts_with(RESERVED_CATEGORY_PREFIX))
+            .map(|c| {
+                Ok(self.enclave.decrypt(
+                    &self.keys.index_key,
+                    c,
+                    &encode_parts(&[CATEGORY_LABEL]),
+                )?)
+            })
+            .collect()
+    }
+
+    /// Find and unprotect all records in `category` that have `tag`
+    pub fn find_by_tag(&self, category: &[u8], tag: &RecordTag) -> ProtectionResult<Vec<Record>> {
+        let found = self.persistence.find_by_tag(
+            &self.protect_category(category)?,
+            &self.protect_tag(category, tag)?,
+        )?;
+        found
+            .into_iter()
+            .map(|r| self.unprotect(category, r))
+            .collect()
+    }
+
+    /// Find and unprotect all records in `category` whose tags match `query`
+    pub fn search(&self, category: &[u8], query: &Query) -> ProtectionResult<Vec<Record>> {
+        let found = self.persistence.search(
+            &self.protect_category(category)?,
+            &self.protect_query(category, query)?,
+        )?;
+        found
+            .into_iter()
+            .map(|r| self.unprotect(category, r))
+            .collect()
+    }
+
+    /// Find and unprotect up to `limit` records in `category` whose tags
+    /// match `query` starting after `cursor` if given. Records are ordered
+    /// by their protected name so the order is stable but not meaningful.
+    pub fn search_page(
+        &self,
+        category: &[u8],
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> ProtectionResult<Page> {
+        let page = self.persistence.search_page(
+            &self.protect_category(category)?,
+            &self.protect_query(category, query)?,
+            cursor,
+            limit,
+        )?;
+        Ok(Page {
+            records: page
+                .records
+                .into_iter()
+                .map(|r| self.unprotect(category, r))
+                .collect::<ProtectionResult<_>>()?,
+            next: page.next,
+        })
+    }
+
+    /// Iterate over and unprotect all records in `category` whose tags
+    /// match `query` reading `page_size` records at a time
+    pub fn scan<'a>(
+        &'a self,
+        category: &'a [u8],
+        query: &Query,
+        page_size: usize,
+    ) -> ProtectionResult<impl Iterator<Item = ProtectionResult<Record>> + 'a> {
+        let scan = self.persistence.scan(
+            &self.protect_category(category)?,
+            self.protect_query(category, query)?,
+            page_size,
+        );
+        Ok(scan.map(move |r| self.unprotect(category, r?)))
+    }
+
+    /// Start a transaction to change multiple protected records atomically
+    pub fn transaction(&self) -> ProtectedTransaction<'_, E, P> {
+        ProtectedTransaction {
+            store: self,
+            transaction: self.persistence.transaction(),
+        }
+    }
+
+    fn protect_category(&self, category: &[u8]) -> ProtectionResult<Vec<u8>> {
+        Ok(self.enclave.encrypt_deterministic(
+            &self.keys.index_key,
+            category,
+            &encode_parts(&[CATEGORY_LABEL]),
+        )?)
+    }
+
+    fn protect_name(&self, category: &[u8], name: &[u8]) -> ProtectionResult<Vec<u8>> {
+        Ok(self.enclave.encrypt_deterministic(
+            &self.keys.index_key,
+            name,
+            &encode_parts(&[NAME_LABEL, category]),
+        )?)
+    }
+
+    fn blind_tag_name(&self, category: &[u8], name: &[u8]) -> ProtectionResult<Vec<u8>> {
+        Ok(self.enclave.sign_hmac(
+            &self.keys.tag_key,
+            &encode_parts(&[TAG_NAME_LABEL, category, name]),
+        )?)
+    }
+
+    fn blind_tag_value(
+        &self,
+        category: &[u8],
+        name: &[u8],
+        value: &[u8],
+    ) -> ProtectionResult<Vec<u8>> {
+        Ok(self.enclave.sign_hmac(
+            &self.keys.tag_key,
+            &encode_parts(&[TAG_VALUE_LABEL, category, name, value]),
+        )?)
+    }
+
+    fn protect_tag(&self, category: &[u8], tag: &RecordTag) -> ProtectionResult<RecordTag> {
+        match tag {
+            RecordTag::Encrypted(name, value) => Ok(RecordTag::Encrypted(
+                self.blind_tag_name(category, name)?,
+                self.blind_tag_value(category, name, value)?,
+            )),
+            RecordTag::Plaintext(..) => Ok(tag.clone()),
+        }
+    }
+
+    /// Replace encrypted tag names and the values they are compared to with
+    /// their blind indexes. Only equality comparisons can be made on encrypted
+    /// tags so every other comparison is left as is.
+    fn protect_query(&self, category: &[u8], query: &Query) -> ProtectionResult<Query> {
+        let protect_all = |qs: &[Query]| -> ProtectionResult<Vec<Query>> {
+            qs.iter().map(|q| self.protect_query(category, q)).collect()
+        };
+        Ok(match query {
+            Query::And(qs) => Query::And(protect_all(qs)?),
+            Query::Or(qs) => Query::Or(protect_all(qs)?),
+            Query::Not(q) => Query::Not(Box::new(self.protect_query(category, q)?)),
+            Query::Eq(TagName::Encrypted(n), v) => Query::Eq(
+                TagName::Encrypted(self.blind_tag_name(category, n)?),
+                self.blind_tag_value(category, n, v)?,
+            ),
+            Query::Neq(TagName::Encrypted(n), v) => Query::Neq(
+                TagName::Encrypted(self.blind_tag_name(category, n)?),
+                self.blind_tag_value(category, n, v)?,
+            ),
+            Query::In(TagName::Encrypted(n), vs) => Query::In(
+                TagName::Encrypted(self.blind_tag_name(category, n)?),
+                vs.iter()
+                    .map(|v| self.blind_tag_value(category, n, v))
+                    .collect::<ProtectionResult<_>>()?,
+            ),
+            _ => query.clone(),
+        })
+    }
+
+    fn protect(&self, record: Record) -> ProtectionResult<Record> {
+        // The value and the actual encrypted tags are sealed together, each
+        // tag with its position so the order of the tags can be restored
+        let positions: Vec<[u8; 4]> = (0..record.tags.len() as u32)
+            .map(u32::to_be_bytes)
+            .collect();
+        let mut sealed: Vec<&[u8]> = vec![&record.value];
+        let mut tags = Vec::with_capacity(record.tags.len());
+        for (tag, position) in record.tags.iter().zip(&positions) {
+            if let RecordTag::Encrypted(name, value) = tag {
+                sealed.push(position);
+                sealed.push(name);
+                sealed.push(value);
+            }
+            tags.push(self.protect_tag(&record.category, tag)?);
+        }
+        Ok(Record {
+            category: self.protect_category(&record.category)?,
+            name: self.protect_name(&record.category, &record.name)?,
+            value: self.enclave.encrypt(
+                &self.keys.value_key,
+                &encode_parts(&sealed),
+                &encode_parts(&[VALUE_LABEL, &record.category, &record.name]),
+            )?,
+            tags,
+        })
+    }
+
+    fn unprotect(&self, category: &[u8], record: Record) -> ProtectionResult<Record> {
+        let name = self.enclave.decrypt(
+            &self.keys.index_key,
+            &record.name,
+            &encode_parts(&[NAME_LABEL, category]),
+        )?;
+        let sealed = self.enclave.decrypt(
+            &self.keys.value_key,
+            &record.value,
+            &encode_parts(&[VALUE_LABEL, category, &name]),
+        )?;
+        let mut parts = decode_parts(&sealed)?.into_iter();
+        let value = parts.next().ok_or(ProtectionErrorKind::InvalidData)?;
+        // Plaintext tags fill the positions between the encrypted tags
+        let mut plaintext = record
+            .tags
+            .into_iter()
+            .filter(|t| matches!(t, RecordTag::Plaintext(..)));
+        let mut tags = Vec::new();
+        while let Some(position) = parts.next() {
+            let position = decode_position(&position)?;
+            let tag_name = parts.next().ok_or(ProtectionErrorKind::InvalidData)?;
+            let tag_value = parts.next().ok_or(ProtectionErrorKind::InvalidData)?;
+            while tags.len() < position {
+                match plaintext.next() {
+                    Some(tag) => tags.push(tag),
+                    None => break,
+                }
+            }
+            tags.push(RecordTag::Encrypted(tag_name, tag_value));
+        }
+        tags.extend(plaintext);
+        Ok(Record {
+            category: category.to_vec(),
+            name,
+            value,
+            tags,
+        })
+    }
+}
+
+/// Length prefix each part so different combinations
+/// can never produce the same output
+fn encode_parts(parts: &[&[u8]]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for part in parts {
+        encoded.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        encoded.extend_from_slice(part);
+    }
+    encoded
+}
+
+/// Split data created by `encode_parts` back into its parts
+fn decode_parts(mut encoded: &[u8]) -> ProtectionResult<Vec<Vec<u8>>> {
+    let mut parts = Vec::new();
+    while !encoded.is_empty() {
+        if encoded.len() < 4 {
+            return Err(ProtectionErrorKind::InvalidData.into());
+        }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&encoded[..4]);
+        let len = u32::from_be_bytes(len) as usize;
+        if encoded.len() - 4 < len {
+            return Err(ProtectionErrorKind::InvalidData.into());
+        }
+        parts.push(encoded[4..4 + len].to_vec());
+        encoded = &encoded[4 + len..];
+    }
+    Ok(parts)
+}
+
+/// The position of a tag sealed by `protect`
+fn decode_position(part: &[u8]) -> ProtectionResult<usize> {
+    let mut position = [0u8; 4];
+    if part.len() != position.len() {
+        return Err(ProtectionErrorKind::InvalidData.into());
+    }
+    position.copy_from_slice(part);
+    Ok(u32::from_be_bytes(position) as usize)
+}
+
+/// The errors that can occur during a data protection operation
+pub mod errors;
+
+/// A transaction on a `ProtectedStore`. Records are protected
+/// as they are added and unprotected as they are read.
+pub struct ProtectedTransaction<'a, E: EnclaveLike, P: PersistenceLike> {
+    store: &'a ProtectedStore<E, P>,
+    transaction: Transaction<'a, P>,
+}
+
+impl<'a, E: EnclaveLike, P: PersistenceLike> ProtectedTransaction<'a, E, P> {
+    /// Retrieve and unprotect the record with `name` in `category`
+    /// including any changes already made in this transaction
+    pub fn fetch(&mut self, category: &[u8], name: &[u8]) -> ProtectionResult<Record> {
+        let record = self.transaction.fetch(
+            &self.store.protect_category(category)?,
+            &self.store.protect_name(category, name)?,
+        )?;
+        self.store.unprotect(category, record)
+    }
+
+    /// Protect and save a new record when the transaction is committed
+    pub fn insert(&mut self, record: Record) -> ProtectionResult<()> {
+        let record = self.store.protect(record)?;
+        self.transaction.insert(record);
+        Ok(())
+    }
+
+    /// Protect and replace an existing record when the transaction is committed
+    pub fn update(&mut self, record: Record) -> ProtectionResult<()> {
+        let record = self.store.protect(record)?;
+        self.transaction.update(record);
+        Ok(())
+    }
+
+    /// Remove the record with `name` in `category` when the transaction is committed
+    pub fn delete(&mut self, category: &[u8], name: &[u8]) -> ProtectionResult<()> {
+        let protected_category = self.store.protect_category(category)?;
+        let protected_name = self.store.protect_name(category, name)?;
+        self.transaction.delete(&protected_category, &protected_name);
+        Ok(())
+    }
+
+    /// Apply all changes atomically
+    pub fn commit(self) -> ProtectionResult<()> {
+        Ok(self.transaction.commit()?)
+    }
+
+    /// Discard all changes
+    pub fn rollback(self) {
+        self.transaction.rollback()
+    }
+}
+
+#[cfg(all(test, feature = "storage-sled"))]
+mod tests {
+    use super::*;
+    use crate::{
+        persistence::{kv::sled::SledStore, KeyValueConnector, PersistenceConnector},
+        security::null::NullEnclave,
+    };
+
+    fn record(name: &str, state: &str, role: &str, issued: &str) -> Record {
+        Record {
+            category: b"connections".to_vec(),
+            name: name.as_bytes().to_vec(),
+            value: name.as_bytes().to_vec(),
+            tags: vec![
+                RecordTag::Encrypted(b"state".to_vec(), state.as_bytes().to_vec()),
+                RecordTag::Encrypted(b"role".to_vec(), role.as_bytes().to_vec()),
+                RecordTag::Plaintext(b"issued".to_vec(), issued.as_bytes().to_vec()),
+            ],
+        }
+    }
+
+    fn names(records: Vec<Record>) -> Vec<Vec<u8>> {
+        let mut names: Vec<_> = records.into_iter().map(|r| r.name).collect();
+        names.sort();
+        names
+    }
+
+    fn sled() -> SledStore {
+        let config = KeyValueConnector::<&str>::new(None);
+        SledStore::connect(PersistenceConnector::<_, &str>::KeyValue(config)).unwrap()
+    }
+
+    #[test]
+    fn blind_index_search_equals_plaintext_search() {
+        let enclave = NullEnclave;
+        let keys = ProtectionKeys::generate(&enclave, "test").unwrap();
+        let protected = ProtectedStore::new(enclave, sled(), keys);
+        let plain = sled();
+        for r in &[
+            record("alice", "active", "holder", "2020-01"),
+            record("bob", "revoked", "issuer", "2020-06"),
+            record("carol", "active", "issuer", "2021-03"),
+            record("dave", "pending", "holder", "2021-09"),
+        ] {
+            plain.insert(r.clone()).unwrap();
+            protected.insert(r.clone()).unwrap();
+        }
+
+        for tag in &[
+            RecordTag::Encrypted(b"state".to_vec(), b"active".to_vec()),
+            RecordTag::Encrypted(b"state".to_vec(), b"missing".to_vec()),
+            RecordTag::Encrypted(b"role".to_vec(), b"issuer".to_vec()),
+            RecordTag::Plaintext(b"issued".to_vec(), b"2020-06".to_vec()),
+        ] {
+            assert_eq!(
+                names(protected.find_by_tag(b"connections", tag).unwrap()),
+                names(plain.find_by_tag(b"connections", tag).unwrap()),
+                "{:?}",
+                tag
+            );
+        }
+        for query in &[
+            r#"{"state": "active"}"#,
+            r#"{"state": "missing"}"#,
+            r#"{"state": {"$neq": "active"}}"#,
+            r#"{"state": {"$in": ["revoked", "pending"]}}"#,
+            r#"{"state": "active", "role": "issuer"}"#,
+            r#"{"$or": [{"state": "revoked"}, {"role": "holder"}]}"#,
+            r#"{"$not": {"role": "holder"}}"#,
+            r#"{"state": "active", "~issued": {"$gte": "2021"}}"#,
+            r#"{"~issued": {"$like": "2020-%"}}"#,
+            "{}",
+        ] {
+            let query: Query = query.parse().unwrap();
+            assert_eq!(
+                names(protected.search(b"connections", &query).unwrap()),
+                names(plain.search(b"connections", &query).unwrap()),
+                "{:?}",
+                query
+            );
+        }
+        assert_eq!(
+            protected.fetch(b"connections", b"bob").unwrap(),
+            record("bob", "revoked", "issuer", "2020-06")
+        );
+    }
+
+    #[test]
+    fn keeps_tag_order() {
+        let enclave = NullEnclave;
+        let keys = ProtectionKeys::generate(&enclave, "test").unwrap();
+        let protected = ProtectedStore::new(enclave, sled(), keys);
+        let alice = Record {
+            tags: vec![
+                RecordTag::Plaintext(b"issued".to_vec(), b"2020-01".to_vec()),
+                RecordTag::Encrypted(b"state".to_vec(), b"active".to_vec()),
+                RecordTag::Plaintext(b"expires".to_vec(), b"2021-01".to_vec()),
+                RecordTag::Encrypted(b"role".to_vec(), b"holder".to_vec()),
+                RecordTag::Encrypted(b"alias".to_vec(), b"al".to_vec()),
+                RecordTag::Plaintext(b"kind".to_vec(), b"peer".to_vec()),
+            ],
+            ..record("alice", "active", "holder", "2020-01")
+        };
+        protected.insert(alice.clone()).unwrap();
+        assert_eq!(protected.fetch(b"connections", b"alice").unwrap(), alice);
+        let query = r#"{"role": "holder"}"#.parse().unwrap();
+        assert_eq!(
+            protected.search(b"connections", &query).unwrap(),
+            vec![alice]
+        );
+    }
+
+    #[cfg(feature = "software-enclave")]
+    #[test]
+    fn encrypted_tags_are_not_persisted() {
+        let enclave = crate::security::software::SoftwareEnclave::new();
+        let keys = ProtectionKeys::generate(&enclave, "test").unwrap();
+        let protected = ProtectedStore::new(enclave, sled(), keys);
+        protected
+            .insert(record("alice", "active", "holder", "2020-01"))
+            .unwrap();
+
+        let category = protected.persistence().categories().unwrap().remove(0);
+        assert_ne!(category, b"connections");
+        let persisted = protected
+            .persistence()
+            .search(&category, &Query::And(Vec::new()))
+            .unwrap();
+        assert_eq!(persisted.len(), 1);
+        assert_ne!(persisted[0].name, b"alice");
+        assert_ne!(persisted[0].value, b"alice");
+        for tag in &persisted[0].tags {
+            match tag {
+                RecordTag::Encrypted(n, v) => {
+                    assert_ne!(n.as_slice(), b"state");
+                    assert_ne!(v.as_slice(), b"active");
+                }
+                RecordTag::Plaintext(n, v) => {
+                    assert_eq!(
+                        (n.as_slice(), v.as_slice()),
+                        (&b"issued"[..], &b"2020-01"[..])
+                    )
+                }
+            }
+        }
+        assert_eq!(
+            protected.fetch(b"connections", b"alice").unwrap(),
+            record("alice", "active", "holder", "2020-01")
+        );
+    }
+}
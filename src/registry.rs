@@ -0,0 +1,504 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Drivers are compiled in with `--features` but chosen at runtime, so one
+//! binary can open whichever enclave and persistence backend a deployment's
+//! config names.
+//!
+//! A driver is registered under a URI scheme and opens the URIs with that
+//! scheme. `Registry::default()` holds the drivers compiled into this build,
+//! and applications or plugins add their own with `register_enclave` and
+//! `register_persistence`. Drivers from shared libraries are added with
+//! `register_plugin` with the `plugins` feature. A driver registered under a
+//! scheme that is already taken replaces the previous one.
+//!
+//! The built in enclave drivers are
+//!
+//! - `null:` - the `NullEnclave`. Only for debugging.
+//! - `oskeyring:` - the OS keyring on macOS and iOS, see `security::config`
+//! - `software:` - the `SoftwareEnclave` with the `software-enclave` feature
+//!
+//! The built in persistence drivers are
+//!
+//! - `memory:` - with the `storage-memory` feature
+//! - `sled:///path?cache-capacity=<bytes>&flush-every-ms=<ms>` or `sled:`
+//!   for a temporary store, with the `storage-sled` feature
+//! - `sqlite:///path` or `sqlite:` for an in-memory database,
+//!   with the `storage-sqlite` feature
+//! - `s3://host[:port]/bucket[/prefix]?region=<region>&access-key-id=<id>&secret-access-key=<secret>`
+//!   with the `storage-s3` feature. Use `s3+http` for plain HTTP endpoints like
+//!   a local MinIO. The secret access key is a reference like `systemd:s3-secret`
+//!   as described in `security::config`.
+
+use crate::{
+    persistence::{
+        errors::{PersistenceError, PersistenceErrorKind},
+        shared::BoxedPersistence,
+        PersistenceResult,
+    },
+    security::{
+        errors::{EnclaveError, EnclaveErrorKind},
+        null::NullEnclave,
+        shared::SharedEnclave,
+        EnclaveResult,
+    },
+    uri,
+};
+
+use std::{collections::BTreeMap, fmt};
+
+/// Opens enclaves from URIs with the scheme it is registered under
+pub type EnclaveDriver = Box<dyn Fn(&str) -> EnclaveResult<SharedEnclave> + Send + Sync>;
+/// Opens persistence backends from URIs with the scheme it is registered under
+pub type PersistenceDriver = Box<dyn Fn(&str) -> PersistenceResult<BoxedPersistence> + Send + Sync>;
+
+/// Enclave and persistence drivers by URI scheme
+pub struct Registry {
+    enclaves: BTreeMap<String, EnclaveDriver>,
+    persistence: BTreeMap<String, PersistenceDriver>,
+}
+
+impl Registry {
+    /// Create a registry without any drivers
+    pub fn empty() -> Self {
+        Self {
+            enclaves: BTreeMap::new(),
+            persistence: BTreeMap::new(),
+        }
+    }
+
+    /// Open enclave URIs with `scheme` with `driver`
+    pub fn register_enclave<F>(&mut self, scheme: &str, driver: F)
+    where
+        F: Fn(&str) -> EnclaveResult<SharedEnclave> + Send + Sync + 'static,
+    {
+        self.enclaves
+            .insert(scheme.to_ascii_lowercase(), Box::new(driver));
+    }
+
+    /// Open persistence URIs with `scheme` with `driver`
+    pub fn register_persistence<F>(&mut self, scheme: &str, driver: F)
+    where
+        F: Fn(&str) -> PersistenceResult<BoxedPersistence> + Send + Sync + 'static,
+    {
+        self.persistence
+            .insert(scheme.to_ascii_lowercase(), Box::new(driver));
+    }
+
+    /// Stop opening enclave URIs with `scheme`, returning its driver
+    pub fn remove_enclave(&mut self, scheme: &str) -> Option<EnclaveDriver> {
+        self.enclaves.remove(&scheme.to_ascii_lowercase())
+    }
+
+    /// Open URIs with the scheme of `plugin` with its drivers
+    #[cfg(feature = "plugins")]
+    pub fn register_plugin(&mut self, plugin: std::sync::Arc<crate::ffi::loader::Plugin>) {
+        if plugin.has_enclave() {
+            let p = plugin.clone();
+            self.register_enclave(plugin.scheme(), move |uri| {
+                Ok(SharedEnclave::new(p.open_enclave(uri)?))
+            });
+        }
+        if plugin.has_persistence() {
+            let p = plugin.clone();
+            self.register_persistence(plugin.scheme(), move |uri| {
+                Ok(BoxedPersistence::new(p.open_persistence(uri)?))
+            });
+        }
+    }
+
+    /// The schemes of the registered enclave drivers
+    pub fn enclave_schemes(&self) -> impl Iterator<Item = &str> {
+        self.enclaves.keys().map(String::as_str)
+    }
+
+    /// The schemes of the registered persistence drivers
+    pub fn persistence_schemes(&self) -> impl Iterator<Item = &str> {
+        self.persistence.keys().map(String::as_str)
+    }
+
+    /// Open the enclave named by `uri`
+    pub fn open_enclave(&self, uri: &str) -> EnclaveResult<SharedEnclave> {
+        let driver = uri::scheme(uri)
+            .and_then(|s| self.enclaves.get(&s.to_ascii_lowercase()))
+            .ok_or_else(|| {
+                EnclaveError::from(EnclaveErrorKind::ConnectionFailure {
+                    msg: format!(
+                        "No enclave driver is registered for {}. Use one of {}",
+                        scheme_of(uri),
+                        self.enclave_schemes().collect::<Vec<_>>().join(", ")
+                    ),
+                })
+            })?;
+        driver(uri)
+    }
+
+    /// Open the persistence backend named by `uri`
+    pub fn open_persistence(&self, uri: &str) -> PersistenceResult<BoxedPersistence> {
+        let driver = uri::scheme(uri)
+            .and_then(|s| self.persistence.get(&s.to_ascii_lowercase()))
+            .ok_or_else(|| {
+                persistence_config(format!(
+                    "No persistence driver is registered for {}. Use one of {}",
+                    scheme_of(uri),
+                    self.persistence_schemes().collect::<Vec<_>>().join(", ")
+                ))
+            })?;
+        driver(uri)
+    }
+}
+
+impl Default for Registry {
+    /// A registry with every driver compiled into this build
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register_enclave("null", |uri| {
+            without_options(uri).map_err(enclave_config)?;
+            Ok(SharedEnclave::new(NullEnclave))
+        });
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        registry.register_enclave("oskeyring", |uri| {
+            use crate::security::{config::EnclaveConfig, os::macos::MacOsKeyRing, EnclaveLike};
+            let config: EnclaveConfig = uri.parse()?;
+            Ok(SharedEnclave::new(MacOsKeyRing::connect(
+                config.into_connector(),
+            )?))
+        });
+        #[cfg(feature = "software-enclave")]
+        registry.register_enclave("software", |uri| {
+            use crate::security::{config::EnclaveConfig, software::SoftwareEnclave, EnclaveLike};
+            let config: EnclaveConfig = uri.parse()?;
+            Ok(SharedEnclave::new(SoftwareEnclave::connect(
+                config.into_connector(),
+            )?))
+        });
+        #[cfg(feature = "storage-memory")]
+        registry.register_persistence("memory", |uri| {
+            without_options(uri).map_err(persistence_config)?;
+            Ok(BoxedPersistence::new(
+                crate::persistence::kv::memory::MemoryStore::new(),
+            ))
+        });
+        #[cfg(feature = "storage-sled")]
+        registry.register_persistence("sled", drivers::sled);
+        #[cfg(feature = "storage-sqlite")]
+        registry.register_persistence("sqlite", drivers::sqlite);
+        #[cfg(feature = "storage-s3")]
+        {
+            registry.register_persistence("s3", drivers::s3);
+            registry.register_persistence("s3+http", drivers::s3);
+        }
+        registry
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("enclaves", &self.enclaves.keys().collect::<Vec<_>>())
+            .field("persistence", &self.persistence.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// The scheme of `uri` for error messages
+fn scheme_of(uri: &str) -> &str {
+    uri::scheme(uri).unwrap_or(uri)
+}
+
+/// Fail if `uri` has more than a scheme
+fn without_options(uri: &str) -> Result<(), String> {
+    let parsed = uri::Uri::parse(uri)?;
+    if parsed.authority.is_some() || !parsed.path.is_empty() {
+        return Err(format!("{}: URIs have no path", parsed.scheme));
+    }
+    parsed.query.finish()
+}
+
+fn enclave_config(msg: String) -> EnclaveError {
+    EnclaveErrorKind::ConnectionFailure {
+        msg: format!("Invalid enclave configuration: {}", msg),
+    }
+    .into()
+}
+
+fn persistence_config(msg: String) -> PersistenceError {
+    PersistenceError::from_msg(PersistenceErrorKind::InvalidConfig, msg)
+}
+
+/// The built in persistence drivers that take options
+#[cfg(any(
+    feature = "storage-s3",
+    feature = "storage-sled",
+    feature = "storage-sqlite"
+))]
+mod drivers {
+    use super::persistence_config;
+    use crate::{
+        persistence::{
+            shared::BoxedPersistence, PersistenceConnector, PersistenceLike, PersistenceResult,
+        },
+        uri::Uri,
+    };
+
+    /// The path of a local store or `None` if it is left out
+    #[allow(dead_code)]
+    fn local_path(uri: &Uri<'_>) -> PersistenceResult<Option<String>> {
+        match uri.local_path().map_err(persistence_config)? {
+            p if p.is_empty() => Ok(None),
+            p => Ok(Some(p)),
+        }
+    }
+
+    #[cfg(feature = "storage-sled")]
+    pub fn sled(uri: &str) -> PersistenceResult<BoxedPersistence> {
+        use crate::persistence::{kv::sled::SledStore, KeyValueConnector};
+        let mut uri = Uri::parse(uri).map_err(persistence_config)?;
+        let mut config = KeyValueConnector::new(local_path(&uri)?);
+        if let Some(bytes) = uri
+            .query
+            .take_parsed("cache-capacity")
+            .map_err(persistence_config)?
+        {
+            config = config.cache_capacity(bytes);
+        }
+        if let Some(ms) = uri
+            .query
+            .take_parsed("flush-every-ms")
+            .map_err(persistence_config)?
+        {
+            config = config.flush_every_ms(Some(ms));
+        }
+        uri.query.finish().map_err(persistence_config)?;
+        let store = SledStore::connect(PersistenceConnector::<_, String>::KeyValue(config))?;
+        Ok(BoxedPersistence::new(store))
+    }
+
+    #[cfg(feature = "storage-sqlite")]
+    pub fn sqlite(uri: &str) -> PersistenceResult<BoxedPersistence> {
+        use crate::persistence::{sql::sqlite::SqliteStore, SqliteConnector};
+        let uri = Uri::parse(uri).map_err(persistence_config)?;
+        let config = SqliteConnector::new(local_path(&uri)?);
+        uri.query.finish().map_err(persistence_config)?;
+        let store = SqliteStore::connect(PersistenceConnector::<_, String>::Sqlite(config))?;
+        Ok(BoxedPersistence::new(store))
+    }
+
+    #[cfg(feature = "storage-s3")]
+    pub fn s3(uri: &str) -> PersistenceResult<BoxedPersistence> {
+        use crate::{
+            persistence::{object::s3::S3Store, ObjectStoreConnector},
+            security::config::SecretRef,
+            uri::percent_decode,
+        };
+        let mut uri = Uri::parse(uri).map_err(persistence_config)?;
+        let host = match uri.authority {
+            Some(h) if !h.is_empty() => h,
+            _ => return Err(persistence_config("S3 URIs need a host".to_string())),
+        };
+        let protocol = if uri.scheme == "s3+http" {
+            "http"
+        } else {
+            "https"
+        };
+        let path = percent_decode(uri.path.trim_start_matches('/')).map_err(persistence_config)?;
+        let mut parts = path.splitn(2, '/');
+        let bucket = match parts.next() {
+            Some(b) if !b.is_empty() => b.to_string(),
+            _ => return Err(persistence_config("S3 URIs need a bucket".to_string())),
+        };
+        let prefix = parts.next().filter(|p| !p.is_empty()).map(str::to_string);
+        let mut required = |name| {
+            uri.query
+                .take(name)
+                .and_then(|v| v.ok_or_else(|| format!("S3 URIs need the {}", name)))
+                .map_err(persistence_config)
+        };
+        let region = required("region")?;
+        let access_key_id = required("access-key-id")?;
+        let secret = required("secret-access-key")?;
+        let secret_access_key = match SecretRef::parse(&secret).map_err(persistence_config)? {
+            SecretRef::Source(source) => source.read().map_err(|e| {
+                persistence_config(format!("Unable to read the secret access key: {}", e))
+            })?,
+            SecretRef::Stored(_) => {
+                return Err(persistence_config(
+                    "The secret access key can't be stored in an enclave".to_string(),
+                ))
+            }
+        };
+        uri.query.finish().map_err(persistence_config)?;
+        let mut config = ObjectStoreConnector::new(
+            format!("{}://{}", protocol, host),
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key.to_string(),
+        );
+        if let Some(p) = prefix {
+            config = config.prefix(p);
+        }
+        let store = S3Store::connect(PersistenceConnector::<&str, _>::ObjectStore(config))?;
+        Ok(BoxedPersistence::new(store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// An enclave driver that records the URIs it opens under `name`
+    fn recording(
+        name: &'static str,
+        opened: &Arc<Mutex<Vec<String>>>,
+    ) -> impl Fn(&str) -> EnclaveResult<SharedEnclave> + Send + Sync + 'static {
+        let opened = opened.clone();
+        move |uri| {
+            opened.lock().unwrap().push(format!("{} {}", name, uri));
+            Ok(SharedEnclave::new(NullEnclave))
+        }
+    }
+
+    #[test]
+    fn registration() {
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = Registry::empty();
+        assert_eq!(registry.enclave_schemes().count(), 0);
+        assert_eq!(registry.persistence_schemes().count(), 0);
+
+        registry.register_enclave("b", recording("b", &opened));
+        registry.register_enclave("A", recording("a", &opened));
+        assert_eq!(registry.enclave_schemes().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(registry.persistence_schemes().count(), 0);
+
+        assert!(registry.remove_enclave("B").is_some());
+        assert!(registry.remove_enclave("b").is_none());
+        assert_eq!(registry.enclave_schemes().collect::<Vec<_>>(), ["a"]);
+        assert_eq!(
+            format!("{:?}", registry),
+            r#"Registry { enclaves: ["a"], persistence: [] }"#
+        );
+    }
+
+    #[test]
+    fn lookup_by_scheme() {
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = Registry::empty();
+        registry.register_enclave("one", recording("one", &opened));
+        registry.register_enclave("two+x", recording("two", &opened));
+
+        for uri in &["one:", "ONE://host/path?x=1", "two+x:", "Two+X:///p"] {
+            registry.open_enclave(uri).unwrap();
+        }
+        assert_eq!(
+            *opened.lock().unwrap(),
+            [
+                "one one:",
+                "one ONE://host/path?x=1",
+                "two two+x:",
+                "two Two+X:///p"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_schemes_replace_the_driver() {
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = Registry::empty();
+        registry.register_enclave("dup", recording("first", &opened));
+        registry.register_enclave("DUP", recording("second", &opened));
+        assert_eq!(registry.enclave_schemes().collect::<Vec<_>>(), ["dup"]);
+
+        registry.open_enclave("dup:").unwrap();
+        assert_eq!(*opened.lock().unwrap(), ["second dup:"]);
+    }
+
+    #[test]
+    fn unknown_schemes_are_rejected() {
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = Registry::empty();
+        registry.register_enclave("known", recording("known", &opened));
+
+        for uri in &["unknown:", "unknown://host", "known", "/known", ""] {
+            let err = registry.open_enclave(uri).unwrap_err();
+            assert!(
+                matches!(err.kind(), EnclaveErrorKind::ConnectionFailure { .. }),
+                "{}",
+                uri
+            );
+            assert!(err.to_string().contains("Use one of known"), "{}", err);
+        }
+        let err = registry.open_persistence("unknown:").unwrap_err();
+        assert_eq!(err.kind(), PersistenceErrorKind::InvalidConfig);
+        assert!(opened.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn built_in_drivers() {
+        let registry = Registry::default();
+        assert!(registry.enclave_schemes().any(|s| s == "null"));
+        registry.open_enclave("null:").unwrap();
+        assert!(registry.open_enclave("null:?x=1").is_err());
+        assert!(registry.open_enclave("null://host").is_err());
+        #[cfg(feature = "software-enclave")]
+        {
+            registry.open_enclave("software:").unwrap();
+            assert!(registry.open_enclave("software:path").is_err());
+        }
+        #[cfg(feature = "storage-memory")]
+        {
+            registry.open_persistence("memory:").unwrap();
+            assert!(registry.open_persistence("memory:path").is_err());
+        }
+        #[cfg(feature = "storage-sqlite")]
+        {
+            registry.open_persistence("sqlite:").unwrap();
+            assert!(registry.open_persistence("sqlite:?journal=wal").is_err());
+        }
+        #[cfg(feature = "storage-s3")]
+        {
+            assert!(registry.persistence_schemes().any(|s| s == "s3+http"));
+            for uri in &[
+                "s3:///bucket?region=r&access-key-id=k&secret-access-key=fd:0",
+                "s3://host?region=r&access-key-id=k&secret-access-key=fd:0",
+                "s3://host/bucket?access-key-id=k&secret-access-key=fd:0",
+                "s3://host/bucket?region=r&access-key-id=k&secret-access-key=stored:s3",
+            ] {
+                let err = registry.open_persistence(uri).unwrap_err();
+                assert_eq!(err.kind(), PersistenceErrorKind::InvalidConfig, "{}", uri);
+            }
+        }
+    }
+
+    #[cfg(feature = "storage-memory")]
+    #[test]
+    fn persistence_drivers() {
+        let mut registry = Registry::empty();
+        registry.register_persistence("mem", |_| {
+            Ok(BoxedPersistence::new(
+                crate::persistence::kv::memory::MemoryStore::new(),
+            ))
+        });
+        registry.register_persistence("MEM", |_| Err(persistence_config("replaced".to_string())));
+        assert_eq!(registry.persistence_schemes().collect::<Vec<_>>(), ["mem"]);
+        assert_eq!(registry.enclave_schemes().count(), 0);
+        let err = registry.open_persistence("Mem:").unwrap_err();
+        assert_eq!(err.kind(), PersistenceErrorKind::InvalidConfig);
+        assert!(err.to_string().contains("replaced"), "{}", err);
+        assert!(registry.open_enclave("mem:").is_err());
+    }
+}
@@ -0,0 +1,614 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Enclaves can be described in config files or as URIs instead of being
+//! built in code. Both produce an `EnclaveConfig` which turns into an
+//! `EnclaveConnector`.
+//!
+//! Secrets can't be written in a config. Passwords and PINs are references
+//! to where the secret is read from when connecting, or are left out to be
+//! prompted for. A reference is one of
+//!
+//! - `stored:<id>` - saved in the enclave this one is connected with
+//! - `systemd:<name>` - a systemd credential
+//! - `docker:<name>` - a Docker or Kubernetes secret
+//! - `file:<path>` - a file holding only the secret
+//! - `fd:<number>` - one line read from an inherited file descriptor
+//!
+//! Configs are tagged with their `type` so any serde format works. In TOML
+//!
+//! ```toml
+//! [enclave]
+//! type = "yubihsm"
+//! url = "http://127.0.0.1:12345"
+//! auth_key_id = 1
+//! password = "systemd:hsm-password"
+//! ```
+//!
+//! The same enclaves as URIs
+//!
+//! - `oskeyring:` or `oskeyring:///path/to/keyring?username=agent&password=fd:3`
+//! - `yubihsm:?auth=1` over USB or `yubihsm://host:12345?auth=1&password=stored:hsm`
+//!   through a yubihsm-connector, which listens on port 12345 if it is left out
+//! - `pkcs11:token=Agent;slot-id=0?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-source=file:/run/secrets/pin`
+//!   as defined in RFC 7512. `pin-value` is rejected so PINs stay out of URIs.
+//! - `software:` for a `SoftwareEnclave` whose keys only live in memory
+//!
+//! Reserved characters in URI components are percent-encoded.
+
+use super::{
+    errors::{EnclaveError, EnclaveErrorKind},
+    source::SecretSource,
+    Credential, EnclaveConnector, OsKeyRingConnector, Pkcs11Connector, YubiHsmConnector,
+};
+
+use crate::uri::{percent_decode, Attributes, Uri};
+
+use serde::Deserialize;
+use std::{convert::TryFrom, fmt, path::PathBuf, str::FromStr};
+
+/// The port a yubihsm-connector listens on by default
+const YUBIHSM_CONNECTOR_PORT: u16 = 12345;
+
+/// Where the secret for connecting to an enclave is read from
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SecretRef {
+    /// The id of the secret in the enclave this one is connected with
+    Stored(String),
+    /// A secret read when connecting
+    Source(SecretSource),
+}
+
+impl SecretRef {
+    pub(crate) fn parse(s: &str) -> Result<Self, String> {
+        let not_a_reference = || {
+            format!(
+                "{:?} is not a secret reference. Use stored:, systemd:, docker:, file: or fd:",
+                s
+            )
+        };
+        let (kind, value) = match s.find(':') {
+            Some(i) if i + 1 < s.len() => (&s[..i], &s[i + 1..]),
+            _ => return Err(not_a_reference()),
+        };
+        match kind {
+            "stored" => Ok(SecretRef::Stored(value.to_string())),
+            "systemd" => Ok(SecretRef::Source(SecretSource::Systemd(value.to_string()))),
+            "docker" => Ok(SecretRef::Source(SecretSource::DockerSecret(
+                value.to_string(),
+            ))),
+            // file:///path is also accepted
+            "file" => Ok(SecretRef::Source(SecretSource::File(PathBuf::from(
+                value.strip_prefix("//").unwrap_or(value),
+            )))),
+            "fd" => value
+                .parse()
+                .map(|fd| SecretRef::Source(SecretSource::Fd(fd)))
+                .map_err(|_| format!("{:?} is not a file descriptor", value)),
+            _ => Err(not_a_reference()),
+        }
+    }
+}
+
+impl FromStr for SecretRef {
+    type Err = EnclaveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s).map_err(invalid)
+    }
+}
+
+impl TryFrom<String> for SecretRef {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::parse(&s)
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Stored(id) => write!(f, "stored:{}", id),
+            SecretRef::Source(SecretSource::Systemd(name)) => write!(f, "systemd:{}", name),
+            SecretRef::Source(SecretSource::DockerSecret(name)) => write!(f, "docker:{}", name),
+            SecretRef::Source(SecretSource::File(path)) => write!(f, "file:{}", path.display()),
+            SecretRef::Source(SecretSource::Fd(fd)) => write!(f, "fd:{}", fd),
+        }
+    }
+}
+
+impl From<SecretRef> for Credential<String> {
+    fn from(r: SecretRef) -> Self {
+        match r {
+            SecretRef::Stored(id) => Credential::Stored(id),
+            SecretRef::Source(s) => Credential::Source(s),
+        }
+    }
+}
+
+/// An enclave described in a config file or URI
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum EnclaveConfig {
+    /// The OS keyring
+    #[serde(rename = "oskeyring")]
+    OsKeyRing {
+        /// Path to the keyring. If `None`, the default OS keyring is used
+        #[serde(default)]
+        path: Option<PathBuf>,
+        /// The username to log in with
+        #[serde(default)]
+        username: Option<String>,
+        /// Where the password is read from
+        #[serde(default)]
+        password: Option<SecretRef>,
+    },
+    /// A YubiHSM 2
+    #[serde(rename = "yubihsm")]
+    YubiHsm {
+        /// URL of the yubihsm-connector. If `None`, the HSM is used over USB
+        #[serde(default)]
+        url: Option<String>,
+        /// The id of the authentication key
+        auth_key_id: u16,
+        /// Where the password of the authentication key is read from
+        #[serde(default)]
+        password: Option<SecretRef>,
+    },
+    /// A token behind a PKCS#11 module
+    #[serde(rename = "pkcs11")]
+    Pkcs11 {
+        /// Path to the PKCS#11 module library
+        module: PathBuf,
+        /// Label of the token
+        #[serde(default)]
+        token: Option<String>,
+        /// Id of the slot holding the token
+        #[serde(default)]
+        slot: Option<u64>,
+        /// Where the user PIN is read from
+        #[serde(default)]
+        pin: Option<SecretRef>,
+    },
+    /// A software enclave in process memory
+    #[serde(rename = "software")]
+    Software,
+}
+
+impl EnclaveConfig {
+    /// Read a config from JSON
+    pub fn from_json(json: &str) -> Result<Self, EnclaveError> {
+        serde_json::from_str(json).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// The connector for this enclave
+    pub fn into_connector(self) -> EnclaveConnector<PathBuf, String> {
+        match self {
+            EnclaveConfig::OsKeyRing {
+                path,
+                username,
+                password,
+            } => {
+                let mut c = OsKeyRingConnector::new(path);
+                if let Some(u) = username {
+                    c = c.username(u);
+                }
+                match password {
+                    Some(SecretRef::Stored(id)) => c = c.stored_password(id),
+                    Some(SecretRef::Source(s)) => c = c.password_from(s),
+                    None => {}
+                }
+                EnclaveConnector::OsKeyRing(c)
+            }
+            EnclaveConfig::YubiHsm {
+                url,
+                auth_key_id,
+                password,
+            } => {
+                let mut c = YubiHsmConnector::new(auth_key_id);
+                if let Some(u) = url {
+                    c = c.url(u);
+                }
+                match password {
+                    Some(SecretRef::Stored(id)) => c = c.stored_password(id),
+                    Some(SecretRef::Source(s)) => c = c.password_from(s),
+                    None => {}
+                }
+                EnclaveConnector::YubiHsm(c)
+            }
+            EnclaveConfig::Pkcs11 {
+                module,
+                token,
+                slot,
+                pin,
+            } => {
+                let mut c = Pkcs11Connector::new(module);
+                if let Some(t) = token {
+                    c = c.token(t);
+                }
+                if let Some(s) = slot {
+                    c = c.slot(s);
+                }
+                match pin {
+                    Some(SecretRef::Stored(id)) => c = c.stored_pin(id),
+                    Some(SecretRef::Source(s)) => c = c.pin_from(s),
+                    None => {}
+                }
+                EnclaveConnector::Pkcs11(c)
+            }
+            EnclaveConfig::Software => EnclaveConnector::Software,
+        }
+    }
+}
+
+impl From<EnclaveConfig> for EnclaveConnector<PathBuf, String> {
+    fn from(config: EnclaveConfig) -> Self {
+        config.into_connector()
+    }
+}
+
+impl EnclaveConfig {
+    fn parse_uri(uri: &str) -> Result<Self, String> {
+        let mut uri = Uri::parse(uri)?;
+        let take_secret = |query: &mut Attributes, name| {
+            query.take(name)?.map(|s| SecretRef::parse(&s)).transpose()
+        };
+        let config = match uri.scheme.as_str() {
+            "oskeyring" => EnclaveConfig::OsKeyRing {
+                path: match uri.local_path()? {
+                    p if p.is_empty() => None,
+                    p => Some(PathBuf::from(p)),
+                },
+                username: uri.query.take("username")?,
+                password: take_secret(&mut uri.query, "password")?,
+            },
+            "yubihsm" => {
+                if !uri.path.is_empty() && uri.path != "/" {
+                    return Err("YubiHSM URIs have no path".to_string());
+                }
+                let url = match percent_decode(uri.authority.unwrap_or(""))? {
+                    h if h.is_empty() => None,
+                    h if h.contains(':') && !h.ends_with(']') => Some(format!("http://{}", h)),
+                    h => Some(format!("http://{}:{}", h, YUBIHSM_CONNECTOR_PORT)),
+                };
+                EnclaveConfig::YubiHsm {
+                    url,
+                    auth_key_id: uri
+                        .query
+                        .take_parsed("auth")?
+                        .ok_or("YubiHSM URIs need the auth key id in ?auth=")?,
+                    password: take_secret(&mut uri.query, "password")?,
+                }
+            }
+            "pkcs11" => {
+                let mut path = Attributes::parse(uri.path, ';')?;
+                if uri.query.take("pin-value")?.is_some() {
+                    return Err(
+                        "PINs can't be in PKCS#11 URIs. Reference them with pin-source".to_string(),
+                    );
+                }
+                let config = EnclaveConfig::Pkcs11 {
+                    module: uri
+                        .query
+                        .take("module-path")?
+                        .map(PathBuf::from)
+                        .ok_or("PKCS#11 URIs need the ?module-path=")?,
+                    token: path.take("token")?,
+                    slot: path.take_parsed("slot-id")?,
+                    pin: take_secret(&mut uri.query, "pin-source")?,
+                };
+                path.finish()?;
+                config
+            }
+            "software" => {
+                if uri.authority.is_some() || !uri.path.is_empty() {
+                    return Err("Software enclave URIs have no path".to_string());
+                }
+                EnclaveConfig::Software
+            }
+            _ => {
+                return Err(format!(
+                    "{} is not an enclave. Use oskeyring, yubihsm, pkcs11 or software",
+                    uri.scheme
+                ))
+            }
+        };
+        uri.query.finish()?;
+        Ok(config)
+    }
+}
+
+impl FromStr for EnclaveConfig {
+    type Err = EnclaveError;
+
+    /// Parse an enclave URI
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        Self::parse_uri(uri).map_err(invalid)
+    }
+}
+
+fn invalid<M: Into<String>>(msg: M) -> EnclaveError {
+    EnclaveErrorKind::ConnectionFailure {
+        msg: format!("Invalid enclave configuration: {}", msg.into()),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yubihsm(url: Option<&str>, auth_key_id: u16, password: Option<SecretRef>) -> EnclaveConfig {
+        EnclaveConfig::YubiHsm {
+            url: url.map(String::from),
+            auth_key_id,
+            password,
+        }
+    }
+
+    fn pkcs11(token: Option<&str>, slot: Option<u64>, pin: Option<SecretRef>) -> EnclaveConfig {
+        EnclaveConfig::Pkcs11 {
+            module: PathBuf::from("/usr/lib/libsofthsm2.so"),
+            token: token.map(String::from),
+            slot,
+            pin,
+        }
+    }
+
+    fn source(s: SecretSource) -> Option<SecretRef> {
+        Some(SecretRef::Source(s))
+    }
+
+    #[test]
+    fn valid_uris() {
+        let table = vec![
+            (
+                "oskeyring:",
+                EnclaveConfig::OsKeyRing {
+                    path: None,
+                    username: None,
+                    password: None,
+                },
+            ),
+            (
+                "OSKeyRing:///path/to/keyring?username=agent&password=fd:3",
+                EnclaveConfig::OsKeyRing {
+                    path: Some(PathBuf::from("/path/to/keyring")),
+                    username: Some("agent".to_string()),
+                    password: source(SecretSource::Fd(3)),
+                },
+            ),
+            (
+                "oskeyring://localhost/my%20keys?username=a%26b&password=stored:os",
+                EnclaveConfig::OsKeyRing {
+                    path: Some(PathBuf::from("/my keys")),
+                    username: Some("a&b".to_string()),
+                    password: Some(SecretRef::Stored("os".to_string())),
+                },
+            ),
+            ("yubihsm:?auth=1", yubihsm(None, 1, None)),
+            ("yubihsm://?auth=1", yubihsm(None, 1, None)),
+            (
+                "yubihsm://hsm.local?auth=2&password=systemd:hsm",
+                yubihsm(
+                    Some("http://hsm.local:12345"),
+                    2,
+                    source(SecretSource::Systemd("hsm".to_string())),
+                ),
+            ),
+            (
+                "yubihsm://127.0.0.1:8080/?auth=3&password=stored:hsm",
+                yubihsm(
+                    Some("http://127.0.0.1:8080"),
+                    3,
+                    Some(SecretRef::Stored("hsm".to_string())),
+                ),
+            ),
+            (
+                "yubihsm://[::1]?auth=4",
+                yubihsm(Some("http://[::1]:12345"), 4, None),
+            ),
+            (
+                "yubihsm://[::1]:8080?auth=4",
+                yubihsm(Some("http://[::1]:8080"), 4, None),
+            ),
+            (
+                "pkcs11:?module-path=/usr/lib/libsofthsm2.so",
+                pkcs11(None, None, None),
+            ),
+            (
+                "pkcs11:token=My%20Token;slot-id=7?module-path=%2Fusr%2Flib%2Flibsofthsm2.so&pin-source=file:///run/secrets/pin",
+                pkcs11(
+                    Some("My Token"),
+                    Some(7),
+                    source(SecretSource::File(PathBuf::from("/run/secrets/pin"))),
+                ),
+            ),
+            (
+                "pkcs11:token=a%3Bb?module-path=/usr/lib/libsofthsm2.so&pin-source=docker:pin",
+                pkcs11(
+                    Some("a;b"),
+                    None,
+                    source(SecretSource::DockerSecret("pin".to_string())),
+                ),
+            ),
+            ("software:", EnclaveConfig::Software),
+        ];
+        for (uri, expected) in table {
+            assert_eq!(uri.parse::<EnclaveConfig>().unwrap(), expected, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn invalid_uris() {
+        let table = [
+            // Not URIs or not enclaves
+            "",
+            "/path/to/keyring",
+            "sqlite:///tmp/agent.db",
+            // oskeyring
+            "oskeyring://host/path",
+            "oskeyring:///path%zz",
+            "oskeyring:?password=secret",
+            "oskeyring:?username=a&username=b",
+            "oskeyring:?user=agent",
+            // yubihsm
+            "yubihsm:",
+            "yubihsm:?auth=",
+            "yubihsm:?auth=x",
+            "yubihsm:?auth=70000",
+            "yubihsm://host/path?auth=1",
+            "yubihsm:?auth=1&password=fd:x",
+            "yubihsm:?auth=1&pasword=stored:hsm",
+            // pkcs11
+            "pkcs11:token=Agent",
+            "pkcs11:slot-id=x?module-path=/lib.so",
+            "pkcs11:serial=1?module-path=/lib.so",
+            "pkcs11:?module-path=/lib.so&pin-value=1234",
+            "pkcs11:?module-path=/lib.so&pin-source=1234",
+            "pkcs11:?module-path=/lib.so&module-name=softhsm",
+            // software
+            "software:path",
+            "software://host",
+            "software:?keys=1",
+        ];
+        for uri in table.iter() {
+            let err = uri.parse::<EnclaveConfig>().unwrap_err();
+            assert!(
+                matches!(err.kind(), EnclaveErrorKind::ConnectionFailure { .. }),
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_parameters_are_named() {
+        let table = [
+            ("oskeyring:?user=agent", "user"),
+            ("yubihsm:?auth=1&port=1", "port"),
+            ("pkcs11:serial=1?module-path=/lib.so", "serial"),
+            ("software:?keys=1", "keys"),
+        ];
+        for (uri, name) in table.iter() {
+            let err = uri.parse::<EnclaveConfig>().unwrap_err().to_string();
+            assert!(
+                err.contains(&format!("{} is not supported", name)),
+                "{}: {}",
+                uri,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn secret_references() {
+        let table = vec![
+            ("stored:hsm", Some(SecretRef::Stored("hsm".to_string()))),
+            (
+                "systemd:pin",
+                source(SecretSource::Systemd("pin".to_string())),
+            ),
+            (
+                "docker:pin",
+                source(SecretSource::DockerSecret("pin".to_string())),
+            ),
+            (
+                "file:/run/pin",
+                source(SecretSource::File(PathBuf::from("/run/pin"))),
+            ),
+            (
+                "file:///run/pin",
+                source(SecretSource::File(PathBuf::from("/run/pin"))),
+            ),
+            ("fd:3", source(SecretSource::Fd(3))),
+            ("fd:x", None),
+            ("fd:", None),
+            ("stored:", None),
+            ("secret", None),
+            ("env:PIN", None),
+        ];
+        for (s, expected) in table {
+            assert_eq!(s.parse::<SecretRef>().ok(), expected, "{}", s);
+            if let Some(r) = expected {
+                assert_eq!(r.to_string().parse::<SecretRef>().unwrap(), r);
+            }
+        }
+    }
+
+    #[test]
+    fn json_configs() {
+        let config = EnclaveConfig::from_json(
+            r#"{"type": "yubihsm", "url": "http://127.0.0.1:12345", "auth_key_id": 1, "password": "systemd:hsm"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            yubihsm(
+                Some("http://127.0.0.1:12345"),
+                1,
+                source(SecretSource::Systemd("hsm".to_string()))
+            )
+        );
+        assert_eq!(
+            EnclaveConfig::from_json(r#"{"type": "software"}"#).unwrap(),
+            EnclaveConfig::Software
+        );
+        for json in &[
+            r#"{"type": "yubihsm"}"#,
+            r#"{"type": "yubihsm", "auth_key_id": 1, "password": "secret"}"#,
+            r#"{"type": "yubihsm", "auth_key_id": 1, "pin": "stored:hsm"}"#,
+            r#"{"type": "pkcs11", "module": "/lib.so", "slot": "x"}"#,
+            r#"{"type": "sqlite"}"#,
+            r#"{"url": "http://127.0.0.1:12345"}"#,
+        ] {
+            assert!(EnclaveConfig::from_json(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn connectors() {
+        let connector = "yubihsm://hsm.local?auth=2&password=stored:hsm"
+            .parse::<EnclaveConfig>()
+            .unwrap()
+            .into_connector();
+        match connector {
+            EnclaveConnector::YubiHsm(c) => assert_eq!(
+                c,
+                YubiHsmConnector::new(2)
+                    .url("http://hsm.local:12345".to_string())
+                    .stored_password("hsm")
+            ),
+            c => panic!("{:?} is not a YubiHSM connector", c),
+        }
+        let connector: EnclaveConnector<_, _> = pkcs11(Some("Agent"), Some(0), None).into();
+        match connector {
+            EnclaveConnector::Pkcs11(c) => assert_eq!(
+                c,
+                Pkcs11Connector::new(PathBuf::from("/usr/lib/libsofthsm2.so"))
+                    .token("Agent".to_string())
+                    .slot(0)
+            ),
+            c => panic!("{:?} is not a PKCS#11 connector", c),
+        }
+        assert!(matches!(
+            EnclaveConfig::Software.into_connector(),
+            EnclaveConnector::Software
+        ));
+    }
+}
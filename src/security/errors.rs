@@ -40,6 +40,15 @@ pub enum EnclaveErrorKind {
     /// When a item in the enclave is does not exist
     #[fail(display = "The specified item is not found in the keyring")]
     ItemNotFound,
+    /// When the enclave does not support the requested operation
+    #[fail(display = "The operation is not supported by the enclave")]
+    UnsupportedOperation,
+    /// When the capabilities of a key don't fit its type
+    #[fail(display = "The key capabilities are invalid: {}", msg)]
+    InvalidCapabilities {
+        /// Description of the invalid combination
+        msg: String,
+    },
     /// Catch all if currently not handled or doesn't meet another error category like a general message
     #[fail(display = "{}", msg)]
     GeneralError {
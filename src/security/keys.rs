@@ -0,0 +1,742 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Keys are created and opened through a handle for what they are used for,
+//! so using a key for the wrong kind of operation doesn't compile.
+//!
+//! ```no_run
+//! # use arieskms::security::{keys::{AeadKey, KeyExport, SigningKey}, null::NullEnclave, WrappingKey};
+//! # fn main() -> arieskms::security::EnclaveResult<()> {
+//! # let enclave = NullEnclave;
+//! let key = AeadKey::generate(&enclave, "wallet", WrappingKey::XChaChaPoly1305, KeyExport::Never)?;
+//! let ciphertext = key.encrypt(b"secret", b"")?;
+//! let key = SigningKey::open(&enclave, "identity")?;
+//! let signature = key.sign(b"message")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! There is no `sign` for AEAD keys
+//!
+//! ```compile_fail
+//! # use arieskms::security::{keys::{AeadKey, KeyExport, SigningKey}, null::NullEnclave, WrappingKey};
+//! # fn main() -> arieskms::security::EnclaveResult<()> {
+//! # let enclave = NullEnclave;
+//! let key = AeadKey::open(&enclave, "wallet")?;
+//! let signature = key.sign(b"message")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! and no `encrypt` for signing keys
+//!
+//! ```compile_fail
+//! # use arieskms::security::{keys::{AeadKey, KeyExport, SigningKey}, null::NullEnclave, WrappingKey};
+//! # fn main() -> arieskms::security::EnclaveResult<()> {
+//! # let enclave = NullEnclave;
+//! let key = SigningKey::open(&enclave, "identity")?;
+//! let ciphertext = key.encrypt(b"secret", b"")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Each handle generates its key with the capabilities its operations need
+//! and nothing more, after checking them against the key type and the
+//! enclave. No handle verifies signatures or tags, so keys are created
+//! without `VERIFY` capabilities and enclaves that can only sign, like the
+//! software enclave, can hold them. Check a tag by comparing it with the one
+//! `HmacKey::sign` computes and signatures with the public key. Keys created
+//! elsewhere, including ones with `VERIFY` capabilities, are opened with
+//! `open`, which checks the type the enclave reports with
+//! `EnclaveLike::key_type`. Enclaves that can't report key types can't open
+//! keys. The enclave still checks the capabilities of the key on every
+//! operation.
+
+use super::{
+    EcCurves, EccCapability, EcdsaAlgorithm, EnclaveErrorKind, EnclaveKeyType, EnclaveLike,
+    EnclaveResult, HmacAlgorithm, KeyCapabilities, RsaCapability, RsaMgf, SymmetricCapability,
+    WrappingKey,
+};
+
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Whether a key can leave the enclave
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyExport {
+    /// The key never leaves the enclave
+    Never,
+    /// The key can be exported wrapped by another key with `export_wrapped`
+    WhenWrapped,
+}
+
+/// The algorithms of `SigningKey`s
+#[derive(Clone, Copy, Debug)]
+pub enum SigningAlgorithm {
+    /// Ed25519 signatures
+    Ed25519,
+    /// ECDSA signatures over a curve with a hash
+    Ecdsa(EcCurves, EcdsaAlgorithm),
+    /// RSASSA-PSS signatures
+    RsaPss(RsaMgf),
+    /// RSA PKCS#1v1.5 signatures. Only use for legacy purposes.
+    RsaPkcs15(RsaMgf),
+}
+
+impl SigningAlgorithm {
+    fn key_type(self, export: KeyExport) -> (EnclaveKeyType, KeyCapabilities) {
+        let signing = EccCapability::SIGN;
+        match self {
+            SigningAlgorithm::Ed25519 => (EnclaveKeyType::Ed25519, ecc(signing, export)),
+            SigningAlgorithm::Ecdsa(curve, hash) => {
+                (EnclaveKeyType::Ecdsa(curve, hash), ecc(signing, export))
+            }
+            SigningAlgorithm::RsaPss(mgf) => (
+                EnclaveKeyType::RsaPss(mgf),
+                rsa(RsaCapability::SIGN_PSS, export),
+            ),
+            SigningAlgorithm::RsaPkcs15(mgf) => (
+                EnclaveKeyType::RsaPkcs15(mgf),
+                rsa(RsaCapability::SIGN_PKCS, export),
+            ),
+        }
+    }
+}
+
+/// The algorithms of `AgreementKey`s
+#[derive(Clone, Copy, Debug)]
+pub enum AgreementAlgorithm {
+    /// Diffie-Hellman over Curve25519
+    X25519,
+    /// Elliptic curve Diffie-Hellman over a curve
+    Ecdh(EcCurves),
+}
+
+/// The capabilities of a symmetric key used for `capabilities`
+fn symmetric(mut capabilities: SymmetricCapability, export: KeyExport) -> KeyCapabilities {
+    if export == KeyExport::WhenWrapped {
+        capabilities |= SymmetricCapability::EXPORTABLE_WHEN_WRAPPED;
+    }
+    KeyCapabilities::Symmetric(capabilities)
+}
+
+/// The capabilities of an elliptic curve key used for `capabilities`
+fn ecc(mut capabilities: EccCapability, export: KeyExport) -> KeyCapabilities {
+    if export == KeyExport::WhenWrapped {
+        capabilities |= EccCapability::EXPORTABLE_WHEN_WRAPPED;
+    }
+    KeyCapabilities::Ecc(capabilities)
+}
+
+/// The capabilities of an RSA key used for `capabilities`
+fn rsa(mut capabilities: RsaCapability, export: KeyExport) -> KeyCapabilities {
+    if export == KeyExport::WhenWrapped {
+        capabilities |= RsaCapability::EXPORTABLE_WHEN_WRAPPED;
+    }
+    KeyCapabilities::Rsa(capabilities)
+}
+
+/// The enclave and id of a key shared by every handle
+struct Handle<'a, E: EnclaveLike> {
+    enclave: &'a E,
+    id: String,
+}
+
+impl<'a, E: EnclaveLike> Handle<'a, E> {
+    fn generate(
+        enclave: &'a E,
+        id: String,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+    ) -> EnclaveResult<Self> {
+        enclave.create_key(&id, key_type, capabilities)?;
+        Ok(Self { enclave, id })
+    }
+
+    fn import(
+        enclave: &'a E,
+        id: String,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+        key: &[u8],
+    ) -> EnclaveResult<Self> {
+        enclave.import_key(&id, key_type, capabilities, key)?;
+        Ok(Self { enclave, id })
+    }
+
+    /// Use the existing key `id` if `fits` its type
+    fn open(
+        enclave: &'a E,
+        id: String,
+        what: &str,
+        fits: fn(EnclaveKeyType) -> bool,
+    ) -> EnclaveResult<Self> {
+        let key_type = enclave.key_type(&id)?;
+        if !fits(key_type) {
+            return Err(EnclaveErrorKind::InvalidCapabilities {
+                msg: format!("The key {} is a {:?} key, not {}", id, key_type, what),
+            }
+            .into());
+        }
+        Ok(Self { enclave, id })
+    }
+
+    fn export_wrapped(&self, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.enclave.export_wrapped_key(&self.id, wrapping_key)
+    }
+}
+
+/// A symmetric key for authenticated encryption
+pub struct AeadKey<'a, E: EnclaveLike>(Handle<'a, E>);
+
+impl<'a, E: EnclaveLike> AeadKey<'a, E> {
+    /// Generate the key `id` in `enclave`
+    pub fn generate<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        algorithm: WrappingKey,
+        export: KeyExport,
+    ) -> EnclaveResult<Self> {
+        let capabilities = symmetric(
+            SymmetricCapability::ENCRYPT | SymmetricCapability::DECRYPT,
+            export,
+        );
+        Handle::generate(
+            enclave,
+            id.into(),
+            EnclaveKeyType::WrapKey(algorithm),
+            capabilities,
+        )
+        .map(Self)
+    }
+
+    /// Save the existing unwrapped `key` as `id` in `enclave`
+    pub fn import<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        algorithm: WrappingKey,
+        export: KeyExport,
+        key: &[u8],
+    ) -> EnclaveResult<Self> {
+        let capabilities = symmetric(
+            SymmetricCapability::ENCRYPT | SymmetricCapability::DECRYPT,
+            export,
+        );
+        Handle::import(
+            enclave,
+            id.into(),
+            EnclaveKeyType::WrapKey(algorithm),
+            capabilities,
+            key,
+        )
+        .map(Self)
+    }
+
+    /// Use the existing AEAD key `id` in `enclave`. Fails with `InvalidCapabilities`
+    /// if it is another type of key.
+    pub fn open<I: Into<String>>(enclave: &'a E, id: I) -> EnclaveResult<Self> {
+        Handle::open(enclave, id.into(), "an AEAD key", |t| {
+            matches!(t, EnclaveKeyType::WrapKey(_))
+        })
+        .map(Self)
+    }
+
+    /// The id of the key in the enclave
+    pub fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    /// Encrypt `plaintext` with a fresh nonce and authenticate `aad`
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.enclave.encrypt(&self.0.id, plaintext, aad)
+    }
+
+    /// Encrypt `plaintext` so the same inputs always produce the same ciphertext
+    pub fn encrypt_deterministic(&self, plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0
+            .enclave
+            .encrypt_deterministic(&self.0.id, plaintext, aad)
+    }
+
+    /// Decrypt `ciphertext` and check `aad`
+    pub fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.enclave.decrypt(&self.0.id, ciphertext, aad)
+    }
+
+    /// Export the key wrapped by `wrapping_key` as described by `EnclaveLike::export_wrapped_key`
+    pub fn export_wrapped(&self, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.export_wrapped(wrapping_key)
+    }
+}
+
+/// A symmetric key for Hash-based Message Authentication Codes
+pub struct HmacKey<'a, E: EnclaveLike>(Handle<'a, E>);
+
+impl<'a, E: EnclaveLike> HmacKey<'a, E> {
+    /// Generate the key `id` in `enclave`
+    pub fn generate<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        algorithm: HmacAlgorithm,
+        export: KeyExport,
+    ) -> EnclaveResult<Self> {
+        let capabilities = symmetric(SymmetricCapability::HMAC_SIGN, export);
+        Handle::generate(
+            enclave,
+            id.into(),
+            EnclaveKeyType::Hmac(algorithm),
+            capabilities,
+        )
+        .map(Self)
+    }
+
+    /// Save the existing unwrapped `key` as `id` in `enclave`
+    pub fn import<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        algorithm: HmacAlgorithm,
+        export: KeyExport,
+        key: &[u8],
+    ) -> EnclaveResult<Self> {
+        let capabilities = symmetric(SymmetricCapability::HMAC_SIGN, export);
+        Handle::import(
+            enclave,
+            id.into(),
+            EnclaveKeyType::Hmac(algorithm),
+            capabilities,
+            key,
+        )
+        .map(Self)
+    }
+
+    /// Use the existing HMAC key `id` in `enclave`. Fails with `InvalidCapabilities`
+    /// if it is another type of key.
+    pub fn open<I: Into<String>>(enclave: &'a E, id: I) -> EnclaveResult<Self> {
+        Handle::open(enclave, id.into(), "an HMAC key", |t| {
+            matches!(t, EnclaveKeyType::Hmac(_))
+        })
+        .map(Self)
+    }
+
+    /// The id of the key in the enclave
+    pub fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    /// Compute the tag of `data`
+    pub fn sign(&self, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.enclave.sign_hmac(&self.0.id, data)
+    }
+
+    /// Export the key wrapped by `wrapping_key` as described by `EnclaveLike::export_wrapped_key`
+    pub fn export_wrapped(&self, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.export_wrapped(wrapping_key)
+    }
+}
+
+/// A private key for digital signatures
+pub struct SigningKey<'a, E: EnclaveLike>(Handle<'a, E>);
+
+impl<'a, E: EnclaveLike> SigningKey<'a, E> {
+    /// Generate the key `id` in `enclave`
+    pub fn generate<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        algorithm: SigningAlgorithm,
+        export: KeyExport,
+    ) -> EnclaveResult<Self> {
+        let (key_type, capabilities) = algorithm.key_type(export);
+        Handle::generate(enclave, id.into(), key_type, capabilities).map(Self)
+    }
+
+    /// Save the existing unwrapped private `key` as `id` in `enclave`
+    pub fn import<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        algorithm: SigningAlgorithm,
+        export: KeyExport,
+        key: &[u8],
+    ) -> EnclaveResult<Self> {
+        let (key_type, capabilities) = algorithm.key_type(export);
+        Handle::import(enclave, id.into(), key_type, capabilities, key).map(Self)
+    }
+
+    /// Use the existing signing key `id` in `enclave`. Fails with `InvalidCapabilities`
+    /// if it is another type of key.
+    pub fn open<I: Into<String>>(enclave: &'a E, id: I) -> EnclaveResult<Self> {
+        Handle::open(enclave, id.into(), "a signing key", |t| {
+            matches!(
+                t,
+                EnclaveKeyType::Ed25519
+                    | EnclaveKeyType::Ecdsa(..)
+                    | EnclaveKeyType::RsaPss(_)
+                    | EnclaveKeyType::RsaPkcs15(_)
+            )
+        })
+        .map(Self)
+    }
+
+    /// The id of the key in the enclave
+    pub fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    /// The public key for verifying signatures
+    pub fn public_key(&self) -> EnclaveResult<Vec<u8>> {
+        self.0.enclave.public_key(&self.0.id)
+    }
+
+    /// Sign `data`
+    pub fn sign(&self, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.enclave.sign(&self.0.id, data)
+    }
+
+    /// Export the key wrapped by `wrapping_key` as described by `EnclaveLike::export_wrapped_key`
+    pub fn export_wrapped(&self, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.export_wrapped(wrapping_key)
+    }
+}
+
+/// A private key for Diffie-Hellman key agreement
+pub struct AgreementKey<'a, E: EnclaveLike>(Handle<'a, E>);
+
+impl<'a, E: EnclaveLike> AgreementKey<'a, E> {
+    fn key_type(algorithm: AgreementAlgorithm) -> EnclaveKeyType {
+        match algorithm {
+            AgreementAlgorithm::X25519 => EnclaveKeyType::X25519,
+            AgreementAlgorithm::Ecdh(curve) => EnclaveKeyType::Ecdh(curve),
+        }
+    }
+
+    /// Generate the key `id` in `enclave`
+    pub fn generate<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        algorithm: AgreementAlgorithm,
+        export: KeyExport,
+    ) -> EnclaveResult<Self> {
+        let capabilities = ecc(EccCapability::DERIVE_DIFFIE_HELLMAN, export);
+        Handle::generate(enclave, id.into(), Self::key_type(algorithm), capabilities).map(Self)
+    }
+
+    /// Save the existing unwrapped private `key` as `id` in `enclave`
+    pub fn import<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        algorithm: AgreementAlgorithm,
+        export: KeyExport,
+        key: &[u8],
+    ) -> EnclaveResult<Self> {
+        let capabilities = ecc(EccCapability::DERIVE_DIFFIE_HELLMAN, export);
+        Handle::import(
+            enclave,
+            id.into(),
+            Self::key_type(algorithm),
+            capabilities,
+            key,
+        )
+        .map(Self)
+    }
+
+    /// Use the existing key-exchange key `id` in `enclave`. Fails with `InvalidCapabilities`
+    /// if it is another type of key.
+    pub fn open<I: Into<String>>(enclave: &'a E, id: I) -> EnclaveResult<Self> {
+        Handle::open(enclave, id.into(), "a key-exchange key", |t| {
+            matches!(t, EnclaveKeyType::X25519 | EnclaveKeyType::Ecdh(_))
+        })
+        .map(Self)
+    }
+
+    /// The id of the key in the enclave
+    pub fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    /// The public key to send to the peer
+    pub fn public_key(&self) -> EnclaveResult<Vec<u8>> {
+        self.0.enclave.public_key(&self.0.id)
+    }
+
+    /// Compute the shared secret with the peer's `public_key`
+    pub fn derive_shared_secret(&self, public_key: &[u8]) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        self.0.enclave.derive_shared_secret(&self.0.id, public_key)
+    }
+
+    /// Export the key wrapped by `wrapping_key` as described by `EnclaveLike::export_wrapped_key`
+    pub fn export_wrapped(&self, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.export_wrapped(wrapping_key)
+    }
+}
+
+/// A private key for RSA-OAEP encryption
+pub struct RsaEncryptionKey<'a, E: EnclaveLike>(Handle<'a, E>);
+
+impl<'a, E: EnclaveLike> RsaEncryptionKey<'a, E> {
+    /// Generate the key `id` in `enclave` using `mgf` for the mask generation function
+    pub fn generate<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        mgf: RsaMgf,
+        export: KeyExport,
+    ) -> EnclaveResult<Self> {
+        Handle::generate(
+            enclave,
+            id.into(),
+            EnclaveKeyType::RsaOaep(mgf),
+            rsa(
+                RsaCapability::ENCRYPT_OAEP | RsaCapability::DECRYPT_OAEP,
+                export,
+            ),
+        )
+        .map(Self)
+    }
+
+    /// Save the existing unwrapped private `key` as `id` in `enclave`
+    pub fn import<I: Into<String>>(
+        enclave: &'a E,
+        id: I,
+        mgf: RsaMgf,
+        export: KeyExport,
+        key: &[u8],
+    ) -> EnclaveResult<Self> {
+        Handle::import(
+            enclave,
+            id.into(),
+            EnclaveKeyType::RsaOaep(mgf),
+            rsa(
+                RsaCapability::ENCRYPT_OAEP | RsaCapability::DECRYPT_OAEP,
+                export,
+            ),
+            key,
+        )
+        .map(Self)
+    }
+
+    /// Use the existing RSA-OAEP key `id` in `enclave`. Fails with `InvalidCapabilities`
+    /// if it is another type of key.
+    pub fn open<I: Into<String>>(enclave: &'a E, id: I) -> EnclaveResult<Self> {
+        Handle::open(enclave, id.into(), "an RSA-OAEP key", |t| {
+            matches!(t, EnclaveKeyType::RsaOaep(_))
+        })
+        .map(Self)
+    }
+
+    /// The id of the key in the enclave
+    pub fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    /// The public key others encrypt to
+    pub fn public_key(&self) -> EnclaveResult<Vec<u8>> {
+        self.0.enclave.public_key(&self.0.id)
+    }
+
+    /// Encrypt `plaintext` with the public key
+    pub fn encrypt(&self, plaintext: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.enclave.encrypt_oaep(&self.0.id, plaintext)
+    }
+
+    /// Decrypt `ciphertext` with the private key
+    pub fn decrypt(&self, ciphertext: &[u8]) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        self.0.enclave.decrypt_oaep(&self.0.id, ciphertext)
+    }
+
+    /// Export the key wrapped by `wrapping_key` as described by `EnclaveLike::export_wrapped_key`
+    pub fn export_wrapped(&self, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.export_wrapped(wrapping_key)
+    }
+}
+
+impl<'a, E: EnclaveLike> fmt::Debug for AeadKey<'a, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AeadKey").field(&self.0.id).finish()
+    }
+}
+
+impl<'a, E: EnclaveLike> fmt::Debug for HmacKey<'a, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("HmacKey").field(&self.0.id).finish()
+    }
+}
+
+impl<'a, E: EnclaveLike> fmt::Debug for SigningKey<'a, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SigningKey").field(&self.0.id).finish()
+    }
+}
+
+impl<'a, E: EnclaveLike> fmt::Debug for AgreementKey<'a, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AgreementKey").field(&self.0.id).finish()
+    }
+}
+
+impl<'a, E: EnclaveLike> fmt::Debug for RsaEncryptionKey<'a, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RsaEncryptionKey").field(&self.0.id).finish()
+    }
+}
+
+#[cfg(all(test, feature = "software-enclave"))]
+mod tests {
+    use super::*;
+    use crate::security::{shared::SharedEnclave, software::SoftwareEnclave, AesModes, AesSizes};
+    use ed25519_dalek::{PublicKey, Signature, Verifier};
+    use std::convert::TryFrom;
+
+    const WRAPPING_KEY: [u8; 32] = [9; 32];
+
+    /// Unwrap a key exported with `export_wrapped` like the enclave it came from
+    fn unwrap(id: &str, wrapped: &[u8]) -> Vec<u8> {
+        use chacha20poly1305::{
+            aead::{Aead, NewAead, Payload},
+            Key, XChaCha20Poly1305, XNonce,
+        };
+        let (nonce, ciphertext) = wrapped.split_at(24);
+        XChaCha20Poly1305::new(<&Key>::from(&WRAPPING_KEY[..]))
+            .decrypt(
+                <&XNonce>::from(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: id.as_bytes(),
+                },
+            )
+            .unwrap()
+    }
+
+    fn assert_wrong_type<T: fmt::Debug>(result: EnclaveResult<T>) {
+        match result.unwrap_err().kind() {
+            EnclaveErrorKind::InvalidCapabilities { .. } => {}
+            kind => panic!("Expected InvalidCapabilities but found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn aead_keys() {
+        let enclave = SoftwareEnclave::new();
+        let algorithm = WrappingKey::Aes(AesSizes::Aes256, AesModes::GcmSiv);
+        let key = AeadKey::generate(&enclave, "aead", algorithm, KeyExport::WhenWrapped).unwrap();
+        let ciphertext = key.encrypt(b"secret", b"aad").unwrap();
+        let deterministic = key.encrypt_deterministic(b"secret", b"aad").unwrap();
+
+        let opened = AeadKey::open(&enclave, "aead").unwrap();
+        assert_eq!(opened.id(), "aead");
+        assert_eq!(opened.decrypt(&ciphertext, b"aad").unwrap(), b"secret");
+        assert_eq!(opened.decrypt(&deterministic, b"aad").unwrap(), b"secret");
+
+        let exported = unwrap("aead", &key.export_wrapped(&WRAPPING_KEY).unwrap());
+        let other = SoftwareEnclave::new();
+        let imported =
+            AeadKey::import(&other, "aead", algorithm, KeyExport::Never, &exported).unwrap();
+        assert_eq!(imported.decrypt(&ciphertext, b"aad").unwrap(), b"secret");
+        assert!(imported.export_wrapped(&WRAPPING_KEY).is_err());
+    }
+
+    #[test]
+    fn hmac_keys() {
+        let enclave = SoftwareEnclave::new();
+        let algorithm = HmacAlgorithm::Sha256;
+        let key = HmacKey::generate(&enclave, "hmac", algorithm, KeyExport::WhenWrapped).unwrap();
+        let tag = key.sign(b"data").unwrap();
+        assert_eq!(tag.len(), 32);
+        assert_eq!(
+            HmacKey::open(&enclave, "hmac")
+                .unwrap()
+                .sign(b"data")
+                .unwrap(),
+            tag
+        );
+
+        let exported = unwrap("hmac", &key.export_wrapped(&WRAPPING_KEY).unwrap());
+        let other = SoftwareEnclave::new();
+        let imported =
+            HmacKey::import(&other, "hmac", algorithm, KeyExport::Never, &exported).unwrap();
+        assert_eq!(imported.sign(b"data").unwrap(), tag);
+    }
+
+    #[test]
+    fn signing_keys() {
+        let enclave = SoftwareEnclave::new();
+        let algorithm = SigningAlgorithm::Ed25519;
+        let key =
+            SigningKey::generate(&enclave, "signing", algorithm, KeyExport::WhenWrapped).unwrap();
+        let public = PublicKey::from_bytes(&key.public_key().unwrap()).unwrap();
+        let opened = SigningKey::open(&enclave, "signing").unwrap();
+        let signature = opened.sign(b"message").unwrap();
+        public
+            .verify(b"message", &Signature::try_from(&signature[..]).unwrap())
+            .unwrap();
+
+        let exported = unwrap("signing", &key.export_wrapped(&WRAPPING_KEY).unwrap());
+        let other = SoftwareEnclave::new();
+        let imported =
+            SigningKey::import(&other, "signing", algorithm, KeyExport::Never, &exported).unwrap();
+        assert_eq!(imported.public_key().unwrap(), public.as_bytes());
+        assert_eq!(imported.sign(b"message").unwrap(), signature);
+    }
+
+    #[test]
+    fn unsupported_keys_are_rejected() {
+        let enclave = SoftwareEnclave::new();
+        for result in &[
+            AgreementKey::generate(
+                &enclave,
+                "x25519",
+                AgreementAlgorithm::X25519,
+                KeyExport::Never,
+            )
+            .map(|_| ()),
+            RsaEncryptionKey::generate(&enclave, "rsa", RsaMgf::Sha256, KeyExport::Never)
+                .map(|_| ()),
+        ] {
+            match result {
+                Err(e) => assert_eq!(e.kind(), EnclaveErrorKind::UnsupportedOperation),
+                Ok(_) => panic!("Generated a key the software enclave can't hold"),
+            }
+        }
+    }
+
+    #[test]
+    fn open_checks_the_key_type() {
+        let enclave = SharedEnclave::new(SoftwareEnclave::new());
+        AeadKey::generate(
+            &enclave,
+            "aead",
+            WrappingKey::XChaChaPoly1305,
+            KeyExport::Never,
+        )
+        .unwrap();
+        HmacKey::generate(&enclave, "hmac", HmacAlgorithm::Sha512, KeyExport::Never).unwrap();
+        SigningKey::generate(
+            &enclave,
+            "signing",
+            SigningAlgorithm::Ed25519,
+            KeyExport::Never,
+        )
+        .unwrap();
+
+        assert_wrong_type(SigningKey::open(&enclave, "aead"));
+        assert_wrong_type(HmacKey::open(&enclave, "aead"));
+        assert_wrong_type(AeadKey::open(&enclave, "hmac"));
+        assert_wrong_type(AeadKey::open(&enclave, "signing"));
+        assert_wrong_type(AgreementKey::open(&enclave, "signing"));
+        assert_wrong_type(RsaEncryptionKey::open(&enclave, "signing"));
+        match AeadKey::open(&enclave, "missing") {
+            Err(e) => assert_eq!(e.kind(), EnclaveErrorKind::ItemNotFound),
+            Ok(_) => panic!("Opened a missing key"),
+        }
+    }
+}
@@ -32,10 +32,15 @@
 //! For example, use the software enclave provided by the operating system to
 //! store credentials to the hardware or external enclave. Once the credentials
 //! are retrieved from the OS enclave, they can be used to connect to the
-//! hardware or external enclave.
+//! hardware or external enclave. A connector marks such a credential as
+//! `Credential::Stored` and `EnclaveLike::connect_with` reads it from the OS
+//! enclave while connecting, so the PIN is never written in a config file.
 
+use errors::EnclaveErrorKind;
+use prompt::{CredentialKind, CredentialPrompt, PromptRequest};
+use source::SecretSource;
 use std::{fmt, path::Path};
-use zeroize::Zeroize;
+use zeroize::{Zeroize, Zeroizing};
 
 /// Typical result from performing and enclave operation or sending an enclave message
 pub type EnclaveResult<T> = Result<T, errors::EnclaveError>;
@@ -57,7 +62,48 @@ where
     /// Connect to an instance of an OsKeyRing
     OsKeyRing(OsKeyRingConnector<A, B>),
     /// Connect to a Yubihsm
-    YubiHsm,
+    YubiHsm(YubiHsmConnector<B>),
+    /// Connect to a token through a PKCS#11 module
+    Pkcs11(Pkcs11Connector<A, B>),
+    /// Create a software enclave in process memory. Its keys are lost
+    /// when it is closed.
+    Software,
+}
+
+impl<A, B> EnclaveConnector<A, B>
+where
+    A: AsRef<Path>,
+    B: Into<String>,
+{
+    /// Replace every `Credential::Stored` with the secret read from `source`
+    pub fn resolve<S: EnclaveLike>(
+        self,
+        source: &S,
+    ) -> EnclaveResult<EnclaveConnector<A, String>> {
+        match self {
+            EnclaveConnector::OsKeyRing(c) => Ok(EnclaveConnector::OsKeyRing(c.resolve(source)?)),
+            EnclaveConnector::YubiHsm(c) => Ok(EnclaveConnector::YubiHsm(c.resolve(source)?)),
+            EnclaveConnector::Pkcs11(c) => Ok(EnclaveConnector::Pkcs11(c.resolve(source)?)),
+            EnclaveConnector::Software => Ok(EnclaveConnector::Software),
+        }
+    }
+
+    /// Ask `prompt` for every credential that was left out
+    pub fn prompt_missing<P: CredentialPrompt>(
+        self,
+        prompt: &P,
+    ) -> EnclaveResult<EnclaveConnector<A, String>> {
+        match self {
+            EnclaveConnector::OsKeyRing(c) => {
+                Ok(EnclaveConnector::OsKeyRing(c.prompt_missing(prompt)?))
+            }
+            EnclaveConnector::YubiHsm(c) => {
+                Ok(EnclaveConnector::YubiHsm(c.prompt_missing(prompt)?))
+            }
+            EnclaveConnector::Pkcs11(c) => Ok(EnclaveConnector::Pkcs11(c.prompt_missing(prompt)?)),
+            EnclaveConnector::Software => Ok(EnclaveConnector::Software),
+        }
+    }
 }
 
 impl<A, B> fmt::Display for EnclaveConnector<A, B>
@@ -66,7 +112,103 @@ where
     B: Into<String>,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "EnclaveConfig ({})", self)
+        match self {
+            EnclaveConnector::OsKeyRing(c) => write!(f, "EnclaveConfig ({})", c),
+            EnclaveConnector::YubiHsm(c) => write!(f, "EnclaveConfig ({})", c),
+            EnclaveConnector::Pkcs11(c) => write!(f, "EnclaveConfig ({})", c),
+            EnclaveConnector::Software => write!(f, "EnclaveConfig (SoftwareConfig)"),
+        }
+    }
+}
+
+/// A secret needed to connect to an enclave like a password or PIN
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credential<B: Into<String>> {
+    /// The secret itself
+    Value(B),
+    /// The id of the secret in another enclave. It is read from that
+    /// enclave when connecting with `EnclaveLike::connect_with` so it
+    /// never has to appear in a config file.
+    Stored(String),
+    /// Where to read the secret from when connecting, like a systemd
+    /// credential or a Docker secret
+    Source(SecretSource),
+    /// A secret that was resolved or prompted for. It is zeroized when dropped.
+    Resolved(Zeroizing<String>),
+}
+
+impl<B: Into<String>> Credential<B> {
+    /// The secret. Fails for stored secrets that have not been resolved.
+    pub fn into_value(self) -> EnclaveResult<Zeroizing<String>> {
+        match self {
+            Credential::Value(v) => Ok(Zeroizing::new(v.into())),
+            Credential::Source(s) => s.read(),
+            Credential::Resolved(v) => Ok(v),
+            Credential::Stored(id) => Err(EnclaveErrorKind::ConnectionFailure {
+                msg: format!(
+                    "The credential {} is stored in another enclave. Connect with `connect_with`",
+                    id
+                ),
+            }
+            .into()),
+        }
+    }
+
+    /// Read a stored secret from `source`
+    pub fn resolve<S: EnclaveLike>(self, source: &S) -> EnclaveResult<Credential<String>> {
+        match self {
+            Credential::Value(v) => Ok(Credential::Resolved(Zeroizing::new(v.into()))),
+            Credential::Source(s) => Ok(Credential::Source(s)),
+            Credential::Resolved(v) => Ok(Credential::Resolved(v)),
+            Credential::Stored(id) => {
+                let secret = source.fetch_secret(&id)?;
+                let secret = std::str::from_utf8(&secret).map_err(|_| {
+                    errors::EnclaveError::from_msg(
+                        EnclaveErrorKind::GeneralError {
+                            msg: format!("The credential {} is not UTF-8", id),
+                        },
+                        "Stored credentials must be UTF-8",
+                    )
+                })?;
+                Ok(Credential::Resolved(Zeroizing::new(secret.to_string())))
+            }
+        }
+    }
+
+    /// Use this credential or ask `prompt` for it if it was left out
+    fn or_prompt<P: CredentialPrompt>(
+        credential: Option<Self>,
+        prompt: &P,
+        request: PromptRequest<'_>,
+    ) -> EnclaveResult<Credential<String>> {
+        match credential {
+            Some(Credential::Value(v)) => Ok(Credential::Resolved(Zeroizing::new(v.into()))),
+            Some(Credential::Stored(id)) => Ok(Credential::Stored(id)),
+            Some(Credential::Source(s)) => Ok(Credential::Source(s)),
+            Some(Credential::Resolved(v)) => Ok(Credential::Resolved(v)),
+            None => Ok(Credential::Resolved(prompt.prompt(&request)?)),
+        }
+    }
+}
+
+impl<B: Into<String>> fmt::Debug for Credential<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Value(_) => write!(f, "Value(*********)"),
+            Credential::Resolved(_) => write!(f, "Resolved(*********)"),
+            Credential::Stored(id) => write!(f, "Stored({:?})", id),
+            Credential::Source(s) => write!(f, "Source({:?})", s),
+        }
+    }
+}
+
+impl<B: Into<String>> fmt::Display for Credential<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Value(_) | Credential::Resolved(_) => write!(f, "*********"),
+            Credential::Stored(id) => write!(f, "stored as {}", id),
+            Credential::Source(s) => write!(f, "read from {}", s),
+        }
     }
 }
 
@@ -76,10 +218,13 @@ where
 pub struct OsKeyRingConnector<A: AsRef<Path>, B: Into<String>> {
     /// Path to the keyring. If `None`, it will use the default OS keyring
     path: Option<A>,
-    /// The username to use for logging in. If `None`, the user will be prompted
+    /// The username to use for logging in. If `None` and the keyring needs
+    /// one, the user will be prompted when connecting with
+    /// `EnclaveLike::connect_with_prompt`
     username: Option<B>,
     /// The password to use for logging in. If `None`, the user will be prompted
-    password: Option<B>,
+    /// when connecting with `EnclaveLike::connect_with_prompt`
+    password: Option<Credential<B>>,
 }
 
 impl<A, B> fmt::Display for OsKeyRingConnector<A, B>
@@ -93,11 +238,274 @@ where
             "OsKeyRingConfig (path: {:?}, username: {:?}, password: {:?})",
             self.path.as_ref().map(|p| p.as_ref().as_os_str()),
             self.username.as_ref().map(|_| "*********"),
-            self.password.as_ref().map(|_| "*********")
+            self.password.as_ref().map(|p| p.to_string())
+        )
+    }
+}
+
+impl<A: AsRef<Path>, B: Into<String>> OsKeyRingConnector<A, B> {
+    /// Create a new configuration for the keyring at `path`
+    pub fn new(path: Option<A>) -> Self {
+        Self {
+            path,
+            username: None,
+            password: None,
+        }
+    }
+
+    /// Set the username to use for logging in
+    pub fn username(mut self, username: B) -> Self {
+        self.username = Some(username);
+        self
+    }
+
+    /// Set the password to use for logging in
+    pub fn password(mut self, password: B) -> Self {
+        self.password = Some(Credential::Value(password));
+        self
+    }
+
+    /// Use the password saved as `id` in the enclave this one is connected with
+    pub fn stored_password<C: Into<String>>(mut self, id: C) -> Self {
+        self.password = Some(Credential::Stored(id.into()));
+        self
+    }
+
+    /// Read the password from `source` when connecting
+    pub fn password_from(mut self, source: SecretSource) -> Self {
+        self.password = Some(Credential::Source(source));
+        self
+    }
+
+    /// Replace a stored password with the one read from `source`
+    pub fn resolve<S: EnclaveLike>(
+        self,
+        source: &S,
+    ) -> EnclaveResult<OsKeyRingConnector<A, String>> {
+        Ok(OsKeyRingConnector {
+            path: self.path,
+            username: self.username.map(Into::into),
+            password: self.password.map(|p| p.resolve(source)).transpose()?,
+        })
+    }
+
+    /// Ask `prompt` for the password if it was left out and for the
+    /// username if it was left out and the keyring needs one
+    pub fn prompt_missing<P: CredentialPrompt>(
+        self,
+        prompt: &P,
+    ) -> EnclaveResult<OsKeyRingConnector<A, String>> {
+        let request = |kind| PromptRequest {
+            enclave: "OsKeyRing",
+            kind,
+        };
+        // Usernames aren't secret so they are kept in a plain `String` like
+        // the ones given to `username`. Passwords stay `Zeroizing`.
+        let username = match self.username {
+            Some(u) => Some(u.into()),
+            None if os::KEYRING_NEEDS_USERNAME => Some(std::mem::take(
+                &mut *prompt.prompt(&request(CredentialKind::Username))?,
+            )),
+            None => None,
+        };
+        Ok(OsKeyRingConnector {
+            path: self.path,
+            username,
+            password: Some(Credential::or_prompt(
+                self.password,
+                prompt,
+                request(CredentialKind::Password),
+            )?),
+        })
+    }
+}
+
+/// Configuration options for connecting to a YubiHSM 2
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct YubiHsmConnector<B: Into<String>> {
+    /// URL of the yubihsm-connector. If `None`, the HSM is used over USB
+    url: Option<String>,
+    /// The id of the authentication key to open a session with
+    auth_key_id: u16,
+    /// The password of the authentication key. If `None`, the user will be prompted
+    /// when connecting with `EnclaveLike::connect_with_prompt`
+    password: Option<Credential<B>>,
+}
+
+impl<B: Into<String>> fmt::Display for YubiHsmConnector<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "YubiHsmConfig (url: {:?}, auth_key_id: {}, password: {:?})",
+            self.url,
+            self.auth_key_id,
+            self.password.as_ref().map(|p| p.to_string())
+        )
+    }
+}
+
+impl<B: Into<String>> YubiHsmConnector<B> {
+    /// Create a new configuration that opens a session with the authentication key `auth_key_id`
+    pub fn new(auth_key_id: u16) -> Self {
+        Self {
+            url: None,
+            auth_key_id,
+            password: None,
+        }
+    }
+
+    /// Connect through the yubihsm-connector at `url` instead of USB
+    pub fn url(mut self, url: B) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Set the password of the authentication key
+    pub fn password(mut self, password: B) -> Self {
+        self.password = Some(Credential::Value(password));
+        self
+    }
+
+    /// Use the password saved as `id` in the enclave this one is connected with
+    pub fn stored_password<C: Into<String>>(mut self, id: C) -> Self {
+        self.password = Some(Credential::Stored(id.into()));
+        self
+    }
+
+    /// Read the password from `source` when connecting
+    pub fn password_from(mut self, source: SecretSource) -> Self {
+        self.password = Some(Credential::Source(source));
+        self
+    }
+
+    /// Replace a stored password with the one read from `source`
+    pub fn resolve<S: EnclaveLike>(self, source: &S) -> EnclaveResult<YubiHsmConnector<String>> {
+        Ok(YubiHsmConnector {
+            url: self.url,
+            auth_key_id: self.auth_key_id,
+            password: self.password.map(|p| p.resolve(source)).transpose()?,
+        })
+    }
+
+    /// Ask `prompt` for the password if it was left out
+    pub fn prompt_missing<P: CredentialPrompt>(
+        self,
+        prompt: &P,
+    ) -> EnclaveResult<YubiHsmConnector<String>> {
+        let request = PromptRequest {
+            enclave: "YubiHsm",
+            kind: CredentialKind::Password,
+        };
+        Ok(YubiHsmConnector {
+            url: self.url,
+            auth_key_id: self.auth_key_id,
+            password: Some(Credential::or_prompt(self.password, prompt, request)?),
+        })
+    }
+}
+
+/// Configuration options for connecting to a token through a PKCS#11 module
+/// like SoftHSM, a smart card or a network HSM
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pkcs11Connector<A: AsRef<Path>, B: Into<String>> {
+    /// Path to the PKCS#11 module library
+    module: A,
+    /// Label of the token. If `None`, the token is chosen by `slot`
+    token: Option<String>,
+    /// Id of the slot holding the token. If neither this nor `token`
+    /// is set, the first slot with a token is used
+    slot: Option<u64>,
+    /// The user PIN. If `None`, the user will be prompted
+    /// when connecting with `EnclaveLike::connect_with_prompt`
+    pin: Option<Credential<B>>,
+}
+
+impl<A, B> fmt::Display for Pkcs11Connector<A, B>
+where
+    A: AsRef<Path>,
+    B: Into<String>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Pkcs11Config (module: {:?}, token: {:?}, slot: {:?}, pin: {:?})",
+            self.module.as_ref().as_os_str(),
+            self.token,
+            self.slot,
+            self.pin.as_ref().map(|p| p.to_string())
         )
     }
 }
 
+impl<A: AsRef<Path>, B: Into<String>> Pkcs11Connector<A, B> {
+    /// Create a new configuration that loads the PKCS#11 `module`
+    pub fn new(module: A) -> Self {
+        Self {
+            module,
+            token: None,
+            slot: None,
+            pin: None,
+        }
+    }
+
+    /// Use the token with `label`
+    pub fn token(mut self, label: B) -> Self {
+        self.token = Some(label.into());
+        self
+    }
+
+    /// Use the token in the slot `id`
+    pub fn slot(mut self, id: u64) -> Self {
+        self.slot = Some(id);
+        self
+    }
+
+    /// Set the user PIN
+    pub fn pin(mut self, pin: B) -> Self {
+        self.pin = Some(Credential::Value(pin));
+        self
+    }
+
+    /// Use the PIN saved as `id` in the enclave this one is connected with
+    pub fn stored_pin<C: Into<String>>(mut self, id: C) -> Self {
+        self.pin = Some(Credential::Stored(id.into()));
+        self
+    }
+
+    /// Read the PIN from `source` when connecting
+    pub fn pin_from(mut self, source: SecretSource) -> Self {
+        self.pin = Some(Credential::Source(source));
+        self
+    }
+
+    /// Replace a stored PIN with the one read from `source`
+    pub fn resolve<S: EnclaveLike>(self, source: &S) -> EnclaveResult<Pkcs11Connector<A, String>> {
+        Ok(Pkcs11Connector {
+            module: self.module,
+            token: self.token,
+            slot: self.slot,
+            pin: self.pin.map(|p| p.resolve(source)).transpose()?,
+        })
+    }
+
+    /// Ask `prompt` for the PIN if it was left out
+    pub fn prompt_missing<P: CredentialPrompt>(
+        self,
+        prompt: &P,
+    ) -> EnclaveResult<Pkcs11Connector<A, String>> {
+        let request = PromptRequest {
+            enclave: "Pkcs11",
+            kind: CredentialKind::Password,
+        };
+        Ok(Pkcs11Connector {
+            module: self.module,
+            token: self.token,
+            slot: self.slot,
+            pin: Some(Credential::or_prompt(self.pin, prompt, request)?),
+        })
+    }
+}
+
 /// All enclaves structs should use this trait so the callers
 /// can simply use them without diving into the details
 /// for each unique configuration. This trait is meant
@@ -107,10 +515,170 @@ pub trait EnclaveLike: Sized {
     /// Establish a connection to the enclave
     fn connect<A: AsRef<Path>, B: Into<String>>(config: EnclaveConnector<A, B>)
         -> EnclaveResult<Self>;
+    /// Connect to the enclave described by `config` after reading every
+    /// `Credential::Stored` in it from `source`, like an HSM PIN kept in the
+    /// OS keyring. Longer chains connect each enclave with the one before it.
+    fn connect_with<S: EnclaveLike, A: AsRef<Path>, B: Into<String>>(
+        source: &S,
+        config: EnclaveConnector<A, B>,
+    ) -> EnclaveResult<Self> {
+        Self::connect(config.resolve(source)?)
+    }
+    /// Connect to the enclave described by `config` after asking `prompt`
+    /// for every credential it leaves out
+    fn connect_with_prompt<P: CredentialPrompt, A: AsRef<Path>, B: Into<String>>(
+        prompt: &P,
+        config: EnclaveConnector<A, B>,
+    ) -> EnclaveResult<Self> {
+        Self::connect(config.prompt_missing(prompt)?)
+    }
     /// Close the connection to the enclave
     fn close(self);
     /// The capabilities of the enclave
     fn capabilities(&self) -> EnclaveCapabilities;
+    /// Create a new key named `id` of the checked type that can only be used as
+    /// allowed by the checked capabilities. Callers create keys with `create_key`,
+    /// which checks the capabilities first and is the only way to get a `CheckedKey`.
+    fn generate_key(&self, id: &str, checked: validation::CheckedKey) -> EnclaveResult<()> {
+        let _ = (id, checked);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Save the existing unwrapped `key` as `id`. This is only meant for moving
+    /// keys from other wallets into the enclave. Prefer `generate_key` otherwise.
+    /// Callers save keys with `import_key`, which checks the capabilities first.
+    fn put_key(&self, id: &str, checked: validation::CheckedKey, key: &[u8]) -> EnclaveResult<()> {
+        let _ = (id, checked, key);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Generate the key `id` with `generate_key` after rejecting capabilities
+    /// that don't fit the key type or the enclave with `EnclaveCapabilities::check_key`
+    fn create_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+    ) -> EnclaveResult<()> {
+        let checked = validation::CheckedKey::new(
+            self.capabilities(),
+            key_type,
+            capabilities,
+            validation::KeyOrigin::Generated,
+        )?;
+        self.generate_key(id, checked)
+    }
+    /// Save the unwrapped `key` as `id` with `put_key` after checking the
+    /// capabilities like `create_key`
+    fn import_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+        key: &[u8],
+    ) -> EnclaveResult<()> {
+        let checked = validation::CheckedKey::new(
+            self.capabilities(),
+            key_type,
+            capabilities,
+            validation::KeyOrigin::Imported,
+        )?;
+        self.put_key(id, checked, key)
+    }
+    /// The type the key `id` was created with. The handles in `keys` check it
+    /// when opening existing keys.
+    fn key_type(&self, id: &str) -> EnclaveResult<EnclaveKeyType> {
+        let _ = id;
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Export the key `id` encrypted with XChaCha20-Poly1305 under `wrapping_key`.
+    /// The result is the 24 byte nonce followed by the ciphertext of the key in
+    /// the format `put_key` takes, with `id` as associated data. Only keys created
+    /// with `EXPORTABLE_WHEN_WRAPPED` can be exported.
+    fn export_wrapped_key(&self, id: &str, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let _ = (id, wrapping_key);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Encrypt `plaintext` with the symmetric key `id` and authenticate `aad`.
+    /// A fresh nonce is used for every call and is included in the result.
+    fn encrypt(&self, id: &str, plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let _ = (id, plaintext, aad);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Encrypt `plaintext` with the symmetric key `id` so the same inputs always
+    /// produce the same ciphertext. This reveals when two plaintexts are equal so
+    /// only use it for values that must be looked up like names.
+    fn encrypt_deterministic(
+        &self,
+        id: &str,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> EnclaveResult<Vec<u8>> {
+        let _ = (id, plaintext, aad);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Decrypt `ciphertext` produced by `encrypt` or `encrypt_deterministic`
+    /// with the symmetric key `id`
+    fn decrypt(&self, id: &str, ciphertext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let _ = (id, ciphertext, aad);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Compute the Hash-based Message-Authentication-Code over `data` with the key `id`
+    fn sign_hmac(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let _ = (id, data);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// The public key of the asymmetric key `id`
+    fn public_key(&self, id: &str) -> EnclaveResult<Vec<u8>> {
+        let _ = id;
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Sign `data` with the private key `id` using the algorithm it was created for
+    fn sign(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let _ = (id, data);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Compute the Diffie-Hellman shared secret of the key-exchange key `id`
+    /// and the peer's `public_key`
+    fn derive_shared_secret(
+        &self,
+        id: &str,
+        public_key: &[u8],
+    ) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        let _ = (id, public_key);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Encrypt `plaintext` with the public part of the RSA-OAEP key `id`
+    fn encrypt_oaep(&self, id: &str, plaintext: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let _ = (id, plaintext);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Decrypt `ciphertext` with the private part of the RSA-OAEP key `id`
+    fn decrypt_oaep(&self, id: &str, ciphertext: &[u8]) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        let _ = (id, ciphertext);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Save a secret like a password or PIN under `id` so other
+    /// enclaves can be connected with it
+    fn put_secret(&self, id: &str, secret: &[u8]) -> EnclaveResult<()> {
+        let _ = (id, secret);
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+    /// Retrieve the secret saved under `id` with `put_secret`.
+    /// Keys can never be retrieved this way.
+    fn fetch_secret(&self, id: &str) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        let _ = id;
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+}
+
+/// The operations a key is allowed to perform
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCapabilities {
+    /// Capabilities for AES, XChaCha20Poly1305 and HMAC keys
+    Symmetric(SymmetricCapability),
+    /// Capabilities for Ed25519, X25519, ECDH and ECDSA keys
+    Ecc(EccCapability),
+    /// Capabilities for RSA keys
+    Rsa(RsaCapability),
 }
 
 /// Valid key types that can be created in an enclave.
@@ -324,6 +892,16 @@ bitflags! {
         const DECRYPT_AES                      = 0x0000_2000_0000_0000;
         /// Can decrypt data using XChaCha20Poly1305 symmetric key
         const DECRYPT_XCHACHA20_POLY1305       = 0x0000_4000_0000_0000;
+        /// Can save secrets like passwords and PINs for connecting to other enclaves
+        const PUT_SECRET                       = 0x0000_8000_0000_0000;
+        /// Can retrieve saved secrets
+        const FETCH_SECRET                     = 0x0001_0000_0000_0000;
+        /// Can use AES keys in CCM mode
+        const AES_CCM                          = 0x0002_0000_0000_0000;
+        /// Can use AES keys in GCM mode
+        const AES_GCM                          = 0x0004_0000_0000_0000;
+        /// Can use AES keys in GCM-SIV mode
+        const AES_GCM_SIV                      = 0x0008_0000_0000_0000;
     }
 }
 
@@ -381,14 +959,224 @@ bitflags! {
     }
 }
 
+/// Enclave configuration read from config files and URIs
+pub mod config;
 /// Provides access to the OS keyring and enclaves
 pub mod os;
 
+/// Typed handles for keys held by an enclave
+pub mod keys;
 /// A null enclave. Basically is just a pass through.
 /// 
 /// Do NOT use this except for debugging purposes or
 /// your backend already provides crypto services
 pub mod null;
+/// Prompts for credentials missing from enclave connectors
+pub mod prompt;
+/// Enclaves shared behind a trait object
+pub mod shared;
+/// An enclave that keeps keys in memory for platforms without one
+#[cfg(feature = "software-enclave")]
+pub mod software;
+/// Reading credentials from systemd, Docker secrets and file descriptors
+pub mod source;
+/// Checking key capabilities against key types and enclaves
+pub mod validation;
 
 /// Errors that can occur for Enclave operations
-pub mod errors;
\ No newline at end of file
+pub mod errors;
+#[cfg(all(test, feature = "software-enclave"))]
+mod tests {
+    use super::*;
+    use software::SoftwareEnclave;
+    use std::path::PathBuf;
+
+    /// A secret file removed when the test ends
+    struct SecretFile(PathBuf);
+
+    impl SecretFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "arieskms-credential-{}-{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for SecretFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// An enclave that remembers the password or PIN it was connected with
+    struct Connected(Option<Zeroizing<String>>);
+
+    impl EnclaveLike for Connected {
+        fn connect<A: AsRef<Path>, B: Into<String>>(
+            config: EnclaveConnector<A, B>,
+        ) -> EnclaveResult<Self> {
+            let credential = match config {
+                EnclaveConnector::OsKeyRing(c) => c.password,
+                EnclaveConnector::YubiHsm(c) => c.password,
+                EnclaveConnector::Pkcs11(c) => c.pin,
+                EnclaveConnector::Software => None,
+            };
+            Ok(Self(credential.map(Credential::into_value).transpose()?))
+        }
+
+        fn close(self) {}
+
+        fn capabilities(&self) -> EnclaveCapabilities {
+            EnclaveCapabilities::empty()
+        }
+    }
+
+    /// A software enclave holding the secrets other enclaves are connected with
+    fn source() -> SoftwareEnclave {
+        let source = SoftwareEnclave::new();
+        source.put_secret("hsm-pin", b"1234").unwrap();
+        source.put_secret("binary", &[0xff, 0xfe]).unwrap();
+        source
+    }
+
+    fn resolved(secret: &str) -> Credential<String> {
+        Credential::Resolved(Zeroizing::new(secret.to_string()))
+    }
+
+    fn kind<T>(result: EnclaveResult<T>) -> EnclaveErrorKind {
+        match result {
+            Err(e) => e.kind(),
+            Ok(_) => panic!("Expected an error"),
+        }
+    }
+
+    fn pkcs11() -> Pkcs11Connector<&'static str, &'static str> {
+        Pkcs11Connector::new("/usr/lib/softhsm/libsofthsm2.so")
+    }
+
+    #[test]
+    fn credential_values() {
+        let file = SecretFile::new("value", "from a file\n");
+        assert_eq!(*Credential::Value("value").into_value().unwrap(), "value");
+        assert_eq!(*resolved("resolved").into_value().unwrap(), "resolved");
+        assert_eq!(
+            *Credential::<String>::Source(SecretSource::File(file.0.clone()))
+                .into_value()
+                .unwrap(),
+            "from a file"
+        );
+        match kind(Credential::<String>::Stored("hsm-pin".to_string()).into_value()) {
+            EnclaveErrorKind::ConnectionFailure { .. } => {}
+            kind => panic!("Expected ConnectionFailure but found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn resolve_stored_credentials() {
+        let source = source();
+        let file = SecretSource::File(PathBuf::from("/run/secrets/pin"));
+        assert_eq!(
+            Credential::Value("value").resolve(&source).unwrap(),
+            resolved("value")
+        );
+        assert_eq!(
+            resolved("resolved").resolve(&source).unwrap(),
+            resolved("resolved")
+        );
+        // Sources are only read when connecting
+        assert_eq!(
+            Credential::<String>::Source(file.clone())
+                .resolve(&source)
+                .unwrap(),
+            Credential::Source(file)
+        );
+        assert_eq!(
+            Credential::<String>::Stored("hsm-pin".to_string())
+                .resolve(&source)
+                .unwrap(),
+            resolved("1234")
+        );
+        assert_eq!(
+            kind(Credential::<String>::Stored("missing".to_string()).resolve(&source)),
+            EnclaveErrorKind::ItemNotFound
+        );
+        match kind(Credential::<String>::Stored("binary".to_string()).resolve(&source)) {
+            EnclaveErrorKind::GeneralError { .. } => {}
+            kind => panic!("Expected GeneralError but found {:?}", kind),
+        }
+
+        match EnclaveConnector::Pkcs11(pkcs11().stored_pin("hsm-pin"))
+            .resolve(&source)
+            .unwrap()
+        {
+            EnclaveConnector::Pkcs11(c) => assert_eq!(c.pin, Some(resolved("1234"))),
+            c => panic!("Resolved into {}", c),
+        }
+    }
+
+    #[test]
+    fn connect_with_stored_credentials() {
+        let source = source();
+        let connected = Connected::connect_with(
+            &source,
+            EnclaveConnector::Pkcs11(pkcs11().stored_pin("hsm-pin")),
+        )
+        .unwrap();
+        assert_eq!(connected.0.as_deref().map(String::as_str), Some("1234"));
+        let connected = Connected::connect_with(
+            &source,
+            EnclaveConnector::<&str, &str>::YubiHsm(YubiHsmConnector::new(1).password("password")),
+        )
+        .unwrap();
+        assert_eq!(connected.0.as_deref().map(String::as_str), Some("password"));
+        let connected = Connected::connect_with(
+            &source,
+            EnclaveConnector::<&str, &str>::OsKeyRing(OsKeyRingConnector::new(None)),
+        )
+        .unwrap();
+        assert!(connected.0.is_none());
+
+        assert_eq!(
+            kind(Connected::connect_with(
+                &source,
+                EnclaveConnector::Pkcs11(pkcs11().stored_pin("missing"))
+            )),
+            EnclaveErrorKind::ItemNotFound
+        );
+        // Without a source the stored credential can't be read
+        match kind(Connected::connect(EnclaveConnector::Pkcs11(
+            pkcs11().stored_pin("hsm-pin"),
+        ))) {
+            EnclaveErrorKind::ConnectionFailure { .. } => {}
+            kind => panic!("Expected ConnectionFailure but found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn connect_with_secret_sources() {
+        let source = source();
+        let file = SecretFile::new("source", "from a file\r\n");
+        let connected = Connected::connect_with(
+            &source,
+            EnclaveConnector::Pkcs11(pkcs11().pin_from(SecretSource::File(file.0.clone()))),
+        )
+        .unwrap();
+        assert_eq!(
+            connected.0.as_deref().map(String::as_str),
+            Some("from a file")
+        );
+
+        let missing = SecretSource::File(file.0.with_extension("missing"));
+        match kind(Connected::connect_with(
+            &source,
+            EnclaveConnector::Pkcs11(pkcs11().pin_from(missing)),
+        )) {
+            EnclaveErrorKind::AccessDenied { .. } => {}
+            kind => panic!("Expected AccessDenied but found {:?}", kind),
+        }
+    }
+}
@@ -17,20 +17,64 @@
 //! A null enclave
 //! Do NOT use this except for debugging purposes or
 //! your backend already provides crypto services
+//!
+//! It provides NO protection at all. Encrypting returns the plaintext and
+//! HMACs are the data itself, so a `ProtectedStore` over a `NullEnclave`
+//! persists record names, values and encrypted tags in the clear.
 
 use super::*;
 
 /// A null enclave struct. Doesn't do anything cryptographically except pass data through.
+/// Never use it to protect real data.
 pub struct NullEnclave;
 
 impl EnclaveLike for NullEnclave {
     fn connect<A: AsRef<Path>, B: Into<String>>(_: EnclaveConnector<A, B>) -> EnclaveResult<Self> {
-        Ok(Self{})
+        Ok(Self {})
     }
 
     fn close(self) {}
 
+    // Keys are never created, so the enclave claims the symmetric operations
+    // it passes data through for
     fn capabilities(&self) -> EnclaveCapabilities {
-        EnclaveCapabilities::empty()
+        EnclaveCapabilities::GENERATE_AES_KEY
+            | EnclaveCapabilities::GENERATE_HMAC_KEY
+            | EnclaveCapabilities::GENERATE_XCHACHA20_POLY1305_KEY
+            | EnclaveCapabilities::PUT_AES_KEY
+            | EnclaveCapabilities::PUT_HMAC_KEY
+            | EnclaveCapabilities::PUT_XCHACHA20_POLY1305_KEY
+            | EnclaveCapabilities::ENCRYPT_AES
+            | EnclaveCapabilities::ENCRYPT_XCHACHA20_POLY1305
+            | EnclaveCapabilities::DECRYPT_AES
+            | EnclaveCapabilities::DECRYPT_XCHACHA20_POLY1305
+            | EnclaveCapabilities::SIGN_HMAC
+            | EnclaveCapabilities::AES_CCM
+            | EnclaveCapabilities::AES_GCM
+            | EnclaveCapabilities::AES_GCM_SIV
     }
-}
\ No newline at end of file
+
+    fn generate_key(&self, _: &str, _: validation::CheckedKey) -> EnclaveResult<()> {
+        Ok(())
+    }
+
+    fn put_key(&self, _: &str, _: validation::CheckedKey, _: &[u8]) -> EnclaveResult<()> {
+        Ok(())
+    }
+
+    fn encrypt(&self, _: &str, plaintext: &[u8], _: &[u8]) -> EnclaveResult<Vec<u8>> {
+        Ok(plaintext.to_vec())
+    }
+
+    fn encrypt_deterministic(&self, _: &str, plaintext: &[u8], _: &[u8]) -> EnclaveResult<Vec<u8>> {
+        Ok(plaintext.to_vec())
+    }
+
+    fn decrypt(&self, _: &str, ciphertext: &[u8], _: &[u8]) -> EnclaveResult<Vec<u8>> {
+        Ok(ciphertext.to_vec())
+    }
+
+    fn sign_hmac(&self, _: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+}
@@ -41,10 +41,14 @@
 //!
 //! [codesign]: https://developer.apple.com/library/archive/documentation/Security/Conceptual/CodeSigningGuide/Procedures/Procedures.html#//apple_ref/doc/uid/TP40005929-CH4-SW4
 
-use crate::security::{EnclaveConnector, EnclaveLike, EnclaveResult, EnclaveCapabilities, errors::EnclaveErrorKind};
+use crate::security::{Credential, EnclaveConnector, EnclaveLike, EnclaveResult, EnclaveCapabilities, errors::EnclaveErrorKind};
 
 use security_framework::os::macos::keychain::*;
 use std::path::Path;
+use zeroize::Zeroizing;
+
+/// The service generic passwords saved with `put_secret` are filed under
+const SECRET_SERVICE: &str = "arieskms";
 
 /// MacOSX and iOS implementation for keyrings that function like an enclave
 pub struct MacOsKeyRing(SecKeychain);
@@ -85,7 +89,7 @@ impl EnclaveLike for MacOsKeyRing {
         config: EnclaveConnector<A, B>,
     ) -> EnclaveResult<Self> {
         if let EnclaveConnector::OsKeyRing(c) = config {
-            let pass = c.password.map(|p| p.into());
+            let pass = c.password.map(Credential::into_value).transpose()?;
             let mut keychain = match c.path {
                 Some(p) => {
                     let path = p.as_ref();
@@ -115,4 +119,14 @@ impl EnclaveLike for MacOsKeyRing {
     fn capabilities(&self) -> EnclaveCapabilities {
         EnclaveCapabilities::all()
     }
+
+    fn put_secret(&self, id: &str, secret: &[u8]) -> EnclaveResult<()> {
+        self.0.set_generic_password(SECRET_SERVICE, id, secret)?;
+        Ok(())
+    }
+
+    fn fetch_secret(&self, id: &str) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        let (password, _) = self.0.find_generic_password(SECRET_SERVICE, id)?;
+        Ok(Zeroizing::new(password.as_ref().to_vec()))
+    }
 }
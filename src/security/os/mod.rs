@@ -1,3 +1,7 @@
 /// Provides access to the MacOS KeyRing and Enclave
 #[cfg(any(target_os = "macos", target_os = "ios"))]
-pub mod macos;
\ No newline at end of file
+pub mod macos;
+
+/// Whether the OS keyring logs in with a username as well as a password.
+/// The macOS keychain only takes a password.
+pub(crate) const KEYRING_NEEDS_USERNAME: bool = false;
@@ -0,0 +1,390 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Prompts supply the credentials an enclave connector leaves out.
+//!
+//! A connector without a username or password is completed with
+//! `EnclaveConnector::prompt_missing` or `EnclaveLike::connect_with_prompt`
+//! before the enclave is connected, so every enclave asks for credentials
+//! the same way instead of relying on its own dialogs. A CLI can read from
+//! the terminal, a GUI can show its own dialog through a callback and a
+//! headless service can read from the environment or a file descriptor.
+
+use super::{errors::EnclaveErrorKind, source::SecretSource, EnclaveResult};
+
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// The credential being asked for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CredentialKind {
+    /// The username to log in with. Not secret.
+    Username,
+    /// The password or PIN to log in with
+    Password,
+}
+
+impl fmt::Display for CredentialKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialKind::Username => write!(f, "username"),
+            CredentialKind::Password => write!(f, "password"),
+        }
+    }
+}
+
+/// What a prompt is asked for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PromptRequest<'a> {
+    /// The enclave being connected like `OsKeyRing` or `YubiHsm`
+    pub enclave: &'a str,
+    /// The credential the connector is missing
+    pub kind: CredentialKind,
+}
+
+impl fmt::Display for PromptRequest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.enclave, self.kind)
+    }
+}
+
+/// All prompts should use this trait so any of them can
+/// complete any enclave connector
+pub trait CredentialPrompt {
+    /// Ask for the credential described by `request`
+    fn prompt(&self, request: &PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>>;
+}
+
+/// Asks on the controlling terminal. Passwords are not echoed.
+#[cfg(feature = "terminal-prompt")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TerminalPrompt;
+
+#[cfg(feature = "terminal-prompt")]
+impl CredentialPrompt for TerminalPrompt {
+    fn prompt(&self, request: &PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>> {
+        let label = format!("{}: ", request);
+        match request.kind {
+            CredentialKind::Password => rpassword::read_password_from_tty(Some(&label))
+                .map(Zeroizing::new)
+                .map_err(prompt_error),
+            CredentialKind::Username => {
+                eprint!("{}", label);
+                let mut line = Zeroizing::new(String::new());
+                std::io::stdin()
+                    .read_line(&mut line)
+                    .map_err(prompt_error)?;
+                Ok(trim_line(line))
+            }
+        }
+    }
+}
+
+/// Asks a function, for example one that shows a GUI dialog
+pub struct CallbackPrompt<F>
+where
+    F: Fn(&PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>>,
+{
+    callback: F,
+}
+
+impl<F> CallbackPrompt<F>
+where
+    F: Fn(&PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>>,
+{
+    /// Prompt by calling `callback`
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F> CredentialPrompt for CallbackPrompt<F>
+where
+    F: Fn(&PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>>,
+{
+    fn prompt(&self, request: &PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>> {
+        (self.callback)(request)
+    }
+}
+
+/// Reads environment variables named `{prefix}{ENCLAVE}_{KIND}`,
+/// like `ARIESKMS_YUBIHSM_PASSWORD`. Each variable is removed once
+/// read so child processes don't inherit it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvironmentPrompt {
+    prefix: String,
+}
+
+impl EnvironmentPrompt {
+    /// Read variables starting with `prefix`
+    pub fn new<B: Into<String>>(prefix: B) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    /// The variable read for `request`
+    pub fn variable(&self, request: &PromptRequest<'_>) -> String {
+        format!("{}{}_{}", self.prefix, request.enclave, request.kind).to_uppercase()
+    }
+}
+
+impl Default for EnvironmentPrompt {
+    fn default() -> Self {
+        Self::new("ARIESKMS_")
+    }
+}
+
+impl CredentialPrompt for EnvironmentPrompt {
+    fn prompt(&self, request: &PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>> {
+        let name = self.variable(request);
+        let value = std::env::var(&name).map(Zeroizing::new).map_err(|_| {
+            EnclaveErrorKind::AccessDenied {
+                msg: format!("The environment variable {} is not set", name),
+            }
+        })?;
+        std::env::remove_var(&name);
+        Ok(value)
+    }
+}
+
+/// Reads one line per credential from an inherited file descriptor,
+/// like `--password-fd` in gpg. Only available on Unix.
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FdPrompt {
+    fd: i32,
+}
+
+#[cfg(unix)]
+impl FdPrompt {
+    /// Read from the file descriptor `fd`
+    pub fn new(fd: i32) -> Self {
+        Self { fd }
+    }
+}
+
+#[cfg(unix)]
+impl CredentialPrompt for FdPrompt {
+    fn prompt(&self, _: &PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>> {
+        SecretSource::Fd(self.fd).read()
+    }
+}
+
+/// Remove the line ending without copying the credential
+#[cfg(feature = "terminal-prompt")]
+fn trim_line(mut line: Zeroizing<String>) -> Zeroizing<String> {
+    while line.ends_with('\n') || line.ends_with('\r') {
+        line.pop();
+    }
+    line
+}
+
+#[cfg(feature = "terminal-prompt")]
+fn prompt_error(e: std::io::Error) -> super::errors::EnclaveError {
+    EnclaveErrorKind::AccessDenied {
+        msg: format!("Unable to prompt for credentials: {}", e),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::{
+        EnclaveConnector, OsKeyRingConnector, Pkcs11Connector, YubiHsmConnector,
+    };
+    use std::cell::RefCell;
+
+    /// Answers prompts from a script and records what it was asked
+    struct ScriptedPrompt {
+        answers: RefCell<Vec<Option<&'static str>>>,
+        asked: RefCell<Vec<String>>,
+    }
+
+    impl ScriptedPrompt {
+        /// `None` answers cancel the prompt
+        fn new(answers: &[Option<&'static str>]) -> Self {
+            Self {
+                answers: RefCell::new(answers.iter().rev().cloned().collect()),
+                asked: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn asked(&self) -> Vec<String> {
+            self.asked.borrow().clone()
+        }
+    }
+
+    impl CredentialPrompt for ScriptedPrompt {
+        fn prompt(&self, request: &PromptRequest<'_>) -> EnclaveResult<Zeroizing<String>> {
+            self.asked.borrow_mut().push(request.to_string());
+            match self.answers.borrow_mut().pop().expect("Too many prompts") {
+                Some(answer) => Ok(Zeroizing::new(answer.to_string())),
+                None => Err(EnclaveErrorKind::AccessDenied {
+                    msg: "The prompt was cancelled".to_string(),
+                }
+                .into()),
+            }
+        }
+    }
+
+    fn pkcs11() -> Pkcs11Connector<&'static str, &'static str> {
+        Pkcs11Connector::new("/usr/lib/softhsm/libsofthsm2.so")
+    }
+
+    #[test]
+    fn prompts_for_missing_credentials() {
+        let prompt = ScriptedPrompt::new(&[Some("1234")]);
+        let config = EnclaveConnector::Pkcs11(pkcs11())
+            .prompt_missing(&prompt)
+            .unwrap();
+        match config {
+            EnclaveConnector::Pkcs11(c) => {
+                assert_eq!(*c.pin.unwrap().into_value().unwrap(), "1234")
+            }
+            c => panic!("Prompted into {}", c),
+        }
+        assert_eq!(prompt.asked(), vec!["Pkcs11 password"]);
+
+        let prompt = ScriptedPrompt::new(&[Some("password")]);
+        EnclaveConnector::<&str, &str>::YubiHsm(YubiHsmConnector::new(1))
+            .prompt_missing(&prompt)
+            .unwrap();
+        assert_eq!(prompt.asked(), vec!["YubiHsm password"]);
+    }
+
+    #[test]
+    fn given_credentials_are_not_prompted_for() {
+        let prompt = ScriptedPrompt::new(&[]);
+        EnclaveConnector::Pkcs11(pkcs11().pin("1234"))
+            .prompt_missing(&prompt)
+            .unwrap();
+        EnclaveConnector::Pkcs11(pkcs11().stored_pin("hsm-pin"))
+            .prompt_missing(&prompt)
+            .unwrap();
+        EnclaveConnector::<&str, &str>::Software
+            .prompt_missing(&prompt)
+            .unwrap();
+        assert!(prompt.asked().is_empty());
+    }
+
+    #[test]
+    fn keyrings_are_only_asked_for_a_username_they_need() {
+        let needs_username = crate::security::os::KEYRING_NEEDS_USERNAME;
+        let prompt = if needs_username {
+            ScriptedPrompt::new(&[Some("user"), Some("password")])
+        } else {
+            ScriptedPrompt::new(&[Some("password")])
+        };
+        let config = OsKeyRingConnector::<&str, &str>::new(None)
+            .prompt_missing(&prompt)
+            .unwrap();
+        assert_eq!(*config.password.unwrap().into_value().unwrap(), "password");
+        if needs_username {
+            assert_eq!(config.username.as_deref(), Some("user"));
+            assert_eq!(
+                prompt.asked(),
+                vec!["OsKeyRing username", "OsKeyRing password"]
+            );
+        } else {
+            assert!(config.username.is_none());
+            assert_eq!(prompt.asked(), vec!["OsKeyRing password"]);
+        }
+
+        // A username that was given is kept
+        let prompt = ScriptedPrompt::new(&[Some("password")]);
+        let config = OsKeyRingConnector::<&str, &str>::new(None)
+            .username("alice")
+            .prompt_missing(&prompt)
+            .unwrap();
+        assert_eq!(config.username.as_deref(), Some("alice"));
+        assert_eq!(prompt.asked(), vec!["OsKeyRing password"]);
+    }
+
+    #[test]
+    fn cancelled_prompts_fail() {
+        let prompt = ScriptedPrompt::new(&[None]);
+        match EnclaveConnector::Pkcs11(pkcs11()).prompt_missing(&prompt) {
+            Err(e) => match e.kind() {
+                EnclaveErrorKind::AccessDenied { .. } => {}
+                kind => panic!("Expected AccessDenied but found {:?}", kind),
+            },
+            Ok(c) => panic!("Prompted into {}", c),
+        }
+    }
+
+    #[test]
+    fn empty_answers_are_kept() {
+        // An empty password is passed on so the enclave decides whether it is valid
+        let prompt = ScriptedPrompt::new(&[Some("")]);
+        match EnclaveConnector::Pkcs11(pkcs11())
+            .prompt_missing(&prompt)
+            .unwrap()
+        {
+            EnclaveConnector::Pkcs11(c) => assert!(c.pin.unwrap().into_value().unwrap().is_empty()),
+            c => panic!("Prompted into {}", c),
+        }
+    }
+
+    #[test]
+    fn callback_prompts() {
+        let prompt = CallbackPrompt::new(|request: &PromptRequest<'_>| {
+            assert_eq!(request.kind, CredentialKind::Password);
+            Ok(Zeroizing::new(format!("{} answer", request.enclave)))
+        });
+        match EnclaveConnector::Pkcs11(pkcs11())
+            .prompt_missing(&prompt)
+            .unwrap()
+        {
+            EnclaveConnector::Pkcs11(c) => {
+                assert_eq!(*c.pin.unwrap().into_value().unwrap(), "Pkcs11 answer")
+            }
+            c => panic!("Prompted into {}", c),
+        }
+    }
+
+    #[test]
+    fn environment_prompts() {
+        let prompt = EnvironmentPrompt::new("ARIESKMS_PROMPT_TEST_");
+        let request = PromptRequest {
+            enclave: "Pkcs11",
+            kind: CredentialKind::Password,
+        };
+        let variable = prompt.variable(&request);
+        assert_eq!(variable, "ARIESKMS_PROMPT_TEST_PKCS11_PASSWORD");
+        std::env::set_var(&variable, "1234");
+        assert_eq!(*prompt.prompt(&request).unwrap(), "1234");
+        // The variable is removed once read
+        assert!(std::env::var_os(&variable).is_none());
+        match prompt.prompt(&request) {
+            Err(e) => match e.kind() {
+                EnclaveErrorKind::AccessDenied { .. } => {}
+                kind => panic!("Expected AccessDenied but found {:?}", kind),
+            },
+            Ok(_) => panic!("Read a removed variable"),
+        }
+    }
+
+    #[cfg(feature = "terminal-prompt")]
+    #[test]
+    fn terminal_lines_are_trimmed() {
+        for line in &["alice\n", "alice\r\n", "alice"] {
+            assert_eq!(*trim_line(Zeroizing::new(line.to_string())), "alice");
+        }
+        assert_eq!(*trim_line(Zeroizing::new("\n".to_string())), "");
+    }
+}
@@ -0,0 +1,269 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Enclaves whose type is only known at runtime, like the ones opened
+//! through language bindings, are shared behind a trait object.
+//!
+//! Keys are created through a shared enclave with `create_key` and
+//! `import_key`, which check their capabilities against the shared enclave.
+
+use super::{
+    errors::EnclaveErrorKind, EnclaveCapabilities, EnclaveConnector, EnclaveKeyType, EnclaveLike,
+    EnclaveResult, KeyCapabilities,
+};
+
+use std::{fmt, path::Path, sync::Arc};
+use zeroize::Zeroizing;
+
+/// The enclave methods that can be called through a trait object
+trait DynEnclaveLike {
+    fn capabilities(&self) -> EnclaveCapabilities;
+    fn create_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+    ) -> EnclaveResult<()>;
+    fn import_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+        key: &[u8],
+    ) -> EnclaveResult<()>;
+    fn key_type(&self, id: &str) -> EnclaveResult<EnclaveKeyType>;
+    fn export_wrapped_key(&self, id: &str, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>>;
+    fn encrypt(&self, id: &str, plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>>;
+    fn encrypt_deterministic(
+        &self,
+        id: &str,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> EnclaveResult<Vec<u8>>;
+    fn decrypt(&self, id: &str, ciphertext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>>;
+    fn sign_hmac(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>>;
+    fn public_key(&self, id: &str) -> EnclaveResult<Vec<u8>>;
+    fn sign(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>>;
+    fn derive_shared_secret(
+        &self,
+        id: &str,
+        public_key: &[u8],
+    ) -> EnclaveResult<Zeroizing<Vec<u8>>>;
+    fn encrypt_oaep(&self, id: &str, plaintext: &[u8]) -> EnclaveResult<Vec<u8>>;
+    fn decrypt_oaep(&self, id: &str, ciphertext: &[u8]) -> EnclaveResult<Zeroizing<Vec<u8>>>;
+    fn put_secret(&self, id: &str, secret: &[u8]) -> EnclaveResult<()>;
+    fn fetch_secret(&self, id: &str) -> EnclaveResult<Zeroizing<Vec<u8>>>;
+}
+
+impl<E: EnclaveLike> DynEnclaveLike for E {
+    fn capabilities(&self) -> EnclaveCapabilities {
+        EnclaveLike::capabilities(self)
+    }
+
+    fn create_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+    ) -> EnclaveResult<()> {
+        EnclaveLike::create_key(self, id, key_type, capabilities)
+    }
+
+    fn import_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+        key: &[u8],
+    ) -> EnclaveResult<()> {
+        EnclaveLike::import_key(self, id, key_type, capabilities, key)
+    }
+
+    fn key_type(&self, id: &str) -> EnclaveResult<EnclaveKeyType> {
+        EnclaveLike::key_type(self, id)
+    }
+
+    fn export_wrapped_key(&self, id: &str, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        EnclaveLike::export_wrapped_key(self, id, wrapping_key)
+    }
+
+    fn encrypt(&self, id: &str, plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        EnclaveLike::encrypt(self, id, plaintext, aad)
+    }
+
+    fn encrypt_deterministic(
+        &self,
+        id: &str,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> EnclaveResult<Vec<u8>> {
+        EnclaveLike::encrypt_deterministic(self, id, plaintext, aad)
+    }
+
+    fn decrypt(&self, id: &str, ciphertext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        EnclaveLike::decrypt(self, id, ciphertext, aad)
+    }
+
+    fn sign_hmac(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        EnclaveLike::sign_hmac(self, id, data)
+    }
+
+    fn public_key(&self, id: &str) -> EnclaveResult<Vec<u8>> {
+        EnclaveLike::public_key(self, id)
+    }
+
+    fn sign(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        EnclaveLike::sign(self, id, data)
+    }
+
+    fn derive_shared_secret(
+        &self,
+        id: &str,
+        public_key: &[u8],
+    ) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        EnclaveLike::derive_shared_secret(self, id, public_key)
+    }
+
+    fn encrypt_oaep(&self, id: &str, plaintext: &[u8]) -> EnclaveResult<Vec<u8>> {
+        EnclaveLike::encrypt_oaep(self, id, plaintext)
+    }
+
+    fn decrypt_oaep(&self, id: &str, ciphertext: &[u8]) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        EnclaveLike::decrypt_oaep(self, id, ciphertext)
+    }
+
+    fn put_secret(&self, id: &str, secret: &[u8]) -> EnclaveResult<()> {
+        EnclaveLike::put_secret(self, id, secret)
+    }
+
+    fn fetch_secret(&self, id: &str) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        EnclaveLike::fetch_secret(self, id)
+    }
+}
+
+/// An enclave of any type that can be cloned and used from any thread
+#[derive(Clone)]
+pub struct SharedEnclave(Arc<dyn DynEnclaveLike + Send + Sync>);
+
+impl SharedEnclave {
+    /// Share `enclave`
+    pub fn new<E: EnclaveLike + Send + Sync + 'static>(enclave: E) -> Self {
+        Self(Arc::new(enclave))
+    }
+}
+
+impl fmt::Debug for SharedEnclave {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedEnclave").finish()
+    }
+}
+
+impl EnclaveLike for SharedEnclave {
+    /// Shared enclaves are created from connected enclaves with `new`
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: EnclaveConnector<A, B>,
+    ) -> EnclaveResult<Self> {
+        let _ = config;
+        Err(EnclaveErrorKind::UnsupportedOperation.into())
+    }
+
+    /// The enclave is closed when the last clone is dropped
+    fn close(self) {}
+
+    fn capabilities(&self) -> EnclaveCapabilities {
+        self.0.capabilities()
+    }
+
+    fn create_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+    ) -> EnclaveResult<()> {
+        self.0.create_key(id, key_type, capabilities)
+    }
+
+    fn import_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+        key: &[u8],
+    ) -> EnclaveResult<()> {
+        self.0.import_key(id, key_type, capabilities, key)
+    }
+
+    fn key_type(&self, id: &str) -> EnclaveResult<EnclaveKeyType> {
+        self.0.key_type(id)
+    }
+
+    fn export_wrapped_key(&self, id: &str, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.export_wrapped_key(id, wrapping_key)
+    }
+
+    fn encrypt(&self, id: &str, plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.encrypt(id, plaintext, aad)
+    }
+
+    fn encrypt_deterministic(
+        &self,
+        id: &str,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> EnclaveResult<Vec<u8>> {
+        self.0.encrypt_deterministic(id, plaintext, aad)
+    }
+
+    fn decrypt(&self, id: &str, ciphertext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.decrypt(id, ciphertext, aad)
+    }
+
+    fn sign_hmac(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.sign_hmac(id, data)
+    }
+
+    fn public_key(&self, id: &str) -> EnclaveResult<Vec<u8>> {
+        self.0.public_key(id)
+    }
+
+    fn sign(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.sign(id, data)
+    }
+
+    fn derive_shared_secret(
+        &self,
+        id: &str,
+        public_key: &[u8],
+    ) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        self.0.derive_shared_secret(id, public_key)
+    }
+
+    fn encrypt_oaep(&self, id: &str, plaintext: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.0.encrypt_oaep(id, plaintext)
+    }
+
+    fn decrypt_oaep(&self, id: &str, ciphertext: &[u8]) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        self.0.decrypt_oaep(id, ciphertext)
+    }
+
+    fn put_secret(&self, id: &str, secret: &[u8]) -> EnclaveResult<()> {
+        self.0.put_secret(id, secret)
+    }
+
+    fn fetch_secret(&self, id: &str) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        self.0.fetch_secret(id)
+    }
+}
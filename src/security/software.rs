@@ -0,0 +1,850 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! An enclave that keeps keys in process memory.
+//!
+//! This gives none of the guarantees of a hardware enclave, keys can be read
+//! by anything that can read the memory of the process. It is meant for
+//! platforms without an enclave like WebAssembly in the browser, and for
+//! tests. Keys are zeroized when they are dropped.
+//!
+//! Keys only live as long as the enclave. Create them with
+//! `EXPORTABLE_WHEN_WRAPPED` and keep them with `export_wrapped_key` to
+//! restore them with `put_key` later.
+//!
+//! Supported keys are XChaCha20-Poly1305, AES-GCM and AES-GCM-SIV with 128,
+//! 192 or 256 bit keys, HMAC with SHA2 and Ed25519. AES-CCM is not supported.
+//! Ed25519 keys are the 32 byte seed, which is also what `put_key` takes and
+//! `export_wrapped_key` wraps. `encrypt` uses a random nonce.
+//! `encrypt_deterministic` derives the nonce from the associated data and
+//! plaintext with an HMAC keyed by a subkey of the key, so equal inputs give
+//! equal ciphertexts.
+
+use super::{
+    errors::{EnclaveError, EnclaveErrorKind},
+    validation::CheckedKey,
+    AesModes, AesSizes, EccCapability, EnclaveCapabilities, EnclaveConnector, EnclaveKeyType,
+    EnclaveLike, EnclaveResult, HmacAlgorithm, KeyCapabilities, SymmetricCapability, WrappingKey,
+};
+
+use aes_gcm::{aes::Aes192, Aes128Gcm, Aes256Gcm, AesGcm};
+use aes_gcm_siv::{
+    aead::{consts::U12, Aead, NewAead as SivNewAead, Payload as SivPayload},
+    Aes128GcmSiv, Aes256GcmSiv, AesGcmSiv, Nonce,
+};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use ed25519_dalek::{ExpandedSecretKey, PublicKey, SecretKey};
+use hmac::{Hmac, Mac, NewMac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Sha256, Sha384, Sha512};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+use zeroize::Zeroizing;
+
+/// Size of keys used to wrap exported keys
+const WRAPPING_KEY_SIZE: usize = 32;
+/// Size of the nonce before wrapped keys
+const WRAPPING_NONCE_SIZE: usize = 24;
+/// Label of the subkey for deriving deterministic nonces
+const NONCE_KEY_LABEL: &[u8] = b"arieskms-software-enclave-nonce";
+
+type Aes192Gcm = AesGcm<Aes192, U12>;
+type Aes192GcmSiv = AesGcmSiv<Aes192>;
+
+/// The algorithms a software enclave key can be used with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Algorithm {
+    XChaCha20Poly1305,
+    Aes128Gcm,
+    Aes192Gcm,
+    Aes256Gcm,
+    Aes128GcmSiv,
+    Aes192GcmSiv,
+    Aes256GcmSiv,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+    Ed25519,
+}
+
+impl Algorithm {
+    fn from_key_type(key_type: EnclaveKeyType) -> EnclaveResult<Self> {
+        match key_type {
+            EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305) => {
+                Ok(Algorithm::XChaCha20Poly1305)
+            }
+            EnclaveKeyType::WrapKey(WrappingKey::Aes(size, AesModes::Gcm)) => Ok(match size {
+                AesSizes::Aes128 => Algorithm::Aes128Gcm,
+                AesSizes::Aes192 => Algorithm::Aes192Gcm,
+                AesSizes::Aes256 => Algorithm::Aes256Gcm,
+            }),
+            EnclaveKeyType::WrapKey(WrappingKey::Aes(size, AesModes::GcmSiv)) => Ok(match size {
+                AesSizes::Aes128 => Algorithm::Aes128GcmSiv,
+                AesSizes::Aes192 => Algorithm::Aes192GcmSiv,
+                AesSizes::Aes256 => Algorithm::Aes256GcmSiv,
+            }),
+            EnclaveKeyType::Hmac(HmacAlgorithm::Sha256) => Ok(Algorithm::HmacSha256),
+            EnclaveKeyType::Hmac(HmacAlgorithm::Sha384) => Ok(Algorithm::HmacSha384),
+            EnclaveKeyType::Hmac(HmacAlgorithm::Sha512) => Ok(Algorithm::HmacSha512),
+            EnclaveKeyType::Ed25519 => Ok(Algorithm::Ed25519),
+            _ => Err(EnclaveError::from_msg(
+                EnclaveErrorKind::UnsupportedOperation,
+                format!(
+                    "{:?} keys are not supported by the software enclave",
+                    key_type
+                ),
+            )),
+        }
+    }
+
+    fn key_type(self) -> EnclaveKeyType {
+        match self {
+            Algorithm::XChaCha20Poly1305 => EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305),
+            Algorithm::Aes128Gcm => aes(AesSizes::Aes128, AesModes::Gcm),
+            Algorithm::Aes192Gcm => aes(AesSizes::Aes192, AesModes::Gcm),
+            Algorithm::Aes256Gcm => aes(AesSizes::Aes256, AesModes::Gcm),
+            Algorithm::Aes128GcmSiv => aes(AesSizes::Aes128, AesModes::GcmSiv),
+            Algorithm::Aes192GcmSiv => aes(AesSizes::Aes192, AesModes::GcmSiv),
+            Algorithm::Aes256GcmSiv => aes(AesSizes::Aes256, AesModes::GcmSiv),
+            Algorithm::HmacSha256 => EnclaveKeyType::Hmac(HmacAlgorithm::Sha256),
+            Algorithm::HmacSha384 => EnclaveKeyType::Hmac(HmacAlgorithm::Sha384),
+            Algorithm::HmacSha512 => EnclaveKeyType::Hmac(HmacAlgorithm::Sha512),
+            Algorithm::Ed25519 => EnclaveKeyType::Ed25519,
+        }
+    }
+
+    fn key_size(self) -> usize {
+        match self {
+            Algorithm::Aes128Gcm | Algorithm::Aes128GcmSiv => 16,
+            Algorithm::Aes192Gcm | Algorithm::Aes192GcmSiv => 24,
+            Algorithm::XChaCha20Poly1305
+            | Algorithm::Aes256Gcm
+            | Algorithm::Aes256GcmSiv
+            | Algorithm::HmacSha256
+            | Algorithm::Ed25519 => 32,
+            Algorithm::HmacSha384 => 48,
+            Algorithm::HmacSha512 => 64,
+        }
+    }
+
+    /// The size of the nonce for ciphers or `None` for HMACs and signatures
+    fn nonce_size(self) -> Option<usize> {
+        match self {
+            Algorithm::XChaCha20Poly1305 => Some(24),
+            Algorithm::Aes128Gcm
+            | Algorithm::Aes192Gcm
+            | Algorithm::Aes256Gcm
+            | Algorithm::Aes128GcmSiv
+            | Algorithm::Aes192GcmSiv
+            | Algorithm::Aes256GcmSiv => Some(12),
+            _ => None,
+        }
+    }
+}
+
+/// A key held by the software enclave
+struct SoftwareKey {
+    algorithm: Algorithm,
+    capabilities: KeyCapabilities,
+    secret: Zeroizing<Vec<u8>>,
+}
+
+impl SoftwareKey {
+    fn allow(&self, id: &str, capability: SymmetricCapability) -> EnclaveResult<()> {
+        match self.capabilities {
+            KeyCapabilities::Symmetric(c) if c.contains(capability) => Ok(()),
+            _ => Err(denied(id, capability)),
+        }
+    }
+
+    fn allow_ecc(&self, id: &str, capability: EccCapability) -> EnclaveResult<()> {
+        match self.capabilities {
+            KeyCapabilities::Ecc(c) if c.contains(capability) => Ok(()),
+            _ => Err(denied(id, capability)),
+        }
+    }
+
+    fn allow_export(&self, id: &str) -> EnclaveResult<()> {
+        match self.capabilities {
+            KeyCapabilities::Ecc(_) => self.allow_ecc(id, EccCapability::EXPORTABLE_WHEN_WRAPPED),
+            _ => self.allow(id, SymmetricCapability::EXPORTABLE_WHEN_WRAPPED),
+        }
+    }
+
+    /// The Ed25519 key pair of the seed
+    fn ed25519(&self) -> EnclaveResult<(SecretKey, PublicKey)> {
+        if self.algorithm != Algorithm::Ed25519 {
+            return Err(EnclaveErrorKind::UnsupportedOperation.into());
+        }
+        let secret = SecretKey::from_bytes(&self.secret)
+            .map_err(|_| general("The key has the wrong size"))?;
+        let public = PublicKey::from(&secret);
+        Ok((secret, public))
+    }
+
+    fn nonce_size(&self) -> EnclaveResult<usize> {
+        self.algorithm
+            .nonce_size()
+            .ok_or_else(|| EnclaveErrorKind::UnsupportedOperation.into())
+    }
+
+    fn seal(&self, nonce: &[u8], plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let ciphertext = match self.algorithm {
+            Algorithm::XChaCha20Poly1305 => {
+                use chacha20poly1305::aead::{Aead, NewAead, Payload};
+                XChaCha20Poly1305::new(<&chacha20poly1305::Key>::from(&self.secret[..]))
+                    .encrypt(
+                        <&XNonce>::from(nonce),
+                        Payload {
+                            msg: plaintext,
+                            aad,
+                        },
+                    )
+                    .ok()
+            }
+            Algorithm::Aes128Gcm => aes_seal::<Aes128Gcm>(&self.secret, nonce, plaintext, aad)?,
+            Algorithm::Aes192Gcm => aes_seal::<Aes192Gcm>(&self.secret, nonce, plaintext, aad)?,
+            Algorithm::Aes256Gcm => aes_seal::<Aes256Gcm>(&self.secret, nonce, plaintext, aad)?,
+            Algorithm::Aes128GcmSiv => {
+                aes_seal::<Aes128GcmSiv>(&self.secret, nonce, plaintext, aad)?
+            }
+            Algorithm::Aes192GcmSiv => {
+                aes_seal::<Aes192GcmSiv>(&self.secret, nonce, plaintext, aad)?
+            }
+            Algorithm::Aes256GcmSiv => {
+                aes_seal::<Aes256GcmSiv>(&self.secret, nonce, plaintext, aad)?
+            }
+            _ => return Err(EnclaveErrorKind::UnsupportedOperation.into()),
+        };
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext.ok_or_else(|| general("Unable to encrypt"))?);
+        Ok(sealed)
+    }
+
+    fn open(&self, nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        let plaintext = match self.algorithm {
+            Algorithm::XChaCha20Poly1305 => {
+                use chacha20poly1305::aead::{Aead, NewAead, Payload};
+                XChaCha20Poly1305::new(<&chacha20poly1305::Key>::from(&self.secret[..]))
+                    .decrypt(
+                        <&XNonce>::from(nonce),
+                        Payload {
+                            msg: ciphertext,
+                            aad,
+                        },
+                    )
+                    .ok()
+            }
+            Algorithm::Aes128Gcm => aes_open::<Aes128Gcm>(&self.secret, nonce, ciphertext, aad)?,
+            Algorithm::Aes192Gcm => aes_open::<Aes192Gcm>(&self.secret, nonce, ciphertext, aad)?,
+            Algorithm::Aes256Gcm => aes_open::<Aes256Gcm>(&self.secret, nonce, ciphertext, aad)?,
+            Algorithm::Aes128GcmSiv => {
+                aes_open::<Aes128GcmSiv>(&self.secret, nonce, ciphertext, aad)?
+            }
+            Algorithm::Aes192GcmSiv => {
+                aes_open::<Aes192GcmSiv>(&self.secret, nonce, ciphertext, aad)?
+            }
+            Algorithm::Aes256GcmSiv => {
+                aes_open::<Aes256GcmSiv>(&self.secret, nonce, ciphertext, aad)?
+            }
+            _ => return Err(EnclaveErrorKind::UnsupportedOperation.into()),
+        };
+        plaintext.ok_or_else(|| general("Unable to decrypt"))
+    }
+
+    fn mac(&self, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        match self.algorithm {
+            Algorithm::HmacSha256 => Ok(compute_mac::<Hmac<Sha256>>(&self.secret, &[data])),
+            Algorithm::HmacSha384 => Ok(compute_mac::<Hmac<Sha384>>(&self.secret, &[data])),
+            Algorithm::HmacSha512 => Ok(compute_mac::<Hmac<Sha512>>(&self.secret, &[data])),
+            _ => Err(EnclaveErrorKind::UnsupportedOperation.into()),
+        }
+    }
+
+    /// Derive the nonce for `encrypt_deterministic` from `plaintext` and `aad`
+    fn synthetic_nonce(&self, size: usize, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let subkey = Zeroizing::new(compute_mac::<Hmac<Sha256>>(
+            &self.secret,
+            &[NONCE_KEY_LABEL],
+        ));
+        let aad_size = (aad.len() as u64).to_be_bytes();
+        let mut nonce = compute_mac::<Hmac<Sha256>>(&subkey, &[&aad_size[..], aad, plaintext]);
+        nonce.truncate(size);
+        nonce
+    }
+}
+
+fn aes_cipher<C: SivNewAead>(key: &[u8]) -> EnclaveResult<C> {
+    C::new_from_slice(key).map_err(|_| general("The key has the wrong size"))
+}
+
+/// Encrypt with one of the AES modes, which all take 12 byte nonces
+fn aes_seal<C: SivNewAead + Aead<NonceSize = U12>>(
+    key: &[u8],
+    nonce: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> EnclaveResult<Option<Vec<u8>>> {
+    Ok(aes_cipher::<C>(key)?
+        .encrypt(
+            <&Nonce>::from(nonce),
+            SivPayload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .ok())
+}
+
+/// Decrypt with one of the AES modes
+fn aes_open<C: SivNewAead + Aead<NonceSize = U12>>(
+    key: &[u8],
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> EnclaveResult<Option<Vec<u8>>> {
+    Ok(aes_cipher::<C>(key)?
+        .decrypt(
+            <&Nonce>::from(nonce),
+            SivPayload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .ok())
+}
+
+fn aes(size: AesSizes, mode: AesModes) -> EnclaveKeyType {
+    EnclaveKeyType::WrapKey(WrappingKey::Aes(size, mode))
+}
+
+fn compute_mac<M: Mac + NewMac>(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let mut mac = M::new_varkey(key).expect("HMAC accepts keys of any size");
+    for d in data {
+        mac.update(d);
+    }
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn denied<C: std::fmt::Debug>(id: &str, capability: C) -> EnclaveError {
+    EnclaveErrorKind::AccessDenied {
+        msg: format!("The key {} does not allow {:?}", id, capability),
+    }
+    .into()
+}
+
+fn general<M: Into<String>>(msg: M) -> EnclaveError {
+    EnclaveErrorKind::GeneralError { msg: msg.into() }.into()
+}
+
+/// An enclave that keeps keys and secrets in memory
+#[derive(Default)]
+pub struct SoftwareEnclave {
+    keys: RwLock<HashMap<String, SoftwareKey>>,
+    secrets: RwLock<HashMap<String, Zeroizing<Vec<u8>>>>,
+}
+
+impl SoftwareEnclave {
+    /// Create an enclave without any keys
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn keys(&self) -> RwLockReadGuard<'_, HashMap<String, SoftwareKey>> {
+        self.keys.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn keys_mut(&self) -> RwLockWriteGuard<'_, HashMap<String, SoftwareKey>> {
+        self.keys.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `f` with the key `id`
+    fn with_key<T, F>(&self, id: &str, f: F) -> EnclaveResult<T>
+    where
+        F: FnOnce(&SoftwareKey) -> EnclaveResult<T>,
+    {
+        match self.keys().get(id) {
+            Some(key) => f(key),
+            None => Err(EnclaveErrorKind::ItemNotFound.into()),
+        }
+    }
+
+    fn add_key(
+        &self,
+        id: &str,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+        secret: Zeroizing<Vec<u8>>,
+    ) -> EnclaveResult<()> {
+        let algorithm = Algorithm::from_key_type(key_type)?;
+        let asymmetric = algorithm == Algorithm::Ed25519;
+        match capabilities {
+            KeyCapabilities::Symmetric(_) if !asymmetric => {}
+            KeyCapabilities::Ecc(_) if asymmetric => {}
+            _ => {
+                return Err(EnclaveError::from_msg(
+                    EnclaveErrorKind::UnsupportedOperation,
+                    format!("{:?} keys can't have {:?}", key_type, capabilities),
+                ))
+            }
+        }
+        if secret.len() != algorithm.key_size() {
+            return Err(general(format!(
+                "{:?} keys are {} bytes",
+                key_type,
+                algorithm.key_size()
+            )));
+        }
+        let mut keys = self.keys_mut();
+        if keys.contains_key(id) {
+            return Err(general(format!("The key {} already exists", id)));
+        }
+        keys.insert(
+            id.to_string(),
+            SoftwareKey {
+                algorithm,
+                capabilities,
+                secret,
+            },
+        );
+        Ok(())
+    }
+}
+
+impl EnclaveLike for SoftwareEnclave {
+    fn connect<A: AsRef<Path>, B: Into<String>>(
+        config: EnclaveConnector<A, B>,
+    ) -> EnclaveResult<Self> {
+        match config {
+            EnclaveConnector::Software => Ok(Self::new()),
+            _ => Err(EnclaveErrorKind::ConnectionFailure {
+                msg: format!(
+                    "Invalid configuration type. Expected Software but found {}",
+                    config
+                ),
+            }
+            .into()),
+        }
+    }
+
+    fn close(self) {}
+
+    fn capabilities(&self) -> EnclaveCapabilities {
+        EnclaveCapabilities::GENERATE_AES_KEY
+            | EnclaveCapabilities::GENERATE_HMAC_KEY
+            | EnclaveCapabilities::GENERATE_XCHACHA20_POLY1305_KEY
+            | EnclaveCapabilities::SIGN_HMAC
+            | EnclaveCapabilities::EXPORT_WRAPPED_KEY
+            | EnclaveCapabilities::PUT_AES_KEY
+            | EnclaveCapabilities::PUT_HMAC_KEY
+            | EnclaveCapabilities::PUT_XCHACHA20_POLY1305_KEY
+            | EnclaveCapabilities::ENCRYPT_AES
+            | EnclaveCapabilities::ENCRYPT_XCHACHA20_POLY1305
+            | EnclaveCapabilities::DECRYPT_AES
+            | EnclaveCapabilities::DECRYPT_XCHACHA20_POLY1305
+            | EnclaveCapabilities::PUT_SECRET
+            | EnclaveCapabilities::FETCH_SECRET
+            | EnclaveCapabilities::AES_GCM
+            | EnclaveCapabilities::AES_GCM_SIV
+            | EnclaveCapabilities::GENERATE_EDDSA_KEY
+            | EnclaveCapabilities::PUT_EDDSA_KEY
+            | EnclaveCapabilities::SIGN_EDDSA
+    }
+
+    fn generate_key(&self, id: &str, checked: CheckedKey) -> EnclaveResult<()> {
+        let size = Algorithm::from_key_type(checked.key_type())?.key_size();
+        let mut secret = Zeroizing::new(vec![0u8; size]);
+        OsRng.fill_bytes(&mut secret);
+        self.add_key(id, checked.key_type(), checked.capabilities(), secret)
+    }
+
+    fn put_key(&self, id: &str, checked: CheckedKey, key: &[u8]) -> EnclaveResult<()> {
+        self.add_key(
+            id,
+            checked.key_type(),
+            checked.capabilities(),
+            Zeroizing::new(key.to_vec()),
+        )
+    }
+
+    fn key_type(&self, id: &str) -> EnclaveResult<EnclaveKeyType> {
+        self.with_key(id, |key| Ok(key.algorithm.key_type()))
+    }
+
+    fn export_wrapped_key(&self, id: &str, wrapping_key: &[u8]) -> EnclaveResult<Vec<u8>> {
+        if wrapping_key.len() != WRAPPING_KEY_SIZE {
+            return Err(general(format!(
+                "Wrapping keys are {} bytes",
+                WRAPPING_KEY_SIZE
+            )));
+        }
+        self.with_key(id, |key| {
+            key.allow_export(id)?;
+            let wrapper = SoftwareKey {
+                algorithm: Algorithm::XChaCha20Poly1305,
+                capabilities: KeyCapabilities::Symmetric(SymmetricCapability::ENCRYPT),
+                secret: Zeroizing::new(wrapping_key.to_vec()),
+            };
+            let mut nonce = [0u8; WRAPPING_NONCE_SIZE];
+            OsRng.fill_bytes(&mut nonce);
+            wrapper.seal(&nonce, &key.secret, id.as_bytes())
+        })
+    }
+
+    fn encrypt(&self, id: &str, plaintext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.with_key(id, |key| {
+            key.allow(id, SymmetricCapability::ENCRYPT)?;
+            let mut nonce = vec![0u8; key.nonce_size()?];
+            OsRng.fill_bytes(&mut nonce);
+            key.seal(&nonce, plaintext, aad)
+        })
+    }
+
+    fn encrypt_deterministic(
+        &self,
+        id: &str,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> EnclaveResult<Vec<u8>> {
+        self.with_key(id, |key| {
+            key.allow(id, SymmetricCapability::ENCRYPT)?;
+            let nonce = key.synthetic_nonce(key.nonce_size()?, plaintext, aad);
+            key.seal(&nonce, plaintext, aad)
+        })
+    }
+
+    fn decrypt(&self, id: &str, ciphertext: &[u8], aad: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.with_key(id, |key| {
+            key.allow(id, SymmetricCapability::DECRYPT)?;
+            let size = key.nonce_size()?;
+            if ciphertext.len() < size {
+                return Err(general("The ciphertext is too short"));
+            }
+            let (nonce, ciphertext) = ciphertext.split_at(size);
+            key.open(nonce, ciphertext, aad)
+        })
+    }
+
+    fn sign_hmac(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.with_key(id, |key| {
+            key.allow(id, SymmetricCapability::HMAC_SIGN)?;
+            key.mac(data)
+        })
+    }
+
+    fn public_key(&self, id: &str) -> EnclaveResult<Vec<u8>> {
+        self.with_key(id, |key| Ok(key.ed25519()?.1.to_bytes().to_vec()))
+    }
+
+    fn sign(&self, id: &str, data: &[u8]) -> EnclaveResult<Vec<u8>> {
+        self.with_key(id, |key| {
+            key.allow_ecc(id, EccCapability::SIGN)?;
+            let (secret, public) = key.ed25519()?;
+            let signature = ExpandedSecretKey::from(&secret).sign(data, &public);
+            Ok(signature.to_bytes().to_vec())
+        })
+    }
+
+    fn put_secret(&self, id: &str, secret: &[u8]) -> EnclaveResult<()> {
+        self.secrets
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id.to_string(), Zeroizing::new(secret.to_vec()));
+        Ok(())
+    }
+
+    fn fetch_secret(&self, id: &str) -> EnclaveResult<Zeroizing<Vec<u8>>> {
+        self.secrets
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(id)
+            .cloned()
+            .ok_or_else(|| EnclaveErrorKind::ItemNotFound.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Signature;
+    use std::convert::TryFrom;
+
+    const XCHACHA: EnclaveKeyType = EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305);
+
+    fn aes(size: AesSizes, mode: AesModes) -> EnclaveKeyType {
+        EnclaveKeyType::WrapKey(WrappingKey::Aes(size, mode))
+    }
+
+    fn symmetric(c: SymmetricCapability) -> KeyCapabilities {
+        KeyCapabilities::Symmetric(c)
+    }
+
+    fn cipher_types() -> Vec<EnclaveKeyType> {
+        let mut types = vec![XCHACHA];
+        for size in &[AesSizes::Aes128, AesSizes::Aes192, AesSizes::Aes256] {
+            for mode in &[AesModes::Gcm, AesModes::GcmSiv] {
+                types.push(aes(*size, *mode));
+            }
+        }
+        types
+    }
+
+    #[test]
+    fn encrypt_round_trip() {
+        let enclave = SoftwareEnclave::new();
+        let both = symmetric(SymmetricCapability::ENCRYPT | SymmetricCapability::DECRYPT);
+        for key_type in cipher_types() {
+            let id = format!("{:?}", key_type);
+            enclave.create_key(&id, key_type, both).unwrap();
+
+            let first = enclave.encrypt(&id, b"plaintext", b"aad").unwrap();
+            let second = enclave.encrypt(&id, b"plaintext", b"aad").unwrap();
+            assert_ne!(first, second, "{}", id);
+            assert_eq!(enclave.decrypt(&id, &first, b"aad").unwrap(), b"plaintext");
+            assert_eq!(enclave.decrypt(&id, &second, b"aad").unwrap(), b"plaintext");
+            assert!(enclave.decrypt(&id, &first, b"other").is_err(), "{}", id);
+            assert!(enclave.decrypt(&id, &first[..4], b"aad").is_err(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn encrypt_deterministic_round_trip() {
+        let enclave = SoftwareEnclave::new();
+        let both = symmetric(SymmetricCapability::ENCRYPT | SymmetricCapability::DECRYPT);
+        for key_type in cipher_types() {
+            let id = format!("{:?}", key_type);
+            enclave.create_key(&id, key_type, both).unwrap();
+
+            let first = enclave.encrypt_deterministic(&id, b"name", b"aad").unwrap();
+            let second = enclave.encrypt_deterministic(&id, b"name", b"aad").unwrap();
+            assert_eq!(first, second, "{}", id);
+            assert_ne!(
+                first,
+                enclave
+                    .encrypt_deterministic(&id, b"name", b"other")
+                    .unwrap(),
+                "{}",
+                id
+            );
+            assert_ne!(
+                first,
+                enclave
+                    .encrypt_deterministic(&id, b"other", b"aad")
+                    .unwrap(),
+                "{}",
+                id
+            );
+            assert_eq!(enclave.decrypt(&id, &first, b"aad").unwrap(), b"name");
+        }
+    }
+
+    #[test]
+    fn hmac_round_trip() {
+        let enclave = SoftwareEnclave::new();
+        let sign = symmetric(SymmetricCapability::HMAC_SIGN);
+        let key = [7u8; 64];
+        for (algorithm, size, expected) in &[
+            (
+                HmacAlgorithm::Sha256,
+                32,
+                compute_mac::<Hmac<Sha256>>(&key[..32], &[b"data"]),
+            ),
+            (
+                HmacAlgorithm::Sha384,
+                48,
+                compute_mac::<Hmac<Sha384>>(&key[..48], &[b"data"]),
+            ),
+            (
+                HmacAlgorithm::Sha512,
+                64,
+                compute_mac::<Hmac<Sha512>>(&key[..64], &[b"data"]),
+            ),
+        ] {
+            let id = format!("{:?}", algorithm);
+            enclave
+                .import_key(&id, EnclaveKeyType::Hmac(*algorithm), sign, &key[..*size])
+                .unwrap();
+            assert_eq!(&enclave.sign_hmac(&id, b"data").unwrap(), expected);
+            assert_ne!(&enclave.sign_hmac(&id, b"other").unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn keys_only_allow_their_capabilities() {
+        let enclave = SoftwareEnclave::new();
+        enclave
+            .create_key("key", XCHACHA, symmetric(SymmetricCapability::ENCRYPT))
+            .unwrap();
+        let ciphertext = enclave.encrypt("key", b"plaintext", b"").unwrap();
+        for result in &[
+            enclave.decrypt("key", &ciphertext, b""),
+            enclave.export_wrapped_key("key", &[0; WRAPPING_KEY_SIZE]),
+        ] {
+            match result.as_ref().map_err(|e| e.kind()) {
+                Err(EnclaveErrorKind::AccessDenied { .. }) => {}
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(
+            enclave.encrypt("missing", b"", b"").unwrap_err().kind(),
+            EnclaveErrorKind::ItemNotFound
+        );
+        assert!(enclave
+            .create_key("key", XCHACHA, symmetric(SymmetricCapability::ENCRYPT))
+            .is_err());
+    }
+
+    #[test]
+    fn unsupported_keys_are_rejected_before_creation() {
+        let enclave = SoftwareEnclave::new();
+        let encrypt = symmetric(SymmetricCapability::ENCRYPT);
+        for size in &[AesSizes::Aes128, AesSizes::Aes192, AesSizes::Aes256] {
+            assert_eq!(
+                enclave
+                    .create_key("ccm", aes(*size, AesModes::Ccm), encrypt)
+                    .unwrap_err()
+                    .kind(),
+                EnclaveErrorKind::UnsupportedOperation
+            );
+        }
+        assert_eq!(
+            enclave.encrypt("ccm", b"", b"").unwrap_err().kind(),
+            EnclaveErrorKind::ItemNotFound
+        );
+    }
+
+    #[test]
+    fn exported_keys_unwrap_into_another_enclave() {
+        let wrapping_key = [9u8; WRAPPING_KEY_SIZE];
+        let capabilities = symmetric(
+            SymmetricCapability::ENCRYPT
+                | SymmetricCapability::DECRYPT
+                | SymmetricCapability::EXPORTABLE_WHEN_WRAPPED,
+        );
+        let key_type = aes(AesSizes::Aes256, AesModes::GcmSiv);
+        let enclave = SoftwareEnclave::new();
+        enclave.create_key("key", key_type, capabilities).unwrap();
+        let ciphertext = enclave.encrypt("key", b"plaintext", b"aad").unwrap();
+
+        let wrapped = enclave.export_wrapped_key("key", &wrapping_key).unwrap();
+        let unwrapper = SoftwareEnclave::new();
+        unwrapper
+            .import_key(
+                "wrapping",
+                XCHACHA,
+                symmetric(SymmetricCapability::DECRYPT),
+                &wrapping_key,
+            )
+            .unwrap();
+        assert!(unwrapper.decrypt("wrapping", &wrapped, b"other").is_err());
+        let key = Zeroizing::new(unwrapper.decrypt("wrapping", &wrapped, b"key").unwrap());
+
+        let restored = SoftwareEnclave::new();
+        restored
+            .import_key("key", key_type, capabilities, &key)
+            .unwrap();
+        assert_eq!(
+            restored.decrypt("key", &ciphertext, b"aad").unwrap(),
+            b"plaintext"
+        );
+        assert!(enclave.export_wrapped_key("key", &[9u8; 16]).is_err());
+    }
+
+    #[test]
+    fn secrets_round_trip() {
+        let enclave = SoftwareEnclave::new();
+        assert_eq!(
+            enclave.fetch_secret("pin").unwrap_err().kind(),
+            EnclaveErrorKind::ItemNotFound
+        );
+        enclave.put_secret("pin", b"1234").unwrap();
+        assert_eq!(&enclave.fetch_secret("pin").unwrap()[..], b"1234");
+    }
+
+    #[test]
+    fn ed25519_signatures() {
+        // RFC 8032 test 1
+        let seed = hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+            .unwrap();
+        let public = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+        let signature = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+                         5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+        let sign = KeyCapabilities::Ecc(EccCapability::SIGN);
+        let enclave = SoftwareEnclave::new();
+        enclave
+            .import_key("imported", EnclaveKeyType::Ed25519, sign, &seed)
+            .unwrap();
+        assert_eq!(hex::encode(enclave.public_key("imported").unwrap()), public);
+        assert_eq!(
+            hex::encode(enclave.sign("imported", b"").unwrap()),
+            signature
+        );
+        assert!(enclave
+            .import_key("short", EnclaveKeyType::Ed25519, sign, &seed[..16])
+            .is_err());
+
+        enclave
+            .create_key("generated", EnclaveKeyType::Ed25519, sign)
+            .unwrap();
+        let public = PublicKey::from_bytes(&enclave.public_key("generated").unwrap()).unwrap();
+        let signature = enclave.sign("generated", b"message").unwrap();
+        public
+            .verify_strict(b"message", &Signature::try_from(&signature[..]).unwrap())
+            .unwrap();
+        assert_eq!(
+            enclave
+                .sign_hmac("generated", b"message")
+                .unwrap_err()
+                .kind(),
+            EnclaveErrorKind::AccessDenied {
+                msg: "The key generated does not allow HMAC_SIGN".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn exported_ed25519_keys_keep_their_public_key() {
+        let wrapping_key = [3u8; WRAPPING_KEY_SIZE];
+        let capabilities =
+            KeyCapabilities::Ecc(EccCapability::SIGN | EccCapability::EXPORTABLE_WHEN_WRAPPED);
+        let enclave = SoftwareEnclave::new();
+        enclave
+            .create_key("key", EnclaveKeyType::Ed25519, capabilities)
+            .unwrap();
+        let wrapped = enclave.export_wrapped_key("key", &wrapping_key).unwrap();
+
+        let restored = SoftwareEnclave::new();
+        restored
+            .import_key(
+                "wrapping",
+                XCHACHA,
+                symmetric(SymmetricCapability::DECRYPT),
+                &wrapping_key,
+            )
+            .unwrap();
+        let seed = Zeroizing::new(restored.decrypt("wrapping", &wrapped, b"key").unwrap());
+        restored
+            .import_key("key", EnclaveKeyType::Ed25519, capabilities, &seed)
+            .unwrap();
+        assert_eq!(
+            restored.public_key("key").unwrap(),
+            enclave.public_key("key").unwrap()
+        );
+    }
+}
@@ -0,0 +1,267 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Secret sources let services that can't be prompted pass credentials
+//! without putting them in config files or environment variables.
+//!
+//! A source only names where the secret is. It is read when the enclave is
+//! connected and the copy held by this library is zeroized once used. A
+//! trailing line ending is removed since most tools that write secret files
+//! add one.
+
+use super::{
+    errors::{EnclaveError, EnclaveErrorKind},
+    EnclaveResult,
+};
+
+use std::{
+    fmt,
+    fs::File,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+use zeroize::Zeroizing;
+
+/// The environment variable systemd sets to the directory holding the credentials of a service
+pub const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+/// The directory Docker and Kubernetes mount secrets in
+pub const DOCKER_SECRETS_DIRECTORY: &str = "/run/secrets";
+/// The largest secret that is read
+const MAX_SECRET_SIZE: u64 = 64 * 1024;
+
+/// Where a secret is read from when an enclave is connected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretSource {
+    /// The systemd credential `name` passed to the service with
+    /// `LoadCredential=` or `LoadCredentialEncrypted=`
+    Systemd(String),
+    /// The Docker or Kubernetes secret `name` in `/run/secrets`
+    DockerSecret(String),
+    /// A file holding only the secret
+    File(PathBuf),
+    /// One line read from an inherited file descriptor.
+    /// Only available on Unix.
+    Fd(i32),
+}
+
+impl SecretSource {
+    /// Read the secret
+    pub fn read(&self) -> EnclaveResult<Zeroizing<String>> {
+        match self {
+            SecretSource::Systemd(name) => {
+                let dir = std::env::var_os(CREDENTIALS_DIRECTORY).ok_or_else(|| {
+                    access_denied(format!(
+                        "${} is not set. The service has no systemd credentials",
+                        CREDENTIALS_DIRECTORY
+                    ))
+                })?;
+                read_file(&Path::new(&dir).join(file_name(name)?))
+            }
+            SecretSource::DockerSecret(name) => {
+                read_file(&Path::new(DOCKER_SECRETS_DIRECTORY).join(file_name(name)?))
+            }
+            SecretSource::File(path) => read_file(path),
+            SecretSource::Fd(fd) => read_fd(*fd),
+        }
+    }
+}
+
+impl fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretSource::Systemd(name) => write!(f, "systemd credential {}", name),
+            SecretSource::DockerSecret(name) => write!(f, "secret {}", name),
+            SecretSource::File(path) => write!(f, "file {}", path.display()),
+            SecretSource::Fd(fd) => write!(f, "file descriptor {}", fd),
+        }
+    }
+}
+
+/// Only allow names that stay inside the secrets directory
+fn file_name(name: &str) -> EnclaveResult<&Path> {
+    let path = Path::new(name);
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(path),
+        _ => Err(access_denied(format!(
+            "{} is not a valid secret name",
+            name
+        ))),
+    }
+}
+
+fn read_file(path: &Path) -> EnclaveResult<Zeroizing<String>> {
+    let file = File::open(path)
+        .map_err(|e| access_denied(format!("Unable to read {}: {}", path.display(), e)))?;
+    read_secret(file, false)
+        .map_err(|e| access_denied(format!("Unable to read {}: {}", path.display(), e)))
+}
+
+#[cfg(unix)]
+fn read_fd(fd: i32) -> EnclaveResult<Zeroizing<String>> {
+    // Opening /dev/fd duplicates the descriptor so it stays open for the next
+    // read, and reading a byte at a time leaves the next line unread
+    let file = File::open(format!("/dev/fd/{}", fd))
+        .map_err(|e| access_denied(format!("Unable to read file descriptor {}: {}", fd, e)))?;
+    let secret = read_secret(file, true)
+        .map_err(|e| access_denied(format!("Unable to read file descriptor {}: {}", fd, e)))?;
+    if secret.is_empty() {
+        return Err(access_denied(format!(
+            "File descriptor {} has no more credentials",
+            fd
+        )));
+    }
+    Ok(secret)
+}
+
+#[cfg(not(unix))]
+fn read_fd(fd: i32) -> EnclaveResult<Zeroizing<String>> {
+    Err(EnclaveError::from_msg(
+        EnclaveErrorKind::UnsupportedOperation,
+        format!("Reading file descriptor {} is only supported on Unix", fd),
+    ))
+}
+
+/// Read until the end or only the first line into a buffer that
+/// is never reallocated, so no copy of the secret is left behind
+fn read_secret(file: File, line: bool) -> std::io::Result<Zeroizing<String>> {
+    let mut buffer = Zeroizing::new(vec![0u8; MAX_SECRET_SIZE as usize]);
+    let mut len = 0;
+    let mut reader = file.take(MAX_SECRET_SIZE);
+    loop {
+        let end = if line { len + 1 } else { buffer.len() };
+        if len == buffer.len() {
+            break;
+        }
+        match reader.read(&mut buffer[len..end])? {
+            0 => break,
+            n => len += n,
+        }
+        if line && buffer[len - 1] == b'\n' {
+            break;
+        }
+    }
+    while len > 0 && (buffer[len - 1] == b'\n' || buffer[len - 1] == b'\r') {
+        len -= 1;
+    }
+    let secret = std::str::from_utf8(&buffer[..len]).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "Secrets must be UTF-8")
+    })?;
+    Ok(Zeroizing::new(secret.to_string()))
+}
+
+fn access_denied(msg: String) -> EnclaveError {
+    EnclaveErrorKind::AccessDenied { msg }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of secret files removed when the test ends
+    struct SecretDir(PathBuf);
+
+    impl SecretDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "arieskms-source-{}-{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, name: &str, contents: &[u8]) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for SecretDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn assert_denied(result: EnclaveResult<Zeroizing<String>>) {
+        match result {
+            Err(e) => match e.kind() {
+                EnclaveErrorKind::AccessDenied { .. } => {}
+                kind => panic!("Expected AccessDenied but found {:?}", kind),
+            },
+            Ok(_) => panic!("Read a secret that isn't there"),
+        }
+    }
+
+    #[test]
+    fn file_sources() {
+        let dir = SecretDir::new("files");
+        for (contents, secret) in &[
+            (&b"secret"[..], "secret"),
+            (b"secret\n", "secret"),
+            (b"secret\r\n", "secret"),
+            (b"secret\n\n", "secret"),
+            (b"two\nlines\n", "two\nlines"),
+            (b" spaced \n", " spaced "),
+            (b"", ""),
+        ] {
+            let path = dir.write("secret", contents);
+            assert_eq!(*SecretSource::File(path).read().unwrap(), *secret);
+        }
+
+        assert_denied(SecretSource::File(dir.0.join("missing")).read());
+        let binary = dir.write("binary", &[0xff, 0xfe, b'\n']);
+        assert_denied(SecretSource::File(binary).read());
+    }
+
+    #[test]
+    fn systemd_sources() {
+        let dir = SecretDir::new("systemd");
+        dir.write("pin", b"1234\n");
+        std::env::set_var(CREDENTIALS_DIRECTORY, &dir.0);
+        let pin = SecretSource::Systemd("pin".to_string()).read();
+        let missing = SecretSource::Systemd("missing".to_string()).read();
+        let outside = SecretSource::Systemd("../pin".to_string()).read();
+        std::env::remove_var(CREDENTIALS_DIRECTORY);
+        let unset = SecretSource::Systemd("pin".to_string()).read();
+
+        assert_eq!(*pin.unwrap(), "1234");
+        assert_denied(missing);
+        assert_denied(outside);
+        assert_denied(unset);
+    }
+
+    #[test]
+    fn secret_names_stay_in_their_directory() {
+        for name in &["../etc/passwd", "/etc/passwd", "a/b", "..", ""] {
+            assert_denied(SecretSource::DockerSecret(name.to_string()).read());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fd_sources() {
+        use std::os::unix::io::AsRawFd;
+
+        let dir = SecretDir::new("fd");
+        let file = File::open(dir.write("lines", b"first\nsecond\n")).unwrap();
+        assert_eq!(*SecretSource::Fd(file.as_raw_fd()).read().unwrap(), "first");
+        let empty = File::open(dir.write("empty", b"")).unwrap();
+        assert_denied(SecretSource::Fd(empty.as_raw_fd()).read());
+    }
+}
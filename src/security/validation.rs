@@ -0,0 +1,590 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Key capabilities are checked against the key type and the enclave by
+//! `EnclaveLike::create_key` and `EnclaveLike::import_key`, which the handles
+//! in `keys` and every `SharedEnclave` create keys with.
+//!
+//! Capabilities must belong to the kind of key, like `HMAC_SIGN` to HMAC keys
+//! and `DERIVE_DIFFIE_HELLMAN` to X25519 and ECDH keys, and every key needs at
+//! least one capability besides `EXPORTABLE_WHEN_WRAPPED`. The enclave must be
+//! able to create the key type and perform every operation the key allows.
+
+use super::{
+    errors::{EnclaveError, EnclaveErrorKind},
+    AesModes, EccCapability, EnclaveCapabilities, EnclaveKeyType, EnclaveResult, KeyCapabilities,
+    RsaCapability, SymmetricCapability, WrappingKey,
+};
+
+/// How a key is created in an enclave
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyOrigin {
+    /// Generated with `EnclaveLike::generate_key`
+    Generated,
+    /// Imported with `EnclaveLike::put_key`
+    Imported,
+}
+
+/// A key type and capabilities that passed `EnclaveCapabilities::check_key`.
+/// Only `EnclaveLike::create_key` and `EnclaveLike::import_key` make these,
+/// so the `generate_key` and `put_key` hooks that take one can't be called
+/// with unchecked capabilities.
+///
+/// ```compile_fail
+/// use arieskms::security::{
+///     null::NullEnclave, AesModes, AesSizes, EnclaveKeyType, EnclaveLike, KeyCapabilities,
+///     SymmetricCapability, WrappingKey,
+/// };
+///
+/// let enclave = NullEnclave;
+/// enclave.put_key(
+///     "aes",
+///     EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::GcmSiv)),
+///     KeyCapabilities::Symmetric(SymmetricCapability::HMAC_SIGN),
+///     &[0; 32],
+/// );
+/// ```
+#[derive(Debug)]
+pub struct CheckedKey {
+    key_type: EnclaveKeyType,
+    capabilities: KeyCapabilities,
+}
+
+impl CheckedKey {
+    /// Check `key_type` and `capabilities` against `enclave`
+    pub(crate) fn new(
+        enclave: EnclaveCapabilities,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+        origin: KeyOrigin,
+    ) -> EnclaveResult<Self> {
+        enclave.check_key(key_type, capabilities, origin)?;
+        Ok(Self {
+            key_type,
+            capabilities,
+        })
+    }
+
+    /// The type of the key
+    pub fn key_type(&self) -> EnclaveKeyType {
+        self.key_type
+    }
+
+    /// What the key can be used for
+    pub fn capabilities(&self) -> KeyCapabilities {
+        self.capabilities
+    }
+}
+
+impl KeyCapabilities {
+    /// Check that a `key_type` key can have these capabilities
+    pub fn check(&self, key_type: EnclaveKeyType) -> EnclaveResult<()> {
+        use EnclaveKeyType::*;
+        let (disallowed, usable) = match (key_type, *self) {
+            (WrapKey(_), KeyCapabilities::Symmetric(c)) => symmetric(
+                c,
+                SymmetricCapability::ENCRYPT
+                    | SymmetricCapability::DECRYPT
+                    | SymmetricCapability::EXPORT_WRAPPED
+                    | SymmetricCapability::IMPORT_WRAPPED,
+            ),
+            (Hmac(_), KeyCapabilities::Symmetric(c)) => symmetric(
+                c,
+                SymmetricCapability::HMAC_SIGN | SymmetricCapability::HMAC_VERIFY,
+            ),
+            (Ed25519, KeyCapabilities::Ecc(c)) | (Ecdsa(..), KeyCapabilities::Ecc(c)) => {
+                ecc(c, EccCapability::SIGN | EccCapability::VERIFY)
+            }
+            (X25519, KeyCapabilities::Ecc(c)) | (Ecdh(_), KeyCapabilities::Ecc(c)) => {
+                ecc(c, EccCapability::DERIVE_DIFFIE_HELLMAN)
+            }
+            (RsaOaep(_), KeyCapabilities::Rsa(c)) => {
+                rsa(c, RsaCapability::ENCRYPT_OAEP | RsaCapability::DECRYPT_OAEP)
+            }
+            (RsaPss(_), KeyCapabilities::Rsa(c)) => {
+                rsa(c, RsaCapability::SIGN_PSS | RsaCapability::VERIFY_PSS)
+            }
+            (RsaPkcs15(_), KeyCapabilities::Rsa(c)) => {
+                rsa(c, RsaCapability::SIGN_PKCS | RsaCapability::VERIFY_PKCS)
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "{:?} keys can't have {} capabilities",
+                    key_type,
+                    self.kind()
+                )))
+            }
+        };
+        if let Some(disallowed) = disallowed {
+            return Err(invalid(format!(
+                "{:?} keys can't have the capabilities {}",
+                key_type, disallowed
+            )));
+        }
+        if !usable {
+            return Err(invalid(format!(
+                "{:?} keys need a capability besides EXPORTABLE_WHEN_WRAPPED",
+                key_type
+            )));
+        }
+        Ok(())
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            KeyCapabilities::Symmetric(_) => "symmetric",
+            KeyCapabilities::Ecc(_) => "elliptic curve",
+            KeyCapabilities::Rsa(_) => "RSA",
+        }
+    }
+}
+
+/// The capabilities in `c` that are not `allowed` and whether any allowed one is
+fn symmetric(c: SymmetricCapability, allowed: SymmetricCapability) -> (Option<String>, bool) {
+    let disallowed = c - allowed - SymmetricCapability::EXPORTABLE_WHEN_WRAPPED;
+    (
+        Some(format!("{:?}", disallowed)).filter(|_| !disallowed.is_empty()),
+        c.intersects(allowed),
+    )
+}
+
+/// The capabilities in `c` that are not `allowed` and whether any allowed one is
+fn ecc(c: EccCapability, allowed: EccCapability) -> (Option<String>, bool) {
+    let disallowed = c - allowed - EccCapability::EXPORTABLE_WHEN_WRAPPED;
+    (
+        Some(format!("{:?}", disallowed)).filter(|_| !disallowed.is_empty()),
+        c.intersects(allowed),
+    )
+}
+
+/// The capabilities in `c` that are not `allowed` and whether any allowed one is
+fn rsa(c: RsaCapability, allowed: RsaCapability) -> (Option<String>, bool) {
+    let disallowed = c - allowed - RsaCapability::EXPORTABLE_WHEN_WRAPPED;
+    (
+        Some(format!("{:?}", disallowed)).filter(|_| !disallowed.is_empty()),
+        c.intersects(allowed),
+    )
+}
+
+impl EnclaveCapabilities {
+    /// Check that `capabilities` make sense for `key_type` and that an enclave
+    /// with these capabilities can create the key and use it as allowed
+    pub fn check_key(
+        &self,
+        key_type: EnclaveKeyType,
+        capabilities: KeyCapabilities,
+        origin: KeyOrigin,
+    ) -> EnclaveResult<()> {
+        capabilities.check(key_type)?;
+        let required = creation(key_type, origin) | usage(key_type, capabilities);
+        let missing = required - *self;
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let action = match origin {
+            KeyOrigin::Generated => "generate",
+            KeyOrigin::Imported => "import",
+        };
+        Err(EnclaveError::from_msg(
+            EnclaveErrorKind::UnsupportedOperation,
+            format!(
+                "The enclave can't {} {:?} keys with {:?}. It lacks {:?}",
+                action, key_type, capabilities, missing
+            ),
+        ))
+    }
+}
+
+/// What an enclave needs to create a `key_type` key
+fn creation(key_type: EnclaveKeyType, origin: KeyOrigin) -> EnclaveCapabilities {
+    use EnclaveKeyType::*;
+    let generated = origin == KeyOrigin::Generated;
+    let pick = |generate, put| if generated { generate } else { put };
+    match key_type {
+        WrapKey(WrappingKey::Aes(_, mode)) => {
+            let mode = match mode {
+                AesModes::Ccm => EnclaveCapabilities::AES_CCM,
+                AesModes::Gcm => EnclaveCapabilities::AES_GCM,
+                AesModes::GcmSiv => EnclaveCapabilities::AES_GCM_SIV,
+            };
+            pick(
+                EnclaveCapabilities::GENERATE_AES_KEY,
+                EnclaveCapabilities::PUT_AES_KEY,
+            ) | mode
+        }
+        WrapKey(WrappingKey::XChaChaPoly1305) => pick(
+            EnclaveCapabilities::GENERATE_XCHACHA20_POLY1305_KEY,
+            EnclaveCapabilities::PUT_XCHACHA20_POLY1305_KEY,
+        ),
+        Hmac(_) => pick(
+            EnclaveCapabilities::GENERATE_HMAC_KEY,
+            EnclaveCapabilities::PUT_HMAC_KEY,
+        ),
+        Ed25519 => pick(
+            EnclaveCapabilities::GENERATE_EDDSA_KEY,
+            EnclaveCapabilities::PUT_EDDSA_KEY,
+        ),
+        Ecdsa(..) => pick(
+            EnclaveCapabilities::GENERATE_ECDSA_KEY,
+            EnclaveCapabilities::PUT_ECDSA_KEY,
+        ),
+        // There are no separate flags for creating key-exchange keys
+        X25519 => EnclaveCapabilities::DERIVE_X25519,
+        Ecdh(_) => EnclaveCapabilities::DERIVE_ECDH,
+        RsaOaep(_) => pick(
+            EnclaveCapabilities::GENERATE_OAEP_KEY,
+            EnclaveCapabilities::PUT_OAEP_KEY,
+        ),
+        RsaPss(_) => pick(
+            EnclaveCapabilities::GENERATE_PSS_KEY,
+            EnclaveCapabilities::PUT_PSS_KEY,
+        ),
+        RsaPkcs15(_) => pick(
+            EnclaveCapabilities::GENERATE_PKCS_KEY,
+            EnclaveCapabilities::PUT_PKCS_KEY,
+        ),
+    }
+}
+
+/// What an enclave needs to use a `key_type` key as allowed by `capabilities`
+fn usage(key_type: EnclaveKeyType, capabilities: KeyCapabilities) -> EnclaveCapabilities {
+    use EnclaveKeyType::*;
+    let required = |table: &[(bool, EnclaveCapabilities)]| {
+        table
+            .iter()
+            .filter(|(allowed, _)| *allowed)
+            .fold(EnclaveCapabilities::empty(), |r, (_, needed)| r | *needed)
+    };
+    match capabilities {
+        KeyCapabilities::Symmetric(c) => {
+            let (encrypt, decrypt) = match key_type {
+                WrapKey(WrappingKey::Aes(..)) => (
+                    EnclaveCapabilities::ENCRYPT_AES,
+                    EnclaveCapabilities::DECRYPT_AES,
+                ),
+                _ => (
+                    EnclaveCapabilities::ENCRYPT_XCHACHA20_POLY1305,
+                    EnclaveCapabilities::DECRYPT_XCHACHA20_POLY1305,
+                ),
+            };
+            required(&[
+                (c.contains(SymmetricCapability::ENCRYPT), encrypt),
+                (c.contains(SymmetricCapability::DECRYPT), decrypt),
+                (
+                    c.contains(SymmetricCapability::HMAC_SIGN),
+                    EnclaveCapabilities::SIGN_HMAC,
+                ),
+                (
+                    c.contains(SymmetricCapability::HMAC_VERIFY),
+                    EnclaveCapabilities::VERIFY_HMAC,
+                ),
+                (
+                    c.contains(SymmetricCapability::EXPORT_WRAPPED),
+                    EnclaveCapabilities::WRAP_KEY,
+                ),
+                (
+                    c.contains(SymmetricCapability::IMPORT_WRAPPED),
+                    EnclaveCapabilities::UNWRAP_KEY,
+                ),
+                (
+                    c.contains(SymmetricCapability::EXPORTABLE_WHEN_WRAPPED),
+                    EnclaveCapabilities::EXPORT_WRAPPED_KEY,
+                ),
+            ])
+        }
+        KeyCapabilities::Ecc(c) => {
+            let (sign, verify) = match key_type {
+                Ed25519 => (
+                    EnclaveCapabilities::SIGN_EDDSA,
+                    EnclaveCapabilities::VERIFY_EDDSA,
+                ),
+                _ => (
+                    EnclaveCapabilities::SIGN_ECDSA,
+                    EnclaveCapabilities::VERIFY_ECDSA,
+                ),
+            };
+            let derive = match key_type {
+                X25519 => EnclaveCapabilities::DERIVE_X25519,
+                _ => EnclaveCapabilities::DERIVE_ECDH,
+            };
+            required(&[
+                (c.contains(EccCapability::SIGN), sign),
+                (c.contains(EccCapability::VERIFY), verify),
+                (c.contains(EccCapability::DERIVE_DIFFIE_HELLMAN), derive),
+                (
+                    c.contains(EccCapability::EXPORTABLE_WHEN_WRAPPED),
+                    EnclaveCapabilities::EXPORT_WRAPPED_KEY,
+                ),
+            ])
+        }
+        KeyCapabilities::Rsa(c) => required(&[
+            (
+                c.contains(RsaCapability::ENCRYPT_OAEP),
+                EnclaveCapabilities::ENCRYPT_OAEP,
+            ),
+            (
+                c.contains(RsaCapability::DECRYPT_OAEP),
+                EnclaveCapabilities::DECRYPT_OAEP,
+            ),
+            (
+                c.contains(RsaCapability::SIGN_PSS),
+                EnclaveCapabilities::SIGN_PSS,
+            ),
+            (
+                c.contains(RsaCapability::VERIFY_PSS),
+                EnclaveCapabilities::VERIFY_PSS,
+            ),
+            (
+                c.contains(RsaCapability::SIGN_PKCS),
+                EnclaveCapabilities::SIGN_PKCS,
+            ),
+            (
+                c.contains(RsaCapability::VERIFY_PKCS),
+                EnclaveCapabilities::VERIFY_PKCS,
+            ),
+            (
+                c.contains(RsaCapability::EXPORTABLE_WHEN_WRAPPED),
+                EnclaveCapabilities::EXPORT_WRAPPED_KEY,
+            ),
+        ]),
+    }
+}
+
+fn invalid(msg: String) -> EnclaveError {
+    EnclaveErrorKind::InvalidCapabilities { msg }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::{
+        null::NullEnclave, shared::SharedEnclave, AesSizes, EcCurves, EcdsaAlgorithm, EnclaveLike,
+        HmacAlgorithm, RsaMgf,
+    };
+
+    const HMAC: EnclaveKeyType = EnclaveKeyType::Hmac(HmacAlgorithm::Sha256);
+    const XCHACHA: EnclaveKeyType = EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305);
+
+    fn symmetric(c: SymmetricCapability) -> KeyCapabilities {
+        KeyCapabilities::Symmetric(c)
+    }
+
+    fn ecc(c: EccCapability) -> KeyCapabilities {
+        KeyCapabilities::Ecc(c)
+    }
+
+    fn rsa(c: RsaCapability) -> KeyCapabilities {
+        KeyCapabilities::Rsa(c)
+    }
+
+    #[test]
+    fn capabilities_that_fit_the_key_type() {
+        let accepted = [
+            (HMAC, symmetric(SymmetricCapability::HMAC_SIGN)),
+            (
+                HMAC,
+                symmetric(
+                    SymmetricCapability::HMAC_SIGN
+                        | SymmetricCapability::HMAC_VERIFY
+                        | SymmetricCapability::EXPORTABLE_WHEN_WRAPPED,
+                ),
+            ),
+            (
+                XCHACHA,
+                symmetric(SymmetricCapability::ENCRYPT | SymmetricCapability::DECRYPT),
+            ),
+            (XCHACHA, symmetric(SymmetricCapability::EXPORT_WRAPPED)),
+            (EnclaveKeyType::Ed25519, ecc(EccCapability::SIGN)),
+            (
+                EnclaveKeyType::Ecdsa(EcCurves::Secp256r1, EcdsaAlgorithm::Sha256),
+                ecc(EccCapability::SIGN | EccCapability::VERIFY),
+            ),
+            (
+                EnclaveKeyType::X25519,
+                ecc(EccCapability::DERIVE_DIFFIE_HELLMAN),
+            ),
+            (
+                EnclaveKeyType::Ecdh(EcCurves::Secp384r1),
+                ecc(EccCapability::DERIVE_DIFFIE_HELLMAN),
+            ),
+            (
+                EnclaveKeyType::RsaOaep(RsaMgf::Sha256),
+                rsa(RsaCapability::ENCRYPT_OAEP | RsaCapability::DECRYPT_OAEP),
+            ),
+            (
+                EnclaveKeyType::RsaPss(RsaMgf::Sha256),
+                rsa(RsaCapability::SIGN_PSS),
+            ),
+            (
+                EnclaveKeyType::RsaPkcs15(RsaMgf::Sha256),
+                rsa(RsaCapability::VERIFY_PKCS),
+            ),
+        ];
+        for (key_type, capabilities) in &accepted {
+            assert!(
+                capabilities.check(*key_type).is_ok(),
+                "{:?} {:?}",
+                key_type,
+                capabilities
+            );
+        }
+    }
+
+    #[test]
+    fn capabilities_that_dont_fit_the_key_type() {
+        let rejected = [
+            (HMAC, symmetric(SymmetricCapability::ENCRYPT)),
+            (
+                HMAC,
+                symmetric(SymmetricCapability::HMAC_SIGN | SymmetricCapability::DECRYPT),
+            ),
+            (
+                HMAC,
+                symmetric(SymmetricCapability::EXPORTABLE_WHEN_WRAPPED),
+            ),
+            (HMAC, symmetric(SymmetricCapability::empty())),
+            (HMAC, ecc(EccCapability::SIGN)),
+            (XCHACHA, symmetric(SymmetricCapability::HMAC_SIGN)),
+            (XCHACHA, rsa(RsaCapability::ENCRYPT_OAEP)),
+            (
+                EnclaveKeyType::Ed25519,
+                ecc(EccCapability::DERIVE_DIFFIE_HELLMAN),
+            ),
+            (EnclaveKeyType::X25519, ecc(EccCapability::SIGN)),
+            (
+                EnclaveKeyType::Ecdh(EcCurves::Secp256r1),
+                symmetric(SymmetricCapability::ENCRYPT),
+            ),
+            (
+                EnclaveKeyType::RsaOaep(RsaMgf::Sha256),
+                rsa(RsaCapability::SIGN_PSS),
+            ),
+            (
+                EnclaveKeyType::RsaPss(RsaMgf::Sha256),
+                rsa(RsaCapability::SIGN_PKCS),
+            ),
+            (
+                EnclaveKeyType::RsaPkcs15(RsaMgf::Sha256),
+                rsa(RsaCapability::EXPORTABLE_WHEN_WRAPPED),
+            ),
+        ];
+        for (key_type, capabilities) in &rejected {
+            match capabilities.check(*key_type).map_err(|e| e.kind()) {
+                Err(EnclaveErrorKind::InvalidCapabilities { .. }) => {}
+                other => panic!("{:?} {:?}: {:?}", key_type, capabilities, other),
+            }
+        }
+    }
+
+    #[test]
+    fn enclave_must_support_the_key() {
+        let enclave = EnclaveCapabilities::GENERATE_HMAC_KEY | EnclaveCapabilities::SIGN_HMAC;
+        let sign = symmetric(SymmetricCapability::HMAC_SIGN);
+        assert!(enclave.check_key(HMAC, sign, KeyOrigin::Generated).is_ok());
+
+        let unsupported = [
+            (HMAC, sign, KeyOrigin::Imported),
+            (
+                HMAC,
+                symmetric(SymmetricCapability::HMAC_SIGN | SymmetricCapability::HMAC_VERIFY),
+                KeyOrigin::Generated,
+            ),
+            (
+                HMAC,
+                symmetric(
+                    SymmetricCapability::HMAC_SIGN | SymmetricCapability::EXPORTABLE_WHEN_WRAPPED,
+                ),
+                KeyOrigin::Generated,
+            ),
+            (
+                XCHACHA,
+                symmetric(SymmetricCapability::ENCRYPT),
+                KeyOrigin::Generated,
+            ),
+        ];
+        for (key_type, capabilities, origin) in &unsupported {
+            assert_eq!(
+                enclave
+                    .check_key(*key_type, *capabilities, *origin)
+                    .unwrap_err()
+                    .kind(),
+                EnclaveErrorKind::UnsupportedOperation,
+                "{:?} {:?} {:?}",
+                key_type,
+                capabilities,
+                origin
+            );
+        }
+        // Capabilities that don't fit the key type are reported first
+        match enclave
+            .check_key(
+                HMAC,
+                symmetric(SymmetricCapability::ENCRYPT),
+                KeyOrigin::Generated,
+            )
+            .map_err(|e| e.kind())
+        {
+            Err(EnclaveErrorKind::InvalidCapabilities { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn shared_enclaves_check_every_key() {
+        let enclave = SharedEnclave::new(NullEnclave);
+        let encrypt = symmetric(SymmetricCapability::ENCRYPT);
+        assert!(enclave.create_key("key", XCHACHA, encrypt).is_ok());
+        assert!(enclave
+            .import_key("key", XCHACHA, encrypt, &[0; 32])
+            .is_ok());
+        match enclave
+            .create_key("key", HMAC, encrypt)
+            .map_err(|e| e.kind())
+        {
+            Err(EnclaveErrorKind::InvalidCapabilities { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            enclave
+                .import_key(
+                    "key",
+                    EnclaveKeyType::Ed25519,
+                    ecc(EccCapability::SIGN),
+                    &[0; 32]
+                )
+                .unwrap_err()
+                .kind(),
+            EnclaveErrorKind::UnsupportedOperation
+        );
+    }
+
+    #[test]
+    fn imported_keys_are_checked() {
+        let aes = EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::GcmSiv));
+        match NullEnclave
+            .import_key(
+                "aes",
+                aes,
+                symmetric(SymmetricCapability::HMAC_SIGN),
+                &[0; 32],
+            )
+            .map_err(|e| e.kind())
+        {
+            Err(EnclaveErrorKind::InvalidCapabilities { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+}
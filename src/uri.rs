@@ -0,0 +1,257 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Splitting the URIs that name enclaves and persistence backends.
+//!
+//! Components are returned raw and attribute names and values are
+//! percent-decoded. Errors are plain messages that each caller turns
+//! into its own error kind.
+
+/// A URI split into its components
+pub(crate) struct Uri<'a> {
+    /// The scheme in lower case
+    pub scheme: String,
+    /// What follows `//` up to the path, if there is a `//`
+    pub authority: Option<&'a str>,
+    /// Everything between the scheme or authority and the query
+    pub path: &'a str,
+    /// The attributes after `?`
+    pub query: Attributes,
+}
+
+impl<'a> Uri<'a> {
+    pub fn parse(uri: &'a str) -> Result<Self, String> {
+        let scheme = scheme(uri).ok_or_else(|| format!("{:?} is not a URI", uri))?;
+        let rest = &uri[scheme.len() + 1..];
+        let (rest, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Attributes::parse(&rest[i + 1..], '&')?),
+            None => (rest, Attributes::default()),
+        };
+        let (authority, path) = match rest.strip_prefix("//") {
+            Some(r) => {
+                let (authority, path) = r.split_at(r.find('/').unwrap_or(r.len()));
+                (Some(authority), path)
+            }
+            None => (None, rest),
+        };
+        Ok(Self {
+            scheme: scheme.to_ascii_lowercase(),
+            authority,
+            path,
+            query,
+        })
+    }
+
+    /// The decoded path of a URI that must be local like `scheme:///path`
+    pub fn local_path(&self) -> Result<String, String> {
+        match self.authority {
+            None | Some("") | Some("localhost") => percent_decode(self.path),
+            Some(host) => Err(format!("A {} URI can't be on {}", self.scheme, host)),
+        }
+    }
+}
+
+/// The scheme of `uri` as written
+pub(crate) fn scheme(uri: &str) -> Option<&str> {
+    let scheme = &uri[..uri.find(':')?];
+    let mut chars = scheme.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {}
+        _ => return None,
+    }
+    if chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.') {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// Decoded `name=value` attributes that are removed as they are read
+#[derive(Default)]
+pub(crate) struct Attributes(Vec<(String, String)>);
+
+impl Attributes {
+    pub fn parse(s: &str, separator: char) -> Result<Self, String> {
+        let mut attributes = Vec::new();
+        for attribute in s.split(separator).filter(|a| !a.is_empty()) {
+            let (name, value) = match attribute.find('=') {
+                Some(i) => (&attribute[..i], &attribute[i + 1..]),
+                None => (attribute, ""),
+            };
+            attributes.push((percent_decode(name)?, percent_decode(value)?));
+        }
+        Ok(Self(attributes))
+    }
+
+    pub fn take(&mut self, name: &str) -> Result<Option<String>, String> {
+        if self.0.iter().filter(|(n, _)| n == name).count() > 1 {
+            return Err(format!("{} is given more than once", name));
+        }
+        Ok(self
+            .0
+            .iter()
+            .position(|(n, _)| n == name)
+            .map(|i| self.0.remove(i).1))
+    }
+
+    /// Read `name` as a number
+    pub fn take_parsed<T: std::str::FromStr>(&mut self, name: &str) -> Result<Option<T>, String> {
+        match self.take(name)? {
+            Some(v) => v
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{:?} is not a valid {}", v, name)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fail if there are attributes that were not read
+    pub fn finish(self) -> Result<(), String> {
+        match self.0.first() {
+            Some((name, _)) => Err(format!("{} is not supported", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn percent_decode(s: &str) -> Result<String, String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s
+                .get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| format!("{:?} has an invalid percent-encoding", s))?;
+            decoded.push(hex);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("{:?} is not UTF-8", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_components() {
+        let table: &[(&str, &str, Option<&str>, &str)] = &[
+            (
+                "sqlite:///tmp/agent.db",
+                "sqlite",
+                Some(""),
+                "/tmp/agent.db",
+            ),
+            (
+                "SQLite://localhost/tmp/agent.db",
+                "sqlite",
+                Some("localhost"),
+                "/tmp/agent.db",
+            ),
+            ("s3://bucket/prefix/", "s3", Some("bucket"), "/prefix/"),
+            ("s3://bucket", "s3", Some("bucket"), ""),
+            ("memory:", "memory", None, ""),
+            (
+                "pkcs11:token=Agent;slot-id=0",
+                "pkcs11",
+                None,
+                "token=Agent;slot-id=0",
+            ),
+            ("a+b.c-d:x?", "a+b.c-d", None, "x"),
+        ];
+        for (uri, scheme, authority, path) in table {
+            let parsed = Uri::parse(uri).unwrap();
+            assert_eq!(&parsed.scheme, scheme, "{}", uri);
+            assert_eq!(parsed.authority, *authority, "{}", uri);
+            assert_eq!(parsed.path, *path, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_uri() {
+        for uri in &[
+            "",
+            "no scheme",
+            "/tmp/agent.db",
+            ":x",
+            "1abc:x",
+            "a b:x",
+            "a_b:x",
+        ] {
+            assert!(Uri::parse(uri).is_err(), "{:?}", uri);
+        }
+    }
+
+    #[test]
+    fn local_paths() {
+        let table: &[(&str, Option<&str>)] = &[
+            ("sqlite:///tmp/agent.db", Some("/tmp/agent.db")),
+            ("sqlite://localhost/tmp/agent.db", Some("/tmp/agent.db")),
+            ("sqlite:relative.db", Some("relative.db")),
+            ("sqlite:///tmp/a%20b%3Fc.db", Some("/tmp/a b?c.db")),
+            ("sqlite://host/tmp/agent.db", None),
+            ("sqlite:///tmp/%zz.db", None),
+        ];
+        for (uri, expected) in table {
+            let path = Uri::parse(uri).unwrap().local_path();
+            assert_eq!(path.ok().as_deref(), *expected, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn attributes() {
+        let mut uri = Uri::parse("x:?a=1&b=&c&d=%41%3d%26&e=%C3%A9").unwrap();
+        assert_eq!(uri.query.take("a").unwrap().as_deref(), Some("1"));
+        assert_eq!(uri.query.take("a").unwrap(), None);
+        assert_eq!(uri.query.take("b").unwrap().as_deref(), Some(""));
+        assert_eq!(uri.query.take("c").unwrap().as_deref(), Some(""));
+        assert_eq!(uri.query.take("d").unwrap().as_deref(), Some("A=&"));
+        assert_eq!(uri.query.take("e").unwrap().as_deref(), Some("é"));
+        assert_eq!(uri.query.take("missing").unwrap(), None);
+        assert!(uri.query.finish().is_ok());
+
+        let mut attributes = Attributes::parse("n=12;m=x;;o=1", ';').unwrap();
+        assert_eq!(attributes.take_parsed::<u16>("n").unwrap(), Some(12));
+        assert!(attributes.take_parsed::<u16>("m").is_err());
+        assert_eq!(attributes.take_parsed::<u16>("p").unwrap(), None);
+        assert_eq!(attributes.finish().unwrap_err(), "o is not supported");
+
+        let mut twice = Attributes::parse("a=1&a=2", '&').unwrap();
+        assert!(twice.take("a").is_err());
+    }
+
+    #[test]
+    fn percent_decoding() {
+        let table: &[(&str, Option<&str>)] = &[
+            ("plain", Some("plain")),
+            ("%2F%2f", Some("//")),
+            ("100%25", Some("100%")),
+            ("%", None),
+            ("%4", None),
+            ("%g0", None),
+            ("%FF", None),
+            ("%C3", None),
+        ];
+        for (s, expected) in table {
+            assert_eq!(percent_decode(s).ok().as_deref(), *expected, "{}", s);
+        }
+    }
+}
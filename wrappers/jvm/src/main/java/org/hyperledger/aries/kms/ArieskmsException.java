@@ -0,0 +1,40 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+/** Thrown for every error returned by the library */
+public class ArieskmsException extends RuntimeException {
+    private static final long serialVersionUID = 1L;
+
+    private final int value;
+
+    /** Called by the native library */
+    public ArieskmsException(int value, String message) {
+        super(message);
+        this.value = value;
+    }
+
+    /** The error kind, or {@code null} if this version doesn't know it */
+    public ErrorCode getCode() {
+        return ErrorCode.fromValue(value);
+    }
+
+    /** The numeric code returned by the C API */
+    public int getValue() {
+        return value;
+    }
+}
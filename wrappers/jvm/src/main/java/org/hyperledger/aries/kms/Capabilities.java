@@ -0,0 +1,45 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+/** Key capability bits combined with {@code |} */
+public final class Capabilities {
+    private Capabilities() {}
+
+    /** Capabilities of symmetric keys */
+    public static final class Symmetric {
+        private Symmetric() {}
+
+        public static final int ENCRYPT = 0x0001;
+        public static final int DECRYPT = 0x0002;
+        public static final int HMAC_SIGN = 0x0004;
+        public static final int HMAC_VERIFY = 0x0008;
+        public static final int EXPORT_WRAPPED = 0x0010;
+        public static final int IMPORT_WRAPPED = 0x0020;
+        public static final int EXPORTABLE_WHEN_WRAPPED = 0x0100;
+    }
+
+    /** Capabilities of Ed25519, X25519 and ECDSA keys */
+    public static final class Ecc {
+        private Ecc() {}
+
+        public static final int SIGN = 0x0001;
+        public static final int VERIFY = 0x0002;
+        public static final int DERIVE_DIFFIE_HELLMAN = 0x0004;
+        public static final int EXPORTABLE_WHEN_WRAPPED = 0x0100;
+    }
+}
@@ -0,0 +1,59 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+/** An enclave holding keys. Keys and stores opened from it keep working after it is closed. */
+public final class Enclave implements AutoCloseable {
+    final Handle handle;
+
+    private Enclave(long handle) {
+        this.handle = new Handle("enclave", handle, Native::enclaveFree);
+    }
+
+    /**
+     * Open the enclave named by {@code uri} like {@code software:}. Keys of the software enclave
+     * only live in memory until the enclave and everything opened from it is closed.
+     */
+    public static Enclave open(String uri) {
+        return new Enclave(Native.enclaveOpen(uri));
+    }
+
+    /**
+     * Open the OS keyring at {@code path}, or the user's default keyring if it is {@code null},
+     * unlocking it with {@code password} if it isn't {@code null}
+     */
+    public static Enclave openOsKeyRing(String path, String password) {
+        return new Enclave(
+                Native.feature("the OS keyring", () -> Native.enclaveOpenOsKeyRing(path, password)));
+    }
+
+    /** Generate the key {@code id} with the {@link Capabilities} bits {@code capabilities} */
+    public Key generateKey(String id, KeyType keyType, int capabilities) {
+        return new Key(
+                handle.use(h -> Native.keyGenerate(h, id, keyType.ordinal(), capabilities)));
+    }
+
+    /** Open the existing key {@code id}. The enclave reports a missing key when it is first used. */
+    public Key key(String id) {
+        return new Key(handle.use(h -> Native.keyOpen(h, id)));
+    }
+
+    @Override
+    public void close() {
+        handle.close();
+    }
+}
@@ -0,0 +1,70 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+/** Mirrors {@code ArieskmsErrorCode} in include/arieskms.h */
+public enum ErrorCode {
+    INVALID_ARGUMENT(1),
+    PANIC(2),
+    ENCLAVE_CONNECTION_FAILURE(100),
+    ENCLAVE_ACCESS_DENIED(101),
+    ENCLAVE_ITEM_NOT_FOUND(102),
+    ENCLAVE_UNSUPPORTED_OPERATION(103),
+    ENCLAVE_GENERAL_ERROR(104),
+    ENCLAVE_INVALID_CAPABILITIES(105),
+    PERSISTENCE_INVALID_CONFIG(200),
+    PERSISTENCE_IO_ERROR(201),
+    PERSISTENCE_ITEM_NOT_FOUND(202),
+    PERSISTENCE_DUPLICATE_ITEM(203),
+    PERSISTENCE_SERIALIZATION_ERROR(204),
+    PERSISTENCE_INVALID_QUERY(205),
+    PERSISTENCE_CONFLICT(206),
+    PERSISTENCE_UNSUPPORTED_VERSION(207),
+    PERSISTENCE_UNSUPPORTED_OPERATION(208),
+    PROTECTION_INVALID_DATA(300);
+
+    private final int value;
+
+    ErrorCode(int value) {
+        this.value = value;
+    }
+
+    /** The numeric code returned by the C API */
+    public int value() {
+        return value;
+    }
+
+    /** The code for {@code value}, or {@code null} if this version doesn't know it */
+    public static ErrorCode fromValue(int value) {
+        for (ErrorCode code : values()) {
+            if (code.value == value) {
+                return code;
+            }
+        }
+        return null;
+    }
+
+    /** Whether the error came from the enclave */
+    public boolean isEnclaveError() {
+        return value >= 100 && value < 200;
+    }
+
+    /** Whether the error came from the persistence backend */
+    public boolean isPersistenceError() {
+        return value >= 200 && value < 300;
+    }
+}
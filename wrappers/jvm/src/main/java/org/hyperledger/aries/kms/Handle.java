@@ -0,0 +1,59 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+import java.util.concurrent.locks.ReentrantReadWriteLock;
+import java.util.function.LongConsumer;
+import java.util.function.LongFunction;
+
+/** A native handle that can be used from many threads and is freed once no call is using it */
+final class Handle {
+    private final ReentrantReadWriteLock lock = new ReentrantReadWriteLock();
+    private final String name;
+    private final LongConsumer free;
+    private long value;
+
+    Handle(String name, long value, LongConsumer free) {
+        this.name = name;
+        this.value = value;
+        this.free = free;
+    }
+
+    /** Call {@code f} with the handle */
+    <T> T use(LongFunction<T> f) {
+        lock.readLock().lock();
+        try {
+            if (value == 0) {
+                throw new IllegalStateException("The " + name + " is closed");
+            }
+            return f.apply(value);
+        } finally {
+            lock.readLock().unlock();
+        }
+    }
+
+    /** Free the handle once calls using it return */
+    void close() {
+        lock.writeLock().lock();
+        try {
+            free.accept(value);
+            value = 0;
+        } finally {
+            lock.writeLock().unlock();
+        }
+    }
+}
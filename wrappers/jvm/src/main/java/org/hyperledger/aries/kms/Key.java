@@ -0,0 +1,47 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+/** A key held by an enclave */
+public final class Key implements AutoCloseable {
+    private final Handle handle;
+
+    Key(long handle) {
+        this.handle = new Handle("key", handle, Native::keyFree);
+    }
+
+    /** Encrypt {@code plaintext} authenticating {@code aad}, which may be {@code null} */
+    public byte[] encrypt(byte[] plaintext, byte[] aad) {
+        return handle.use(h -> Native.keyEncrypt(h, plaintext, aad));
+    }
+
+    /** Decrypt {@code ciphertext} authenticating {@code aad}, which may be {@code null} */
+    public byte[] decrypt(byte[] ciphertext, byte[] aad) {
+        return handle.use(h -> Native.keyDecrypt(h, ciphertext, aad));
+    }
+
+    /** Compute the HMAC of {@code data} */
+    public byte[] signHmac(byte[] data) {
+        return handle.use(h -> Native.keySignHmac(h, data));
+    }
+
+    /** Release the key. It stays in the enclave. */
+    @Override
+    public void close() {
+        handle.close();
+    }
+}
@@ -0,0 +1,37 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+/** The key types that can be generated. Mirrors {@code ArieskmsKeyType} in include/arieskms.h */
+public enum KeyType {
+    ED25519,
+    X25519,
+    ECDSA_P256,
+    ECDSA_P384,
+    ECDSA_SECP256K1,
+    XCHACHA20_POLY1305,
+    AES128_GCM,
+    AES256_GCM,
+    AES256_GCM_SIV,
+    HMAC_SHA256,
+    HMAC_SHA512;
+
+    /** Whether capabilities are {@link Capabilities.Ecc} rather than {@link Capabilities.Symmetric} bits */
+    public boolean isEcc() {
+        return ordinal() <= ECDSA_SECP256K1.ordinal();
+    }
+}
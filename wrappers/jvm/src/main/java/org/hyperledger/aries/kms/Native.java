@@ -0,0 +1,101 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+/** Native methods implemented by the arieskms_jni library */
+final class Native {
+    /** Set to the path of the library to load instead of searching java.library.path */
+    static final String LIBRARY_PROPERTY = "arieskms.library";
+
+    static {
+        String path = System.getProperty(LIBRARY_PROPERTY);
+        if (path != null) {
+            System.load(path);
+        } else {
+            System.loadLibrary("arieskms_jni");
+        }
+    }
+
+    private Native() {}
+
+    static native long enclaveOpen(String uri);
+
+    static native long enclaveOpenOsKeyRing(String path, String password);
+
+    static native void enclaveFree(long enclave);
+
+    static native long keyGenerate(long enclave, String id, int keyType, int capabilities);
+
+    static native long keyOpen(long enclave, String id);
+
+    static native byte[] keyEncrypt(long key, byte[] plaintext, byte[] aad);
+
+    static native byte[] keyDecrypt(long key, byte[] ciphertext, byte[] aad);
+
+    static native byte[] keySignHmac(long key, byte[] data);
+
+    static native void keyFree(long key);
+
+    static native long storeOpenSled(long enclave, String path, String keyPrefix, boolean generateKeys);
+
+    static native long storeOpenSqlite(long enclave, String path, String keyPrefix, boolean generateKeys);
+
+    static native long storeOpenS3(
+            long enclave,
+            String endpoint,
+            String bucket,
+            String region,
+            String accessKeyId,
+            String secretAccessKey,
+            String keyPrefix,
+            boolean generateKeys);
+
+    static native void storeInsert(
+            long store,
+            byte[] category,
+            byte[] name,
+            byte[] value,
+            byte[][] tagNames,
+            byte[][] tagValues,
+            boolean[] tagPlaintext);
+
+    static native void storeUpdate(
+            long store,
+            byte[] category,
+            byte[] name,
+            byte[] value,
+            byte[][] tagNames,
+            byte[][] tagValues,
+            boolean[] tagPlaintext);
+
+    static native byte[] storeFetch(long store, byte[] category, byte[] name);
+
+    static native void storeDelete(long store, byte[] category, byte[] name);
+
+    static native void storeFree(long store);
+
+    /** Call a native method a library built without its feature doesn't have */
+    static <T> T feature(String feature, java.util.function.Supplier<T> call) {
+        try {
+            return call.get();
+        } catch (UnsatisfiedLinkError e) {
+            throw new ArieskmsException(
+                    ErrorCode.ENCLAVE_UNSUPPORTED_OPERATION.value(),
+                    "The arieskms_jni library was built without " + feature);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+import java.nio.charset.StandardCharsets;
+
+/** A record tag. Encrypted tags only support equality lookups. Plaintext tags are persisted as is. */
+public final class RecordTag {
+    private final byte[] name;
+    private final byte[] value;
+    private final boolean plaintext;
+
+    public RecordTag(byte[] name, byte[] value, boolean plaintext) {
+        this.name = name.clone();
+        this.value = value.clone();
+        this.plaintext = plaintext;
+    }
+
+    /** An encrypted tag */
+    public static RecordTag encrypted(String name, String value) {
+        return new RecordTag(
+                name.getBytes(StandardCharsets.UTF_8), value.getBytes(StandardCharsets.UTF_8), false);
+    }
+
+    /** A plaintext tag */
+    public static RecordTag plaintext(String name, String value) {
+        return new RecordTag(
+                name.getBytes(StandardCharsets.UTF_8), value.getBytes(StandardCharsets.UTF_8), true);
+    }
+
+    public byte[] getName() {
+        return name.clone();
+    }
+
+    public byte[] getValue() {
+        return value.clone();
+    }
+
+    public boolean isPlaintext() {
+        return plaintext;
+    }
+}
@@ -0,0 +1,163 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+package org.hyperledger.aries.kms;
+
+import java.nio.charset.StandardCharsets;
+import java.util.Collections;
+import java.util.List;
+
+/**
+ * A persistence backend protected by the keys starting with {@code keyPrefix}. Pass {@code
+ * generateKeys} to generate the keys when the store is new.
+ */
+public final class Store implements AutoCloseable {
+    private final Handle handle;
+
+    private Store(long handle) {
+        this.handle = new Handle("store", handle, Native::storeFree);
+    }
+
+    /** Open the sled database at {@code path}, or a temporary one if it is {@code null} */
+    public static Store openSled(Enclave enclave, String path, String keyPrefix, boolean generateKeys) {
+        return new Store(
+                enclave.handle.use(
+                        e ->
+                                Native.feature(
+                                        "storage-sled",
+                                        () -> Native.storeOpenSled(e, path, keyPrefix, generateKeys))));
+    }
+
+    /** Open the SQLite database at {@code path}, or an in memory one if it is {@code null} */
+    public static Store openSqlite(
+            Enclave enclave, String path, String keyPrefix, boolean generateKeys) {
+        return new Store(
+                enclave.handle.use(
+                        e ->
+                                Native.feature(
+                                        "storage-sqlite",
+                                        () -> Native.storeOpenSqlite(e, path, keyPrefix, generateKeys))));
+    }
+
+    /** Open the S3 bucket {@code bucket} at {@code endpoint} */
+    public static Store openS3(
+            Enclave enclave,
+            String endpoint,
+            String bucket,
+            String region,
+            String accessKeyId,
+            String secretAccessKey,
+            String keyPrefix,
+            boolean generateKeys) {
+        return new Store(
+                enclave.handle.use(
+                        e ->
+                                Native.feature(
+                                        "storage-s3",
+                                        () ->
+                                                Native.storeOpenS3(
+                                                        e,
+                                                        endpoint,
+                                                        bucket,
+                                                        region,
+                                                        accessKeyId,
+                                                        secretAccessKey,
+                                                        keyPrefix,
+                                                        generateKeys))));
+    }
+
+    /** Save a new record */
+    public void insert(byte[] category, byte[] name, byte[] value, List<RecordTag> tags) {
+        Tags t = new Tags(tags);
+        handle.use(
+                h -> {
+                    Native.storeInsert(h, category, name, value, t.names, t.values, t.plaintext);
+                    return null;
+                });
+    }
+
+    /** Save a new record without tags */
+    public void insert(String category, String name, byte[] value) {
+        insert(utf8(category), utf8(name), value, Collections.emptyList());
+    }
+
+    /** Replace the value and tags of an existing record */
+    public void update(byte[] category, byte[] name, byte[] value, List<RecordTag> tags) {
+        Tags t = new Tags(tags);
+        handle.use(
+                h -> {
+                    Native.storeUpdate(h, category, name, value, t.names, t.values, t.plaintext);
+                    return null;
+                });
+    }
+
+    /** Replace the value of an existing record and remove its tags */
+    public void update(String category, String name, byte[] value) {
+        update(utf8(category), utf8(name), value, Collections.emptyList());
+    }
+
+    /** Fetch the value of a record */
+    public byte[] fetch(byte[] category, byte[] name) {
+        return handle.use(h -> Native.storeFetch(h, category, name));
+    }
+
+    /** Fetch the value of a record */
+    public byte[] fetch(String category, String name) {
+        return fetch(utf8(category), utf8(name));
+    }
+
+    /** Delete a record */
+    public void delete(byte[] category, byte[] name) {
+        handle.use(
+                h -> {
+                    Native.storeDelete(h, category, name);
+                    return null;
+                });
+    }
+
+    /** Delete a record */
+    public void delete(String category, String name) {
+        delete(utf8(category), utf8(name));
+    }
+
+    /** Close and release the store */
+    @Override
+    public void close() {
+        handle.close();
+    }
+
+    private static byte[] utf8(String s) {
+        return s.getBytes(StandardCharsets.UTF_8);
+    }
+
+    /** Tags split into the arrays the native methods take */
+    private static final class Tags {
+        final byte[][] names;
+        final byte[][] values;
+        final boolean[] plaintext;
+
+        Tags(List<RecordTag> tags) {
+            names = new byte[tags.size()][];
+            values = new byte[tags.size()][];
+            plaintext = new boolean[tags.size()];
+            for (int i = 0; i < tags.size(); i++) {
+                names[i] = tags.get(i).getName();
+                values[i] = tags.get(i).getValue();
+                plaintext[i] = tags.get(i).isPlaintext();
+            }
+        }
+    }
+}
@@ -0,0 +1,621 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! JNI glue between `org.hyperledger.aries.kms.Native` and the C API.
+//!
+//! Java objects keep the C API handles as `long`s. Errors returned by the
+//! C API are thrown as `ArieskmsException` with the numeric error code.
+#![deny(warnings, unused_import_braces, unused_qualifications)]
+
+use arieskms::ffi::{
+    arieskms_buffer_free, arieskms_last_error_message,
+    enclave::{
+        arieskms_enclave_free, arieskms_enclave_open, arieskms_key_decrypt,
+        arieskms_key_encrypt, arieskms_key_free, arieskms_key_generate, arieskms_key_open,
+        arieskms_key_sign_hmac, ArieskmsEnclave, ArieskmsKey,
+    },
+    store::{
+        arieskms_store_delete, arieskms_store_fetch, arieskms_store_free, arieskms_store_insert,
+        arieskms_store_update, ArieskmsStore, ArieskmsTag,
+    },
+    ArieskmsBuffer, ArieskmsErrorCode, ArieskmsSlice,
+};
+use jni::{
+    objects::{JBooleanArray, JByteArray, JClass, JObjectArray, JString, JThrowable, JValue},
+    sys::{jboolean, jbyteArray, jint, jlong, JNI_FALSE},
+    JNIEnv,
+};
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    ptr,
+};
+
+const EXCEPTION_CLASS: &str = "org/hyperledger/aries/kms/ArieskmsException";
+
+/// Why a native method failed
+enum Failure {
+    /// The C API returned an error code
+    Code(ArieskmsErrorCode, String),
+    /// A JNI call failed and may have left a Java exception pending
+    Jni(jni::errors::Error),
+}
+
+impl From<jni::errors::Error> for Failure {
+    fn from(e: jni::errors::Error) -> Self {
+        Failure::Jni(e)
+    }
+}
+
+type JniResult<T> = Result<T, Failure>;
+
+/// Turn the result of a C API call into an error with the last message
+fn check(code: ArieskmsErrorCode) -> JniResult<()> {
+    if code == ArieskmsErrorCode::Success {
+        return Ok(());
+    }
+    let message = arieskms_last_error_message();
+    let message = if message.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Err(Failure::Code(code, message))
+}
+
+/// Run `f` and throw its error as a Java exception, returning `default`
+fn run<'local, T, F>(env: &mut JNIEnv<'local>, default: T, f: F) -> T
+where
+    F: FnOnce(&mut JNIEnv<'local>) -> JniResult<T>,
+{
+    match f(env) {
+        Ok(value) => value,
+        Err(failure) => {
+            if !env.exception_check().unwrap_or(true) {
+                throw(env, failure);
+            }
+            default
+        }
+    }
+}
+
+fn throw(env: &mut JNIEnv<'_>, failure: Failure) {
+    let (code, message) = match failure {
+        Failure::Code(code, message) => (code as jint, message),
+        Failure::Jni(e) => (ArieskmsErrorCode::InvalidArgument as jint, e.to_string()),
+    };
+    let thrown = env.new_string(message).and_then(|message| {
+        let exception = env.new_object(
+            EXCEPTION_CLASS,
+            "(ILjava/lang/String;)V",
+            &[JValue::Int(code), JValue::Object(&message)],
+        )?;
+        env.throw(JThrowable::from(exception))
+    });
+    if thrown.is_err() && !env.exception_check().unwrap_or(true) {
+        let _ = env.throw_new(
+            "java/lang/RuntimeException",
+            "Unable to throw ArieskmsException",
+        );
+    }
+}
+
+/// A nul terminated copy of `s`, or `None` if it is null
+fn string(env: &mut JNIEnv<'_>, s: &JString<'_>) -> JniResult<Option<CString>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    let s: String = env.get_string(s)?.into();
+    CString::new(s).map(Some).map_err(|_| {
+        Failure::Code(
+            ArieskmsErrorCode::InvalidArgument,
+            "Strings can't contain nul".into(),
+        )
+    })
+}
+
+fn optional_ptr(s: &Option<CString>) -> *const c_char {
+    s.as_ref().map(|s| s.as_ptr()).unwrap_or_else(ptr::null)
+}
+
+/// A copy of the Java array `array`, empty if it is null
+fn bytes(env: &JNIEnv<'_>, array: &JByteArray<'_>) -> JniResult<Vec<u8>> {
+    if array.is_null() {
+        Ok(Vec::new())
+    } else {
+        Ok(env.convert_byte_array(array)?)
+    }
+}
+
+fn slice(bytes: &[u8]) -> ArieskmsSlice {
+    ArieskmsSlice {
+        data: bytes.as_ptr(),
+        len: bytes.len(),
+    }
+}
+
+/// Copy `buffer` to a Java array and release it
+fn buffer(env: &JNIEnv<'_>, buffer: ArieskmsBuffer) -> JniResult<jbyteArray> {
+    let bytes = if buffer.len == 0 {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(buffer.data, buffer.len) }
+    };
+    let array = env.byte_array_from_slice(bytes);
+    unsafe { arieskms_buffer_free(buffer) };
+    Ok(array?.into_raw())
+}
+
+fn empty_buffer() -> ArieskmsBuffer {
+    ArieskmsBuffer {
+        data: ptr::null_mut(),
+        len: 0,
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_enclaveOpen(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    uri: JString<'_>,
+) -> jlong {
+    run(&mut env, 0, |env| {
+        let uri = string(env, &uri)?;
+        let mut enclave = ptr::null_mut();
+        check(unsafe { arieskms_enclave_open(optional_ptr(&uri), &mut enclave) })?;
+        Ok(enclave as jlong)
+    })
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_enclaveOpenOsKeyRing(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    path: JString<'_>,
+    password: JString<'_>,
+) -> jlong {
+    use arieskms::ffi::enclave::arieskms_enclave_open_os_keyring;
+    run(&mut env, 0, |env| {
+        let path = string(env, &path)?;
+        let password = string(env, &password)?;
+        let mut enclave = ptr::null_mut();
+        check(unsafe {
+            arieskms_enclave_open_os_keyring(
+                optional_ptr(&path),
+                optional_ptr(&password),
+                &mut enclave,
+            )
+        })?;
+        Ok(enclave as jlong)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_enclaveFree(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    enclave: jlong,
+) {
+    unsafe { arieskms_enclave_free(enclave as *mut ArieskmsEnclave) }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_keyGenerate(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    enclave: jlong,
+    id: JString<'_>,
+    key_type: jint,
+    capabilities: jint,
+) -> jlong {
+    run(&mut env, 0, |env| {
+        let id = string(env, &id)?;
+        let mut key = ptr::null_mut();
+        check(unsafe {
+            arieskms_key_generate(
+                enclave as *const ArieskmsEnclave,
+                optional_ptr(&id),
+                key_type as u32,
+                capabilities as u16,
+                &mut key,
+            )
+        })?;
+        Ok(key as jlong)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_keyOpen(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    enclave: jlong,
+    id: JString<'_>,
+) -> jlong {
+    run(&mut env, 0, |env| {
+        let id = string(env, &id)?;
+        let mut key = ptr::null_mut();
+        check(unsafe {
+            arieskms_key_open(
+                enclave as *const ArieskmsEnclave,
+                optional_ptr(&id),
+                &mut key,
+            )
+        })?;
+        Ok(key as jlong)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_keyEncrypt(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    key: jlong,
+    plaintext: JByteArray<'_>,
+    aad: JByteArray<'_>,
+) -> jbyteArray {
+    run(&mut env, ptr::null_mut(), |env| {
+        let (plaintext, aad) = (bytes(env, &plaintext)?, bytes(env, &aad)?);
+        let mut out = empty_buffer();
+        check(unsafe {
+            arieskms_key_encrypt(
+                key as *const ArieskmsKey,
+                slice(&plaintext),
+                slice(&aad),
+                &mut out,
+            )
+        })?;
+        buffer(env, out)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_keyDecrypt(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    key: jlong,
+    ciphertext: JByteArray<'_>,
+    aad: JByteArray<'_>,
+) -> jbyteArray {
+    run(&mut env, ptr::null_mut(), |env| {
+        let (ciphertext, aad) = (bytes(env, &ciphertext)?, bytes(env, &aad)?);
+        let mut out = empty_buffer();
+        check(unsafe {
+            arieskms_key_decrypt(
+                key as *const ArieskmsKey,
+                slice(&ciphertext),
+                slice(&aad),
+                &mut out,
+            )
+        })?;
+        buffer(env, out)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_keySignHmac(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    key: jlong,
+    data: JByteArray<'_>,
+) -> jbyteArray {
+    run(&mut env, ptr::null_mut(), |env| {
+        let data = bytes(env, &data)?;
+        let mut out = empty_buffer();
+        check(unsafe {
+            arieskms_key_sign_hmac(key as *const ArieskmsKey, slice(&data), &mut out)
+        })?;
+        buffer(env, out)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_keyFree(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    key: jlong,
+) {
+    unsafe { arieskms_key_free(key as *mut ArieskmsKey) }
+}
+
+#[cfg(feature = "storage-sled")]
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_storeOpenSled(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    enclave: jlong,
+    path: JString<'_>,
+    key_prefix: JString<'_>,
+    generate_keys: jboolean,
+) -> jlong {
+    use arieskms::ffi::store::arieskms_store_open_sled;
+    run(&mut env, 0, |env| {
+        let (path, key_prefix) = (string(env, &path)?, string(env, &key_prefix)?);
+        let mut store = ptr::null_mut();
+        check(unsafe {
+            arieskms_store_open_sled(
+                enclave as *const ArieskmsEnclave,
+                optional_ptr(&path),
+                optional_ptr(&key_prefix),
+                generate_keys != JNI_FALSE,
+                &mut store,
+            )
+        })?;
+        Ok(store as jlong)
+    })
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_storeOpenSqlite(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    enclave: jlong,
+    path: JString<'_>,
+    key_prefix: JString<'_>,
+    generate_keys: jboolean,
+) -> jlong {
+    use arieskms::ffi::store::arieskms_store_open_sqlite;
+    run(&mut env, 0, |env| {
+        let (path, key_prefix) = (string(env, &path)?, string(env, &key_prefix)?);
+        let mut store = ptr::null_mut();
+        check(unsafe {
+            arieskms_store_open_sqlite(
+                enclave as *const ArieskmsEnclave,
+                optional_ptr(&path),
+                optional_ptr(&key_prefix),
+                generate_keys != JNI_FALSE,
+                &mut store,
+            )
+        })?;
+        Ok(store as jlong)
+    })
+}
+
+#[cfg(feature = "storage-s3")]
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_storeOpenS3(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    enclave: jlong,
+    endpoint: JString<'_>,
+    bucket: JString<'_>,
+    region: JString<'_>,
+    access_key_id: JString<'_>,
+    secret_access_key: JString<'_>,
+    key_prefix: JString<'_>,
+    generate_keys: jboolean,
+) -> jlong {
+    use arieskms::ffi::store::arieskms_store_open_s3;
+    run(&mut env, 0, |env| {
+        let endpoint = string(env, &endpoint)?;
+        let bucket = string(env, &bucket)?;
+        let region = string(env, &region)?;
+        let access_key_id = string(env, &access_key_id)?;
+        let secret_access_key = string(env, &secret_access_key)?;
+        let key_prefix = string(env, &key_prefix)?;
+        let mut store = ptr::null_mut();
+        check(unsafe {
+            arieskms_store_open_s3(
+                enclave as *const ArieskmsEnclave,
+                optional_ptr(&endpoint),
+                optional_ptr(&bucket),
+                optional_ptr(&region),
+                optional_ptr(&access_key_id),
+                optional_ptr(&secret_access_key),
+                optional_ptr(&key_prefix),
+                generate_keys != JNI_FALSE,
+                &mut store,
+            )
+        })?;
+        Ok(store as jlong)
+    })
+}
+
+/// Copies of the tag names, values and plaintext flags passed from Java
+struct Tags {
+    names: Vec<Vec<u8>>,
+    values: Vec<Vec<u8>>,
+    plaintext: Vec<jboolean>,
+}
+
+impl Tags {
+    fn read(
+        env: &mut JNIEnv<'_>,
+        names: &JObjectArray<'_>,
+        values: &JObjectArray<'_>,
+        plaintext: &JBooleanArray<'_>,
+    ) -> JniResult<Self> {
+        let mut tags = Tags {
+            names: Vec::new(),
+            values: Vec::new(),
+            plaintext: Vec::new(),
+        };
+        if names.is_null() {
+            return Ok(tags);
+        }
+        let len = env.get_array_length(names)?;
+        for i in 0..len {
+            let name = JByteArray::from(env.get_object_array_element(names, i)?);
+            let value = JByteArray::from(env.get_object_array_element(values, i)?);
+            tags.names.push(bytes(env, &name)?);
+            tags.values.push(bytes(env, &value)?);
+        }
+        tags.plaintext = vec![JNI_FALSE; len as usize];
+        env.get_boolean_array_region(plaintext, 0, &mut tags.plaintext)?;
+        Ok(tags)
+    }
+
+    fn borrow(&self) -> Vec<ArieskmsTag> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .zip(&self.plaintext)
+            .map(|((name, value), plaintext)| ArieskmsTag {
+                name: slice(name),
+                value: slice(value),
+                plaintext: *plaintext != JNI_FALSE,
+            })
+            .collect()
+    }
+}
+
+type SaveFn = unsafe extern "C" fn(
+    *const ArieskmsStore,
+    ArieskmsSlice,
+    ArieskmsSlice,
+    ArieskmsSlice,
+    *const ArieskmsTag,
+    usize,
+) -> ArieskmsErrorCode;
+
+#[allow(clippy::too_many_arguments)]
+fn save(
+    env: &mut JNIEnv<'_>,
+    save: SaveFn,
+    store: jlong,
+    category: JByteArray<'_>,
+    name: JByteArray<'_>,
+    value: JByteArray<'_>,
+    tag_names: JObjectArray<'_>,
+    tag_values: JObjectArray<'_>,
+    tag_plaintext: JBooleanArray<'_>,
+) {
+    run(env, (), |env| {
+        let category = bytes(env, &category)?;
+        let name = bytes(env, &name)?;
+        let value = bytes(env, &value)?;
+        let tags = Tags::read(env, &tag_names, &tag_values, &tag_plaintext)?;
+        let tags = tags.borrow();
+        check(unsafe {
+            save(
+                store as *const ArieskmsStore,
+                slice(&category),
+                slice(&name),
+                slice(&value),
+                tags.as_ptr(),
+                tags.len(),
+            )
+        })
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_storeInsert(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    store: jlong,
+    category: JByteArray<'_>,
+    name: JByteArray<'_>,
+    value: JByteArray<'_>,
+    tag_names: JObjectArray<'_>,
+    tag_values: JObjectArray<'_>,
+    tag_plaintext: JBooleanArray<'_>,
+) {
+    save(
+        &mut env,
+        arieskms_store_insert,
+        store,
+        category,
+        name,
+        value,
+        tag_names,
+        tag_values,
+        tag_plaintext,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_storeUpdate(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    store: jlong,
+    category: JByteArray<'_>,
+    name: JByteArray<'_>,
+    value: JByteArray<'_>,
+    tag_names: JObjectArray<'_>,
+    tag_values: JObjectArray<'_>,
+    tag_plaintext: JBooleanArray<'_>,
+) {
+    save(
+        &mut env,
+        arieskms_store_update,
+        store,
+        category,
+        name,
+        value,
+        tag_names,
+        tag_values,
+        tag_plaintext,
+    )
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_storeFetch(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    store: jlong,
+    category: JByteArray<'_>,
+    name: JByteArray<'_>,
+) -> jbyteArray {
+    run(&mut env, ptr::null_mut(), |env| {
+        let (category, name) = (bytes(env, &category)?, bytes(env, &name)?);
+        let mut out = empty_buffer();
+        check(unsafe {
+            arieskms_store_fetch(
+                store as *const ArieskmsStore,
+                slice(&category),
+                slice(&name),
+                &mut out,
+            )
+        })?;
+        buffer(env, out)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_storeDelete(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    store: jlong,
+    category: JByteArray<'_>,
+    name: JByteArray<'_>,
+) {
+    run(&mut env, (), |env| {
+        let (category, name) = (bytes(env, &category)?, bytes(env, &name)?);
+        check(unsafe {
+            arieskms_store_delete(
+                store as *const ArieskmsStore,
+                slice(&category),
+                slice(&name),
+            )
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_hyperledger_aries_kms_Native_storeFree(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    store: jlong,
+) {
+    unsafe { arieskms_store_free(store as *mut ArieskmsStore) }
+}
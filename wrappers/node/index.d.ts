@@ -0,0 +1,116 @@
+/// <reference types="node" />
+
+/** The key types that can be generated */
+export const enum KeyType {
+  Ed25519 = 0,
+  X25519 = 1,
+  EcdsaP256 = 2,
+  EcdsaP384 = 3,
+  EcdsaSecp256k1 = 4,
+  XChaCha20Poly1305 = 5,
+  Aes128Gcm = 6,
+  Aes256Gcm = 7,
+  Aes256GcmSiv = 8,
+  HmacSha256 = 9,
+  HmacSha512 = 10,
+}
+
+/** Capabilities of symmetric keys */
+export const enum Symmetric {
+  Encrypt = 1,
+  Decrypt = 2,
+  HmacSign = 4,
+  HmacVerify = 8,
+  ExportWrapped = 16,
+  ImportWrapped = 32,
+  ExportableWhenWrapped = 256,
+}
+
+/** Capabilities of Ed25519, X25519 and ECDSA keys */
+export const enum Ecc {
+  Sign = 1,
+  Verify = 2,
+  DeriveDiffieHellman = 4,
+  ExportableWhenWrapped = 256,
+}
+
+/** The `code` of errors rejected by this library */
+export type ErrorCode =
+  | 'INVALID_ARGUMENT'
+  | 'ENCLAVE_CONNECTION_FAILURE'
+  | 'ENCLAVE_ACCESS_DENIED'
+  | 'ENCLAVE_ITEM_NOT_FOUND'
+  | 'ENCLAVE_UNSUPPORTED_OPERATION'
+  | 'ENCLAVE_GENERAL_ERROR'
+  | 'ENCLAVE_INVALID_CAPABILITIES'
+  | 'PERSISTENCE_INVALID_CONFIG'
+  | 'PERSISTENCE_IO_ERROR'
+  | 'PERSISTENCE_ITEM_NOT_FOUND'
+  | 'PERSISTENCE_DUPLICATE_ITEM'
+  | 'PERSISTENCE_SERIALIZATION_ERROR'
+  | 'PERSISTENCE_INVALID_QUERY'
+  | 'PERSISTENCE_CONFLICT'
+  | 'PERSISTENCE_UNSUPPORTED_VERSION'
+  | 'PERSISTENCE_UNSUPPORTED_OPERATION'
+  | 'PROTECTION_INVALID_DATA'
+
+/** Connection settings for the OS keyring */
+export interface OsKeyRingConnector {
+  /** The keyring to open instead of the user's default keyring */
+  path?: string
+  /** The password to unlock the keyring with */
+  password?: string
+}
+
+/** Connection settings for an S3-compatible bucket */
+export interface ObjectStoreConnector {
+  endpoint: string
+  bucket: string
+  region: string
+  accessKeyId: string
+  secretAccessKey: string
+}
+
+/** A record tag. Encrypted tags only support equality lookups. */
+export interface RecordTag {
+  name: string
+  value: string
+  /** Persist the tag as is instead of encrypting it */
+  plaintext?: boolean
+}
+
+/** An enclave holding keys */
+export class Enclave {
+  /** Open the enclave named by `uri` like `software:`. Keys of the software enclave only live in memory. */
+  static open(uri: string): Promise<Enclave>
+  /** Connect to the OS keyring */
+  static connectOsKeyRing(connector: OsKeyRingConnector): Promise<Enclave>
+  /** Generate the key `id` with the `Symmetric` or `Ecc` bits `capabilities` */
+  generateKey(id: string, keyType: KeyType, capabilities: number): Promise<Key>
+  /** Use the existing key `id` */
+  key(id: string): Key
+}
+
+/** A key held by an enclave */
+export class Key {
+  readonly id: string
+  encrypt(plaintext: Buffer, aad?: Buffer | null): Promise<Buffer>
+  decrypt(ciphertext: Buffer, aad?: Buffer | null): Promise<Buffer>
+  signHmac(data: Buffer): Promise<Buffer>
+}
+
+/** A store whose records are protected by an enclave */
+export class Store {
+  /** Only available when built with the `storage-sled` feature */
+  static openSled(enclave: Enclave, path: string | null, keyPrefix: string, generateKeys?: boolean): Promise<Store>
+  /** Available unless built without the default `storage-sqlite` feature */
+  static openSqlite(enclave: Enclave, path: string | null, keyPrefix: string, generateKeys?: boolean): Promise<Store>
+  /** Only available when built with the `storage-s3` feature */
+  static openS3(enclave: Enclave, connector: ObjectStoreConnector, keyPrefix: string, generateKeys?: boolean): Promise<Store>
+  insert(category: string | Buffer, name: string | Buffer, value: string | Buffer, tags?: RecordTag[]): Promise<void>
+  update(category: string | Buffer, name: string | Buffer, value: string | Buffer, tags?: RecordTag[]): Promise<void>
+  fetch(category: string | Buffer, name: string | Buffer): Promise<Buffer>
+  delete(category: string | Buffer, name: string | Buffer): Promise<void>
+  /** Close the store once pending operations finish */
+  close(): void
+}
@@ -0,0 +1,2 @@
+// Set ARIESKMS_NODE_LIBRARY to load the addon from another path
+module.exports = require(process.env.ARIESKMS_NODE_LIBRARY || './arieskms.node')
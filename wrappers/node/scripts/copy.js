@@ -0,0 +1,13 @@
+// Copy the built library to arieskms.node so Node can load it
+const fs = require('fs')
+const path = require('path')
+
+const profile = process.argv[2] || 'release'
+const names = {
+  darwin: 'libarieskms_node.dylib',
+  win32: 'arieskms_node.dll',
+}
+const name = names[process.platform] || 'libarieskms_node.so'
+const target = process.env.CARGO_TARGET_DIR || path.join(__dirname, '..', 'target')
+
+fs.copyFileSync(path.join(target, profile, name), path.join(__dirname, '..', 'arieskms.node'))
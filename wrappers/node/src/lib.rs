@@ -0,0 +1,664 @@
+/*
+ * Copyright 2020
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+//! Node.js bindings for enclaves, keys and stores.
+//!
+//! Every method that talks to an enclave or a persistence backend returns
+//! a `Promise` and runs on the libuv thread pool. Rejected promises carry
+//! an `Error` whose `code` names the error kind like `ENCLAVE_ITEM_NOT_FOUND`.
+#![deny(warnings, unused_import_braces, unused_qualifications)]
+
+use arieskms::{
+    persistence::{
+        errors::{PersistenceError, PersistenceErrorKind},
+        shared::BoxedPersistence,
+        PersistenceConnector, PersistenceLike, Record, RecordTag as StoreTag,
+    },
+    protection::{
+        errors::{ProtectionError, ProtectionErrorKind},
+        ProtectedStore, ProtectionKeys,
+    },
+    registry::Registry,
+    security::{
+        errors::{EnclaveError, EnclaveErrorKind},
+        shared::SharedEnclave,
+        AesModes, AesSizes, EcCurves, EccCapability, EcdsaAlgorithm, EnclaveKeyType, EnclaveLike,
+        HmacAlgorithm, KeyCapabilities, SymmetricCapability, WrappingKey,
+    },
+};
+use napi::{
+    bindgen_prelude::{AsyncTask, Buffer, Either, ToNapiValue, TypeName},
+    Env, Error, Result, Task,
+};
+use napi_derive::napi;
+use std::{convert::TryFrom, sync::Arc};
+
+/// An error with the code it is rejected with
+struct KmsError {
+    code: &'static str,
+    message: String,
+}
+
+impl KmsError {
+    fn invalid_argument(message: &str) -> Self {
+        Self {
+            code: "INVALID_ARGUMENT",
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<EnclaveErrorKind> for KmsError {
+    fn from(kind: EnclaveErrorKind) -> Self {
+        let code = match kind {
+            EnclaveErrorKind::ConnectionFailure { .. } => "ENCLAVE_CONNECTION_FAILURE",
+            EnclaveErrorKind::AccessDenied { .. } => "ENCLAVE_ACCESS_DENIED",
+            EnclaveErrorKind::ItemNotFound => "ENCLAVE_ITEM_NOT_FOUND",
+            EnclaveErrorKind::UnsupportedOperation => "ENCLAVE_UNSUPPORTED_OPERATION",
+            EnclaveErrorKind::GeneralError { .. } => "ENCLAVE_GENERAL_ERROR",
+            EnclaveErrorKind::InvalidCapabilities { .. } => "ENCLAVE_INVALID_CAPABILITIES",
+        };
+        Self {
+            code,
+            message: kind.to_string(),
+        }
+    }
+}
+
+impl From<PersistenceErrorKind> for KmsError {
+    fn from(kind: PersistenceErrorKind) -> Self {
+        let code = match kind {
+            PersistenceErrorKind::InvalidConfig => "PERSISTENCE_INVALID_CONFIG",
+            PersistenceErrorKind::IOError => "PERSISTENCE_IO_ERROR",
+            PersistenceErrorKind::ItemNotFound => "PERSISTENCE_ITEM_NOT_FOUND",
+            PersistenceErrorKind::DuplicateItem => "PERSISTENCE_DUPLICATE_ITEM",
+            PersistenceErrorKind::SerializationError => "PERSISTENCE_SERIALIZATION_ERROR",
+            PersistenceErrorKind::InvalidQuery => "PERSISTENCE_INVALID_QUERY",
+            PersistenceErrorKind::Conflict => "PERSISTENCE_CONFLICT",
+            PersistenceErrorKind::UnsupportedVersion => "PERSISTENCE_UNSUPPORTED_VERSION",
+            PersistenceErrorKind::UnsupportedOperation => "PERSISTENCE_UNSUPPORTED_OPERATION",
+        };
+        Self {
+            code,
+            message: kind.to_string(),
+        }
+    }
+}
+
+impl From<ProtectionErrorKind> for KmsError {
+    fn from(kind: ProtectionErrorKind) -> Self {
+        match kind {
+            ProtectionErrorKind::Enclave(kind) => kind.into(),
+            ProtectionErrorKind::Persistence(kind) => kind.into(),
+            ProtectionErrorKind::InvalidData => Self {
+                code: "PROTECTION_INVALID_DATA",
+                message: kind.to_string(),
+            },
+        }
+    }
+}
+
+impl From<EnclaveError> for KmsError {
+    fn from(e: EnclaveError) -> Self {
+        Self {
+            message: e.to_string(),
+            ..e.kind().into()
+        }
+    }
+}
+
+impl From<PersistenceError> for KmsError {
+    fn from(e: PersistenceError) -> Self {
+        Self {
+            message: e.to_string(),
+            ..e.kind().into()
+        }
+    }
+}
+
+impl From<ProtectionError> for KmsError {
+    fn from(e: ProtectionError) -> Self {
+        Self {
+            message: e.to_string(),
+            ..e.kind().into()
+        }
+    }
+}
+
+impl KmsError {
+    /// Build the JS error thrown for this error
+    fn into_js(self, env: Env) -> Error {
+        let code = self.code;
+        let result = env
+            .create_error(Error::from_reason(self.message))
+            .and_then(|mut error| {
+                error.set_named_property("code", env.create_string(code)?)?;
+                Ok(error.into_unknown())
+            });
+        match result {
+            Ok(error) => Error::from(error),
+            Err(e) => e,
+        }
+    }
+}
+
+type KmsResult<T> = std::result::Result<T, KmsError>;
+
+/// Runs a closure on the libuv thread pool and settles a `Promise` with its result
+pub struct Job<T> {
+    run: Option<Box<dyn FnOnce() -> KmsResult<T> + Send>>,
+    error: Option<KmsError>,
+}
+
+impl<T> Job<T> {
+    fn spawn<F: FnOnce() -> KmsResult<T> + Send + 'static>(run: F) -> AsyncTask<Self>
+    where
+        Self: Task,
+    {
+        AsyncTask::new(Self {
+            run: Some(Box::new(run)),
+            error: None,
+        })
+    }
+}
+
+impl<T: Send + ToNapiValue + TypeName + 'static> Task for Job<T> {
+    type Output = T;
+    type JsValue = T;
+
+    fn compute(&mut self) -> Result<T> {
+        let run = self
+            .run
+            .take()
+            .ok_or_else(|| Error::from_reason("The job already ran"))?;
+        run().map_err(|e| {
+            let error = Error::from_reason(e.message.clone());
+            self.error = Some(e);
+            error
+        })
+    }
+
+    fn resolve(&mut self, _: Env, output: T) -> Result<T> {
+        Ok(output)
+    }
+
+    fn reject(&mut self, env: Env, error: Error) -> Result<T> {
+        Err(self.error.take().map(|e| e.into_js(env)).unwrap_or(error))
+    }
+}
+
+/// Throw `e` from a synchronous method
+fn throw<T>(env: Env, result: KmsResult<T>) -> Result<T> {
+    result.map_err(|e| e.into_js(env))
+}
+
+/// The key types that can be generated
+#[napi]
+pub enum KeyType {
+    /// Ed25519 signing key
+    Ed25519,
+    /// X25519 key agreement key
+    X25519,
+    /// ECDSA with SHA-256 over P-256
+    EcdsaP256,
+    /// ECDSA with SHA-384 over P-384
+    EcdsaP384,
+    /// ECDSA with SHA-256 over secp256k1
+    EcdsaSecp256k1,
+    /// XChaCha20-Poly1305 wrapping key
+    XChaCha20Poly1305,
+    /// AES-128-GCM wrapping key
+    Aes128Gcm,
+    /// AES-256-GCM wrapping key
+    Aes256Gcm,
+    /// AES-256-GCM-SIV wrapping key
+    Aes256GcmSiv,
+    /// HMAC-SHA256 key
+    HmacSha256,
+    /// HMAC-SHA512 key
+    HmacSha512,
+}
+
+impl KeyType {
+    fn key_type(self, capabilities: u32) -> KmsResult<(EnclaveKeyType, KeyCapabilities)> {
+        let key_type = match self {
+            KeyType::Ed25519 => EnclaveKeyType::Ed25519,
+            KeyType::X25519 => EnclaveKeyType::X25519,
+            KeyType::EcdsaP256 => {
+                EnclaveKeyType::Ecdsa(EcCurves::Secp256r1, EcdsaAlgorithm::Sha256)
+            }
+            KeyType::EcdsaP384 => {
+                EnclaveKeyType::Ecdsa(EcCurves::Secp384r1, EcdsaAlgorithm::Sha384)
+            }
+            KeyType::EcdsaSecp256k1 => {
+                EnclaveKeyType::Ecdsa(EcCurves::Secp256k1, EcdsaAlgorithm::Sha256)
+            }
+            KeyType::XChaCha20Poly1305 => EnclaveKeyType::WrapKey(WrappingKey::XChaChaPoly1305),
+            KeyType::Aes128Gcm => {
+                EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes128, AesModes::Gcm))
+            }
+            KeyType::Aes256Gcm => {
+                EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::Gcm))
+            }
+            KeyType::Aes256GcmSiv => {
+                EnclaveKeyType::WrapKey(WrappingKey::Aes(AesSizes::Aes256, AesModes::GcmSiv))
+            }
+            KeyType::HmacSha256 => EnclaveKeyType::Hmac(HmacAlgorithm::Sha256),
+            KeyType::HmacSha512 => EnclaveKeyType::Hmac(HmacAlgorithm::Sha512),
+        };
+        let invalid = || KmsError::invalid_argument("Unknown capabilities for the key type");
+        let bits = u16::try_from(capabilities).map_err(|_| invalid())?;
+        let capabilities = match key_type {
+            EnclaveKeyType::Ed25519 | EnclaveKeyType::X25519 | EnclaveKeyType::Ecdsa(..) => {
+                KeyCapabilities::Ecc(EccCapability::from_bits(bits).ok_or_else(invalid)?)
+            }
+            _ => KeyCapabilities::Symmetric(
+                SymmetricCapability::from_bits(bits).ok_or_else(invalid)?,
+            ),
+        };
+        Ok((key_type, capabilities))
+    }
+}
+
+/// Capabilities of symmetric keys
+#[napi]
+pub enum Symmetric {
+    /// Encrypt data
+    Encrypt = 0x0001,
+    /// Decrypt data
+    Decrypt = 0x0002,
+    /// Compute HMACs
+    HmacSign = 0x0004,
+    /// Verify HMACs
+    HmacVerify = 0x0008,
+    /// Export the key wrapped by another key
+    ExportWrapped = 0x0010,
+    /// Import a key wrapped by this key
+    ImportWrapped = 0x0020,
+    /// Allow the key to be exported when wrapped
+    ExportableWhenWrapped = 0x0100,
+}
+
+/// Capabilities of Ed25519, X25519 and ECDSA keys
+#[napi]
+pub enum Ecc {
+    /// Sign data
+    Sign = 0x0001,
+    /// Verify signatures
+    Verify = 0x0002,
+    /// Compute Diffie-Hellman secrets
+    DeriveDiffieHellman = 0x0004,
+    /// Allow the key to be exported when wrapped
+    ExportableWhenWrapped = 0x0100,
+}
+
+/// Connection settings for the OS keyring
+#[napi(object)]
+pub struct OsKeyRingConnector {
+    /// The keyring to open instead of the user's default keyring
+    pub path: Option<String>,
+    /// The password to unlock the keyring with
+    pub password: Option<String>,
+}
+
+/// An enclave holding keys
+#[napi]
+pub struct Enclave {
+    inner: SharedEnclave,
+}
+
+#[napi]
+impl Enclave {
+    /// Open the enclave named by `uri` like `software:`. Keys of the
+    /// software enclave only live in memory until it is closed.
+    #[napi(ts_return_type = "Promise<Enclave>")]
+    pub fn open(uri: String) -> AsyncTask<Job<Enclave>> {
+        Job::spawn(move || {
+            // The null enclave would store everything unencrypted
+            let mut registry = Registry::default();
+            registry.remove_enclave("null");
+            Ok(Enclave {
+                inner: registry.open_enclave(&uri)?,
+            })
+        })
+    }
+
+    /// Connect to the OS keyring
+    #[napi(ts_return_type = "Promise<Enclave>")]
+    pub fn connect_os_key_ring(connector: OsKeyRingConnector) -> AsyncTask<Job<Enclave>> {
+        Job::spawn(move || os_key_ring(connector))
+    }
+
+    /// Generate the key `id` with the `Symmetric` or `Ecc` bits `capabilities`
+    #[napi(ts_return_type = "Promise<Key>")]
+    pub fn generate_key(
+        &self,
+        id: String,
+        key_type: KeyType,
+        capabilities: u32,
+    ) -> AsyncTask<Job<Key>> {
+        let enclave = self.inner.clone();
+        Job::spawn(move || {
+            let (key_type, capabilities) = key_type.key_type(capabilities)?;
+            enclave.create_key(&id, key_type, capabilities)?;
+            Ok(Key { enclave, id })
+        })
+    }
+
+    /// Use the existing key `id`. The enclave reports a missing
+    /// key when the key is first used.
+    #[napi]
+    pub fn key(&self, id: String) -> Key {
+        Key {
+            enclave: self.inner.clone(),
+            id,
+        }
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn os_key_ring(connector: OsKeyRingConnector) -> KmsResult<Enclave> {
+    use arieskms::security::{os::macos::MacOsKeyRing, EnclaveConnector};
+    let mut config = arieskms::security::OsKeyRingConnector::new(connector.path);
+    if let Some(password) = connector.password {
+        config = config.password(password);
+    }
+    let enclave = MacOsKeyRing::connect(EnclaveConnector::OsKeyRing(config))?;
+    Ok(Enclave {
+        inner: SharedEnclave::new(enclave),
+    })
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn os_key_ring(connector: OsKeyRingConnector) -> KmsResult<Enclave> {
+    let _ = connector;
+    Err(EnclaveErrorKind::UnsupportedOperation.into())
+}
+
+/// A key held by an enclave
+#[napi]
+pub struct Key {
+    enclave: SharedEnclave,
+    id: String,
+}
+
+#[napi]
+impl Key {
+    /// The id of the key in the enclave
+    #[napi(getter)]
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Encrypt `plaintext` authenticating `aad`
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn encrypt(&self, plaintext: Buffer, aad: Option<Buffer>) -> AsyncTask<Job<Buffer>> {
+        let (enclave, id) = (self.enclave.clone(), self.id.clone());
+        let (plaintext, aad) = (
+            plaintext.to_vec(),
+            aad.map(|a| a.to_vec()).unwrap_or_default(),
+        );
+        Job::spawn(move || Ok(enclave.encrypt(&id, &plaintext, &aad)?.into()))
+    }
+
+    /// Decrypt `ciphertext` authenticating `aad`
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn decrypt(&self, ciphertext: Buffer, aad: Option<Buffer>) -> AsyncTask<Job<Buffer>> {
+        let (enclave, id) = (self.enclave.clone(), self.id.clone());
+        let (ciphertext, aad) = (
+            ciphertext.to_vec(),
+            aad.map(|a| a.to_vec()).unwrap_or_default(),
+        );
+        Job::spawn(move || Ok(enclave.decrypt(&id, &ciphertext, &aad)?.into()))
+    }
+
+    /// Compute the HMAC of `data`
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn sign_hmac(&self, data: Buffer) -> AsyncTask<Job<Buffer>> {
+        let (enclave, id, data) = (self.enclave.clone(), self.id.clone(), data.to_vec());
+        Job::spawn(move || Ok(enclave.sign_hmac(&id, &data)?.into()))
+    }
+}
+
+/// A record tag. Encrypted tags only support equality lookups.
+/// Plaintext tags are persisted as is.
+#[napi(object)]
+pub struct RecordTag {
+    /// The tag name
+    pub name: String,
+    /// The tag value
+    pub value: String,
+    /// Persist the tag as is instead of encrypting it
+    pub plaintext: Option<bool>,
+}
+
+/// Connection settings for an S3-compatible bucket
+#[napi(object)]
+pub struct ObjectStoreConnector {
+    /// Base URL of the service like `https://s3.us-east-1.amazonaws.com`
+    pub endpoint: String,
+    /// The bucket holding the records
+    pub bucket: String,
+    /// The region of the bucket
+    pub region: String,
+    /// The access key id
+    pub access_key_id: String,
+    /// The secret access key
+    pub secret_access_key: String,
+}
+
+type Protected = ProtectedStore<SharedEnclave, BoxedPersistence>;
+
+/// A store whose records are protected by an enclave
+#[napi]
+pub struct Store {
+    inner: Option<Arc<Protected>>,
+}
+
+/// Connect `config` and protect it with the keys starting with `key_prefix`,
+/// generating them first if `generate_keys` is set
+// Unused when no storage feature is enabled
+#[allow(dead_code)]
+fn open<P: PersistenceLike + Send + Sync + 'static>(
+    enclave: SharedEnclave,
+    config: PersistenceConnector<String, String>,
+    key_prefix: String,
+    generate_keys: bool,
+) -> KmsResult<Store> {
+    let keys = if generate_keys {
+        ProtectionKeys::generate(&enclave, &key_prefix)?
+    } else {
+        ProtectionKeys::with_prefix(&key_prefix)
+    };
+    let persistence = BoxedPersistence::new(P::connect(config)?);
+    Ok(Store {
+        inner: Some(Arc::new(ProtectedStore::new(enclave, persistence, keys))),
+    })
+}
+
+/// Bytes from a string or a `Buffer`
+fn bytes(data: Either<String, Buffer>) -> Vec<u8> {
+    match data {
+        Either::A(s) => s.into_bytes(),
+        Either::B(b) => b.to_vec(),
+    }
+}
+
+#[napi]
+impl Store {
+    /// Save a new record
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn insert(
+        &self,
+        env: Env,
+        category: Either<String, Buffer>,
+        name: Either<String, Buffer>,
+        value: Either<String, Buffer>,
+        tags: Option<Vec<RecordTag>>,
+    ) -> Result<AsyncTask<Job<()>>> {
+        let store = throw(env, self.store())?;
+        let record = record(category, name, value, tags);
+        Ok(Job::spawn(move || Ok(store.insert(record)?)))
+    }
+
+    /// Replace the value and tags of an existing record
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn update(
+        &self,
+        env: Env,
+        category: Either<String, Buffer>,
+        name: Either<String, Buffer>,
+        value: Either<String, Buffer>,
+        tags: Option<Vec<RecordTag>>,
+    ) -> Result<AsyncTask<Job<()>>> {
+        let store = throw(env, self.store())?;
+        let record = record(category, name, value, tags);
+        Ok(Job::spawn(move || Ok(store.update(record)?)))
+    }
+
+    /// Fetch the value of a record
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn fetch(
+        &self,
+        env: Env,
+        category: Either<String, Buffer>,
+        name: Either<String, Buffer>,
+    ) -> Result<AsyncTask<Job<Buffer>>> {
+        let store = throw(env, self.store())?;
+        let (category, name) = (bytes(category), bytes(name));
+        Ok(Job::spawn(move || {
+            Ok(store.fetch(&category, &name)?.value.into())
+        }))
+    }
+
+    /// Delete a record
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn delete(
+        &self,
+        env: Env,
+        category: Either<String, Buffer>,
+        name: Either<String, Buffer>,
+    ) -> Result<AsyncTask<Job<()>>> {
+        let store = throw(env, self.store())?;
+        let (category, name) = (bytes(category), bytes(name));
+        Ok(Job::spawn(move || Ok(store.delete(&category, &name)?)))
+    }
+
+    /// Close the store once pending operations finish
+    #[napi]
+    pub fn close(&mut self) {
+        if let Some(store) = self.inner.take().and_then(|s| Arc::try_unwrap(s).ok()) {
+            store.close();
+        }
+    }
+
+    fn store(&self) -> KmsResult<Arc<Protected>> {
+        self.inner
+            .clone()
+            .ok_or_else(|| KmsError::invalid_argument("The store is closed"))
+    }
+}
+
+fn record(
+    category: Either<String, Buffer>,
+    name: Either<String, Buffer>,
+    value: Either<String, Buffer>,
+    tags: Option<Vec<RecordTag>>,
+) -> Record {
+    Record {
+        category: bytes(category),
+        name: bytes(name),
+        value: bytes(value),
+        tags: tags
+            .unwrap_or_default()
+            .into_iter()
+            .map(|t| {
+                let (name, value) = (t.name.into_bytes(), t.value.into_bytes());
+                if t.plaintext.unwrap_or(false) {
+                    StoreTag::Plaintext(name, value)
+                } else {
+                    StoreTag::Encrypted(name, value)
+                }
+            })
+            .collect(),
+    }
+}
+
+#[cfg(feature = "storage-sled")]
+#[napi]
+impl Store {
+    /// Open the sled database at `path`, or a temporary one if `path` is null
+    #[napi(ts_return_type = "Promise<Store>")]
+    pub fn open_sled(
+        enclave: &Enclave,
+        path: Option<String>,
+        key_prefix: String,
+        generate_keys: Option<bool>,
+    ) -> AsyncTask<Job<Store>> {
+        use arieskms::persistence::{kv::sled::SledStore, KeyValueConnector};
+        let enclave = enclave.inner.clone();
+        Job::spawn(move || {
+            let config = PersistenceConnector::KeyValue(KeyValueConnector::new(path));
+            open::<SledStore>(enclave, config, key_prefix, generate_keys.unwrap_or(false))
+        })
+    }
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[napi]
+impl Store {
+    /// Open the SQLite database at `path`, or an in memory one if `path` is null
+    #[napi(ts_return_type = "Promise<Store>")]
+    pub fn open_sqlite(
+        enclave: &Enclave,
+        path: Option<String>,
+        key_prefix: String,
+        generate_keys: Option<bool>,
+    ) -> AsyncTask<Job<Store>> {
+        use arieskms::persistence::{sql::sqlite::SqliteStore, SqliteConnector};
+        let enclave = enclave.inner.clone();
+        Job::spawn(move || {
+            let config = PersistenceConnector::Sqlite(SqliteConnector::new(path));
+            open::<SqliteStore>(enclave, config, key_prefix, generate_keys.unwrap_or(false))
+        })
+    }
+}
+
+#[cfg(feature = "storage-s3")]
+#[napi]
+impl Store {
+    /// Open an S3-compatible bucket
+    #[napi(ts_return_type = "Promise<Store>")]
+    pub fn open_s3(
+        enclave: &Enclave,
+        connector: ObjectStoreConnector,
+        key_prefix: String,
+        generate_keys: Option<bool>,
+    ) -> AsyncTask<Job<Store>> {
+        use arieskms::persistence::{object::s3::S3Store, ObjectStoreConnector as S3Connector};
+        let enclave = enclave.inner.clone();
+        Job::spawn(move || {
+            let config = PersistenceConnector::ObjectStore(S3Connector::new(
+                connector.endpoint,
+                connector.bucket,
+                connector.region,
+                connector.access_key_id,
+                connector.secret_access_key,
+            ));
+            open::<S3Store>(enclave, config, key_prefix, generate_keys.unwrap_or(false))
+        })
+    }
+}
@@ -0,0 +1,90 @@
+# Copyright 2020
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+# -----------------------------------------------------------------------------
+"""Python bindings for the Aries Key Management Service.
+
+The bindings load the arieskms library built with the `ffi` feature
+and the storage features the scripts need.
+"""
+
+from .bindings import version
+from .enclave import (
+    EccCapability,
+    Enclave,
+    Key,
+    KeyType,
+    OsKeyRingConnector,
+    SymmetricCapability,
+    YubiHsmConnector,
+)
+from .error import (
+    AccessDenied,
+    ArieskmsError,
+    Conflict,
+    ConnectionFailure,
+    DuplicateRecord,
+    EnclaveError,
+    ErrorCode,
+    GeneralError,
+    InvalidArgument,
+    InvalidCapabilities,
+    InvalidConfig,
+    InvalidData,
+    InvalidQuery,
+    KeyNotFound,
+    Panic,
+    PersistenceError,
+    PersistenceIOError,
+    RecordNotFound,
+    SerializationError,
+    UnsupportedOperation,
+    UnsupportedStoreOperation,
+    UnsupportedVersion,
+)
+from .store import RecordTag, Store
+
+__all__ = [
+    "AccessDenied",
+    "ArieskmsError",
+    "Conflict",
+    "ConnectionFailure",
+    "DuplicateRecord",
+    "EccCapability",
+    "Enclave",
+    "EnclaveError",
+    "ErrorCode",
+    "GeneralError",
+    "InvalidArgument",
+    "InvalidCapabilities",
+    "InvalidConfig",
+    "InvalidData",
+    "InvalidQuery",
+    "Key",
+    "KeyNotFound",
+    "KeyType",
+    "OsKeyRingConnector",
+    "Panic",
+    "PersistenceError",
+    "PersistenceIOError",
+    "RecordNotFound",
+    "RecordTag",
+    "SerializationError",
+    "Store",
+    "SymmetricCapability",
+    "UnsupportedOperation",
+    "UnsupportedStoreOperation",
+    "UnsupportedVersion",
+    "YubiHsmConnector",
+    "version",
+]
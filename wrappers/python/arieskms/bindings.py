@@ -0,0 +1,146 @@
+# Copyright 2020
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+# -----------------------------------------------------------------------------
+"""Loads the arieskms library and converts between Python and C values."""
+
+import ctypes
+import ctypes.util
+import os
+import sys
+from ctypes import POINTER, Structure, c_bool, c_char_p, c_size_t, c_ubyte, c_void_p
+from typing import Optional
+
+from .error import ErrorCode, InvalidArgument, UnsupportedOperation, exception
+
+#: Set to the path of the library to load instead of searching for it
+LIBRARY_ENV = "ARIESKMS_LIBRARY"
+
+
+class Slice(Structure):
+    """`ArieskmsSlice`"""
+
+    _fields_ = [("data", POINTER(c_ubyte)), ("len", c_size_t)]
+
+
+class Buffer(Structure):
+    """`ArieskmsBuffer`"""
+
+    _fields_ = [("data", POINTER(c_ubyte)), ("len", c_size_t)]
+
+
+class Tag(Structure):
+    """`ArieskmsTag`"""
+
+    _fields_ = [("name", Slice), ("value", Slice), ("plaintext", c_bool)]
+
+
+_LIBRARY = None
+
+
+def _library_names():
+    if sys.platform == "darwin":
+        return ["libarieskms.dylib"]
+    if sys.platform == "win32":
+        return ["arieskms.dll"]
+    return ["libarieskms.so"]
+
+
+def library() -> ctypes.CDLL:
+    """Load the library from `$ARIESKMS_LIBRARY`, next to this
+    package or from the system library path"""
+    global _LIBRARY
+    if _LIBRARY is None:
+        path = os.environ.get(LIBRARY_ENV)
+        if not path:
+            here = os.path.dirname(os.path.abspath(__file__))
+            local = [os.path.join(here, name) for name in _library_names()]
+            path = next(
+                (p for p in local if os.path.exists(p)),
+                ctypes.util.find_library("arieskms"),
+            )
+        if not path:
+            raise OSError(
+                "The arieskms library was not found. Set ${} to its path".format(
+                    LIBRARY_ENV
+                )
+            )
+        lib = ctypes.CDLL(path)
+        lib.arieskms_last_error_message.restype = c_char_p
+        lib.arieskms_version.restype = c_char_p
+        lib.arieskms_buffer_free.argtypes = [Buffer]
+        _LIBRARY = lib
+    return _LIBRARY
+
+
+def function(name: str):
+    """The C function `name`, raising `UnsupportedOperation` if
+    the library was built without the feature providing it"""
+    try:
+        return getattr(library(), name)
+    except AttributeError:
+        raise UnsupportedOperation(
+            ErrorCode.ENCLAVE_UNSUPPORTED_OPERATION,
+            "The arieskms library was built without {}".format(name),
+        ) from None
+
+
+def call(name: str, *args):
+    """Call the C function `name` and raise the error it returns"""
+    code = function(name)(*args)
+    if code != ErrorCode.SUCCESS:
+        message = library().arieskms_last_error_message()
+        raise exception(code, message.decode("utf-8", "replace") if message else "")
+
+
+def free(name: str, handle: c_void_p):
+    """Release `handle` with the C function `name`"""
+    if handle:
+        getattr(library(), name)(handle)
+
+
+def to_slice(data) -> Slice:
+    """Borrow `data`, which must stay alive while the slice is used"""
+    if isinstance(data, str):
+        data = data.encode("utf-8")
+    if not isinstance(data, (bytes, bytearray)):
+        raise InvalidArgument(
+            ErrorCode.INVALID_ARGUMENT,
+            "Expected bytes or str but found {}".format(type(data).__name__),
+        )
+    buffer = (c_ubyte * len(data)).from_buffer_copy(data)
+    result = Slice(ctypes.cast(buffer, POINTER(c_ubyte)), len(data))
+    result._buffer = buffer
+    return result
+
+
+def to_string(value: Optional[str]) -> Optional[bytes]:
+    """Encode `value` as a nul terminated string"""
+    if value is None:
+        return None
+    if "\0" in value:
+        raise InvalidArgument(ErrorCode.INVALID_ARGUMENT, "Strings can't contain nul")
+    return value.encode("utf-8")
+
+
+def take_buffer(buffer: Buffer) -> bytes:
+    """Copy and release a buffer returned by the library"""
+    try:
+        return ctypes.string_at(buffer.data, buffer.len) if buffer.len else b""
+    finally:
+        library().arieskms_buffer_free(buffer)
+
+
+def version() -> str:
+    """The version of the loaded library"""
+    return library().arieskms_version().decode("utf-8")
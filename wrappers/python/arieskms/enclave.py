@@ -0,0 +1,218 @@
+# Copyright 2020
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+# -----------------------------------------------------------------------------
+"""Enclaves and the keys they hold."""
+
+from ctypes import byref, c_char_p, c_uint16, c_uint32, c_void_p
+from enum import IntEnum, IntFlag
+from typing import Optional, Union
+
+from .bindings import Buffer, call, free, take_buffer, to_slice, to_string
+from .error import ErrorCode, UnsupportedOperation
+
+
+class KeyType(IntEnum):
+    """Mirrors `ArieskmsKeyType` in include/arieskms.h"""
+
+    ED25519 = 0
+    X25519 = 1
+    ECDSA_P256 = 2
+    ECDSA_P384 = 3
+    ECDSA_SECP256K1 = 4
+    XCHACHA20_POLY1305 = 5
+    AES128_GCM = 6
+    AES256_GCM = 7
+    AES256_GCM_SIV = 8
+    HMAC_SHA256 = 9
+    HMAC_SHA512 = 10
+
+
+class SymmetricCapability(IntFlag):
+    """`SymmetricCapability`"""
+
+    ENCRYPT = 0x0001
+    DECRYPT = 0x0002
+    HMAC_SIGN = 0x0004
+    HMAC_VERIFY = 0x0008
+    EXPORT_WRAPPED = 0x0010
+    IMPORT_WRAPPED = 0x0020
+    EXPORTABLE_WHEN_WRAPPED = 0x0100
+
+
+class EccCapability(IntFlag):
+    """`EccCapability`"""
+
+    SIGN = 0x0001
+    VERIFY = 0x0002
+    DERIVE_DIFFIE_HELLMAN = 0x0004
+    EXPORTABLE_WHEN_WRAPPED = 0x0100
+
+
+class OsKeyRingConnector:
+    """`EnclaveConnector::OsKeyRing`. Connects to the keyring at `path`
+    or the user's default keyring, unlocking it with `password`."""
+
+    def __init__(self, path: Optional[str] = None, password: Optional[str] = None):
+        self.path = path
+        self.password = password
+
+    def __repr__(self):
+        password = "None" if self.password is None else "'****'"
+        return "OsKeyRingConnector(path={!r}, password={})".format(self.path, password)
+
+
+class YubiHsmConnector:
+    """`EnclaveConnector::YubiHsm`. Connects to the YubiHSM connector
+    at `url` with the authentication key `auth_key_id`."""
+
+    def __init__(self, auth_key_id: int, url: Optional[str] = None, password: Optional[str] = None):
+        self.auth_key_id = auth_key_id
+        self.url = url
+        self.password = password
+
+    def __repr__(self):
+        password = "None" if self.password is None else "'****'"
+        return "YubiHsmConnector(auth_key_id={}, url={!r}, password={})".format(
+            self.auth_key_id, self.url, password
+        )
+
+
+Connector = Union[OsKeyRingConnector, YubiHsmConnector]
+
+
+class Enclave:
+    """An enclave holding keys. Keys and stores opened from it
+    keep working after it is closed."""
+
+    def __init__(self, handle: c_void_p):
+        self._handle = handle
+
+    @classmethod
+    def connect(cls, connector: Connector) -> "Enclave":
+        """Connect to the enclave described by `connector`"""
+        handle = c_void_p()
+        if isinstance(connector, OsKeyRingConnector):
+            call(
+                "arieskms_enclave_open_os_keyring",
+                c_char_p(to_string(connector.path)),
+                c_char_p(to_string(connector.password)),
+                byref(handle),
+            )
+        elif isinstance(connector, YubiHsmConnector):
+            raise UnsupportedOperation(
+                ErrorCode.ENCLAVE_UNSUPPORTED_OPERATION,
+                "No YubiHSM enclave is available",
+            )
+        else:
+            raise TypeError("Expected an enclave connector but found {}".format(connector))
+        return cls(handle)
+
+    @classmethod
+    def open(cls, uri: str) -> "Enclave":
+        """Open the enclave named by `uri` like `software:`. Keys of the
+        software enclave only live in memory until it is closed."""
+        handle = c_void_p()
+        call("arieskms_enclave_open", c_char_p(to_string(uri)), byref(handle))
+        return cls(handle)
+
+    @property
+    def handle(self) -> c_void_p:
+        """The `ArieskmsEnclave` handle"""
+        if not self._handle:
+            raise ValueError("The enclave is closed")
+        return self._handle
+
+    def generate_key(
+        self,
+        id: str,
+        key_type: KeyType,
+        capabilities: Union[SymmetricCapability, EccCapability],
+    ) -> "Key":
+        """Generate the key `id`"""
+        handle = c_void_p()
+        call(
+            "arieskms_key_generate",
+            self.handle,
+            c_char_p(to_string(id)),
+            c_uint32(key_type),
+            c_uint16(capabilities),
+            byref(handle),
+        )
+        return Key(handle)
+
+    def key(self, id: str) -> "Key":
+        """Open the existing key `id`"""
+        handle = c_void_p()
+        call("arieskms_key_open", self.handle, c_char_p(to_string(id)), byref(handle))
+        return Key(handle)
+
+    def close(self):
+        """Release the enclave"""
+        free("arieskms_enclave_free", self._handle)
+        self._handle = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *_):
+        self.close()
+
+    def __del__(self):
+        self.close()
+
+
+class Key:
+    """A key held by an enclave"""
+
+    def __init__(self, handle: c_void_p):
+        self._handle = handle
+
+    @property
+    def handle(self) -> c_void_p:
+        """The `ArieskmsKey` handle"""
+        if not self._handle:
+            raise ValueError("The key is closed")
+        return self._handle
+
+    def encrypt(self, plaintext: bytes, aad: bytes = b"") -> bytes:
+        """Encrypt `plaintext` authenticating `aad`"""
+        out = Buffer()
+        call("arieskms_key_encrypt", self.handle, to_slice(plaintext), to_slice(aad), byref(out))
+        return take_buffer(out)
+
+    def decrypt(self, ciphertext: bytes, aad: bytes = b"") -> bytes:
+        """Decrypt `ciphertext` authenticating `aad`"""
+        out = Buffer()
+        call("arieskms_key_decrypt", self.handle, to_slice(ciphertext), to_slice(aad), byref(out))
+        return take_buffer(out)
+
+    def sign_hmac(self, data: bytes) -> bytes:
+        """Compute the HMAC of `data`"""
+        out = Buffer()
+        call("arieskms_key_sign_hmac", self.handle, to_slice(data), byref(out))
+        return take_buffer(out)
+
+    def close(self):
+        """Release the key. It stays in the enclave."""
+        free("arieskms_key_free", self._handle)
+        self._handle = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *_):
+        self.close()
+
+    def __del__(self):
+        self.close()
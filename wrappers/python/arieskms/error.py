@@ -0,0 +1,161 @@
+# Copyright 2020
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+# -----------------------------------------------------------------------------
+"""Exceptions raised for the error codes returned by the C API."""
+
+from enum import IntEnum
+
+
+class ErrorCode(IntEnum):
+    """Mirrors `ArieskmsErrorCode` in include/arieskms.h"""
+
+    SUCCESS = 0
+    INVALID_ARGUMENT = 1
+    PANIC = 2
+    ENCLAVE_CONNECTION_FAILURE = 100
+    ENCLAVE_ACCESS_DENIED = 101
+    ENCLAVE_ITEM_NOT_FOUND = 102
+    ENCLAVE_UNSUPPORTED_OPERATION = 103
+    ENCLAVE_GENERAL_ERROR = 104
+    ENCLAVE_INVALID_CAPABILITIES = 105
+    PERSISTENCE_INVALID_CONFIG = 200
+    PERSISTENCE_IO_ERROR = 201
+    PERSISTENCE_ITEM_NOT_FOUND = 202
+    PERSISTENCE_DUPLICATE_ITEM = 203
+    PERSISTENCE_SERIALIZATION_ERROR = 204
+    PERSISTENCE_INVALID_QUERY = 205
+    PERSISTENCE_CONFLICT = 206
+    PERSISTENCE_UNSUPPORTED_VERSION = 207
+    PERSISTENCE_UNSUPPORTED_OPERATION = 208
+    PROTECTION_INVALID_DATA = 300
+
+
+class ArieskmsError(Exception):
+    """Base class of every error raised by this package"""
+
+    def __init__(self, code: ErrorCode, message: str):
+        super().__init__(message)
+        self.code = code
+
+
+class InvalidArgument(ArieskmsError):
+    """An argument was rejected before reaching the library"""
+
+
+class Panic(ArieskmsError):
+    """The library panicked"""
+
+
+class EnclaveError(ArieskmsError):
+    """`EnclaveErrorKind`"""
+
+
+class ConnectionFailure(EnclaveError):
+    """`EnclaveErrorKind::ConnectionFailure`"""
+
+
+class AccessDenied(EnclaveError):
+    """`EnclaveErrorKind::AccessDenied`"""
+
+
+class KeyNotFound(EnclaveError):
+    """`EnclaveErrorKind::ItemNotFound`"""
+
+
+class UnsupportedOperation(EnclaveError):
+    """`EnclaveErrorKind::UnsupportedOperation`"""
+
+
+class GeneralError(EnclaveError):
+    """`EnclaveErrorKind::GeneralError`"""
+
+
+class InvalidCapabilities(EnclaveError):
+    """`EnclaveErrorKind::InvalidCapabilities`. The capabilities of a key
+    don't fit its type."""
+
+
+class PersistenceError(ArieskmsError):
+    """`PersistenceErrorKind`"""
+
+
+class InvalidConfig(PersistenceError):
+    """`PersistenceErrorKind::InvalidConfig`"""
+
+
+class PersistenceIOError(PersistenceError):
+    """`PersistenceErrorKind::IOError`"""
+
+
+class RecordNotFound(PersistenceError):
+    """`PersistenceErrorKind::ItemNotFound`"""
+
+
+class DuplicateRecord(PersistenceError):
+    """`PersistenceErrorKind::DuplicateItem`"""
+
+
+class SerializationError(PersistenceError):
+    """`PersistenceErrorKind::SerializationError`"""
+
+
+class InvalidQuery(PersistenceError):
+    """`PersistenceErrorKind::InvalidQuery`"""
+
+
+class Conflict(PersistenceError):
+    """`PersistenceErrorKind::Conflict`. The operation can be retried."""
+
+
+class UnsupportedVersion(PersistenceError):
+    """`PersistenceErrorKind::UnsupportedVersion`"""
+
+
+class UnsupportedStoreOperation(PersistenceError):
+    """`PersistenceErrorKind::UnsupportedOperation`"""
+
+
+class InvalidData(ArieskmsError):
+    """`ProtectionErrorKind::InvalidData`. A record could not be decrypted."""
+
+
+_EXCEPTIONS = {
+    ErrorCode.INVALID_ARGUMENT: InvalidArgument,
+    ErrorCode.PANIC: Panic,
+    ErrorCode.ENCLAVE_CONNECTION_FAILURE: ConnectionFailure,
+    ErrorCode.ENCLAVE_ACCESS_DENIED: AccessDenied,
+    ErrorCode.ENCLAVE_ITEM_NOT_FOUND: KeyNotFound,
+    ErrorCode.ENCLAVE_UNSUPPORTED_OPERATION: UnsupportedOperation,
+    ErrorCode.ENCLAVE_GENERAL_ERROR: GeneralError,
+    ErrorCode.ENCLAVE_INVALID_CAPABILITIES: InvalidCapabilities,
+    ErrorCode.PERSISTENCE_INVALID_CONFIG: InvalidConfig,
+    ErrorCode.PERSISTENCE_IO_ERROR: PersistenceIOError,
+    ErrorCode.PERSISTENCE_ITEM_NOT_FOUND: RecordNotFound,
+    ErrorCode.PERSISTENCE_DUPLICATE_ITEM: DuplicateRecord,
+    ErrorCode.PERSISTENCE_SERIALIZATION_ERROR: SerializationError,
+    ErrorCode.PERSISTENCE_INVALID_QUERY: InvalidQuery,
+    ErrorCode.PERSISTENCE_CONFLICT: Conflict,
+    ErrorCode.PERSISTENCE_UNSUPPORTED_VERSION: UnsupportedVersion,
+    ErrorCode.PERSISTENCE_UNSUPPORTED_OPERATION: UnsupportedStoreOperation,
+    ErrorCode.PROTECTION_INVALID_DATA: InvalidData,
+}
+
+
+def exception(code: int, message: str) -> ArieskmsError:
+    """The exception for `code` returned with `message`"""
+    try:
+        code = ErrorCode(code)
+    except ValueError:
+        return ArieskmsError(code, message)
+    return _EXCEPTIONS.get(code, ArieskmsError)(code, message)
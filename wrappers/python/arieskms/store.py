@@ -0,0 +1,156 @@
+# Copyright 2020
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+# -----------------------------------------------------------------------------
+"""Stores whose records are protected by an enclave."""
+
+from ctypes import byref, c_bool, c_char_p, c_size_t, c_void_p
+from typing import Iterable, Optional, Union
+
+from .bindings import Buffer, Tag, call, free, take_buffer, to_slice, to_string
+from .enclave import Enclave
+
+Data = Union[bytes, str]
+
+
+class RecordTag:
+    """A record tag. Encrypted tags only support equality lookups.
+    Plaintext tags are persisted as is."""
+
+    def __init__(self, name: Data, value: Data, plaintext: bool = False):
+        self.name = name
+        self.value = value
+        self.plaintext = plaintext
+
+    def __repr__(self):
+        return "RecordTag({!r}, {!r}, plaintext={})".format(self.name, self.value, self.plaintext)
+
+
+class Store:
+    """A persistence backend protected by the keys starting with `key_prefix`.
+    Pass `generate_keys=True` to generate the keys when the store is new."""
+
+    def __init__(self, handle: c_void_p):
+        self._handle = handle
+
+    @classmethod
+    def open_sled(
+        cls, enclave: Enclave, path: Optional[str], key_prefix: str, generate_keys: bool = False
+    ) -> "Store":
+        """Open the sled database at `path`, or a temporary one if `path` is None"""
+        handle = c_void_p()
+        call(
+            "arieskms_store_open_sled",
+            enclave.handle,
+            c_char_p(to_string(path)),
+            c_char_p(to_string(key_prefix)),
+            c_bool(generate_keys),
+            byref(handle),
+        )
+        return cls(handle)
+
+    @classmethod
+    def open_sqlite(
+        cls, enclave: Enclave, path: Optional[str], key_prefix: str, generate_keys: bool = False
+    ) -> "Store":
+        """Open the SQLite database at `path`, or an in memory one if `path` is None"""
+        handle = c_void_p()
+        call(
+            "arieskms_store_open_sqlite",
+            enclave.handle,
+            c_char_p(to_string(path)),
+            c_char_p(to_string(key_prefix)),
+            c_bool(generate_keys),
+            byref(handle),
+        )
+        return cls(handle)
+
+    @classmethod
+    def open_s3(
+        cls,
+        enclave: Enclave,
+        endpoint: str,
+        bucket: str,
+        region: str,
+        access_key_id: str,
+        secret_access_key: str,
+        key_prefix: str,
+        generate_keys: bool = False,
+    ) -> "Store":
+        """Open the S3 bucket `bucket` at `endpoint`"""
+        handle = c_void_p()
+        call(
+            "arieskms_store_open_s3",
+            enclave.handle,
+            c_char_p(to_string(endpoint)),
+            c_char_p(to_string(bucket)),
+            c_char_p(to_string(region)),
+            c_char_p(to_string(access_key_id)),
+            c_char_p(to_string(secret_access_key)),
+            c_char_p(to_string(key_prefix)),
+            c_bool(generate_keys),
+            byref(handle),
+        )
+        return cls(handle)
+
+    @property
+    def handle(self) -> c_void_p:
+        """The `ArieskmsStore` handle"""
+        if not self._handle:
+            raise ValueError("The store is closed")
+        return self._handle
+
+    def insert(self, category: Data, name: Data, value: Data, tags: Iterable[RecordTag] = ()):
+        """Save a new record"""
+        self._save("arieskms_store_insert", category, name, value, tags)
+
+    def update(self, category: Data, name: Data, value: Data, tags: Iterable[RecordTag] = ()):
+        """Replace the value and tags of an existing record"""
+        self._save("arieskms_store_update", category, name, value, tags)
+
+    def fetch(self, category: Data, name: Data) -> bytes:
+        """Fetch the value of a record"""
+        out = Buffer()
+        call("arieskms_store_fetch", self.handle, to_slice(category), to_slice(name), byref(out))
+        return take_buffer(out)
+
+    def delete(self, category: Data, name: Data):
+        """Delete a record"""
+        call("arieskms_store_delete", self.handle, to_slice(category), to_slice(name))
+
+    def _save(self, function: str, category: Data, name: Data, value: Data, tags):
+        tags = [Tag(to_slice(t.name), to_slice(t.value), t.plaintext) for t in tags]
+        array = (Tag * len(tags))(*tags)
+        call(
+            function,
+            self.handle,
+            to_slice(category),
+            to_slice(name),
+            to_slice(value),
+            array,
+            c_size_t(len(tags)),
+        )
+
+    def close(self):
+        """Close and release the store"""
+        free("arieskms_store_free", self._handle)
+        self._handle = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *_):
+        self.close()
+
+    def __del__(self):
+        self.close()
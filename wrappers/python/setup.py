@@ -0,0 +1,25 @@
+# Copyright 2020
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+# -----------------------------------------------------------------------------
+from setuptools import setup
+
+setup(
+    name="arieskms",
+    version="0.1.0",
+    description="Python bindings for the Aries Key Management Service",
+    license="Apache-2.0",
+    packages=["arieskms"],
+    package_data={"arieskms": ["libarieskms.so", "libarieskms.dylib", "arieskms.dll"]},
+    python_requires=">=3.6",
+)